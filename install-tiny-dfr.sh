@@ -53,6 +53,10 @@ sudo cp target/release/tiny-dfr /usr/bin/
 sudo mkdir -p /usr/share/tiny-dfr
 sudo cp share/tiny-dfr/* /usr/share/tiny-dfr/
 sudo cp etc/systemd/system/tiny-dfr.service /etc/systemd/system/
+sudo mkdir -p /usr/share/dbus-1/system.d
+sudo cp etc/dbus-1/system.d/org.tinydfr.Control.conf /usr/share/dbus-1/system.d/
+sudo mkdir -p /usr/share/polkit-1/actions
+sudo cp etc/polkit-1/actions/org.tinydfr.Control.policy /usr/share/polkit-1/actions/
 
 # Install udev rules (critical for device detection)
 echo "Installing udev rules..."
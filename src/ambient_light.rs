@@ -0,0 +1,52 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// IIO light sensor directory, probed once
+static SENSOR_PATH: std::sync::LazyLock<Option<PathBuf>> = std::sync::LazyLock::new(find_sensor);
+
+// Brightness the backlight manager would pick in the active state
+static TARGET_BRIGHTNESS: std::sync::LazyLock<Arc<Mutex<Option<u32>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
+
+fn find_sensor() -> Option<PathBuf> {
+    for entry in fs::read_dir("/sys/bus/iio/devices/").ok()?.flatten() {
+        let path = entry.path();
+        if path.join("in_illuminance_input").exists() || path.join("in_illuminance_raw").exists() {
+            println!("Using ambient light sensor at {}", path.display());
+            return Some(path);
+        }
+    }
+    None
+}
+
+fn read_value(path: &Path, attr: &str) -> Option<f64> {
+    fs::read_to_string(path.join(attr)).ok()?.trim().parse().ok()
+}
+
+// Public API
+
+pub fn read_lux() -> Option<f64> {
+    let path = SENSOR_PATH.as_ref()?;
+    if let Some(lux) = read_value(path, "in_illuminance_input") {
+        return Some(lux);
+    }
+    let raw = read_value(path, "in_illuminance_raw")?;
+    let offset = read_value(path, "in_illuminance_offset").unwrap_or(0.0);
+    let scale = read_value(path, "in_illuminance_scale").unwrap_or(1.0);
+    Some((raw + offset) * scale)
+}
+
+pub fn sensor_path() -> Option<&'static PathBuf> {
+    SENSOR_PATH.as_ref()
+}
+
+pub fn set_target_brightness(brightness: u32) {
+    if let Ok(mut target) = TARGET_BRIGHTNESS.lock() {
+        *target = Some(brightness);
+    }
+}
+
+pub fn get_target_brightness() -> Option<u32> {
+    TARGET_BRIGHTNESS.lock().ok().and_then(|target| *target)
+}
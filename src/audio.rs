@@ -0,0 +1,132 @@
+use crate::media_state;
+use crate::privileged::{Command, Stdio};
+use crate::user_cache;
+use std::io::{BufRead, BufReader};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+// How long to wait before subscribing again after pactl exited
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+// Level from 0 to 1 and whether the default sink is muted
+static VOLUME: Mutex<Option<(f64, bool)>> = Mutex::new(None);
+// Bumped whenever the volume or mute state changes so the main loop knows to redraw
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// Only started once a Volume button is created
+static AUDIO_MONITOR: std::sync::LazyLock<thread::JoinHandle<()>> =
+    std::sync::LazyLock::new(|| thread::spawn(monitor_loop));
+
+fn set_volume(volume: Option<(f64, bool)>) {
+    if let Ok(mut current) = VOLUME.lock() {
+        if *current != volume {
+            *current = volume;
+            GENERATION.fetch_add(1, Ordering::Relaxed);
+            media_state::wake();
+        }
+    }
+}
+
+fn pactl(env: &user_cache::CachedUserEnvironment) -> Command {
+    let mut command = Command::session_user(&env.username, "env");
+    command
+        .arg(format!("XDG_RUNTIME_DIR={}", env.runtime_dir))
+        .arg("pactl");
+    command
+}
+
+// "Volume: front-left: 29491 /  45% / -20.81 dB,   front-right: ...", the first channel stands for all
+fn parse_volume(output: &str) -> Option<f64> {
+    let percent = output.split_whitespace().find_map(|word| word.strip_suffix('%'))?;
+    Some(percent.parse::<f64>().ok()? / 100.0)
+}
+
+// "Mute: yes" or "Mute: no"
+fn parse_mute(output: &str) -> Option<bool> {
+    match output.trim().strip_prefix("Mute:")?.trim() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+// Asked of the default sink, which is whichever one the user picked last
+fn read_volume(env: &user_cache::CachedUserEnvironment) -> Option<(f64, bool)> {
+    let volume = pactl(env).args(["get-sink-volume", "@DEFAULT_SINK@"]).output().ok()?;
+    let mute = pactl(env).args(["get-sink-mute", "@DEFAULT_SINK@"]).output().ok()?;
+    Some((
+        parse_volume(&String::from_utf8_lossy(&volume.stdout))?,
+        parse_mute(&String::from_utf8_lossy(&mute.stdout))?,
+    ))
+}
+
+// pactl speaks PulseAudio's native protocol, which pipewire-pulse serves as well, and prints a
+// line per event; sink changes cover volume and mute, server changes a new default sink
+fn follow_sink(env: &user_cache::CachedUserEnvironment) -> std::io::Result<()> {
+    let mut child = pactl(env)
+        .arg("subscribe")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Whatever changed while nobody was listening
+    set_volume(read_volume(env));
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines() {
+            let line = line?;
+            if line.starts_with("Event 'change' on sink ") || line.starts_with("Event 'change' on server") {
+                set_volume(read_volume(env));
+            }
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
+
+fn monitor_loop() {
+    let mut reported_failure = false;
+    loop {
+        if let Some(env) = user_cache::get_cached_user_environment() {
+            match follow_sink(&env) {
+                Ok(()) => reported_failure = false,
+                Err(e) if !reported_failure => {
+                    eprintln!("Failed to follow the volume with pactl: {}", e);
+                    reported_failure = true;
+                }
+                Err(_) => {}
+            }
+        }
+        set_volume(None);
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+// Public API
+
+pub fn initialize_audio_monitor() {
+    std::sync::LazyLock::force(&AUDIO_MONITOR);
+}
+
+// None until the sound server answered, or while there is none
+pub fn volume() -> Option<(f64, bool)> {
+    *VOLUME.lock().ok()?
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pactl_output() {
+        let volume = "Volume: front-left: 29491 /  45% / -20.81 dB,   front-right: 29491 /  45% / -20.81 dB\n        balance 0.00\n";
+        assert_eq!(parse_volume(volume), Some(0.45));
+        assert_eq!(parse_mute("Mute: yes\n"), Some(true));
+        assert_eq!(parse_mute("Mute: no\n"), Some(false));
+        assert_eq!(parse_volume("Connection failure: Connection refused"), None);
+    }
+}
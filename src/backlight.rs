@@ -1,4 +1,6 @@
+use crate::ambient_light;
 use crate::config::Config;
+use crate::privileged;
 use crate::TIMEOUT_MS;
 use anyhow::{anyhow, Result};
 use input::event::{
@@ -10,7 +12,8 @@ use std::{
     fs::{self, File, OpenOptions},
     io::Write,
     path::{Path, PathBuf},
-    time::Instant,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 const MAX_DISPLAY_BRIGHTNESS: u32 = 509;
@@ -19,6 +22,10 @@ const BRIGHTNESS_DIM_TIMEOUT: i32 = TIMEOUT_MS * 3; // should be a multiple of T
 const BRIGHTNESS_OFF_TIMEOUT: i32 = TIMEOUT_MS * 6; // should be a multiple of TIMEOUT_MS
 const DIMMED_BRIGHTNESS: u32 = 1;
 
+// Whether the backlight is currently off, for code that runs outside the main loop
+static BAR_OFF: std::sync::LazyLock<Arc<Mutex<bool>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(false)));
+
 fn read_attr(path: &Path, attr: &str) -> u32 {
     fs::read_to_string(path.join(attr))
         .unwrap_or_else(|_| panic!("Failed to read {attr}"))
@@ -27,7 +34,7 @@ fn read_attr(path: &Path, attr: &str) -> u32 {
         .unwrap_or_else(|_| panic!("Failed to parse {attr}"))
 }
 
-fn find_backlight() -> Result<PathBuf> {
+pub fn find_backlight() -> Result<PathBuf> {
     for entry in fs::read_dir("/sys/class/backlight/")? {
         let entry = entry?;
         let file_name = entry.file_name();
@@ -43,7 +50,7 @@ fn find_backlight() -> Result<PathBuf> {
     Err(anyhow!("No Touch Bar backlight device found"))
 }
 
-fn find_display_backlight() -> Result<PathBuf> {
+pub fn find_display_backlight() -> Result<PathBuf> {
     for entry in fs::read_dir("/sys/class/backlight/")? {
         let entry = entry?;
         if [
@@ -71,7 +78,15 @@ pub struct BacklightManager {
     current_bl: u32,
     lid_state: SwitchState,
     bl_file: File,
+    bl_path: PathBuf,
     display_bl_path: PathBuf,
+    dimmed: bool,
+    care_mode: bool,
+    quiet: bool,
+    battery_saver: bool,
+    // Level adaptive brightness is held at after a manual change or a wake up
+    override_level: Option<(u32, Instant)>,
+    last_active_bl: u32,
 }
 
 impl BacklightManager {
@@ -88,7 +103,14 @@ impl BacklightManager {
             max_bl: read_attr(&bl_path, "max_brightness"),
             current_bl: read_attr(&bl_path, "brightness"),
             last_active: Instant::now(),
+            bl_path,
             display_bl_path,
+            dimmed: false,
+            care_mode: false,
+            quiet: false,
+            battery_saver: false,
+            override_level: None,
+            last_active_bl: 0,
         }
     }
     fn display_to_touchbar(display: u32, active_brightness: u32) -> u32 {
@@ -116,31 +138,124 @@ impl BacklightManager {
     }
     pub fn update_backlight(&mut self, cfg: &Config) {
         let since_last_active = (Instant::now() - self.last_active).as_millis() as u64;
+        let (dim_timeout, off_timeout) = if self.battery_saver {
+            let dim_timeout = cfg.battery_saver_dim_seconds as u64 * 1000;
+            (dim_timeout, dim_timeout * 2)
+        } else {
+            (BRIGHTNESS_DIM_TIMEOUT as u64, BRIGHTNESS_OFF_TIMEOUT as u64)
+        };
+        self.dimmed = self.lid_state != SwitchState::On
+            && since_last_active >= dim_timeout
+            && since_last_active < off_timeout;
+        let active = self.lid_state != SwitchState::On
+            && !self.care_mode
+            && since_last_active < dim_timeout;
+        let hold = Duration::from_secs(cfg.adaptive_brightness_hold_seconds as u64);
+        if active && cfg.adaptive_brightness && !hold.is_zero() {
+            // Someone else wrote to the backlight, e.g. brightnessctl
+            let actual_bl = read_attr(&self.bl_path, "brightness");
+            if self.current_bl != 0 && actual_bl != self.current_bl {
+                self.override_level = Some((actual_bl, Instant::now()));
+                self.current_bl = actual_bl;
+            } else if self.current_bl <= DIMMED_BRIGHTNESS && self.last_active_bl != 0 {
+                // Woken up by input, come back at the level used before dimming
+                self.override_level = Some((self.last_active_bl, Instant::now()));
+            }
+        }
+        if self.override_level.is_some_and(|(_, since)| since.elapsed() >= hold) {
+            self.override_level = None;
+        }
+        let target_bl = if let (true, Some((level, _))) = (cfg.adaptive_brightness, self.override_level) {
+            level
+        } else if cfg.adaptive_brightness {
+            BacklightManager::display_to_touchbar(
+                read_attr(&self.display_bl_path, "brightness"),
+                cfg.active_brightness,
+            )
+        } else {
+            cfg.active_brightness
+        };
+        let target_bl = if self.quiet {
+            target_bl.min(cfg.quiet_hours_brightness)
+        } else {
+            target_bl
+        };
+        let target_bl = if self.battery_saver {
+            target_bl.min(cfg.battery_saver_brightness)
+        } else {
+            target_bl
+        };
+        ambient_light::set_target_brightness(target_bl);
         let new_bl = min(
             self.max_bl,
             if self.lid_state == SwitchState::On {
                 0
-            } else if since_last_active < BRIGHTNESS_DIM_TIMEOUT as u64 {
-                if cfg.adaptive_brightness {
-                    BacklightManager::display_to_touchbar(
-                        read_attr(&self.display_bl_path, "brightness"),
-                        cfg.active_brightness,
-                    )
-                } else {
-                    cfg.active_brightness
-                }
-            } else if since_last_active < BRIGHTNESS_OFF_TIMEOUT as u64 {
+            } else if self.care_mode {
+                DIMMED_BRIGHTNESS
+            } else if since_last_active < dim_timeout {
+                target_bl
+            } else if since_last_active < off_timeout {
                 DIMMED_BRIGHTNESS
             } else {
                 0
             },
         );
+        if active {
+            self.last_active_bl = new_bl;
+        }
         if self.current_bl != new_bl {
             self.current_bl = new_bl;
             set_backlight(&self.bl_file, self.current_bl);
         }
+        if let Ok(mut bar_off) = BAR_OFF.lock() {
+            *bar_off = new_bl == 0;
+        }
     }
     pub fn current_bl(&self) -> u32 {
         self.current_bl
     }
+    pub fn is_dimmed(&self) -> bool {
+        self.dimmed
+    }
+    pub fn idle_for(&self) -> Duration {
+        self.last_active.elapsed()
+    }
+    pub fn lid_closed(&self) -> bool {
+        self.lid_state == SwitchState::On
+    }
+    pub fn brightness_fraction(&self) -> f64 {
+        self.current_bl as f64 / self.max_bl.max(1) as f64
+    }
+    // Sets the built in display's brightness, from 0 to 1, the bar follows it as usual
+    pub fn set_display_brightness(&mut self, fraction: f64) {
+        let max = read_attr(&self.display_bl_path, "max_brightness");
+        let value = (fraction.clamp(0.0, 1.0) * max as f64).round() as u32;
+        let path = self.display_bl_path.join("brightness");
+        let result = privileged::open(&path.to_string_lossy(), true).and_then(|mut file| file.write_all(format!("{}\n", value).as_bytes()));
+        if let Err(e) = result {
+            eprintln!("Failed to set the display brightness: {}", e);
+        }
+    }
+    // Keeps the panel lit at the dimmed level while an OLED care pattern is shown
+    pub fn set_care_mode(&mut self, care_mode: bool) {
+        self.care_mode = care_mode;
+    }
+    // Counts as input, e.g. to keep the bar lit while an alarm goes off
+    pub fn wake(&mut self) {
+        self.last_active = Instant::now();
+    }
+    // Caps the brightness at QuietHoursBrightness
+    pub fn set_quiet(&mut self, quiet: bool) {
+        self.quiet = quiet;
+    }
+    // Caps the brightness at BatterySaverBrightness and dims after BatterySaverDimSeconds
+    pub fn set_battery_saver(&mut self, battery_saver: bool) {
+        self.battery_saver = battery_saver;
+    }
+}
+
+// Public API
+
+pub fn bar_off() -> bool {
+    BAR_OFF.lock().map(|bar_off| *bar_off).unwrap_or(false)
 }
@@ -1,4 +1,8 @@
+use crate::media_state;
+use std::collections::VecDeque;
 use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
@@ -14,15 +18,32 @@ pub enum BatteryState {
 pub struct BatteryInfo {
     pub capacity: u32,
     pub state: BatteryState,
+    // Watts going in or out, whichever way the battery is going
+    pub power_now: Option<f64>,
+    // Only known while discharging
+    pub time_to_empty: Option<Duration>,
     pub last_updated: Instant,
 }
 
 impl BatteryInfo {
-    fn new() -> Self {
-        BatteryInfo {
-            capacity: 100,
-            state: BatteryState::NotCharging,
-            last_updated: Instant::now(),
+    // Whether a battery button would look any different, the rate wobbles below a watt
+    fn same_reading(&self, other: &BatteryInfo) -> bool {
+        self.capacity == other.capacity
+            && self.state == other.state
+            && self.power_now.map(f64::round) == other.power_now.map(f64::round)
+            && self.time_to_empty.map(|t| t.as_secs() / 60) == other.time_to_empty.map(|t| t.as_secs() / 60)
+    }
+
+    // "2:13 remaining" on battery, the charge rate like "+23 W" while charging
+    pub fn remaining_text(&self) -> Option<String> {
+        match (self.state, self.time_to_empty, self.power_now) {
+            (BatteryState::Charging, _, Some(power)) if power >= 0.5 => Some(format!("+{:.0} W", power)),
+            (BatteryState::Charging, _, _) => None,
+            (_, Some(left), _) => {
+                let minutes = left.as_secs() / 60;
+                Some(format!("{}:{:02} remaining", minutes / 60, minutes % 60))
+            }
+            _ => None,
         }
     }
 }
@@ -30,6 +51,21 @@ impl BatteryInfo {
 // Global battery state
 static BATTERY_STATE: std::sync::LazyLock<Arc<Mutex<Option<BatteryInfo>>>> =
     std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
+// Bumped whenever a battery button would change so the main loop knows to redraw
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+// Wakes the monitor early, for udev power_supply events
+static REFRESH: Mutex<Option<Sender<()>>> = Mutex::new(None);
+
+// Reads in between udev events, which don't come for every percent on all machines
+const UPDATE_INTERVAL: Duration = Duration::from_secs(30);
+const HISTORY_SAMPLE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const HISTORY_MAX_AGE: Duration = Duration::from_secs(48 * 60 * 60);
+
+// Capacity samples for the history graph, oldest first
+type BatteryHistory = VecDeque<(Instant, u32)>;
+
+static BATTERY_HISTORY: std::sync::LazyLock<Arc<Mutex<BatteryHistory>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(VecDeque::new())));
 
 pub struct BatteryMonitor {
     _handle: thread::JoinHandle<()>,
@@ -37,80 +73,127 @@ pub struct BatteryMonitor {
 
 impl BatteryMonitor {
     pub fn new(battery_name: String) -> Self {
+        // Read once up front so the first frame already has something to show
+        Self::store(read_battery_state(&battery_name));
+        let (sender, receiver) = mpsc::channel();
+        if let Ok(mut refresh) = REFRESH.lock() {
+            *refresh = Some(sender);
+        }
         let handle = thread::spawn(move || {
-            Self::monitor_loop(&battery_name);
+            Self::monitor_loop(&battery_name, receiver);
         });
 
         BatteryMonitor { _handle: handle }
     }
 
-    fn monitor_loop(battery_name: &str) {
-        let mut last_update = Instant::now();
-        let update_interval = Duration::from_secs(30); // Update every 30 seconds
-
-        loop {
-            if last_update.elapsed() >= update_interval {
-                let battery_info = Self::read_battery_state(battery_name);
+    fn store(battery_info: BatteryInfo) {
+        if let Ok(mut state) = BATTERY_STATE.lock() {
+            if !state.as_ref().is_some_and(|current| current.same_reading(&battery_info)) {
+                GENERATION.fetch_add(1, Ordering::Relaxed);
+                media_state::wake();
+            }
+            *state = Some(battery_info);
+        }
+    }
 
-                if let Ok(mut state) = BATTERY_STATE.lock() {
-                    *state = Some(battery_info);
-                }
+    fn monitor_loop(battery_name: &str, refresh: Receiver<()>) {
+        let mut last_history_sample: Option<Instant> = None;
 
-                last_update = Instant::now();
+        loop {
+            if last_history_sample.is_none_or(|t| t.elapsed() >= HISTORY_SAMPLE_INTERVAL) {
+                Self::record_history(read_capacity(battery_name));
+                last_history_sample = Some(Instant::now());
             }
 
-            thread::sleep(Duration::from_secs(1));
+            match refresh.recv_timeout(UPDATE_INTERVAL) {
+                // Plugging in the charger comes with a burst of events, one read covers them
+                Ok(()) => while refresh.try_recv().is_ok() {},
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            Self::store(read_battery_state(battery_name));
         }
     }
 
-    fn read_battery_state(battery: &str) -> BatteryInfo {
-        let status_path = format!("/sys/class/power_supply/{}/status", battery);
-        let status = fs::read_to_string(&status_path)
-            .unwrap_or_else(|_| "Unknown".to_string());
+    fn record_history(capacity: u32) {
+        if let Ok(mut history) = BATTERY_HISTORY.lock() {
+            let now = Instant::now();
+            history.push_back((now, capacity));
+            while history.front().is_some_and(|(t, _)| now.duration_since(*t) > HISTORY_MAX_AGE) {
+                history.pop_front();
+            }
+        }
+    }
+}
 
-        let capacity = Self::read_capacity(battery);
+fn read_value(battery: &str, name: &str) -> Option<f64> {
+    fs::read_to_string(format!("/sys/class/power_supply/{}/{}", battery, name))
+        .ok()
+        .and_then(|s| s.trim().parse::<f64>().ok())
+}
 
-        let state = match status.trim() {
-            "Charging" | "Full" => BatteryState::Charging,
-            "Discharging" if capacity < 10 => BatteryState::Low,
-            _ => BatteryState::NotCharging,
-        };
+// Drivers report power_now, or current_now and voltage_now to multiply, in µW, µA and µV.
+// Some sign the current by direction, only the amount matters here
+fn read_power(battery: &str) -> Option<f64> {
+    let microwatts = read_value(battery, "power_now")
+        .or_else(|| Some(read_value(battery, "current_now")? * read_value(battery, "voltage_now")? / 1e6))?;
+    Some(microwatts.abs() / 1e6)
+}
 
-        BatteryInfo {
-            capacity,
-            state,
-            last_updated: Instant::now(),
-        }
+// What the driver estimates, or what's left divided by the rate it's drawn at, in the
+// energy (µWh / µW) or charge (µAh / µA) units the driver uses
+fn read_time_to_empty(battery: &str) -> Option<Duration> {
+    if let Some(seconds) = read_value(battery, "time_to_empty_now") {
+        return (seconds > 0.0).then(|| Duration::from_secs(seconds as u64));
     }
+    let (left, rate) = match read_value(battery, "energy_now") {
+        Some(energy) => (energy, read_value(battery, "power_now")?),
+        None => (read_value(battery, "charge_now")?, read_value(battery, "current_now")?),
+    };
+    let rate = rate.abs();
+    (rate > 0.0).then(|| Duration::from_secs_f64(left / rate * 3600.0))
+}
 
-    #[cfg(target_arch = "x86_64")]
-    fn read_capacity(battery: &str) -> u32 {
-        let charge_now_path = format!("/sys/class/power_supply/{}/charge_now", battery);
-        let charge_full_path = format!("/sys/class/power_supply/{}/charge_full", battery);
-
-        let charge_now = fs::read_to_string(&charge_now_path)
-            .ok()
-            .and_then(|s| s.trim().parse::<f64>().ok());
-        let charge_full = fs::read_to_string(&charge_full_path)
-            .ok()
-            .and_then(|s| s.trim().parse::<f64>().ok());
-
-        match (charge_now, charge_full) {
-            (Some(now), Some(full)) if full > 0.0 => ((now / full) * 100.0).round() as u32,
-            _ => 100,
-        }
+pub fn read_battery_state(battery: &str) -> BatteryInfo {
+    let status_path = format!("/sys/class/power_supply/{}/status", battery);
+    let status = fs::read_to_string(&status_path)
+        .unwrap_or_else(|_| "Unknown".to_string());
+
+    let capacity = read_capacity(battery);
+
+    let state = match status.trim() {
+        "Charging" | "Full" => BatteryState::Charging,
+        "Discharging" if capacity < 10 => BatteryState::Low,
+        _ => BatteryState::NotCharging,
+    };
+    let time_to_empty = if status.trim() == "Discharging" { read_time_to_empty(battery) } else { None };
+
+    BatteryInfo {
+        capacity,
+        state,
+        power_now: read_power(battery),
+        time_to_empty,
+        last_updated: Instant::now(),
     }
+}
 
-    #[cfg(target_arch = "aarch64")]
-    fn read_capacity(battery: &str) -> u32 {
-        let capacity_path = format!("/sys/class/power_supply/{}/capacity", battery);
-        fs::read_to_string(&capacity_path)
-            .ok()
-            .and_then(|s| s.trim().parse::<u32>().ok())
-            .unwrap_or(100)
+#[cfg(target_arch = "x86_64")]
+fn read_capacity(battery: &str) -> u32 {
+    match (read_value(battery, "charge_now"), read_value(battery, "charge_full")) {
+        (Some(now), Some(full)) if full > 0.0 => ((now / full) * 100.0).round() as u32,
+        _ => 100,
     }
 }
 
+#[cfg(target_arch = "aarch64")]
+fn read_capacity(battery: &str) -> u32 {
+    let capacity_path = format!("/sys/class/power_supply/{}/capacity", battery);
+    fs::read_to_string(&capacity_path)
+        .ok()
+        .and_then(|s| s.trim().parse::<u32>().ok())
+        .unwrap_or(100)
+}
+
 // Public API
 pub fn get_cached_battery_state() -> Option<(u32, BatteryState)> {
     if let Ok(state) = BATTERY_STATE.lock() {
@@ -120,6 +203,10 @@ pub fn get_cached_battery_state() -> Option<(u32, BatteryState)> {
     }
 }
 
+pub fn get_cached_battery_info() -> Option<BatteryInfo> {
+    BATTERY_STATE.lock().ok()?.clone()
+}
+
 pub fn is_battery_data_fresh() -> bool {
     if let Ok(state) = BATTERY_STATE.lock() {
         if let Some(info) = state.as_ref() {
@@ -130,4 +217,53 @@ pub fn is_battery_data_fresh() -> bool {
     } else {
         false
     }
-}
\ No newline at end of file
+}
+
+// Reads the battery again without waiting for the next interval
+pub fn refresh() {
+    if let Ok(refresh) = REFRESH.lock() {
+        if let Some(refresh) = refresh.as_ref() {
+            let _ = refresh.send(());
+        }
+    }
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+// Samples from within the last `max_age` as (age, capacity), oldest first
+pub fn get_battery_history(max_age: Duration) -> Vec<(Duration, u32)> {
+    if let Ok(history) = BATTERY_HISTORY.lock() {
+        history
+            .iter()
+            .map(|(t, capacity)| (t.elapsed(), *capacity))
+            .filter(|(age, _)| *age <= max_age)
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remaining_text() {
+        let mut info = BatteryInfo {
+            capacity: 64,
+            state: BatteryState::NotCharging,
+            power_now: Some(8.4),
+            time_to_empty: Some(Duration::from_secs(2 * 3600 + 13 * 60 + 40)),
+            last_updated: Instant::now(),
+        };
+        assert_eq!(info.remaining_text().as_deref(), Some("2:13 remaining"));
+        info.state = BatteryState::Charging;
+        info.power_now = Some(23.2);
+        assert_eq!(info.remaining_text().as_deref(), Some("+23 W"));
+        // Full and on the charger
+        info.power_now = Some(0.0);
+        assert_eq!(info.remaining_text(), None);
+    }
+}
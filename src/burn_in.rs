@@ -0,0 +1,217 @@
+use crate::config::{self, Config, OledCareMode};
+use crate::{apply_rotation, bar_point_to_fb, bar_rect_to_clip};
+use cairo::{Context, Format, ImageSurface};
+use drm::control::ClipRect;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::time::{Duration, Instant};
+
+// Number of slices the bar is split into along its length for wear tracking
+const REGION_COUNT: usize = 32;
+// Distance in pixels between two sampled pixels when measuring how lit a region is
+const SAMPLE_STEP: i32 = 4;
+const MEASURE_INTERVAL: Duration = Duration::from_secs(10);
+const SAVE_INTERVAL: Duration = Duration::from_secs(15 * 60);
+const STATS_DIR: &str = "/var/lib/tiny-dfr";
+const STATS_PATH: &str = "/var/lib/tiny-dfr/burn-in.json";
+// Brightest gray used by the care patterns, the backlight is dimmed on top of that
+const CARE_LEVEL: f64 = 0.6;
+const PAN_STEP_MS: i32 = 2000;
+const PAN_STEP_PX: f64 = 4.0;
+
+#[derive(Serialize, Deserialize, Default)]
+struct BurnInStats {
+    // Accumulated seconds at full white and full brightness, per region
+    regions: Vec<f64>,
+}
+
+pub struct BurnInTracker {
+    stats: BurnInStats,
+    // Average luminance of each region in the last drawn frame
+    luma: Vec<f64>,
+    last_measure: Option<Instant>,
+    last_tick: Instant,
+    last_save: Instant,
+    save_failed: bool,
+    care_started: Option<Instant>,
+    pan_offset: f64,
+}
+
+impl BurnInTracker {
+    pub fn new() -> BurnInTracker {
+        let stats = fs::read_to_string(STATS_PATH)
+            .ok()
+            .and_then(|s| serde_json::from_str::<BurnInStats>(&s).ok())
+            .filter(|s| s.regions.len() == REGION_COUNT)
+            .unwrap_or_else(|| BurnInStats {
+                regions: vec![0.0; REGION_COUNT],
+            });
+        BurnInTracker {
+            stats,
+            luma: vec![0.0; REGION_COUNT],
+            last_measure: None,
+            last_tick: Instant::now(),
+            last_save: Instant::now(),
+            save_failed: false,
+            care_started: None,
+            pan_offset: 0.0,
+        }
+    }
+
+    // Called after a frame was drawn, measuring is rate limited since it walks the whole surface
+    pub fn frame_drawn(&mut self, surface: &mut ImageSurface, rotation: u32, width: i32, height: i32) {
+        if self.last_measure.is_some_and(|t| t.elapsed() < MEASURE_INTERVAL) {
+            return;
+        }
+        self.last_measure = Some(Instant::now());
+        let format = surface.format();
+        let stride = surface.stride() as usize;
+        let Ok(data) = surface.data() else {
+            return;
+        };
+        let region_width = width as f64 / REGION_COUNT as f64;
+        for (region, luma) in self.luma.iter_mut().enumerate() {
+            let start = (region as f64 * region_width) as i32;
+            let end = ((region + 1) as f64 * region_width) as i32;
+            let mut total = 0.0;
+            let mut samples = 0;
+            for x in (start..end).step_by(SAMPLE_STEP as usize) {
+                for y in (0..height).step_by(SAMPLE_STEP as usize) {
+                    let (fx, fy) = bar_point_to_fb(rotation, width, height, x as f64, y as f64);
+                    let (fx, fy) = (fx as usize, fy as usize);
+                    total += pixel_luma(&data, format, stride, fx, fy);
+                    samples += 1;
+                }
+            }
+            *luma = if samples > 0 { total / samples as f64 } else { 0.0 };
+        }
+    }
+
+    // Accumulates wear for the time since the last call, `brightness` is the backlight level from 0 to 1
+    pub fn tick(&mut self, brightness: f64) {
+        let elapsed = self.last_tick.elapsed().as_secs_f64();
+        self.last_tick = Instant::now();
+        for (wear, luma) in self.stats.regions.iter_mut().zip(&self.luma) {
+            *wear += luma * brightness * elapsed;
+        }
+        if self.last_save.elapsed() >= SAVE_INTERVAL {
+            self.last_save = Instant::now();
+            self.save();
+        }
+    }
+
+    fn save(&mut self) {
+        let result = fs::create_dir_all(STATS_DIR)
+            .and_then(|_| fs::write(STATS_PATH, serde_json::to_string(&self.stats).unwrap()));
+        match result {
+            Ok(()) => self.save_failed = false,
+            // Only complain once until it works again
+            Err(e) if !self.save_failed => {
+                eprintln!("Failed to save burn-in statistics to {}: {}", STATS_PATH, e);
+                self.save_failed = true;
+            }
+            Err(_) => {}
+        }
+    }
+
+    pub fn care_mode_active(&self, cfg: &Config, idle_for: Duration, lid_closed: bool) -> bool {
+        if cfg.oled_care_mode == OledCareMode::None || lid_closed {
+            return false;
+        }
+        config::within_hours(cfg.oled_care_hours) && idle_for >= Duration::from_secs(cfg.oled_care_idle_minutes as u64 * 60)
+    }
+
+    pub fn set_care_mode(&mut self, active: bool) {
+        match (active, self.care_started) {
+            (true, None) => {
+                println!("Entering OLED care mode");
+                self.care_started = Some(Instant::now());
+            }
+            (false, Some(_)) => {
+                println!("Leaving OLED care mode");
+                self.care_started = None;
+            }
+            _ => {}
+        }
+    }
+
+    // Returns whether the pattern moved and how long until it moves again
+    pub fn care_update(&mut self, cfg: &Config) -> (bool, i32) {
+        let Some(started) = self.care_started else {
+            return (false, i32::MAX);
+        };
+        if cfg.oled_care_mode != OledCareMode::Pan {
+            return (false, i32::MAX);
+        }
+        let elapsed_ms = started.elapsed().as_millis() as i32;
+        let offset = (elapsed_ms / PAN_STEP_MS) as f64 * PAN_STEP_PX;
+        let moved = offset != self.pan_offset;
+        self.pan_offset = offset;
+        (moved, PAN_STEP_MS - elapsed_ms % PAN_STEP_MS)
+    }
+
+    pub fn draw_care_pattern(&self, cfg: &Config, width: i32, height: i32, surface: &ImageSurface) -> Vec<ClipRect> {
+        let c = Context::new(surface).unwrap();
+        apply_rotation(&c, cfg.rotation, width, height);
+        c.set_source_rgb(0.0, 0.0, 0.0);
+        c.paint().unwrap();
+        let region_width = width as f64 / REGION_COUNT as f64;
+        match cfg.oled_care_mode {
+            OledCareMode::None => {}
+            OledCareMode::Invert => {
+                // Exercise the least worn regions the most to even out the wear
+                let max_wear = self.stats.regions.iter().cloned().fold(0.0, f64::max);
+                for (i, wear) in self.stats.regions.iter().enumerate() {
+                    let level = if max_wear > 0.0 {
+                        CARE_LEVEL * (1.0 - wear / max_wear)
+                    } else {
+                        CARE_LEVEL
+                    };
+                    c.set_source_rgb(level, level, level);
+                    c.rectangle(i as f64 * region_width, 0.0, region_width.ceil(), height as f64);
+                    c.fill().unwrap();
+                }
+            }
+            OledCareMode::Pan => {
+                // A soft band slowly travelling along the bar
+                let band = width as f64 / 8.0;
+                let x = self.pan_offset % (width as f64 + band) - band;
+                let gradient = cairo::LinearGradient::new(x, 0.0, x + band, 0.0);
+                gradient.add_color_stop_rgb(0.0, 0.0, 0.0, 0.0);
+                gradient.add_color_stop_rgb(0.5, CARE_LEVEL, CARE_LEVEL, CARE_LEVEL);
+                gradient.add_color_stop_rgb(1.0, 0.0, 0.0, 0.0);
+                c.set_source(&gradient).unwrap();
+                c.rectangle(x, 0.0, band, height as f64);
+                c.fill().unwrap();
+            }
+        }
+        vec![bar_rect_to_clip(cfg.rotation, width, height, 0.0, 0.0, width as f64, height as f64)]
+    }
+}
+
+fn pixel_luma(data: &[u8], format: Format, stride: usize, x: usize, y: usize) -> f64 {
+    match format {
+        Format::Rgb16_565 => {
+            let offset = y * stride + x * 2;
+            let Some(bytes) = data.get(offset..offset + 2) else {
+                return 0.0;
+            };
+            let pixel = u16::from_ne_bytes([bytes[0], bytes[1]]);
+            let r = ((pixel >> 11) & 0x1f) as f64 / 31.0;
+            let g = ((pixel >> 5) & 0x3f) as f64 / 63.0;
+            let b = (pixel & 0x1f) as f64 / 31.0;
+            0.2126 * r + 0.7152 * g + 0.0722 * b
+        }
+        _ => {
+            let offset = y * stride + x * 4;
+            let Some(bytes) = data.get(offset..offset + 4) else {
+                return 0.0;
+            };
+            let pixel = u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            let r = ((pixel >> 16) & 0xff) as f64 / 255.0;
+            let g = ((pixel >> 8) & 0xff) as f64 / 255.0;
+            let b = (pixel & 0xff) as f64 / 255.0;
+            0.2126 * r + 0.7152 * g + 0.0722 * b
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::privileged::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+// systemd-inhibit holds logind's idle and sleep locks for as long as its command runs,
+// waiting on our own pid lets go of them should tiny-dfr exit without releasing them
+static INHIBITOR: std::sync::LazyLock<Arc<Mutex<Option<Child>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
+
+fn take_lock() -> Option<Child> {
+    let result = Command::new("systemd-inhibit")
+        .args(["--what=idle:sleep", "--who=tiny-dfr", "--why=Caffeine button", "--mode=block"])
+        .args(["tail", &format!("--pid={}", std::process::id()), "-f", "/dev/null"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    match result {
+        Ok(child) => Some(child),
+        Err(e) => {
+            eprintln!("Failed to run systemd-inhibit: {}", e);
+            None
+        }
+    }
+}
+
+// Public API
+
+// Whether idle and sleep are inhibited, false again if systemd-inhibit went away
+pub fn active() -> bool {
+    let Ok(mut inhibitor) = INHIBITOR.lock() else {
+        return false;
+    };
+    if let Some(child) = inhibitor.as_mut() {
+        if !matches!(child.try_wait(), Ok(None)) {
+            *inhibitor = None;
+        }
+    }
+    inhibitor.is_some()
+}
+
+pub fn toggle() {
+    let Ok(mut inhibitor) = INHIBITOR.lock() else {
+        return;
+    };
+    match inhibitor.take() {
+        Some(mut child) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            println!("Caffeine off, idle and sleep are allowed again");
+        }
+        None => {
+            *inhibitor = take_lock();
+            if inhibitor.is_some() {
+                println!("Caffeine on, idle and sleep are inhibited");
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use crate::privileged::Command;
+use crate::user_cache;
+use input_linux::Key;
+use std::sync::{Arc, Mutex};
+
+// Longest number that can be typed in, more digits than an f64 keeps anyway
+const MAX_DIGITS: usize = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Plus,
+    Minus,
+    Times,
+    Divide,
+}
+
+impl Operator {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Plus => "+",
+            Operator::Minus => "−",
+            Operator::Times => "×",
+            Operator::Divide => "÷",
+        }
+    }
+
+    fn apply(&self, left: f64, right: f64) -> Option<f64> {
+        let result = match self {
+            Operator::Plus => left + right,
+            Operator::Minus => left - right,
+            Operator::Times => left * right,
+            Operator::Divide => left / right,
+        };
+        result.is_finite().then_some(result)
+    }
+}
+
+// Buttons of the calculator, Calculator_7, Calculator_Plus, Calculator_Type, etc.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalculatorKey {
+    Digit(u8),
+    Point,
+    Operator(Operator),
+    Equals,
+    Clear,
+    Type, // types the result into the focused application
+    Copy, // puts the result on the clipboard
+}
+
+impl CalculatorKey {
+    pub fn parse(action: &str) -> Option<CalculatorKey> {
+        let key = match action.strip_prefix("Calculator_")? {
+            "Point" => CalculatorKey::Point,
+            "Plus" => CalculatorKey::Operator(Operator::Plus),
+            "Minus" => CalculatorKey::Operator(Operator::Minus),
+            "Times" => CalculatorKey::Operator(Operator::Times),
+            "Divide" => CalculatorKey::Operator(Operator::Divide),
+            "Equals" => CalculatorKey::Equals,
+            "Clear" => CalculatorKey::Clear,
+            "Type" => CalculatorKey::Type,
+            "Copy" => CalculatorKey::Copy,
+            digit => CalculatorKey::Digit(digit.parse().ok().filter(|digit| *digit < 10)?),
+        };
+        Some(key)
+    }
+
+    pub fn label(&self) -> String {
+        let name = match self {
+            CalculatorKey::Digit(digit) => return format!("Calculator_{}", digit),
+            CalculatorKey::Point => "Point",
+            CalculatorKey::Operator(Operator::Plus) => "Plus",
+            CalculatorKey::Operator(Operator::Minus) => "Minus",
+            CalculatorKey::Operator(Operator::Times) => "Times",
+            CalculatorKey::Operator(Operator::Divide) => "Divide",
+            CalculatorKey::Equals => "Equals",
+            CalculatorKey::Clear => "Clear",
+            CalculatorKey::Type => "Type",
+            CalculatorKey::Copy => "Copy",
+        };
+        format!("Calculator_{}", name)
+    }
+
+    // What the button shows in the built-in calculator
+    pub fn text(&self) -> String {
+        match self {
+            CalculatorKey::Digit(digit) => digit.to_string(),
+            CalculatorKey::Point => ".".to_string(),
+            CalculatorKey::Operator(operator) => operator.symbol().to_string(),
+            CalculatorKey::Equals => "=".to_string(),
+            CalculatorKey::Clear => "C".to_string(),
+            CalculatorKey::Type => "Type".to_string(),
+            CalculatorKey::Copy => "Copy".to_string(),
+        }
+    }
+}
+
+// Works like a pocket calculator, each operator applies the previous one
+#[derive(Default)]
+struct Calculator {
+    total: Option<f64>,
+    operator: Option<Operator>,
+    entry: String,
+    // Divided by zero or overflowed, cleared by the next key
+    error: bool,
+}
+
+fn format_number(value: f64) -> String {
+    let text = format!("{:.10}", value);
+    let text = text.trim_end_matches('0').trim_end_matches('.');
+    if text == "-0" { "0".to_string() } else { text.to_string() }
+}
+
+impl Calculator {
+    fn press(&mut self, key: CalculatorKey) {
+        if self.error {
+            *self = Calculator::default();
+        }
+        match key {
+            CalculatorKey::Digit(digit) => {
+                // A new number after = starts over
+                if self.operator.is_none() && self.entry.is_empty() {
+                    self.total = None;
+                }
+                if self.entry.len() < MAX_DIGITS {
+                    if self.entry == "0" {
+                        self.entry.clear();
+                    }
+                    self.entry.push(char::from(b'0' + digit));
+                }
+            }
+            CalculatorKey::Point => {
+                if self.operator.is_none() && self.entry.is_empty() {
+                    self.total = None;
+                }
+                if self.entry.is_empty() {
+                    self.entry.push('0');
+                }
+                if !self.entry.contains('.') {
+                    self.entry.push('.');
+                }
+            }
+            CalculatorKey::Operator(operator) => {
+                self.evaluate();
+                if !self.error {
+                    self.total.get_or_insert(0.0);
+                    self.operator = Some(operator);
+                }
+            }
+            CalculatorKey::Equals => self.evaluate(),
+            CalculatorKey::Clear => *self = Calculator::default(),
+            CalculatorKey::Type | CalculatorKey::Copy => {}
+        }
+    }
+
+    // Applies the pending operator to the total and the number being typed in
+    fn evaluate(&mut self) {
+        let Ok(entry) = self.entry.parse::<f64>() else {
+            return;
+        };
+        self.entry.clear();
+        self.total = match (self.total, self.operator.take()) {
+            (Some(total), Some(operator)) => operator.apply(total, entry),
+            _ => Some(entry),
+        };
+        self.error = self.total.is_none();
+    }
+
+    fn display(&self) -> String {
+        if self.error {
+            return "Error".to_string();
+        }
+        let mut parts = Vec::new();
+        if let Some(total) = self.total {
+            parts.push(format_number(total));
+        }
+        if let Some(operator) = self.operator {
+            parts.push(operator.symbol().to_string());
+        }
+        if !self.entry.is_empty() {
+            parts.push(self.entry.clone());
+        }
+        if parts.is_empty() { "0".to_string() } else { parts.join(" ") }
+    }
+
+    fn result(&self) -> String {
+        if !self.entry.is_empty() {
+            return self.entry.trim_end_matches('.').to_string();
+        }
+        self.total.filter(|_| !self.error).map(format_number).unwrap_or_else(|| "0".to_string())
+    }
+}
+
+static CALCULATOR: std::sync::LazyLock<Arc<Mutex<Calculator>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(Calculator::default())));
+
+// Keys typing the result needs on the virtual keyboard
+pub const TYPED_KEYS: [Key; 12] = [
+    Key::Num0, Key::Num1, Key::Num2, Key::Num3, Key::Num4, Key::Num5,
+    Key::Num6, Key::Num7, Key::Num8, Key::Num9, Key::Dot, Key::Minus,
+];
+
+// Public API
+
+pub fn press(key: CalculatorKey) {
+    if let Ok(mut calculator) = CALCULATOR.lock() {
+        calculator.press(key);
+    }
+}
+
+// The running calculation, e.g. "12 + 3"
+pub fn display() -> String {
+    CALCULATOR.lock().map(|calculator| calculator.display()).unwrap_or_default()
+}
+
+// The number being typed in, or the total when there is none
+pub fn result() -> String {
+    CALCULATOR.lock().map(|calculator| calculator.result()).unwrap_or_default()
+}
+
+// Keys to press one after the other to type `text` on a US layout
+pub fn typed_keys(text: &str) -> Vec<Key> {
+    text.chars()
+        .filter_map(|c| match c {
+            '0'..='9' => Some(TYPED_KEYS[c as usize - '0' as usize]),
+            '.' => Some(Key::Dot),
+            '-' => Some(Key::Minus),
+            _ => None,
+        })
+        .collect()
+}
+
+// Hands the result to wl-copy in the user's Wayland session
+pub fn copy_to_clipboard(text: String) {
+    let Some(env) = user_cache::get_cached_user_environment() else {
+        return;
+    };
+    std::thread::spawn(move || {
+        let result = Command::session_user(&env.username, "env")
+            .arg(format!("XDG_RUNTIME_DIR={}", env.runtime_dir))
+            .arg(format!("WAYLAND_DISPLAY={}", env.wayland_display))
+            .args(["wl-copy", "--", &text])
+            .status();
+        if let Err(e) = result {
+            eprintln!("Failed to copy the calculator result with wl-copy: {}", e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(keys: &[&str]) -> Calculator {
+        let mut calculator = Calculator::default();
+        for key in keys {
+            calculator.press(CalculatorKey::parse(&format!("Calculator_{}", key)).unwrap());
+        }
+        calculator
+    }
+
+    #[test]
+    fn operators_apply_in_order() {
+        let calculator = run(&["1", "2", "Plus", "3", "Times", "2", "Equals"]);
+        assert_eq!(calculator.result(), "30");
+        assert_eq!(run(&["1", "Point", "5", "Minus", "2"]).display(), "1.5 − 2");
+    }
+
+    #[test]
+    fn results_are_rounded_for_display() {
+        assert_eq!(run(&["0", "Point", "1", "Plus", "0", "Point", "2", "Equals"]).result(), "0.3");
+        assert_eq!(run(&["1", "Divide", "3", "Equals"]).result(), "0.3333333333");
+    }
+
+    #[test]
+    fn dividing_by_zero_is_an_error() {
+        let mut calculator = run(&["4", "Divide", "0", "Equals"]);
+        assert_eq!(calculator.display(), "Error");
+        calculator.press(CalculatorKey::Digit(7));
+        assert_eq!(calculator.result(), "7");
+    }
+}
@@ -1,16 +1,23 @@
+use crate::calculator::CalculatorKey;
 use crate::fonts::{FontConfig, Pattern};
-use crate::FunctionLayer;
+use crate::midi::MidiMessage;
+use crate::pixel_shift::{DEFAULT_PIXEL_SHIFT_HEIGHT_PX, DEFAULT_PIXEL_SHIFT_WIDTH_PX};
+use crate::privileged;
+use crate::{Background, FunctionLayer, BUTTON_SPACING_PX};
 use anyhow::Error;
 use cairo::FontFace;
+use chrono::{Local, NaiveTime, Timelike};
+use drm::buffer::DrmFourcc;
 use freetype::Library as FtLibrary;
 use input_linux::Key;
 use nix::{
     errno::Errno,
     sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor},
 };
-use serde::{Deserialize, Deserializer};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
 use serde::de::value;
-use std::{fs::read_to_string, os::fd::AsFd, collections::HashMap};
+use std::{fs::{self, read_to_string}, os::{fd::AsFd, unix::fs::PermissionsExt}, collections::HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(untagged)]
@@ -28,18 +35,110 @@ impl ButtonColor {
     }
 }
 
-const USER_CFG_PATH: &str = "/etc/tiny-dfr/config.toml";
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DimmedContentStyle {
+    None,
+    Grayscale,
+    Fade,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelShiftPattern {
+    Sweep,
+    Random,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OledCareMode {
+    None,
+    Invert,
+    Pan,
+}
+
+// How a battery button shows it is charging or low besides its green or red tint
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BatteryStateStyle {
+    #[default]
+    Color,
+    Pattern, // diagonal stripes while charging, crosshatch when low
+    Glyph,   // an exclamation mark when low, the bolt already marks charging
+}
+
+// Which wheel dragging across a scroll strip turns
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrollAxis {
+    Vertical,
+    Horizontal,
+}
+
+// What pinching two fingers on a button does, true zooms like Ctrl and the mouse wheel,
+// two actions are done for every step pinching in and spreading apart respectively
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum PinchConfig {
+    Wheel(bool),
+    Actions([ButtonAction; 2]),
+}
+
+// What dragging across a slider adjusts
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum SliderKind {
+    Brightness, // of the built in display
+    Volume,     // of PipeWire's default sink
+}
+
+// Generated expandable listing unresolved references, opened after loading a broken config
+pub const CONFIG_ERRORS_EXPANDABLE: &str = "Expand_ConfigErrors";
+
+pub const USER_CFG_PATH: &str = "/etc/tiny-dfr/config.toml";
 const USER_COMMANDS_PATH: &str = "/etc/tiny-dfr/commands.toml";
 const USER_ENV_PATH: &str = "/etc/tiny-dfr/user-env.toml";
 const USER_EXPANDABLES_PATH: &str = "/etc/tiny-dfr/expandables.toml";
+// Every executable in here becomes a button of ACTIONS_EXPANDABLE
+const ACTIONS_DIR: &str = "/etc/tiny-dfr/actions.d";
+pub const ACTIONS_EXPANDABLE: &str = "Expand_Actions";
+pub const WORLD_CLOCK_EXPANDABLE: &str = "Expand_WorldClock";
+pub const CALCULATOR_EXPANDABLE: &str = "Expand_Calculator";
+pub const NUMBERS_EXPANDABLE: &str = "Expand_Numbers";
+// How much bigger text and icons get with HighContrast
+const HIGH_CONTRAST_SCALE: f64 = 1.25;
+// Narrowest a single stretch unit should get before a layer counts as overfull
+const MIN_PRACTICAL_BUTTON_PX: f64 = 48.0;
+// Set after repeated crashes, only the built-in function keys are shown until config.toml changes
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+// An external monitor and the charger are connected, the [Docked] layers are shown
+static DOCKED: AtomicBool = AtomicBool::new(false);
+const DEFAULT_TOUCH_SOUND: &str = "/usr/share/sounds/freedesktop/stereo/audio-volume-change.oga";
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ButtonAction {
     Key(Key),
     Command(String), // Command_1, Command_2, etc.
     Expand(String),  // Expand_Something
-    HyprlandExpand(String), // Hyprland_Expand_ActiveWindow
+    HyprlandExpand(String), // Hyprland_Expand_ActiveWindow, Sway_Expand_ActiveWindow
     KeyCombos(Vec<Key>), // KeyCombos_CTRL_SHIFT_I
+    KeySequence(Vec<Vec<Key>>), // KeySequence_CTRL_B_THEN_C, combos typed one after the other
+    NavigateTo(usize), // Breadcrumb jump to a navigation level, not available in config files
+    ConfirmCommand(String), // Second tap on a confirmation prompt, not available in config files
+    CancelConfirm,          // Dismisses a confirmation prompt, not available in config files
+    LockLayer,              // Toggles keeping the current layer on the bar
+    CheatSheet,             // Toggles showing what each button of the current layer does
+    SnoozeAlarm(usize),     // Alarm prompt buttons, not available in config files
+    DismissAlarm,
+    Midi(MidiMessage),      // MidiNote_60, MidiCC_20
+    Calculator(CalculatorKey), // Calculator_7, Calculator_Plus, Calculator_Type
+    AirplaneMode,           // Toggles blocking every radio with rfkill
+    Caffeine,               // Toggles holding off idle and suspend
+    LockSession,            // Locks the user's session through logind
+    Torch,                  // Toggles the keyboard backlight between off and full
+    SwitchWorkspace(i32),   // A workspace of a Workspaces button, not available in config files
+    LayerSwitch(String),    // LayerSwitch_Dev, LayerSwitch_MediaLayer, LayerSwitch_Next, LayerSwitch_Previous
 }
 
 impl<'de> Deserialize<'de> for ButtonAction {
@@ -49,8 +148,8 @@ impl<'de> Deserialize<'de> for ButtonAction {
     {
         let s = String::deserialize(deserializer)?;
 
-        // Check for Hyprland expand actions
-        if s.starts_with("Hyprland_Expand_") {
+        // Check for Hyprland expand actions, sway's match the active window the same way
+        if s.starts_with("Hyprland_Expand_") || s.starts_with("Sway_Expand_") {
             return Ok(ButtonAction::HyprlandExpand(s));
         }
 
@@ -62,11 +161,57 @@ impl<'de> Deserialize<'de> for ButtonAction {
             }
         }
 
+        if let Some(sequence) = s.strip_prefix("KeySequence_") {
+            let combos: Vec<Vec<Key>> = sequence
+                .split("_THEN_")
+                .map(|combo| crate::hyprland::parse_key_combos(&format!("KeyCombos_{}", combo)))
+                .collect();
+            if combos.iter().all(|keys| !keys.is_empty()) {
+                return Ok(ButtonAction::KeySequence(combos));
+            }
+        }
+
+        if let Some(message) = MidiMessage::parse(&s) {
+            return Ok(ButtonAction::Midi(message));
+        }
+
+        if let Some(key) = CalculatorKey::parse(&s) {
+            return Ok(ButtonAction::Calculator(key));
+        }
+
         // Check if it's an Expand action
         if s.starts_with("Expand_") {
             return Ok(ButtonAction::Expand(s));
         }
 
+        if let Some(name) = s.strip_prefix("LayerSwitch_") {
+            return Ok(ButtonAction::LayerSwitch(name.to_string()));
+        }
+
+        if s == "LockLayer" {
+            return Ok(ButtonAction::LockLayer);
+        }
+
+        if s == "CheatSheet" {
+            return Ok(ButtonAction::CheatSheet);
+        }
+
+        if s == "AirplaneMode" {
+            return Ok(ButtonAction::AirplaneMode);
+        }
+
+        if s == "Caffeine" {
+            return Ok(ButtonAction::Caffeine);
+        }
+
+        if s == "LockSession" {
+            return Ok(ButtonAction::LockSession);
+        }
+
+        if s == "Torch" {
+            return Ok(ButtonAction::Torch);
+        }
+
         // Try to deserialize as Key using serde
         let key_result: Result<Key, _> = serde::de::Deserialize::deserialize(
             value::StringDeserializer::<serde::de::value::Error>::new(s.clone())
@@ -81,6 +226,76 @@ impl<'de> Deserialize<'de> for ButtonAction {
     }
 }
 
+impl ButtonAction {
+    // Roughly what the action is called in config files, shown by CheatSheet
+    pub fn label(&self) -> String {
+        match self {
+            ButtonAction::Key(key) => format!("{:?}", key),
+            ButtonAction::Command(id) | ButtonAction::Expand(id) | ButtonAction::HyprlandExpand(id) => id.clone(),
+            ButtonAction::KeyCombos(keys) => keys.iter().map(|key| format!("{:?}", key)).collect::<Vec<_>>().join("+"),
+            ButtonAction::KeySequence(combos) => combos
+                .iter()
+                .map(|keys| keys.iter().map(|key| format!("{:?}", key)).collect::<Vec<_>>().join("+"))
+                .collect::<Vec<_>>()
+                .join(", "),
+            ButtonAction::LockLayer => "LockLayer".to_string(),
+            ButtonAction::CheatSheet => "CheatSheet".to_string(),
+            ButtonAction::NavigateTo(level) => format!("Level {}", level),
+            ButtonAction::ConfirmCommand(id) => format!("Confirm {}", id),
+            ButtonAction::CancelConfirm => "Cancel".to_string(),
+            ButtonAction::SnoozeAlarm(_) => "Snooze".to_string(),
+            ButtonAction::DismissAlarm => "Dismiss".to_string(),
+            ButtonAction::Midi(message) => message.label(),
+            ButtonAction::Calculator(key) => key.label(),
+            ButtonAction::AirplaneMode => "AirplaneMode".to_string(),
+            ButtonAction::Caffeine => "Caffeine".to_string(),
+            ButtonAction::LockSession => "LockSession".to_string(),
+            ButtonAction::Torch => "Torch".to_string(),
+            ButtonAction::SwitchWorkspace(id) => format!("Workspace {}", id),
+            ButtonAction::LayerSwitch(name) => format!("LayerSwitch_{}", name),
+        }
+    }
+}
+
+// What a Hyprland workspace or a keyboard shortcut brings up on the bar
+#[derive(Debug, Clone, PartialEq)]
+pub enum WorkspaceTarget {
+    Layer(usize), // index into the layers, 0 being the one shown without Fn
+    Expandable(String),
+}
+
+// An entry of commands.toml, either just the shell command or a table
+// with extra settings, e.g. { Run = "systemctl reboot", RunAs = "root" }
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum CommandEntry {
+    Plain(String),
+    Detailed(CommandSpec),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct CommandSpec {
+    pub run: String,
+    // User to run the command as, defaults to the logged in user
+    pub run_as: Option<String>,
+    // Ask for a second tap before running, for every button using this command
+    pub confirm: Option<bool>,
+}
+
+impl From<CommandEntry> for CommandSpec {
+    fn from(entry: CommandEntry) -> CommandSpec {
+        match entry {
+            CommandEntry::Plain(run) => CommandSpec {
+                run,
+                run_as: None,
+                confirm: None,
+            },
+            CommandEntry::Detailed(spec) => spec,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct UserEnvironment {
     pub username: String,
@@ -99,46 +314,243 @@ struct UserEnvConfig {
 pub struct Config {
     pub show_button_outlines: bool,
     pub enable_pixel_shift: bool,
+    pub pixel_shift_width: u64,
+    pub pixel_shift_height: u64,
+    pub pixel_shift_interval_ms: i32,
+    pub pixel_shift_pattern: PixelShiftPattern,
+    pub pixel_shift_pause_on_touch: bool,
+    pub oled_care_mode: OledCareMode,
+    pub oled_care_hours: (u32, u32),
+    pub oled_care_idle_minutes: u32,
+    pub quiet_hours: Option<(u32, u32)>,
+    pub quiet_hours_brightness: u32,
+    pub quiet_hours_expandable: Option<String>,
+    pub battery_saver_threshold: u32,
+    pub battery_saver_brightness: u32,
+    pub battery_saver_dim_seconds: u32,
+    pub battery_saver_slow_refresh: bool,
+    pub idle_refresh_seconds: u32,
     pub font_face: FontFace,
     pub adaptive_brightness: bool,
+    pub adaptive_brightness_hold_seconds: u32,
     pub active_brightness: u32,
     pub keyboard_brightness_step: u32,
     pub keyboard_brightness_enabled: bool,
-    pub commands: HashMap<String, String>,
+    pub commands: HashMap<String, CommandSpec>,
+    pub allow_commands_as_root: bool,
+    pub allow_commands_while_locked: bool,
+    pub drop_privileges: bool,
+    pub touch_sound: bool,
+    pub touch_sound_file: String,
+    pub haptic_feedback: bool,
+    pub show_osd: bool,
+    pub charging_animation: bool,
+    pub notification_banner: bool,
+    pub notification_seconds: u32,
+    pub display_indicator: bool,
+    pub pip_titles: Vec<String>,
+    pub display_profile_command: Option<String>,
+    pub touch_explore: bool,
+    // Command run with BUTTON_LABEL set whenever touch exploration lands on a button
+    pub touch_explore_speech: Option<String>,
+    pub fn_tap: Option<FnTap>,
+    pub fn_tap_seconds: f64,
+    pub midi_device: Option<String>,
+    pub midi_channel: u8,
+    pub separate_media_device: bool,
+    pub gamepad_expandables: Vec<String>,
+    pub metrics_socket: Option<String>,
+    pub dbus_control: bool,
+    pub alarms: Vec<Alarm>,
+    pub alarm_snooze_minutes: u32,
     pub user_env: Option<UserEnvironment>,
     pub back_button_show_outlines: bool,
     pub back_button_outline_color: Option<ButtonColor>,
     pub expandable_timeout_seconds: u32,
+    pub max_visible_buttons: usize,
     pub expandables: HashMap<String, Vec<ButtonConfig>>,
     pub hyprland_expandables: HashMap<String, Vec<HyprlandExpandConfig>>,
+    pub background: Option<Background>,
+    // Backgrounds of expandables that don't show the bar's own, by name
+    pub expandable_backgrounds: HashMap<String, Background>,
+    pub dimmed_content_style: DimmedContentStyle,
+    pub high_contrast: bool,
+    pub swipe_up_expandable: Option<String>,
+    pub swipe_up_distance: f64,
+    pub swipe_left: Option<ButtonAction>,
+    pub swipe_right: Option<ButtonAction>,
+    pub swipe_up: Option<ButtonAction>,
+    pub swipe_down: Option<ButtonAction>,
+    pub scrub_step: f64,
+    pub workspace_bindings: HashMap<String, WorkspaceTarget>,
+    pub keyboard_shortcuts: Vec<(Vec<Key>, WorkspaceTarget)>,
+    pub media_layer_default: bool,
+    // Config key each layer was built from and how many generated buttons precede its entries,
+    // None when the bar shows something else in its place
+    pub layer_sources: Vec<Option<(&'static str, usize)>>,
+    // Layers from Layers, shown after PrimaryLayer and MediaLayer
+    pub custom_layers: Vec<(String, LayerActivation)>,
+    pub has_docked_profile: bool,
+    pub rotation: u32,
+    pub scale: f64,
+    pub touch_seat: String,
+    pub touch_device: String,
+    pub config_errors: Vec<String>,
+}
+
+// Hardware selection, needed before the rest of the configuration can be loaded
+pub struct DisplayConfig {
+    pub card: Option<String>,
+    pub connector: Option<String>,
+    pub mode: Option<(u16, u16)>,
+    pub format: DrmFourcc,
+    pub touch_seat: String,
+    pub touch_device: String,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "PascalCase")]
 pub struct HyprlandExpandConfig {
-    pub class: String,
+    // Glob patterns matched against the whole class and title, an entry
+    // without either matches any window
+    pub class: Option<String>,
+    pub title: Option<String>,
     pub button_title: Option<String>, // "title", "class", "initialTitle", "initialClass"
     pub show_app_icon_alongside_text: Option<bool>,
     pub app_icon: Option<String>,
     pub layer_keys: Vec<ButtonConfig>,
 }
 
+impl HyprlandExpandConfig {
+    pub fn matches(&self, class: &str, title: &str) -> bool {
+        self.class.as_deref().is_none_or(|pattern| glob_match(pattern, class))
+            && self.title.as_deref().is_none_or(|pattern| glob_match(pattern, title))
+    }
+}
+
+// Whether the current local hour falls in [start, end), which may wrap around midnight
+pub fn within_hours((start, end): (u32, u32)) -> bool {
+    let hour = Local::now().hour();
+    if start <= end {
+        hour >= start && hour < end
+    } else {
+        hour >= start || hour < end
+    }
+}
+
+// `*` matches any run of characters and `?` a single one, everything else literally
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it currently stands for
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, star_t)) = backtrack {
+            // Let the last `*` swallow one more character
+            p = star + 1;
+            t = star_t + 1;
+            backtrack = Some((star, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct ConfigProxy {
     media_layer_default: Option<bool>,
+    number_layer: Option<bool>,
     show_button_outlines: Option<bool>,
     enable_pixel_shift: Option<bool>,
+    pixel_shift_width: Option<u64>,
+    pixel_shift_height: Option<u64>,
+    pixel_shift_interval: Option<u32>,
+    pixel_shift_pattern: Option<PixelShiftPattern>,
+    pixel_shift_pause_on_touch: Option<bool>,
+    oled_care_mode: Option<OledCareMode>,
+    oled_care_hours: Option<[u32; 2]>,
+    oled_care_idle_minutes: Option<u32>,
+    quiet_hours: Option<[u32; 2]>,
+    quiet_hours_brightness: Option<u32>,
+    quiet_hours_expandable: Option<String>,
+    battery_saver_threshold: Option<u32>,
+    battery_saver_brightness: Option<u32>,
+    battery_saver_dim_seconds: Option<u32>,
+    battery_saver_slow_refresh: Option<bool>,
+    idle_refresh_seconds: Option<u32>,
     font_template: Option<String>,
     adaptive_brightness: Option<bool>,
+    adaptive_brightness_hold_seconds: Option<u32>,
     active_brightness: Option<u32>,
     primary_layer_keys: Option<Vec<ButtonConfig>>,
     media_layer_keys: Option<Vec<ButtonConfig>>,
+    docked: Option<LayerProfile>,
+    layers: Option<Vec<LayerConfig>>,
     keyboard_brightness_step: Option<u32>,
     keyboard_brightness_enabled: Option<bool>,
     back_button_show_outlines: Option<bool>,
     back_button_outline_color: Option<ButtonColor>,
     expandable_timeout_seconds: Option<u32>,
+    max_visible_buttons: Option<u32>,
+    background_image: Option<String>,
+    primary_layer_background: Option<String>,
+    media_layer_background: Option<String>,
+    expandable_backgrounds: Option<HashMap<String, String>>,
+    dimmed_content_style: Option<DimmedContentStyle>,
+    high_contrast: Option<bool>,
+    high_contrast_max_buttons: Option<usize>,
+    swipe_up_expandable: Option<String>,
+    swipe_up_distance: Option<f64>,
+    swipe_left: Option<ButtonAction>,
+    swipe_right: Option<ButtonAction>,
+    swipe_up: Option<ButtonAction>,
+    swipe_down: Option<ButtonAction>,
+    scrub_step: Option<f64>,
+    workspace_bindings: Option<HashMap<String, String>>,
+    keyboard_shortcuts: Option<HashMap<String, String>>,
+    rotation: Option<u32>,
+    scale: Option<f64>,
+    display_card: Option<String>,
+    display_connector: Option<String>,
+    display_mode: Option<String>,
+    display_format: Option<String>,
+    touch_seat: Option<String>,
+    touch_device: Option<String>,
+    allow_commands_as_root: Option<bool>,
+    allow_commands_while_locked: Option<bool>,
+    drop_privileges: Option<bool>,
+    touch_sound: Option<bool>,
+    touch_sound_file: Option<String>,
+    haptic_feedback: Option<bool>,
+    show_osd: Option<bool>,
+    charging_animation: Option<bool>,
+    notification_banner: Option<bool>,
+    notification_seconds: Option<u32>,
+    display_indicator: Option<bool>,
+    pip_titles: Option<Vec<String>>,
+    display_profile_command: Option<String>,
+    touch_explore: Option<bool>,
+    touch_explore_speech: Option<String>,
+    fn_tap: Option<String>,
+    fn_tap_seconds: Option<f64>,
+    midi_device: Option<String>,
+    midi_channel: Option<u8>,
+    separate_media_device: Option<bool>,
+    gamepad_expandables: Option<Vec<String>>,
+    world_clocks: Option<Vec<WorldClockConfig>>,
+    metrics_socket: Option<String>,
+    dbus_control: Option<bool>,
+    alarms: Option<Vec<AlarmConfig>>,
+    alarm_snooze_minutes: Option<u32>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -150,6 +562,10 @@ pub struct ButtonConfig {
     pub theme: Option<String>,
     pub time: Option<String>,
     pub battery: Option<String>,
+    pub battery_state_style: Option<BatteryStateStyle>,
+    pub ambient_light: Option<String>,
+    pub battery_history: Option<u32>,
+    pub status: Option<Vec<StatusItemConfig>>,
     pub locale: Option<String>,
     pub action: ButtonAction,
     pub stretch: Option<usize>,
@@ -157,26 +573,220 @@ pub struct ButtonConfig {
     pub button_outlines_color: Option<ButtonColor>,
     pub show_app_icon_alongside_text: Option<bool>,
     pub app_icon: Option<String>,
+    // Buttons of the expandable opened by this button's Expand_ action, as an
+    // alternative to defining it in expandables.toml
+    pub expandable: Option<Vec<ButtonConfig>>,
+    // Ask for a second tap before running this button's command
+    pub confirm: Option<bool>,
+    pub touch_sound: Option<bool>,
+    // Actions of equally wide tap zones replacing Action, from left to right
+    pub zones: Option<Vec<ButtonAction>>,
+    // Keys sent while dragging across the button to the left and to the right
+    pub scrub: Option<[ButtonAction; 2]>,
+    // Counts down this many seconds on the button before doing Action, tap again to cancel
+    pub countdown: Option<u32>,
+    // Keeps the key of a Key or KeyCombos action pressed this long after a tap
+    pub hold_seconds: Option<f64>,
+    // Done instead of Action when the button is held down for a moment
+    pub long_press: Option<ButtonAction>,
+    // Shows the time in this zone from /usr/share/zoneinfo, labelled with Text
+    pub world_clock: Option<String>,
+    // Prices of one or more symbols, cycling between them
+    pub ticker: Option<TickerConfig>,
+    // Envelope with the number of unread messages
+    pub mail: Option<MailConfig>,
+    // The first line a command prints, run again every few seconds
+    pub script: Option<ScriptConfig>,
+    // Dragging across the button scrolls like a mouse wheel instead
+    pub scroll: Option<ScrollAxis>,
+    // Two fingers pinching on the button zoom instead
+    pub pinch: Option<PinchConfig>,
+    // Artist and title of the track playing, Text is shown while nothing plays
+    pub now_playing: Option<bool>,
+    // Fill bar set to where it's touched or dragged to, instead of doing Action
+    pub slider: Option<SliderKind>,
+    // The calculation Calculator_ buttons are making
+    pub calculator: Option<bool>,
+    // Volume of the default output and whether it's muted, following changes made anywhere
+    pub volume: Option<bool>,
+    // Hyprland's workspaces, tapping one switches to it
+    pub workspaces: Option<bool>,
 }
 
-fn load_commands() -> HashMap<String, String> {
-    let mut commands = HashMap::new();
+// An alarm as written in config.toml, e.g. { Time = "07:30", Label = "Wake up" }
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct AlarmConfig {
+    pub time: String,
+    pub label: Option<String>,
+    pub action: Option<ButtonAction>,
+}
 
-    // Load base commands from /usr/share/tiny-dfr/commands.toml
-    if let Ok(content) = read_to_string("/usr/share/tiny-dfr/commands.toml") {
-        if let Ok(base_commands) = toml::from_str::<HashMap<String, String>>(&content) {
-            commands.extend(base_commands);
+// What a quick tap of Fn does, holding it still switches layers while held
+#[derive(Debug, Clone)]
+pub enum FnTap {
+    ToggleLayers,
+    CycleLayers,
+    Action(ButtonAction),
+}
+
+// Goes off every day at the given local time
+#[derive(Debug, Clone)]
+pub struct Alarm {
+    pub hour: u32,
+    pub minute: u32,
+    pub label: String,
+    pub action: Option<ButtonAction>,
+}
+
+// Price widget, e.g. { Symbols = ["BTC", "AAPL"], Command = "quote {symbol}", Interval = 300 }
+// The command prints the price, optionally followed by the change like "+1.2%"
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct TickerConfig {
+    pub symbols: Vec<String>,
+    pub command: String,
+    pub interval: Option<u64>, // seconds between runs of the command for each symbol
+}
+
+// A command run as the logged in user, e.g. { Command = "sensors | awk '/Tctl/ {print $2}'", Interval = 5 }
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct ScriptConfig {
+    pub command: String,
+    pub interval: Option<u64>, // seconds between runs
+}
+
+// Unread mail counter, either { Command = "notmuch count tag:unread", Interval = 60 }
+// or { Maildir = "~/Mail/INBOX" }, the latter being watched for new messages
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct MailConfig {
+    pub command: Option<String>,
+    pub maildir: Option<String>,
+    pub interval: Option<u64>, // seconds between runs of Command
+}
+
+// Layers replacing the usual ones while docked, either can be left out to keep it
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct LayerProfile {
+    pub primary_layer_keys: Option<Vec<ButtonConfig>>,
+    pub media_layer_keys: Option<Vec<ButtonConfig>>,
+}
+
+// A layer of its own, e.g. { Name = "Dev", Activation = "App", App = "code*", Keys = [...] }
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct LayerConfig {
+    pub name: String,
+    pub keys: Vec<ButtonConfig>,
+    pub activation: Option<String>,
+    pub app: Option<String>,
+    pub background: Option<String>, // overrides BackgroundImage
+}
+
+// What brings a layer of Layers up besides LayerSwitch buttons, SetLayer and bindings
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayerActivation {
+    Toggle,      // nothing else
+    FnHeld,      // shown instead of the other layer while Fn is held
+    FnLock,      // a tap of Fn switches to it and back
+    App(String), // shown while the focused window's class matches the pattern
+}
+
+// One city of the generated world clock expandable
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct WorldClockConfig {
+    pub city: String,
+    pub zone: String,
+}
+
+// One item of a status widget, e.g. { Item = "battery", Action = "Expand_Battery" }
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+pub struct StatusItemConfig {
+    pub item: String,
+    pub format: Option<String>, // strftime format of the clock
+    pub action: Option<ButtonAction>, // defaults to the widget's own Action
+}
+
+impl ButtonConfig {
+    pub fn new(action: ButtonAction) -> ButtonConfig {
+        ButtonConfig {
+            icon: None,
+            text: None,
+            theme: None,
+            time: None,
+            battery: None,
+            battery_state_style: None,
+            ambient_light: None,
+            battery_history: None,
+            status: None,
+            locale: None,
+            action,
+            stretch: None,
+            show_button_outlines: None,
+            button_outlines_color: None,
+            show_app_icon_alongside_text: None,
+            app_icon: None,
+            expandable: None,
+            confirm: None,
+            touch_sound: None,
+            zones: None,
+            scrub: None,
+            countdown: None,
+            hold_seconds: None,
+            long_press: None,
+            world_clock: None,
+            ticker: None,
+            mail: None,
+            script: None,
+            scroll: None,
+            pinch: None,
+            now_playing: None,
+            volume: None,
+            workspaces: None,
+            slider: None,
+            calculator: None,
         }
     }
+}
 
-    // Override with user commands from /etc/tiny-dfr/commands.toml
-    if let Ok(content) = read_to_string(USER_COMMANDS_PATH) {
-        if let Ok(user_commands) = toml::from_str::<HashMap<String, String>>(&content) {
-            commands.extend(user_commands);
+// Reads a table of named entries from the file in /usr/share, then the one in /etc
+// on top of it, and returns each entry along with the file it came from.
+// Names defined twice in one file are already rejected by the TOML parser.
+fn load_layered<T: DeserializeOwned>(
+    base_path: &'static str,
+    user_path: &'static str,
+    kind: &str,
+) -> HashMap<String, (T, &'static str)> {
+    let mut entries = HashMap::new();
+    for path in [base_path, user_path] {
+        let Ok(content) = read_to_string(path) else {
+            continue;
+        };
+        match toml::from_str::<HashMap<String, T>>(&content) {
+            Ok(file_entries) => {
+                let mut names: Vec<_> = file_entries.keys().filter(|name| entries.contains_key(*name)).collect();
+                names.sort();
+                for name in names {
+                    println!("{}: {} {} replaces the one from {}", path, kind, name, base_path);
+                }
+                entries.extend(file_entries.into_iter().map(|(name, entry)| (name, (entry, path))));
+            }
+            Err(e) => eprintln!("Failed to parse {}: {}", path, e),
         }
     }
+    entries
+}
 
-    commands
+fn load_commands() -> HashMap<String, CommandSpec> {
+    load_layered::<CommandEntry>("/usr/share/tiny-dfr/commands.toml", USER_COMMANDS_PATH, "command")
+        .into_iter()
+        .map(|(id, (entry, _))| (id, entry.into()))
+        .collect()
 }
 
 fn load_user_environment() -> Option<UserEnvironment> {
@@ -188,74 +798,393 @@ fn load_user_environment() -> Option<UserEnvironment> {
     None
 }
 
-fn load_expandables() -> HashMap<String, Vec<ButtonConfig>> {
-    let mut expandables = HashMap::new();
+fn load_expandables() -> HashMap<String, (Vec<ButtonConfig>, &'static str)> {
+    load_layered("/usr/share/tiny-dfr/expandables.toml", USER_EXPANDABLES_PATH, "expandable")
+}
 
-    // Load base expandables from /usr/share/tiny-dfr/expandables.toml
-    if let Ok(content) = read_to_string("/usr/share/tiny-dfr/expandables.toml") {
-        if let Ok(base_expandables) = toml::from_str::<HashMap<String, Vec<ButtonConfig>>>(&content) {
-            expandables.extend(base_expandables);
+// One button per executable script in actions.d, labelled with its file name and
+// showing a .svg of the same name next to it if there is one. Each script gets a
+// generated command so it runs like any other command from commands.toml.
+fn load_script_actions(commands: &mut HashMap<String, CommandSpec>) -> Option<Vec<ButtonConfig>> {
+    let mut scripts: Vec<_> = fs::read_dir(ACTIONS_DIR)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_none_or(|ext| ext != "svg"))
+        .filter(|path| {
+            fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        })
+        .collect();
+    if scripts.is_empty() {
+        return None;
+    }
+    scripts.sort();
+    let mut buttons = Vec::new();
+    for script in scripts {
+        let Some(label) = script.file_stem().map(|stem| stem.to_string_lossy().to_string()) else {
+            continue;
+        };
+        let file_name = script.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let command_id = format!("Command_Actions_{}", file_name);
+        if commands.contains_key(&command_id) {
+            println!("{}: script {} replaces command {} from commands.toml", ACTIONS_DIR, file_name, command_id);
         }
+        commands.insert(
+            command_id.clone(),
+            CommandSpec {
+                run: format!("'{}'", script.to_string_lossy().replace('\'', "'\\''")),
+                run_as: None,
+                confirm: None,
+            },
+        );
+        let icon = script.with_extension("svg").exists().then(|| format!("actions.d/{}", label));
+        buttons.push(ButtonConfig {
+            text: icon.is_none().then(|| label.clone()),
+            icon,
+            ..ButtonConfig::new(ButtonAction::Command(command_id))
+        });
     }
+    Some(buttons)
+}
 
-    // Override with user expandables from /etc/tiny-dfr/expandables.toml
-    if let Ok(content) = read_to_string(USER_EXPANDABLES_PATH) {
-        if let Ok(user_expandables) = toml::from_str::<HashMap<String, Vec<ButtonConfig>>>(&content) {
-            expandables.extend(user_expandables);
+// Moves expandables defined inline under their trigger button into `expandables`,
+// including ones nested inside other inline expandables
+fn hoist_inline_expandables(buttons: &mut [ButtonConfig], expandables: &mut HashMap<String, Vec<ButtonConfig>>) {
+    for button in buttons {
+        let Some(mut inline) = button.expandable.take() else {
+            continue;
+        };
+        let ButtonAction::Expand(name) = &button.action else {
+            eprintln!(
+                "Ignoring inline Expandable on a button with action {:?}, it needs an Expand_ action naming it",
+                button.action
+            );
+            continue;
+        };
+        hoist_inline_expandables(&mut inline, expandables);
+        if expandables.insert(name.clone(), inline).is_some() {
+            println!("Inline expandable {} overrides the one from expandables.toml", name);
         }
     }
+}
 
-    expandables
+fn more_button(expandable: &str) -> ButtonConfig {
+    ButtonConfig {
+        text: Some("More".to_string()),
+        ..ButtonConfig::new(ButtonAction::Expand(expandable.to_string()))
+    }
 }
 
-fn load_hyprland_expandables() -> HashMap<String, Vec<HyprlandExpandConfig>> {
-    let mut hyprland_expandables = HashMap::new();
+// Keeps at most max buttons on a layer, moving the others into Expand_More_<name>,
+// Expand_More_<name>_2 and so on, each ending in a More button to the next.
+// Expandables lose a slot to their Back button.
+fn split_overflow(
+    buttons: &mut Vec<ButtonConfig>,
+    max: usize,
+    name: &str,
+    is_expandable: bool,
+    expandables: &mut HashMap<String, Vec<ButtonConfig>>,
+) {
+    let max = max.max(3);
+    let first_page = if is_expandable { max - 1 } else { max };
+    if buttons.len() <= first_page {
+        return;
+    }
+    let mut rest = buttons.split_off(first_page - 1);
+    let mut more = format!("Expand_More_{}", name);
+    buttons.push(more_button(&more));
+    let mut pages = 1;
+    while rest.len() > max - 1 {
+        let overflow = rest.split_off(max - 2);
+        pages += 1;
+        let next = format!("Expand_More_{}_{}", name, pages);
+        rest.push(more_button(&next));
+        expandables.insert(std::mem::replace(&mut more, next), std::mem::replace(&mut rest, overflow));
+    }
+    expandables.insert(more, rest);
+}
 
-    // Load base hyprland expandables from /usr/share/tiny-dfr/hyprland.toml
-    if let Ok(content) = read_to_string("/usr/share/tiny-dfr/hyprland.toml") {
-        if let Ok(base_hyprland_expandables) = toml::from_str::<HashMap<String, Vec<HyprlandExpandConfig>>>(&content) {
-            hyprland_expandables.extend(base_hyprland_expandables);
+// Collects every action that points at an expandable or command which doesn't exist
+fn find_unresolved_references(
+    layers: &[(&str, &[ButtonConfig])],
+    expandables: &HashMap<String, Vec<ButtonConfig>>,
+    hyprland_expandables: &HashMap<String, Vec<HyprlandExpandConfig>>,
+    commands: &HashMap<String, CommandSpec>,
+    layer_names: &[&str],
+) -> Vec<String> {
+    let is_missing = |action: &ButtonAction| match action {
+        ButtonAction::LayerSwitch(name) => !layer_names.contains(&name.as_str()),
+        ButtonAction::Expand(name) => !expandables.contains_key(name),
+        ButtonAction::HyprlandExpand(name) => !hyprland_expandables.contains_key(name),
+        ButtonAction::Command(id) => id != "Back" && !commands.contains_key(id),
+        _ => false,
+    };
+    let mut unresolved = Vec::new();
+    for (location, buttons) in layers {
+        for button in buttons.iter() {
+            // Widgets use their action only as a label
+            let is_widget = button.time.is_some()
+                || button.world_clock.is_some()
+                || button.ticker.is_some()
+                || button.script.is_some()
+                || button.battery.is_some()
+                || button.ambient_light.is_some()
+                || button.battery_history.is_some()
+                || button.status.is_some()
+                || button.slider.is_some()
+                || button.calculator.is_some();
+            let mut actions = Vec::new();
+            if !(is_widget && matches!(button.action, ButtonAction::Command(_))) {
+                actions.push(&button.action);
+            }
+            for item in button.status.iter().flatten() {
+                actions.extend(item.action.as_ref());
+            }
+            actions.extend(button.zones.iter().flatten());
+            actions.extend(button.long_press.as_ref());
+            for action in actions.into_iter().filter(|action| is_missing(action)) {
+                let name = match action {
+                    ButtonAction::Expand(name)
+                    | ButtonAction::HyprlandExpand(name)
+                    | ButtonAction::Command(name) => name.clone(),
+                    ButtonAction::LayerSwitch(name) => format!("LayerSwitch_{}", name),
+                    other => format!("{:?}", other),
+                };
+                unresolved.push(format!("{} in {}", name, location));
+            }
         }
     }
+    unresolved
+}
+
+fn load_hyprland_expandables() -> HashMap<String, (Vec<HyprlandExpandConfig>, &'static str)> {
+    load_layered("/usr/share/tiny-dfr/hyprland.toml", "/etc/tiny-dfr/hyprland.toml", "Hyprland expandable")
+}
+
+// The number row and the symbols next to it on a US layout, holding a button types
+// its shifted symbol instead
+fn number_layer_buttons() -> Vec<ButtonConfig> {
+    let keys = [
+        (Key::Num1, "1"), (Key::Num2, "2"), (Key::Num3, "3"), (Key::Num4, "4"), (Key::Num5, "5"),
+        (Key::Num6, "6"), (Key::Num7, "7"), (Key::Num8, "8"), (Key::Num9, "9"), (Key::Num0, "0"),
+        (Key::Minus, "-"), (Key::Equal, "="), (Key::LeftBrace, "["), (Key::RightBrace, "]"),
+        (Key::Backslash, "\\"), (Key::Semicolon, ";"), (Key::Apostrophe, "'"), (Key::Grave, "`"), (Key::Slash, "/"),
+    ];
+    keys.into_iter()
+        .map(|(key, text)| ButtonConfig {
+            text: Some(text.to_string()),
+            long_press: Some(ButtonAction::KeyCombos(vec![Key::LeftShift, key])),
+            ..ButtonConfig::new(ButtonAction::Key(key))
+        })
+        .collect()
+}
+
+// What PrimaryLayer, MediaLayer or an expandable's name brings up
+fn layer_target(
+    name: &str,
+    media_layer_default: bool,
+    custom_layers: &[(String, LayerActivation)],
+    expandables: &HashMap<String, Vec<ButtonConfig>>,
+) -> Option<WorkspaceTarget> {
+    match name {
+        "PrimaryLayer" => Some(WorkspaceTarget::Layer(if media_layer_default { 1 } else { 0 })),
+        "MediaLayer" => Some(WorkspaceTarget::Layer(if media_layer_default { 0 } else { 1 })),
+        name if expandables.contains_key(name) => Some(WorkspaceTarget::Expandable(name.to_string())),
+        name => custom_layers.iter().position(|(layer, _)| layer == name).map(|i| WorkspaceTarget::Layer(i + 2)),
+    }
+}
 
-    // Override with user hyprland expandables from /etc/tiny-dfr/hyprland.toml
-    if let Ok(content) = read_to_string("/etc/tiny-dfr/hyprland.toml") {
-        if let Ok(user_hyprland_expandables) = toml::from_str::<HashMap<String, Vec<HyprlandExpandConfig>>>(&content) {
-            hyprland_expandables.extend(user_hyprland_expandables);
-        }
+impl Config {
+    pub fn layer_target(&self, name: &str) -> Option<WorkspaceTarget> {
+        layer_target(name, self.media_layer_default, &self.custom_layers, &self.expandables)
+    }
+    // The layer shown while Fn is held, the second one unless a layer of Layers takes its place
+    pub fn fn_layer(&self) -> usize {
+        self.custom_layers
+            .iter()
+            .position(|(_, activation)| *activation == LayerActivation::FnHeld)
+            .map_or(1, |i| i + 2)
+    }
+    pub fn fn_lock_layer(&self) -> Option<usize> {
+        self.custom_layers
+            .iter()
+            .position(|(_, activation)| *activation == LayerActivation::FnLock)
+            .map(|i| i + 2)
+    }
+    // The first layer whose App pattern matches the class of the focused window
+    pub fn app_layer(&self, class: &str) -> Option<usize> {
+        self.custom_layers
+            .iter()
+            .position(|(_, activation)| matches!(activation, LayerActivation::App(pattern) if glob_match(pattern, class)))
+            .map(|i| i + 2)
     }
+    // Whether the focused window is a video popped out of its page, e.g. a browser's Picture-in-Picture
+    pub fn is_pip_window(&self, title: &str) -> bool {
+        self.pip_titles.iter().any(|pattern| glob_match(pattern, title))
+    }
+}
+
+// What PipTitles windows add to the end of the bar while they're focused
+pub fn pip_controls() -> Vec<ButtonConfig> {
+    [("fast_rewind", Key::PreviousSong), ("play_pause", Key::PlayPause), ("fast_forward", Key::NextSong)]
+        .into_iter()
+        .map(|(icon, key)| ButtonConfig {
+            icon: Some(icon.to_string()),
+            ..ButtonConfig::new(ButtonAction::Key(key))
+        })
+        .collect()
+}
+
+// The built-in calculator, laid out in a single row like a keypad read line by line
+fn calculator_buttons() -> Vec<ButtonConfig> {
+    let keys = [
+        "Clear", "7", "8", "9", "Divide", "4", "5", "6", "Times",
+        "1", "2", "3", "Minus", "0", "Point", "Equals", "Plus", "Type",
+    ];
+    let mut buttons = vec![ButtonConfig {
+        calculator: Some(true),
+        stretch: Some(3),
+        ..ButtonConfig::new(ButtonAction::Calculator(CalculatorKey::Copy))
+    }];
+    buttons.extend(keys.iter().filter_map(|key| CalculatorKey::parse(&format!("Calculator_{}", key))).map(|key| ButtonConfig {
+        text: Some(key.text()),
+        ..ButtonConfig::new(ButtonAction::Calculator(key))
+    }));
+    buttons
+}
 
-    hyprland_expandables
+// Warns about layers with so many stretch units that each one ends up too narrow
+// to reliably hit. Each layer comes with a description of where it is defined and the
+// number of buttons added to it when shown
+fn lint_stretch(layers: &[(String, &[ButtonConfig], usize)], width: u16, scale: f64) {
+    let spacing = BUTTON_SPACING_PX as f64 * scale;
+    let min_width = MIN_PRACTICAL_BUTTON_PX * scale;
+    for (name, buttons, added) in layers {
+        let units: usize = added + buttons.iter().map(|button| button.stretch.unwrap_or(1).max(1)).sum::<usize>();
+        let unit_width = (width as f64 - spacing * (units as f64 - 1.0)) / units as f64;
+        if unit_width < min_width {
+            println!(
+                "{}: {} stretch units leave {:.0}px per unit, buttons narrower than {:.0}px are hard to hit",
+                name, units, unit_width.max(0.0), min_width
+            );
+        }
+    }
 }
 
-fn load_font(name: &str) -> FontFace {
+fn load_font(name: &str) -> Result<FontFace, String> {
     let fontconfig = FontConfig::new();
     let mut pattern = Pattern::new(name);
     fontconfig.perform_substitutions(&mut pattern);
-    let pat_match = match fontconfig.match_pattern(&pattern) {
-        Ok(pat) => pat,
-        Err(_) => panic!("Unable to find specified font. If you are using the default config, make sure you have at least one font installed")
-    };
+    let pat_match = fontconfig.match_pattern(&pattern).map_err(|_| {
+        format!("Unable to find font {}. If you are using the default config, make sure you have at least one font installed", name)
+    })?;
     let file_name = pat_match.get_file_name();
     let file_idx = pat_match.get_font_index();
-    let ft_library = FtLibrary::init().unwrap();
-    let face = ft_library.new_face(file_name, file_idx).unwrap();
-    FontFace::create_from_ft(&face).unwrap()
+    let ft_library = FtLibrary::init().map_err(|e| format!("Failed to initialize FreeType: {}", e))?;
+    let face = ft_library.new_face(file_name, file_idx).map_err(|e| format!("Failed to load font {}: {}", file_name, e))?;
+    FontFace::create_from_ft(&face).map_err(|e| format!("Failed to load font {}: {}", file_name, e))
 }
 
-fn load_config(width: u16) -> (Config, [FunctionLayer; 2]) {
-    let mut base =
-        toml::from_str::<ConfigProxy>(&read_to_string("/usr/share/tiny-dfr/config.toml").unwrap())
-            .unwrap();
-    let user = read_to_string(USER_CFG_PATH)
-        .map_err::<Error, _>(|e| e.into())
-        .and_then(|r| Ok(toml::from_str::<ConfigProxy>(&r)?));
+fn load_background(name: &str) -> Option<Background> {
+    match Background::load(name) {
+        Ok(background) => Some(background),
+        Err(e) => {
+            eprintln!("Failed to load background image '{}': {}", name, e);
+            None
+        }
+    }
+}
+
+pub fn enter_safe_mode() {
+    SAFE_MODE.store(true, Ordering::Relaxed);
+}
+
+pub fn safe_mode() -> bool {
+    SAFE_MODE.load(Ordering::Relaxed)
+}
+
+pub fn set_docked(docked: bool) {
+    DOCKED.store(docked, Ordering::Relaxed);
+}
+
+pub fn docked() -> bool {
+    DOCKED.load(Ordering::Relaxed)
+}
+
+// Function keys that always work, used when the configured layout can't be shown.
+// Wide bars get their Esc added along with every other layout.
+fn safe_layer_keys(width: u16) -> Vec<ButtonConfig> {
+    let keys = [
+        Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6,
+        Key::F7, Key::F8, Key::F9, Key::F10, Key::F11, Key::F12,
+    ];
+    let esc = (width < 2170).then_some(Key::Esc);
+    esc.into_iter()
+        .chain(keys)
+        .map(|key| ButtonConfig {
+            text: Some(if key == Key::Esc { "esc".to_string() } else { format!("{:?}", key) }),
+            ..ButtonConfig::new(ButtonAction::Key(key))
+        })
+        .collect()
+}
+
+// Errors list everything that kept the configuration from being shown
+fn load_config(width: u16) -> Result<(Config, Vec<FunctionLayer>), Vec<String>> {
+    let safe_mode = safe_mode();
+    // Safe mode doesn't rely on anything installed either
+    let mut base = if safe_mode {
+        toml::from_str::<ConfigProxy>(include_str!("../share/tiny-dfr/config.toml")).unwrap()
+    } else {
+        read_to_string("/usr/share/tiny-dfr/config.toml")
+            .map_err::<Error, _>(|e| e.into())
+            .and_then(|r| Ok(toml::from_str::<ConfigProxy>(&r)?))
+            .map_err(|e| vec![format!("Failed to load /usr/share/tiny-dfr/config.toml: {}", e)])?
+    };
+    let user = if safe_mode {
+        Err(Error::msg("safe mode"))
+    } else {
+        read_to_string(USER_CFG_PATH)
+            .map_err::<Error, _>(|e| e.into())
+            .and_then(|r| Ok(toml::from_str::<ConfigProxy>(&r)?))
+    };
+    let mut primary_layer_origin = "/usr/share/tiny-dfr/config.toml";
+    let mut media_layer_origin = "/usr/share/tiny-dfr/config.toml";
+    let mut layers_origin = "/usr/share/tiny-dfr/config.toml";
     if let Ok(user) = user {
+        if user.primary_layer_keys.is_some() {
+            primary_layer_origin = USER_CFG_PATH;
+        }
+        if user.media_layer_keys.is_some() {
+            media_layer_origin = USER_CFG_PATH;
+        }
+        if user.layers.is_some() {
+            layers_origin = USER_CFG_PATH;
+        }
+        base.docked = user.docked.or(base.docked);
+        base.layers = user.layers.or(base.layers);
         base.media_layer_default = user.media_layer_default.or(base.media_layer_default);
+        base.number_layer = user.number_layer.or(base.number_layer);
         base.show_button_outlines = user.show_button_outlines.or(base.show_button_outlines);
         base.enable_pixel_shift = user.enable_pixel_shift.or(base.enable_pixel_shift);
+        base.pixel_shift_width = user.pixel_shift_width.or(base.pixel_shift_width);
+        base.pixel_shift_height = user.pixel_shift_height.or(base.pixel_shift_height);
+        base.pixel_shift_interval = user.pixel_shift_interval.or(base.pixel_shift_interval);
+        base.pixel_shift_pattern = user.pixel_shift_pattern.or(base.pixel_shift_pattern);
+        base.pixel_shift_pause_on_touch = user.pixel_shift_pause_on_touch.or(base.pixel_shift_pause_on_touch);
+        base.oled_care_mode = user.oled_care_mode.or(base.oled_care_mode);
+        base.oled_care_hours = user.oled_care_hours.or(base.oled_care_hours);
+        base.oled_care_idle_minutes = user.oled_care_idle_minutes.or(base.oled_care_idle_minutes);
+        base.quiet_hours = user.quiet_hours.or(base.quiet_hours);
+        base.quiet_hours_brightness = user.quiet_hours_brightness.or(base.quiet_hours_brightness);
+        base.quiet_hours_expandable = user.quiet_hours_expandable.or(base.quiet_hours_expandable);
+        base.battery_saver_threshold = user.battery_saver_threshold.or(base.battery_saver_threshold);
+        base.battery_saver_brightness = user.battery_saver_brightness.or(base.battery_saver_brightness);
+        base.battery_saver_dim_seconds = user.battery_saver_dim_seconds.or(base.battery_saver_dim_seconds);
+        base.battery_saver_slow_refresh = user.battery_saver_slow_refresh.or(base.battery_saver_slow_refresh);
+        base.idle_refresh_seconds = user.idle_refresh_seconds.or(base.idle_refresh_seconds);
         base.font_template = user.font_template.or(base.font_template);
         base.adaptive_brightness = user.adaptive_brightness.or(base.adaptive_brightness);
+        base.adaptive_brightness_hold_seconds = user.adaptive_brightness_hold_seconds.or(base.adaptive_brightness_hold_seconds);
         base.media_layer_keys = user.media_layer_keys.or(base.media_layer_keys);
         base.primary_layer_keys = user.primary_layer_keys.or(base.primary_layer_keys);
         base.active_brightness = user.active_brightness.or(base.active_brightness);
@@ -264,59 +1193,856 @@ fn load_config(width: u16) -> (Config, [FunctionLayer; 2]) {
         base.back_button_show_outlines = user.back_button_show_outlines.or(base.back_button_show_outlines);
         base.back_button_outline_color = user.back_button_outline_color.or(base.back_button_outline_color);
         base.expandable_timeout_seconds = user.expandable_timeout_seconds.or(base.expandable_timeout_seconds);
+        base.max_visible_buttons = user.max_visible_buttons.or(base.max_visible_buttons);
+        base.background_image = user.background_image.or(base.background_image);
+        base.primary_layer_background = user.primary_layer_background.or(base.primary_layer_background);
+        base.media_layer_background = user.media_layer_background.or(base.media_layer_background);
+        base.expandable_backgrounds = user.expandable_backgrounds.or(base.expandable_backgrounds);
+        base.dimmed_content_style = user.dimmed_content_style.or(base.dimmed_content_style);
+        base.high_contrast = user.high_contrast.or(base.high_contrast);
+        base.high_contrast_max_buttons = user.high_contrast_max_buttons.or(base.high_contrast_max_buttons);
+        base.swipe_up_expandable = user.swipe_up_expandable.or(base.swipe_up_expandable);
+        base.swipe_up_distance = user.swipe_up_distance.or(base.swipe_up_distance);
+        base.swipe_left = user.swipe_left.or(base.swipe_left);
+        base.swipe_right = user.swipe_right.or(base.swipe_right);
+        base.swipe_up = user.swipe_up.or(base.swipe_up);
+        base.swipe_down = user.swipe_down.or(base.swipe_down);
+        base.scrub_step = user.scrub_step.or(base.scrub_step);
+        base.workspace_bindings = user.workspace_bindings.or(base.workspace_bindings);
+        base.keyboard_shortcuts = user.keyboard_shortcuts.or(base.keyboard_shortcuts);
+        base.rotation = user.rotation.or(base.rotation);
+        base.scale = user.scale.or(base.scale);
+        base.touch_seat = user.touch_seat.or(base.touch_seat);
+        base.touch_device = user.touch_device.or(base.touch_device);
+        base.allow_commands_as_root = user.allow_commands_as_root.or(base.allow_commands_as_root);
+        base.allow_commands_while_locked = user.allow_commands_while_locked.or(base.allow_commands_while_locked);
+        base.drop_privileges = user.drop_privileges.or(base.drop_privileges);
+        base.touch_sound = user.touch_sound.or(base.touch_sound);
+        base.touch_sound_file = user.touch_sound_file.or(base.touch_sound_file);
+        base.haptic_feedback = user.haptic_feedback.or(base.haptic_feedback);
+        base.show_osd = user.show_osd.or(base.show_osd);
+        base.charging_animation = user.charging_animation.or(base.charging_animation);
+        base.notification_banner = user.notification_banner.or(base.notification_banner);
+        base.notification_seconds = user.notification_seconds.or(base.notification_seconds);
+        base.display_indicator = user.display_indicator.or(base.display_indicator);
+        base.pip_titles = user.pip_titles.or(base.pip_titles);
+        base.display_profile_command = user.display_profile_command.or(base.display_profile_command);
+        base.touch_explore = user.touch_explore.or(base.touch_explore);
+        base.touch_explore_speech = user.touch_explore_speech.or(base.touch_explore_speech);
+        base.fn_tap = user.fn_tap.or(base.fn_tap);
+        base.fn_tap_seconds = user.fn_tap_seconds.or(base.fn_tap_seconds);
+        base.midi_device = user.midi_device.or(base.midi_device);
+        base.midi_channel = user.midi_channel.or(base.midi_channel);
+        base.separate_media_device = user.separate_media_device.or(base.separate_media_device);
+        base.gamepad_expandables = user.gamepad_expandables.or(base.gamepad_expandables);
+        base.world_clocks = user.world_clocks.or(base.world_clocks);
+        base.metrics_socket = user.metrics_socket.or(base.metrics_socket);
+        base.dbus_control = user.dbus_control.or(base.dbus_control);
+        base.alarms = user.alarms.or(base.alarms);
+        base.alarm_snooze_minutes = user.alarm_snooze_minutes.or(base.alarm_snooze_minutes);
     };
     let mut media_layer_keys = base.media_layer_keys.unwrap();
     let mut primary_layer_keys = base.primary_layer_keys.unwrap();
+    let mut expandable_origins = HashMap::new();
+    let mut expandables: HashMap<String, Vec<ButtonConfig>> = load_expandables()
+        .into_iter()
+        .map(|(name, (buttons, origin))| {
+            expandable_origins.insert(name.clone(), origin);
+            (name, buttons)
+        })
+        .collect();
+    let mut file_expandables: Vec<String> = expandables.keys().cloned().collect();
+    file_expandables.sort();
+    for name in file_expandables {
+        if let Some(mut buttons) = expandables.remove(&name) {
+            hoist_inline_expandables(&mut buttons, &mut expandables);
+            expandables.entry(name).or_insert(buttons);
+        }
+    }
+    hoist_inline_expandables(&mut media_layer_keys, &mut expandables);
+    hoist_inline_expandables(&mut primary_layer_keys, &mut expandables);
+    let has_docked_profile = base.docked.is_some();
+    let (mut docked_primary_keys, mut docked_media_keys) = base
+        .docked
+        .take()
+        .map(|profile| (profile.primary_layer_keys, profile.media_layer_keys))
+        .unwrap_or_default();
+    for keys in [&mut docked_primary_keys, &mut docked_media_keys].into_iter().flatten() {
+        hoist_inline_expandables(keys, &mut expandables);
+    }
+    // Checked whether docked or not, so mistakes don't wait for the next time it is
+    let docked_layers: Vec<(&str, Vec<ButtonConfig>)> = [
+        ("Docked.PrimaryLayerKeys", docked_primary_keys.clone()),
+        ("Docked.MediaLayerKeys", docked_media_keys.clone()),
+    ]
+    .into_iter()
+    .filter_map(|(name, keys)| Some((name, keys?)))
+    .collect();
+    // Whether PrimaryLayerKeys and MediaLayerKeys are shown as written, so edits to them can be saved
+    let mut as_written = [!safe_mode, !safe_mode];
+    if docked() && !safe_mode {
+        as_written = [docked_primary_keys.is_none(), docked_media_keys.is_none()];
+        primary_layer_keys = docked_primary_keys.unwrap_or(primary_layer_keys);
+        media_layer_keys = docked_media_keys.unwrap_or(media_layer_keys);
+    }
+    for (i, (name, keys)) in [("PrimaryLayerKeys", &mut primary_layer_keys), ("MediaLayerKeys", &mut media_layer_keys)].into_iter().enumerate() {
+        if !safe_mode && keys.is_empty() {
+            eprintln!("{} has no buttons, showing the built-in function keys instead", name);
+            as_written[i] = false;
+        }
+        if safe_mode || keys.is_empty() {
+            *keys = safe_layer_keys(width);
+        }
+    }
+    // The layer shown while Fn is held makes way for digits and symbols
+    if base.number_layer == Some(true) && !safe_mode {
+        let fn_layer = if base.media_layer_default.unwrap() { 0 } else { 1 };
+        as_written[fn_layer] = false;
+        let fn_layer_keys = if fn_layer == 0 { &mut primary_layer_keys } else { &mut media_layer_keys };
+        *fn_layer_keys = number_layer_buttons();
+    }
+    // Layers of their own follow PrimaryLayer and MediaLayer, in the order they're written
+    let mut custom_layers = Vec::new();
+    let mut custom_layer_keys = Vec::new();
+    let mut custom_layer_backgrounds = Vec::new();
+    for mut layer in base.layers.take().unwrap_or_default() {
+        if ["PrimaryLayer", "MediaLayer", "Next", "Previous"].contains(&layer.name.as_str())
+            || custom_layers.iter().any(|(name, _)| *name == layer.name)
+        {
+            eprintln!("There already is a layer called {}, ignoring the second one", layer.name);
+            continue;
+        }
+        let activation = match (layer.activation.as_deref(), layer.app) {
+            (None | Some("Toggle"), _) => LayerActivation::Toggle,
+            (Some("FnHeld"), _) => LayerActivation::FnHeld,
+            (Some("FnLock"), _) => LayerActivation::FnLock,
+            (Some("App"), Some(app)) => LayerActivation::App(app),
+            (Some(other), _) => {
+                eprintln!(
+                    "Invalid Activation {} of layer {}, accepted values: Toggle, FnHeld, FnLock, App along with an App pattern",
+                    other, layer.name
+                );
+                LayerActivation::Toggle
+            }
+        };
+        hoist_inline_expandables(&mut layer.keys, &mut expandables);
+        if layer.keys.is_empty() {
+            eprintln!("Layer {} has no buttons, showing the built-in function keys instead", layer.name);
+            layer.keys = safe_layer_keys(width);
+        }
+        custom_layers.push((layer.name, activation));
+        custom_layer_keys.push(layer.keys);
+        custom_layer_backgrounds.push(layer.background);
+    }
     if width >= 2170 {
-        for layer in [&mut media_layer_keys, &mut primary_layer_keys] {
+        for layer in [&mut media_layer_keys, &mut primary_layer_keys].into_iter().chain(custom_layer_keys.iter_mut()) {
             layer.insert(
                 0,
                 ButtonConfig {
-                    icon: None,
                     text: Some("esc".into()),
-                    theme: None,
-                    action: ButtonAction::Key(Key::Esc),
-                    stretch: None,
-                    time: None,
-                    locale: None,
-                    battery: None,
-                    show_button_outlines: None,
-                    button_outlines_color: None,
-                    show_app_icon_alongside_text: None,
-                    app_icon: None,
+                    ..ButtonConfig::new(ButtonAction::Key(Key::Esc))
                 },
+
             );
         }
     }
-    let media_layer = FunctionLayer::with_config(media_layer_keys);
-    let fkey_layer = FunctionLayer::with_config(primary_layer_keys);
-    let layers = if base.media_layer_default.unwrap() {
-        [media_layer, fkey_layer]
+    let high_contrast = base.high_contrast.unwrap_or(false);
+    let scale = base.scale.unwrap_or(1.0).clamp(0.5, 4.0) * if high_contrast { HIGH_CONTRAST_SCALE } else { 1.0 };
+    let rotation = match base.rotation.unwrap_or(90) {
+        r @ (0 | 90 | 180 | 270) => r,
+        r => {
+            eprintln!("Invalid Rotation {}, accepted values: 0, 90, 180, 270. Using 90.", r);
+            90
+        }
+    };
+    let mut commands = load_commands();
+    if let Some(buttons) = load_script_actions(&mut commands) {
+        if expandables.contains_key(ACTIONS_EXPANDABLE) {
+            println!("{} is defined in the configuration, ignoring the scripts in {}", ACTIONS_EXPANDABLE, ACTIONS_DIR);
+        } else {
+            expandables.insert(ACTIONS_EXPANDABLE.to_string(), buttons);
+        }
+    }
+    if let Some(clocks) = base.world_clocks.filter(|clocks| !clocks.is_empty()) {
+        if expandables.contains_key(WORLD_CLOCK_EXPANDABLE) {
+            println!("{} is defined in the configuration, ignoring WorldClocks", WORLD_CLOCK_EXPANDABLE);
+        } else {
+            let buttons = clocks
+                .into_iter()
+                .map(|clock| ButtonConfig {
+                    text: Some(clock.city),
+                    world_clock: Some(clock.zone),
+                    ..ButtonConfig::new(ButtonAction::Key(Key::Time))
+                })
+                .collect();
+            expandables.insert(WORLD_CLOCK_EXPANDABLE.to_string(), buttons);
+        }
+    }
+    if !expandables.contains_key(CALCULATOR_EXPANDABLE) {
+        expandables.insert(CALCULATOR_EXPANDABLE.to_string(), calculator_buttons());
+    }
+    if !expandables.contains_key(NUMBERS_EXPANDABLE) {
+        expandables.insert(NUMBERS_EXPANDABLE.to_string(), number_layer_buttons());
+    }
+    if high_contrast {
+        let max_buttons = base.high_contrast_max_buttons.unwrap_or(8);
+        // Paged layers no longer line up with the config
+        as_written[0] &= primary_layer_keys.len() <= max_buttons.max(3);
+        as_written[1] &= media_layer_keys.len() <= max_buttons.max(3);
+        split_overflow(&mut primary_layer_keys, max_buttons, "PrimaryLayerKeys", false, &mut expandables);
+        split_overflow(&mut media_layer_keys, max_buttons, "MediaLayerKeys", false, &mut expandables);
+        for ((name, _), keys) in custom_layers.iter().zip(custom_layer_keys.iter_mut()) {
+            split_overflow(keys, max_buttons, name, false, &mut expandables);
+        }
+        let mut names: Vec<String> = expandables.keys().cloned().collect();
+        names.sort();
+        for name in names {
+            if let Some(mut buttons) = expandables.remove(&name) {
+                split_overflow(&mut buttons, max_buttons, name.trim_start_matches("Expand_"), true, &mut expandables);
+                expandables.insert(name, buttons);
+            }
+        }
+    }
+    let mut hyprland_origins = HashMap::new();
+    let hyprland_expandables: HashMap<String, Vec<HyprlandExpandConfig>> = load_hyprland_expandables()
+        .into_iter()
+        .map(|(name, (configs, origin))| {
+            hyprland_origins.insert(name.clone(), origin);
+            (name, configs)
+        })
+        .collect();
+    let mut sorted_expandables: Vec<_> = expandables.iter().collect();
+    sorted_expandables.sort_by_key(|(name, _)| name.as_str());
+    let mut checked_layers: Vec<(&str, &[ButtonConfig])> = vec![
+        ("PrimaryLayerKeys", &primary_layer_keys),
+        ("MediaLayerKeys", &media_layer_keys),
+    ];
+    checked_layers.extend(docked_layers.iter().map(|(name, buttons)| (*name, buttons.as_slice())));
+    checked_layers.extend(custom_layers.iter().zip(&custom_layer_keys).map(|((name, _), keys)| (name.as_str(), keys.as_slice())));
+    checked_layers.extend(sorted_expandables.iter().map(|(name, buttons)| (name.as_str(), buttons.as_slice())));
+    for (name, configs) in &hyprland_expandables {
+        checked_layers.extend(configs.iter().map(|c| (name.as_str(), c.layer_keys.as_slice())));
+    }
+    let mut linted_layers = vec![
+        (format!("{}: PrimaryLayerKeys", primary_layer_origin), primary_layer_keys.as_slice(), 0),
+        (format!("{}: MediaLayerKeys", media_layer_origin), media_layer_keys.as_slice(), 0),
+    ];
+    for ((name, _), keys) in custom_layers.iter().zip(&custom_layer_keys) {
+        linted_layers.push((format!("{}: Layers.{}", layers_origin, name), keys.as_slice(), 0));
+    }
+    // Expandables are shown with a Back button in front of their own buttons
+    for (name, buttons) in &sorted_expandables {
+        let origin = expandable_origins.get(name.as_str()).copied().unwrap_or("Inline expandable");
+        linted_layers.push((format!("{}: {}", origin, name), buttons.as_slice(), 1));
+    }
+    for (name, configs) in &hyprland_expandables {
+        let origin = hyprland_origins.get(name).copied().unwrap_or_default();
+        linted_layers.extend(configs.iter().map(|c| (format!("{}: {}", origin, name), c.layer_keys.as_slice(), 1)));
+    }
+    lint_stretch(&linted_layers, width, scale);
+    let layer_names: Vec<&str> = ["PrimaryLayer", "MediaLayer", "Next", "Previous"]
+        .into_iter()
+        .chain(custom_layers.iter().map(|(name, _)| name.as_str()))
+        .collect();
+    let mut config_errors = find_unresolved_references(&checked_layers, &expandables, &hyprland_expandables, &commands, &layer_names);
+    if let Some(name) = &base.swipe_up_expandable {
+        if !expandables.contains_key(name) {
+            config_errors.push(format!("{} in SwipeUpExpandable", name));
+        }
+    }
+    if let Some(name) = &base.quiet_hours_expandable {
+        if !expandables.contains_key(name) {
+            config_errors.push(format!("{} in QuietHoursExpandable", name));
+        }
+    }
+    for name in base.gamepad_expandables.iter().flatten() {
+        if !expandables.contains_key(name) {
+            config_errors.push(format!("{} in GamepadExpandables", name));
+        }
+    }
+    let swipes = [
+        ("SwipeLeft", &base.swipe_left),
+        ("SwipeRight", &base.swipe_right),
+        ("SwipeUp", &base.swipe_up),
+        ("SwipeDown", &base.swipe_down),
+    ];
+    for (location, action) in swipes {
+        if let Some(action) = action {
+            let button = ButtonConfig::new(action.clone());
+            config_errors.extend(find_unresolved_references(&[(location, &[button])], &expandables, &hyprland_expandables, &commands, &layer_names));
+        }
+    }
+    if let Some(command_id) = &base.touch_explore_speech {
+        if !commands.contains_key(command_id) {
+            config_errors.push(format!("{} in TouchExploreSpeech", command_id));
+        }
+    }
+    if let Some(command_id) = &base.display_profile_command {
+        if !commands.contains_key(command_id) {
+            config_errors.push(format!("{} in DisplayProfileCommand", command_id));
+        }
+    }
+    let fn_tap = match base.fn_tap.as_deref() {
+        None => None,
+        Some("ToggleLayers") => Some(FnTap::ToggleLayers),
+        Some("CycleLayers") => Some(FnTap::CycleLayers),
+        Some(action) => {
+            let action = ButtonAction::deserialize(value::StringDeserializer::<serde::de::value::Error>::new(action.to_string())).unwrap();
+            let button = ButtonConfig::new(action.clone());
+            config_errors.extend(find_unresolved_references(&[("FnTap", &[button])], &expandables, &hyprland_expandables, &commands, &layer_names));
+            Some(FnTap::Action(action))
+        }
+    };
+    let mut alarms = Vec::new();
+    for alarm in base.alarms.unwrap_or_default() {
+        let Ok(time) = NaiveTime::parse_from_str(&alarm.time, "%H:%M") else {
+            eprintln!("Invalid alarm time '{}', expected hours and minutes like 07:30", alarm.time);
+            continue;
+        };
+        if let Some(action) = &alarm.action {
+            let button = ButtonConfig::new(action.clone());
+            config_errors.extend(find_unresolved_references(&[("Alarms", &[button])], &expandables, &hyprland_expandables, &commands, &layer_names));
+        }
+        alarms.push(Alarm {
+            hour: time.hour(),
+            minute: time.minute(),
+            label: alarm.label.unwrap_or(alarm.time),
+            action: alarm.action,
+        });
+    }
+    let media_layer_default = base.media_layer_default.unwrap();
+    let target = |name: &str| layer_target(name, media_layer_default, &custom_layers, &expandables);
+    let mut workspace_bindings = HashMap::new();
+    for (workspace, name) in base.workspace_bindings.unwrap_or_default() {
+        match target(&name) {
+            Some(target) => {
+                workspace_bindings.insert(workspace, target);
+            }
+            None => config_errors.push(format!("{} in WorkspaceBindings", name)),
+        }
+    }
+    let mut keyboard_shortcuts = Vec::new();
+    for (shortcut, name) in base.keyboard_shortcuts.unwrap_or_default() {
+        let keys = crate::hyprland::parse_key_combos(&format!("KeyCombos_{}", shortcut));
+        if keys.len() != shortcut.split('_').count() {
+            config_errors.push(format!("{} in KeyboardShortcuts", shortcut));
+            continue;
+        }
+        match target(&name) {
+            Some(target) => keyboard_shortcuts.push((keys, target)),
+            None => config_errors.push(format!("{} in KeyboardShortcuts", name)),
+        }
+    }
+    if !config_errors.is_empty() {
+        eprintln!("Found {} unresolved references in the configuration:", config_errors.len());
+        for error in &config_errors {
+            eprintln!("  {}", error);
+        }
+        let mut error_buttons = vec![ButtonConfig {
+            text: Some(format!("{} unresolved:", config_errors.len())),
+            ..ButtonConfig::new(ButtonAction::Command("Back".to_string()))
+        }];
+        error_buttons.extend(config_errors.iter().map(|error| ButtonConfig {
+            text: Some(error.clone()),
+            stretch: Some(2),
+            ..ButtonConfig::new(ButtonAction::Command("Back".to_string()))
+        }));
+        expandables.insert(CONFIG_ERRORS_EXPANDABLE.to_string(), error_buttons);
+    }
+    let (media_layer, fkey_layer, font_face) = match (
+        FunctionLayer::with_config(media_layer_keys),
+        FunctionLayer::with_config(primary_layer_keys),
+        load_font(base.font_template.as_deref().unwrap()),
+    ) {
+        (Ok(media_layer), Ok(fkey_layer), Ok(font_face)) => (media_layer, fkey_layer, font_face),
+        (media_layer, fkey_layer, font_face) => {
+            let mut errors = Vec::new();
+            errors.extend(media_layer.err().into_iter().flatten().map(|e| format!("MediaLayerKeys: {}", e)));
+            errors.extend(fkey_layer.err().into_iter().flatten().map(|e| format!("PrimaryLayerKeys: {}", e)));
+            errors.extend(font_face.err());
+            return Err(errors);
+        }
+    };
+    let background = base.background_image.as_deref().and_then(load_background);
+    let media_layer = media_layer.with_background(
+        base.media_layer_background
+            .as_deref()
+            .and_then(load_background)
+            .or_else(|| background.clone()),
+    );
+    let fkey_layer = fkey_layer.with_background(
+        base.primary_layer_background
+            .as_deref()
+            .and_then(load_background)
+            .or_else(|| background.clone()),
+    );
+    let mut layers = if media_layer_default {
+        vec![media_layer, fkey_layer]
+    } else {
+        vec![fkey_layer, media_layer]
+    };
+    for (((name, _), keys), layer_background) in custom_layers.iter().zip(custom_layer_keys).zip(custom_layer_backgrounds) {
+        let layer = FunctionLayer::with_config(keys).map_err(|errors| {
+            errors.into_iter().map(|e| format!("Layers.{}: {}", name, e)).collect::<Vec<_>>()
+        })?;
+        layers.push(layer.with_background(layer_background.as_deref().and_then(load_background).or_else(|| background.clone())));
+    }
+    let expandable_backgrounds = base
+        .expandable_backgrounds
+        .take()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(name, image)| Some((name, load_background(&image)?)))
+        .collect();
+    // Esc is put in front of both layers when there's room
+    let offset = if width >= 2170 { 1 } else { 0 };
+    let primary_source = as_written[0].then_some((EDITABLE_LAYERS[0], offset));
+    let media_source = as_written[1].then_some((EDITABLE_LAYERS[1], offset));
+    let mut layer_sources = if media_layer_default {
+        vec![media_source, primary_source]
     } else {
-        [fkey_layer, media_layer]
+        vec![primary_source, media_source]
     };
+    // Edits to them aren't saved, they're nested in the Layers array
+    layer_sources.extend(custom_layers.iter().map(|_| None));
     let cfg = Config {
         show_button_outlines: base.show_button_outlines.unwrap(),
         enable_pixel_shift: base.enable_pixel_shift.unwrap(),
-        adaptive_brightness: base.adaptive_brightness.unwrap(),
-        font_face: load_font(&base.font_template.unwrap()),
-        active_brightness: base.active_brightness.unwrap(),
+        // Shifting by half the range in each direction needs even values, a sweep needs
+        // somewhere to go and more than a quarter of the bar would be distracting
+        pixel_shift_width: base.pixel_shift_width.unwrap_or(DEFAULT_PIXEL_SHIFT_WIDTH_PX).clamp(2, (width as u64 / 4).max(2)) & !1,
+        pixel_shift_height: base.pixel_shift_height.unwrap_or(DEFAULT_PIXEL_SHIFT_HEIGHT_PX) & !1,
+        pixel_shift_interval_ms: (base.pixel_shift_interval.unwrap_or(10).clamp(1, 3600) * 1000) as i32,
+        pixel_shift_pattern: base.pixel_shift_pattern.unwrap_or(PixelShiftPattern::Sweep),
+        pixel_shift_pause_on_touch: base.pixel_shift_pause_on_touch.unwrap_or(false),
+        oled_care_mode: base.oled_care_mode.unwrap_or(OledCareMode::None),
+        oled_care_hours: base.oled_care_hours.map(|[start, end]| (start % 24, end % 24)).unwrap_or((1, 6)),
+        oled_care_idle_minutes: base.oled_care_idle_minutes.unwrap_or(30),
+        quiet_hours: base.quiet_hours.map(|[start, end]| (start % 24, end % 24)),
+        quiet_hours_brightness: base.quiet_hours_brightness.unwrap_or(16),
+        quiet_hours_expandable: base.quiet_hours_expandable,
+        battery_saver_threshold: base.battery_saver_threshold.unwrap_or(0).min(100),
+        battery_saver_brightness: base.battery_saver_brightness.unwrap_or(64),
+        battery_saver_dim_seconds: base.battery_saver_dim_seconds.unwrap_or(10).max(1),
+        battery_saver_slow_refresh: base.battery_saver_slow_refresh.unwrap_or(true),
+        idle_refresh_seconds: base.idle_refresh_seconds.unwrap_or(5).min(60),
+        adaptive_brightness: base.adaptive_brightness.unwrap() && !high_contrast,
+        adaptive_brightness_hold_seconds: base.adaptive_brightness_hold_seconds.unwrap_or(60),
+        font_face,
+        // High contrast wants the bar as bright as it gets
+        active_brightness: if high_contrast { 255 } else { base.active_brightness.unwrap() },
         keyboard_brightness_step: base.keyboard_brightness_step.unwrap_or(32),
         keyboard_brightness_enabled: base.keyboard_brightness_enabled.unwrap_or(true),
-        commands: load_commands(),
+        commands,
+        allow_commands_as_root: base.allow_commands_as_root.unwrap_or(false),
+        allow_commands_while_locked: base.allow_commands_while_locked.unwrap_or(false),
+        // Off until following the active window works without the session user's sockets
+        drop_privileges: base.drop_privileges.unwrap_or(true),
+        touch_sound: base.touch_sound.unwrap_or(false),
+        touch_sound_file: base.touch_sound_file.unwrap_or_else(|| DEFAULT_TOUCH_SOUND.to_string()),
+        haptic_feedback: base.haptic_feedback.unwrap_or(false),
+        show_osd: base.show_osd.unwrap_or(false),
+        charging_animation: base.charging_animation.unwrap_or(false),
+        notification_banner: base.notification_banner.unwrap_or(false),
+        notification_seconds: base.notification_seconds.unwrap_or(5).clamp(1, 60),
+        display_indicator: base.display_indicator.unwrap_or(false),
+        pip_titles: base.pip_titles.unwrap_or_default(),
+        display_profile_command: base.display_profile_command,
+        touch_explore: base.touch_explore.unwrap_or(false),
+        touch_explore_speech: base.touch_explore_speech,
+        fn_tap,
+        fn_tap_seconds: base.fn_tap_seconds.unwrap(),
+        midi_device: base.midi_device,
+        midi_channel: base.midi_channel.unwrap_or(1).clamp(1, 16),
+        separate_media_device: base.separate_media_device.unwrap_or(false),
+        gamepad_expandables: base.gamepad_expandables.unwrap_or_default(),
+        metrics_socket: base.metrics_socket,
+        dbus_control: base.dbus_control.unwrap_or(false),
+        alarms,
+        alarm_snooze_minutes: base.alarm_snooze_minutes.unwrap_or(9).max(1),
         user_env: load_user_environment(),
         back_button_show_outlines: base.back_button_show_outlines.unwrap_or(false),
         back_button_outline_color: base.back_button_outline_color,
         expandable_timeout_seconds: base.expandable_timeout_seconds.unwrap_or(5),
-        expandables: load_expandables(),
-        hyprland_expandables: load_hyprland_expandables(),
+        max_visible_buttons: base.max_visible_buttons.unwrap_or(0) as usize,
+        expandables,
+        hyprland_expandables,
+        background,
+        expandable_backgrounds,
+        dimmed_content_style: base.dimmed_content_style.unwrap_or(DimmedContentStyle::None),
+        high_contrast,
+        swipe_up_expandable: base.swipe_up_expandable,
+        swipe_up_distance: base.swipe_up_distance.unwrap_or(0.5).clamp(0.05, 1.0),
+        swipe_left: base.swipe_left,
+        swipe_right: base.swipe_right,
+        swipe_up: base.swipe_up,
+        swipe_down: base.swipe_down,
+        scrub_step: base.scrub_step.unwrap_or(40.0).clamp(5.0, 500.0),
+        workspace_bindings,
+        keyboard_shortcuts,
+        media_layer_default,
+        layer_sources,
+        custom_layers,
+        has_docked_profile,
+        rotation,
+        scale,
+        touch_seat: base.touch_seat.unwrap_or_else(|| "seat-touchbar".to_string()),
+        touch_device: base.touch_device.unwrap_or_else(|| " Touch Bar".to_string()),
+        config_errors,
+    };
+    Ok((cfg, layers))
+}
+
+fn parse_display_mode(mode: &str) -> Option<(u16, u16)> {
+    let (w, h) = mode.split_once('x')?;
+    Some((w.trim().parse().ok()?, h.trim().parse().ok()?))
+}
+
+pub fn load_display_config() -> DisplayConfig {
+    let parse = |path: &str| {
+        read_to_string(path)
+            .ok()
+            .and_then(|content| toml::from_str::<ConfigProxy>(&content).ok())
     };
-    (cfg, layers)
+    let base = parse("/usr/share/tiny-dfr/config.toml");
+    let user = parse(USER_CFG_PATH);
+    let pick = |f: fn(&ConfigProxy) -> &Option<String>| {
+        user.as_ref()
+            .and_then(|u| f(u).clone())
+            .or_else(|| base.as_ref().and_then(|b| f(b).clone()))
+    };
+    let mode = pick(|c| &c.display_mode);
+    let format = match pick(|c| &c.display_format).as_deref() {
+        None | Some("xrgb8888") => DrmFourcc::Xrgb8888,
+        Some("rgb565") => DrmFourcc::Rgb565,
+        Some(other) => {
+            eprintln!("Invalid DisplayFormat '{}', accepted formats: xrgb8888, rgb565", other);
+            DrmFourcc::Xrgb8888
+        }
+    };
+    DisplayConfig {
+        card: pick(|c| &c.display_card),
+        connector: pick(|c| &c.display_connector),
+        mode: mode.as_deref().and_then(|m| {
+            let parsed = parse_display_mode(m);
+            if parsed.is_none() {
+                eprintln!("Invalid DisplayMode '{}', expected WIDTHxHEIGHT", m);
+            }
+            parsed
+        }),
+        format,
+        touch_seat: pick(|c| &c.touch_seat).unwrap_or_else(|| "seat-touchbar".to_string()),
+        touch_device: pick(|c| &c.touch_device).unwrap_or_else(|| " Touch Bar".to_string()),
+    }
+}
+
+// Read by the privileged helper for every command it's asked to run as root, so edits apply right away
+pub fn commands_as_root_allowed() -> bool {
+    let parse = |path: &str| {
+        read_to_string(path)
+            .ok()
+            .and_then(|content| toml::from_str::<ConfigProxy>(&content).ok())
+            .and_then(|config| config.allow_commands_as_root)
+    };
+    parse(USER_CFG_PATH)
+        .or_else(|| parse("/usr/share/tiny-dfr/config.toml"))
+        .unwrap_or(false)
+}
+
+// A change to the buttons of a layer, indices count from the left
+#[derive(Debug, Clone)]
+pub enum LayoutEdit {
+    Move(usize, usize),
+    // The button is written like an entry of PrimaryLayerKeys
+    Add(usize, String),
+    Remove(usize),
+}
+
+impl LayoutEdit {
+    // Applies the edit to anything in layer order, making the added entry with `new_entry`
+    pub fn apply<T>(&self, entries: &mut Vec<T>, new_entry: impl FnOnce(&str) -> Result<T, String>) -> Result<(), String> {
+        let len = entries.len();
+        match self {
+            LayoutEdit::Move(from, to) if *from < len && *to < len => {
+                let entry = entries.remove(*from);
+                entries.insert(*to, entry);
+            }
+            LayoutEdit::Add(index, text) if *index <= len => entries.insert(*index, new_entry(text)?),
+            // Nothing is drawn or hit on a layer without buttons
+            LayoutEdit::Remove(_) if len == 1 => return Err("a layer keeps at least one button".to_string()),
+            LayoutEdit::Remove(index) if *index < len => {
+                entries.remove(*index);
+            }
+            _ => return Err(format!("the layer has {} buttons", len)),
+        }
+        Ok(())
+    }
+
+    // The same edit with `offset` fewer buttons in front, None when it touches one of those
+    fn shifted(&self, offset: usize) -> Option<LayoutEdit> {
+        Some(match self {
+            LayoutEdit::Move(from, to) => LayoutEdit::Move(from.checked_sub(offset)?, to.checked_sub(offset)?),
+            LayoutEdit::Add(index, text) => LayoutEdit::Add(index.checked_sub(offset)?, text.clone()),
+            LayoutEdit::Remove(index) => LayoutEdit::Remove(index.checked_sub(offset)?),
+        })
+    }
+}
+
+fn parse_button_value(text: &str) -> Result<toml::Value, String> {
+    toml::from_str::<toml::Table>(&format!("Button = {}", text))
+        .map_err(|e| e.message().to_string())?
+        .remove("Button")
+        .ok_or_else(|| "no button given".to_string())
+}
+
+// Reads a button written as an inline table, `{ Text = "F1", Action = "F1" }`
+pub fn parse_button(text: &str) -> Result<ButtonConfig, String> {
+    parse_button_value(text)?.try_into().map_err(|e: toml::de::Error| e.message().to_string())
+}
+
+// Where the value assigned to a top-level key starts and ends, from the key to before a
+// trailing comment, skipping strings and comments. Without one, where the top-level keys end.
+fn find_top_level_value(text: &str, key: &str) -> Result<(usize, usize), usize> {
+    let bytes = text.as_bytes();
+    let mut start = None;
+    let mut depth = 0;
+    let mut line_start = true;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\n' | b'#' if depth == 0 && start.is_some() => break,
+            b'\n' => line_start = true,
+            b' ' | b'\t' | b'\r' => {}
+            b'#' => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            quote @ (b'"' | b'\'') => {
+                let triple = bytes[i..].starts_with(&[quote; 3]);
+                i += if triple { 3 } else { 1 };
+                while i < bytes.len() {
+                    if quote == b'"' && bytes[i] == b'\\' {
+                        i += 2;
+                        continue;
+                    }
+                    if triple && bytes[i..].starts_with(&[quote; 3]) {
+                        i += 3;
+                        break;
+                    }
+                    if !triple && bytes[i] == quote {
+                        i += 1;
+                        break;
+                    }
+                    i += 1;
+                }
+                line_start = false;
+                continue;
+            }
+            // A table header, top-level keys end here
+            b'[' if depth == 0 && line_start => return Err(i),
+            b'[' | b'{' => {
+                depth += 1;
+                line_start = false;
+            }
+            b']' | b'}' => {
+                depth -= 1;
+                line_start = false;
+            }
+            _ if depth == 0 && line_start && bytes[i..].starts_with(key.as_bytes()) => {
+                let rest = text[i + key.len()..].trim_start_matches([' ', '\t']);
+                if rest.starts_with('=') {
+                    start = Some(i);
+                }
+                line_start = false;
+            }
+            _ => line_start = false,
+        }
+        i += 1;
+    }
+    match start {
+        Some(start) => Ok((start, start + text[start..i].trim_end().len())),
+        None => Err(text.len()),
+    }
+}
+
+// Layers one button per line like the shipped file, everything else as TOML writes it
+fn render_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::Array(items) if !items.is_empty() && items.iter().all(toml::Value::is_table) => {
+            let mut array = "[\n".to_string();
+            for item in items {
+                array += &format!("    {},\n", item);
+            }
+            array + "]"
+        }
+        value => value.to_string(),
+    }
+}
+
+// The user's settings written into the shipped file, which keeps its documentation and
+// defaults. A setting commented out there goes below its comment, tables go at the end.
+// Comments of the user's file don't survive.
+fn normalized_config(user: &toml::Table, shipped_text: &str) -> Result<String, String> {
+    let shipped: toml::Table = toml::from_str(shipped_text).map_err(|e| format!("the shipped configuration can't be parsed: {}", e.message()))?;
+    let mut text = shipped_text.to_string();
+    let mut tables = String::new();
+    for (key, value) in user {
+        if let toml::Value::Table(table) = value {
+            tables += &format!("\n[{}]\n", key);
+            for (key, value) in table {
+                tables += &format!("{} = {}\n", key, render_value(value));
+            }
+            continue;
+        }
+        if shipped.get(key) == Some(value) {
+            continue;
+        }
+        let setting = format!("{} = {}", key, render_value(value));
+        match find_top_level_value(&text, key) {
+            Ok((start, end)) => text.replace_range(start..end, &setting),
+            Err(end) => {
+                let commented = format!("#{} =", key);
+                let mut offset = 0;
+                let mut below_comment = None;
+                for line in text[..end].split_inclusive('\n') {
+                    offset += line.len();
+                    if line.starts_with(&commented) && line.ends_with('\n') {
+                        below_comment = Some(offset);
+                        break;
+                    }
+                }
+                match below_comment {
+                    Some(at) => text.insert_str(at, &format!("{}\n", setting)),
+                    None => {
+                        let before = &text[..end];
+                        let separator = if before.is_empty() || before.ends_with("\n\n") {
+                            ""
+                        } else if before.ends_with('\n') {
+                            "\n"
+                        } else {
+                            "\n\n"
+                        };
+                        let after = if end < text.len() { "\n" } else { "" };
+                        text.insert_str(end, &format!("{}{}\n{}", separator, setting, after));
+                    }
+                }
+            }
+        }
+    }
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text += &tables;
+    // Whatever goes wrong above shouldn't end up on disk
+    toml::from_str::<ConfigProxy>(&text).map_err(|e| format!("the normalized configuration doesn't parse: {}", e.message()))?;
+    Ok(text)
+}
+
+// The settings of the user's file, none if there is no such file
+fn read_user_settings() -> Result<toml::Table, String> {
+    match read_to_string(USER_CFG_PATH) {
+        Ok(text) => toml::from_str(&text).map_err(|e| format!("{} can't be parsed: {}", USER_CFG_PATH, e.message())),
+        Err(_) => Ok(toml::Table::new()),
+    }
+}
+
+// Replaces the user's file with the shipped one carrying the user's settings, in one step
+// so the daemon watching it never reads half a file
+pub fn write_config(user: &toml::Table) -> Result<(), String> {
+    let shipped = read_to_string("/usr/share/tiny-dfr/config.toml").map_err(|e| format!("Failed to read /usr/share/tiny-dfr/config.toml: {}", e))?;
+    let text = normalized_config(user, &shipped)?;
+    privileged::replace(USER_CFG_PATH, text.as_bytes()).map_err(|e| format!("{} can't be written: {}", USER_CFG_PATH, e))
+}
+
+// Applies an edit of the layer built from `key` to the user's settings, copying the layer
+// from the shipped file first if need be
+fn edit_layer_settings(user: &mut toml::Table, shipped: &str, key: &str, edit: &LayoutEdit) -> Result<(), String> {
+    let mut buttons = match user.get(key) {
+        Some(toml::Value::Array(buttons)) => buttons.clone(),
+        _ => match toml::from_str::<toml::Table>(shipped).ok().and_then(|mut shipped| shipped.remove(key)) {
+            Some(toml::Value::Array(buttons)) => buttons,
+            _ => return Err(format!("{} isn't configured anywhere", key)),
+        },
+    };
+    edit.apply(&mut buttons, parse_button_value)?;
+    user.insert(key.to_string(), toml::Value::Array(buttons));
+    Ok(())
+}
+
+// The user's file with the edited layer written over the old one, the rest of the file is
+// left as written, comments included
+fn edit_layer_text(text: &str, shipped: &str, key: &str, edit: &LayoutEdit) -> Result<String, String> {
+    let mut user: toml::Table = toml::from_str(text).map_err(|e| format!("{} can't be parsed: {}", USER_CFG_PATH, e.message()))?;
+    edit_layer_settings(&mut user, shipped, key, edit)?;
+    let setting = format!("{} = {}", key, render_value(&user[key]));
+    let edited = match find_top_level_value(text, key) {
+        Ok((start, end)) => format!("{}{}{}", &text[..start], setting, &text[end..]),
+        // Added before the first table so it doesn't end up in it
+        Err(end) => {
+            let before = text[..end].trim_end();
+            let separator = if before.is_empty() { "" } else { "\n\n" };
+            format!("{}{}{}\n\n{}", before, separator, setting, &text[end..]).trim_end().to_string() + "\n"
+        }
+    };
+    toml::from_str::<ConfigProxy>(&edited).map_err(|e| format!("the edited configuration doesn't parse: {}", e.message()))?;
+    Ok(edited)
+}
+
+// Saves an edit of the layer shown at `layer` to the user configuration, `cfg.layer_sources`
+// tells which key it came from
+pub fn save_layout_edit(cfg: &Config, layer: usize, edit: &LayoutEdit) -> Result<(), String> {
+    let (key, offset) = cfg.layer_sources[layer].ok_or("the layer isn't shown as configured")?;
+    let edit = edit.shifted(offset).ok_or("the Esc button in front isn't part of the configuration")?;
+    let text = read_to_string(USER_CFG_PATH).unwrap_or_default();
+    let shipped = read_to_string("/usr/share/tiny-dfr/config.toml").unwrap_or_default();
+    let edited = edit_layer_text(&text, &shipped, key, &edit)?;
+    privileged::replace(USER_CFG_PATH, edited.as_bytes()).map_err(|e| format!("{} can't be written: {}", USER_CFG_PATH, e))?;
+    println!("Saved the edited {} to {}", key, USER_CFG_PATH);
+    Ok(())
+}
+
+// The top level layers a layout edit may save, `layer_sources` only ever names these
+const EDITABLE_LAYERS: [&str; 2] = ["PrimaryLayerKeys", "MediaLayerKeys"];
+
+// Checked by the privileged helper before it saves a layout edit: the new file has to differ
+// from the old one in the editable layers only, AllowCommandsAsRoot and the rest stay as root left them
+pub fn only_layout_changed(old: &str, new: &str) -> Result<(), String> {
+    let settings = |text: &str| -> Result<toml::Table, String> {
+        let mut settings: toml::Table = toml::from_str(text).map_err(|e| e.message().to_string())?;
+        for key in EDITABLE_LAYERS {
+            settings.remove(key);
+        }
+        Ok(settings)
+    };
+    toml::from_str::<ConfigProxy>(new).map_err(|e| format!("the new configuration doesn't parse: {}", e.message()))?;
+    if settings(old)? != settings(new)? {
+        return Err("only PrimaryLayerKeys and MediaLayerKeys may be changed".to_string());
+    }
+    Ok(())
+}
+
+// Entry point of tiny-dfr --migrate-config, rewrites the user's file as the current shipped
+// one with the user's settings in it, keeping the previous file next to it
+pub fn migrate_config() -> Result<(), String> {
+    let user = read_user_settings()?;
+    let backup = format!("{}.bak", USER_CFG_PATH);
+    if fs::metadata(USER_CFG_PATH).is_ok() {
+        fs::copy(USER_CFG_PATH, &backup).map_err(|e| format!("Failed to keep a copy at {}: {}", backup, e))?;
+        println!("The previous configuration is kept at {}", backup);
+    }
+    write_config(&user)?;
+    println!("Wrote {} with {} settings of its own", USER_CFG_PATH, user.len());
+    Ok(())
 }
 
 pub struct ConfigManager {
     inotify_fd: Inotify,
     watch_desc: Option<WatchDescriptor>,
+    actions_watch_desc: Option<WatchDescriptor>,
+    // Set to load the configuration again without it having changed
+    reload_requested: bool,
 }
 
 fn arm_inotify(inotify_fd: &Inotify) -> Option<WatchDescriptor> {
@@ -328,27 +2054,52 @@ fn arm_inotify(inotify_fd: &Inotify) -> Option<WatchDescriptor> {
     }
 }
 
+// Scripts being added, removed or made executable change the actions expandable
+fn arm_actions_inotify(inotify_fd: &Inotify) -> Option<WatchDescriptor> {
+    let flags = AddWatchFlags::IN_CREATE
+        | AddWatchFlags::IN_DELETE
+        | AddWatchFlags::IN_MOVED_TO
+        | AddWatchFlags::IN_MOVED_FROM
+        | AddWatchFlags::IN_ATTRIB
+        | AddWatchFlags::IN_CLOSE_WRITE;
+    inotify_fd.add_watch(ACTIONS_DIR, flags).ok()
+}
+
 impl ConfigManager {
     pub fn new() -> ConfigManager {
         let inotify_fd = Inotify::init(InitFlags::IN_NONBLOCK).unwrap();
         let watch_desc = arm_inotify(&inotify_fd);
+        let actions_watch_desc = arm_actions_inotify(&inotify_fd);
         ConfigManager {
             inotify_fd,
             watch_desc,
+            actions_watch_desc,
+            reload_requested: false,
         }
     }
-    pub fn load_config(&self, width: u16) -> (Config, [FunctionLayer; 2]) {
-        load_config(width)
+    pub fn load_config(&self, width: u16) -> Result<(Config, Vec<FunctionLayer>), Vec<String>> {
+        crate::poller::begin_load();
+        let parts = load_config(width);
+        if parts.is_ok() {
+            crate::poller::finish_load();
+        }
+        parts
     }
     pub fn update_config(
         &mut self,
         cfg: &mut Config,
-        layers: &mut [FunctionLayer; 2],
+        layers: &mut Vec<FunctionLayer>,
         width: u16,
     ) -> bool {
+        if self.reload_requested {
+            return self.handle_events(cfg, layers, width, Ok(Vec::new()));
+        }
         if self.watch_desc.is_none() {
             self.watch_desc = arm_inotify(&self.inotify_fd);
-            return false;
+            // Changes to actions.d still have to be read
+            if self.actions_watch_desc.is_none() {
+                return false;
+            }
         }
         match self.inotify_fd.read_events() {
             Err(Errno::EAGAIN) => false,
@@ -356,21 +2107,192 @@ impl ConfigManager {
         }
     }
     #[cold]
-    fn handle_events(&mut self, cfg: &mut Config, layers: &mut [FunctionLayer; 2], width: u16, evts: Result<Vec<InotifyEvent>, Errno>) -> bool {
-        let mut ret = false;
+    fn handle_events(&mut self, cfg: &mut Config, layers: &mut Vec<FunctionLayer>, width: u16, evts: Result<Vec<InotifyEvent>, Errno>) -> bool {
+        let mut config_changed = std::mem::take(&mut self.reload_requested);
+        let mut actions_changed = false;
         for evt in evts.unwrap() {
-            if Some(evt.wd) != self.watch_desc {
-                continue;
+            config_changed |= Some(evt.wd) == self.watch_desc;
+            actions_changed |= Some(evt.wd) == self.actions_watch_desc;
+        }
+        if !config_changed && !actions_changed {
+            return false;
+        }
+        // Give the edited configuration another chance, crashing again brings safe mode back
+        if config_changed && safe_mode() {
+            println!("Configuration changed, leaving safe mode");
+            SAFE_MODE.store(false, Ordering::Relaxed);
+        }
+        // Unchanged buttons are carried over as they are, along with what they display
+        crate::keep_buttons_for_reload(layers);
+        let parts = self.load_config(width);
+        let rebuilt = crate::drop_kept_buttons();
+        let loaded = match parts {
+            Ok(parts) => {
+                if rebuilt > 0 {
+                    println!("Configuration reloaded, {} buttons were changed or removed", rebuilt);
+                }
+                *cfg = parts.0;
+                *layers = parts.1;
+                true
+            }
+            // What is on the bar keeps working until the next edit fixes it
+            Err(errors) => {
+                eprintln!("Keeping the previous configuration, the new one can't be shown:");
+                for error in errors {
+                    eprintln!("  {}", error);
+                }
+                false
             }
-            let parts = load_config(width);
-            *cfg = parts.0;
-            *layers = parts.1;
-            ret = true;
+        };
+        if config_changed {
             self.watch_desc = arm_inotify(&self.inotify_fd);
+            // Picks up an actions.d created since startup
+            if self.actions_watch_desc.is_none() {
+                self.actions_watch_desc = arm_actions_inotify(&self.inotify_fd);
+            }
         }
-        ret
+        loaded
+    }
+    pub fn request_reload(&mut self) {
+        self.reload_requested = true;
     }
     pub fn fd(&self) -> &impl AsFd {
         &self.inotify_fd
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("code", "code"));
+        assert!(!glob_match("code", "code-oss"));
+        assert!(glob_match("code*", "code-oss"));
+        assert!(glob_match("* - YouTube*", "Some video - YouTube — Mozilla Firefox"));
+        assert!(!glob_match("* - YouTube*", "YouTube Music"));
+        assert!(glob_match("?im", "vim"));
+        assert!(!glob_match("?im", "nvim"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a*b*c", "aXXbYYbc"));
+    }
+
+    #[test]
+    fn test_normalized_config() {
+        let shipped = include_str!("../share/tiny-dfr/config.toml");
+        let mut user: toml::Table = toml::from_str("Scale = 1.5\nQuietHours = [22, 7]\n\n[Docked]\nPrimaryLayerKeys = [{ Text = \"HDMI\", Action = \"F1\" }]\n").unwrap();
+        edit_layer_settings(&mut user, shipped, "MediaLayerKeys", &LayoutEdit::Move(1, 0)).unwrap();
+        edit_layer_settings(&mut user, shipped, "MediaLayerKeys", &LayoutEdit::Add(0, "{ Text = \"[a]\", Action = \"F13\" }".to_string())).unwrap();
+        assert!(edit_layer_settings(&mut user, shipped, "MediaLayerKeys", &LayoutEdit::Remove(100)).is_err());
+        let text = normalized_config(&user, shipped).unwrap();
+        let config: ConfigProxy = toml::from_str(&text).unwrap();
+        assert_eq!(config.scale, Some(1.5));
+        assert_eq!(config.quiet_hours, Some([22, 7]));
+        assert!(text.contains("#QuietHours = [22, 7]\nQuietHours = [22, 7]\n"));
+        assert_eq!(config.docked.unwrap().primary_layer_keys.unwrap()[0].text.as_deref(), Some("HDMI"));
+        let shipped_media: ConfigProxy = toml::from_str(shipped).unwrap();
+        let (shipped_media, media) = (shipped_media.media_layer_keys.unwrap(), config.media_layer_keys.unwrap());
+        assert_eq!(media[0].text.as_deref(), Some("[a]"));
+        assert_eq!(format!("{:?}", media[1]), format!("{:?}", shipped_media[1]));
+        assert_eq!(format!("{:?}", media[2]), format!("{:?}", shipped_media[0]));
+        // The shipped documentation stays, settings left alone keep their defaults
+        assert!(text.contains("# This key defines the contents of the media key layer\n"));
+        assert_eq!(normalized_config(&toml::Table::new(), shipped).unwrap(), shipped);
+    }
+
+    #[test]
+    fn test_edit_layer_text() {
+        let shipped = include_str!("../share/tiny-dfr/config.toml");
+        let text = "# My bar\nScale = 1.5 # a bit bigger\n\nMediaLayerKeys = [\n    { Text = \"A\", Action = \"F1\" }, # first\n    { Text = \"B\", Action = \"F2\" },\n]\n# The end\n\n[Docked]\nMediaLayerKeys = []\n";
+        let edited = edit_layer_text(text, shipped, "MediaLayerKeys", &LayoutEdit::Move(1, 0)).unwrap();
+        // Everything around the layer stays as written
+        assert!(edited.starts_with("# My bar\nScale = 1.5 # a bit bigger\n\nMediaLayerKeys = [\n"));
+        assert!(edited.ends_with("]\n# The end\n\n[Docked]\nMediaLayerKeys = []\n"));
+        let config: ConfigProxy = toml::from_str(&edited).unwrap();
+        let media = config.media_layer_keys.unwrap();
+        assert_eq!(media[0].text.as_deref(), Some("B"));
+        assert_eq!(media[1].text.as_deref(), Some("A"));
+        // A layer only the shipped file has is copied in, in front of the first table
+        let edited = edit_layer_text(text, shipped, "PrimaryLayerKeys", &LayoutEdit::Remove(0)).unwrap();
+        assert!(edited.contains("# The end\n\nPrimaryLayerKeys = [\n"));
+        assert!(edited.contains("]\n\n[Docked]\nMediaLayerKeys = []\n"));
+        let shipped_primary = toml::from_str::<ConfigProxy>(shipped).unwrap().primary_layer_keys.unwrap();
+        let primary = toml::from_str::<ConfigProxy>(&edited).unwrap().primary_layer_keys.unwrap();
+        assert_eq!(primary.len(), shipped_primary.len() - 1);
+        assert!(edit_layer_text(text, shipped, "MediaLayerKeys", &LayoutEdit::Remove(2)).is_err());
+        // The last button stays
+        let single = "MediaLayerKeys = [{ Text = \"A\", Action = \"F1\" }]\n";
+        assert!(edit_layer_text(single, shipped, "MediaLayerKeys", &LayoutEdit::Remove(0)).is_err());
+    }
+
+    #[test]
+    fn test_only_layout_changed() {
+        let old = "AllowCommandsAsRoot = false\nMediaLayerKeys = [{ Text = \"A\", Action = \"F1\" }]\n";
+        let shipped = include_str!("../share/tiny-dfr/config.toml");
+        let edited = edit_layer_text(old, shipped, "MediaLayerKeys", &LayoutEdit::Add(0, "{ Text = \"B\", Action = \"F2\" }".to_string())).unwrap();
+        assert!(only_layout_changed(old, &edited).is_ok());
+        let edited = edit_layer_text(old, shipped, "PrimaryLayerKeys", &LayoutEdit::Remove(0)).unwrap();
+        assert!(only_layout_changed(old, &edited).is_ok());
+        assert!(only_layout_changed(old, &edited.replace("= false", "= true")).is_err());
+        assert!(only_layout_changed(old, &format!("{}DropPrivileges = false\n", old)).is_err());
+        assert!(only_layout_changed("", "AllowCommandsAsRoot = true\n").is_err());
+        assert!(only_layout_changed(old, "MediaLayerKeys = [").is_err());
+    }
+
+    #[test]
+    fn test_shipped_debug_layer() {
+        let hyprland_expandables: HashMap<String, Vec<HyprlandExpandConfig>> =
+            toml::from_str(include_str!("../share/tiny-dfr/hyprland.toml")).unwrap();
+        let editor = hyprland_expandables["Hyprland_Expand_ActiveWindow"]
+            .iter()
+            .find(|config| config.matches("code", "main.rs - tiny-dfr - Visual Studio Code"))
+            .unwrap();
+        assert!(editor.layer_keys.iter().any(|button| button.action == ButtonAction::HyprlandExpand("Hyprland_Expand_Debug".to_string())));
+        let debug = hyprland_expandables["Hyprland_Expand_Debug"]
+            .iter()
+            .find(|config| config.matches("code-oss", "main.rs - tiny-dfr - Code - OSS"))
+            .unwrap();
+        let actions: Vec<&ButtonAction> = debug.layer_keys.iter().map(|button| &button.action).collect();
+        assert_eq!(actions[0], &ButtonAction::Key(Key::F5));
+        assert_eq!(actions[3], &ButtonAction::KeyCombos(vec![Key::LeftShift, Key::F11]));
+        let layers: Vec<(&str, &[ButtonConfig])> = hyprland_expandables
+            .values()
+            .flatten()
+            .map(|config| ("hyprland.toml", config.layer_keys.as_slice()))
+            .collect();
+        assert!(find_unresolved_references(&layers, &HashMap::new(), &hyprland_expandables, &HashMap::new(), &[]).is_empty());
+    }
+
+    #[test]
+    fn test_shipped_terminal_layer() {
+        let hyprland_expandables: HashMap<String, Vec<HyprlandExpandConfig>> =
+            toml::from_str(include_str!("../share/tiny-dfr/hyprland.toml")).unwrap();
+        let terminal = hyprland_expandables["Hyprland_Expand_Terminal"]
+            .iter()
+            .find(|config| config.matches("Alacritty", "~/src/tiny-dfr"))
+            .unwrap();
+        let split = terminal.layer_keys.iter().find(|button| button.text.as_deref() == Some("tmux Split |")).unwrap();
+        assert_eq!(split.action, ButtonAction::KeySequence(vec![vec![Key::LeftCtrl, Key::B], vec![Key::LeftShift, Key::Num5]]));
+        // Sequences with a key that isn't known are left to be reported as a missing command
+        let typo: ButtonConfig = toml::from_str("Action = \"KeySequence_CTRL_B_THEN_NOPE\"").unwrap();
+        assert!(matches!(typo.action, ButtonAction::Command(_)));
+    }
+
+    #[test]
+    fn test_custom_layers() {
+        let proxy: ConfigProxy = toml::from_str(
+            "Layers = [{ Name = \"Dev\", Keys = [{ Text = \"Main\", Action = \"LayerSwitch_PrimaryLayer\" }] }]",
+        )
+        .unwrap();
+        let layer = &proxy.layers.unwrap()[0];
+        assert_eq!(layer.keys[0].action, ButtonAction::LayerSwitch("PrimaryLayer".to_string()));
+        let custom_layers = vec![("Dev".to_string(), LayerActivation::Toggle), ("Web".to_string(), LayerActivation::App("firefox".to_string()))];
+        assert_eq!(layer_target("Web", false, &custom_layers, &HashMap::new()), Some(WorkspaceTarget::Layer(3)));
+        assert_eq!(layer_target("MediaLayer", true, &custom_layers, &HashMap::new()), Some(WorkspaceTarget::Layer(0)));
+        assert_eq!(layer_target("Docs", false, &custom_layers, &HashMap::new()), None);
+        let buttons = [ButtonConfig::new(ButtonAction::LayerSwitch("Next".to_string())), ButtonConfig::new(ButtonAction::LayerSwitch("Docs".to_string()))];
+        let unresolved = find_unresolved_references(&[("Dev", &buttons)], &HashMap::new(), &HashMap::new(), &HashMap::new(), &["Next", "Dev"]);
+        assert_eq!(unresolved, vec!["LayerSwitch_Docs in Dev".to_string()]);
+    }
+}
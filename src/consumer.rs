@@ -0,0 +1,68 @@
+use input_linux::{EventKind, Key, UInputHandle};
+use std::fs::{File, OpenOptions};
+use std::sync::{Arc, Mutex};
+
+// Keys a consumer control device sends rather than a keyboard
+const MEDIA_KEYS: [Key; 20] = [
+    Key::Mute, Key::VolumeDown, Key::VolumeUp, Key::MicMute,
+    Key::PlayPause, Key::PlayCD, Key::PauseCD, Key::StopCD,
+    Key::NextSong, Key::PreviousSong, Key::FastForward, Key::Rewind,
+    Key::BrightnessDown, Key::BrightnessUp, Key::IllumToggle, Key::IllumDown,
+    Key::IllumUp, Key::Scale, Key::Search, Key::Media,
+];
+
+struct Consumer {
+    enabled: bool,
+    device: Option<UInputHandle<File>>,
+}
+
+// Made on the first media key while SeparateMediaDevice is on
+static CONSUMER: std::sync::LazyLock<Arc<Mutex<Consumer>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(Consumer { enabled: false, device: None })));
+
+fn create_consumer_control() -> UInputHandle<File> {
+    let uinput = UInputHandle::new(OpenOptions::new().write(true).open("/dev/uinput").unwrap());
+    uinput.set_evbit(EventKind::Key).unwrap();
+    for key in MEDIA_KEYS {
+        uinput.set_keybit(key).unwrap();
+    }
+    // The name is what remappers and udev rules tell consumer control devices apart by
+    crate::finish_uinput(uinput, "Dynamic Function Row Virtual Consumer Control")
+}
+
+// Public API
+
+pub fn is_media_key(key: Key) -> bool {
+    MEDIA_KEYS.contains(&key)
+}
+
+pub fn set_enabled(enabled: bool) {
+    let Ok(mut consumer) = CONSUMER.lock() else {
+        return;
+    };
+    consumer.enabled = enabled;
+    if enabled {
+        return;
+    }
+    if let Some(device) = consumer.device.take() {
+        if let Err(e) = device.dev_destroy() {
+            eprintln!("Failed to remove the virtual consumer control device: {}", e);
+        }
+    }
+}
+
+// Sends a media key on the consumer control device, false when it goes to the keyboard
+pub fn toggle(key: Key, value: i32) -> bool {
+    if !is_media_key(key) {
+        return false;
+    }
+    let Ok(mut consumer) = CONSUMER.lock() else {
+        return false;
+    };
+    if !consumer.enabled {
+        return false;
+    }
+    let device = consumer.device.get_or_insert_with(create_consumer_control);
+    crate::toggle_key(device, key, value);
+    true
+}
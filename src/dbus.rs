@@ -0,0 +1,1074 @@
+use crate::config::{self, LayoutEdit};
+use crate::media_state;
+use crate::user_cache;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Just enough of the D-Bus wire protocol to own a name on the system bus, answer
+// method calls and send and receive signals, there is no D-Bus library to lean on
+const SYSTEM_BUS_SOCKET: &str = "/run/dbus/system_bus_socket";
+const BUS_NAME: &str = "org.tinydfr.Control";
+// The longest message the D-Bus specification allows
+const MAX_MESSAGE_LEN: usize = 128 * 1024 * 1024;
+const OBJECT_PATH: &str = "/org/tinydfr/Control";
+const INTERFACE: &str = "org.tinydfr.Control";
+// How long to wait before connecting again after the bus went away
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+// Connections told about button presses at once, a bar has one desktop and a few tools on it
+const MAX_SUBSCRIBERS: usize = 16;
+// Calls waiting for the bus to say who made them, or for polkit to allow them
+const MAX_PENDING_CALLS: usize = 64;
+// Asked of polkit before a caller other than root changes the layout
+const EDIT_LAYOUT_ACTION: &str = "org.tinydfr.Control.edit-layout";
+// Lets polkit ask for a password on behalf of the caller
+const ALLOW_USER_INTERACTION: u32 = 0x1;
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const SIGNAL: u8 = 4;
+const NO_REPLY_EXPECTED: u8 = 0x1;
+
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.tinydfr.Control">
+    <method name="SetLayer"><arg name="name" type="s" direction="in"/></method>
+    <method name="ShowMessage"><arg name="text" type="s" direction="in"/><arg name="seconds" type="u" direction="in"/></method>
+    <method name="SetButtonText"><arg name="index" type="u" direction="in"/><arg name="text" type="s" direction="in"/></method>
+    <method name="ReloadConfig"/>
+    <method name="MoveButton"><arg name="from" type="u" direction="in"/><arg name="to" type="u" direction="in"/><arg name="persist" type="b" direction="in"/></method>
+    <method name="AddButton"><arg name="index" type="u" direction="in"/><arg name="button" type="s" direction="in"/><arg name="persist" type="b" direction="in"/></method>
+    <method name="RemoveButton"><arg name="index" type="u" direction="in"/><arg name="persist" type="b" direction="in"/></method>
+    <method name="Subscribe"/>
+    <method name="GetButtons"><arg name="buttons" type="a(ss)" direction="out"/></method>
+    <signal name="ButtonPressed"><arg name="action" type="s"/></signal>
+    <signal name="ButtonTouched"><arg name="index" type="u"/><arg name="label" type="s"/></signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect"><arg name="xml" type="s" direction="out"/></method>
+  </interface>
+</node>
+"#;
+
+// What the main loop is asked to do, in the order the calls came in
+#[derive(Debug, Clone)]
+pub enum ControlCommand {
+    SetLayer(String), // PrimaryLayer, MediaLayer, an expandable or Back
+    ShowMessage(String, u32),
+    SetButtonText(u32, String), // index into the buttons shown
+    ReloadConfig,
+    EditLayout(LayoutEdit, bool), // on the main layer shown, and whether to save it to the config file
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    U32(u32),
+    Pairs(Vec<(String, String)>),
+    Strings(Vec<String>),
+    // The a{ss} of details polkit is given
+    Dict(Vec<(String, String)>),
+    // A polkit subject, its kind and string details
+    Subject(String, Vec<(String, String)>),
+    // An array of anything else, read past without looking inside, never written
+    Skipped,
+}
+
+impl Value {
+    fn signature(&self) -> &'static str {
+        match self {
+            Value::Str(_) => "s",
+            Value::U32(_) => "u",
+            Value::Pairs(_) => "a(ss)",
+            Value::Strings(_) => "as",
+            Value::Dict(_) => "a{ss}",
+            Value::Subject(..) => "(sa{sv})",
+            Value::Skipped => "",
+        }
+    }
+}
+
+// Header field values are strings, object paths, signatures or numbers
+enum Field<'a> {
+    Path(&'a str),
+    Str(&'a str),
+    Signature(&'a str),
+    U32(u32),
+}
+
+static COMMANDS: std::sync::LazyLock<Arc<Mutex<Vec<ControlCommand>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(Vec::new())));
+// Write half of the bus connection, for signals sent from the main thread
+static CONNECTION: std::sync::LazyLock<Arc<Mutex<Option<UnixStream>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
+static SERIAL: AtomicU32 = AtomicU32::new(1);
+// Unique names of the connections that called Subscribe, signals are sent to each of them
+static SUBSCRIBERS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+// What each button of the layer shown does and says, as last drawn
+static BUTTONS: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+static CONTROL_SERVICE: std::sync::LazyLock<thread::JoinHandle<()>> =
+    std::sync::LazyLock::new(|| thread::spawn(service_loop));
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn new() -> Writer {
+        Writer { buf: Vec::new() }
+    }
+
+    fn align(&mut self, alignment: usize) {
+        while !self.buf.len().is_multiple_of(alignment) {
+            self.buf.push(0);
+        }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn signature(&mut self, value: &str) {
+        self.u8(value.len() as u8);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    // An array of string pairs, the second one wrapped in a variant when asked to
+    fn pairs(&mut self, pairs: &[(String, String)], variants: bool) {
+        // The length counts from the first struct, after its padding
+        self.u32(0);
+        let len_at = self.buf.len() - 4;
+        self.align(8);
+        let start = self.buf.len();
+        for (first, second) in pairs {
+            self.align(8);
+            self.string(first);
+            if variants {
+                self.signature("s");
+            }
+            self.string(second);
+        }
+        let len = (self.buf.len() - start) as u32;
+        self.buf[len_at..len_at + 4].copy_from_slice(&len.to_le_bytes());
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Str(s) => self.string(s),
+            Value::U32(n) => self.u32(*n),
+            // Structs and dict entries are laid out alike
+            Value::Pairs(pairs) | Value::Dict(pairs) => self.pairs(pairs, false),
+            Value::Subject(kind, details) => {
+                self.align(8);
+                self.string(kind);
+                self.pairs(details, true);
+            }
+            Value::Strings(strings) => {
+                self.u32(0);
+                let start = self.buf.len();
+                strings.iter().for_each(|string| self.string(string));
+                let len = (self.buf.len() - start) as u32;
+                self.buf[start - 4..start].copy_from_slice(&len.to_le_bytes());
+            }
+            Value::Skipped => {}
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn align(&mut self, alignment: usize) {
+        self.pos = self.pos.next_multiple_of(alignment);
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        let value = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(value)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.align(4);
+        let bytes = self.data.get(self.pos..self.pos + 4)?;
+        self.pos += 4;
+        Some(u32::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<String> {
+        let bytes = self.data.get(self.pos..self.pos + len)?;
+        // Skips the terminating nul too
+        self.pos += len + 1;
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    fn signature(&mut self) -> Option<String> {
+        let len = self.u8()? as usize;
+        self.bytes(len)
+    }
+
+    fn pairs(&mut self) -> Option<Vec<(String, String)>> {
+        let len = self.u32()? as usize;
+        self.align(8);
+        let end = self.pos + len;
+        let mut pairs = Vec::new();
+        while self.pos < end {
+            self.align(8);
+            pairs.push((self.string()?, self.string()?));
+        }
+        Some(pairs)
+    }
+
+    fn strings(&mut self) -> Option<Vec<String>> {
+        let len = self.u32()? as usize;
+        let end = self.pos + len;
+        let mut strings = Vec::new();
+        while self.pos < end {
+            strings.push(self.string()?);
+        }
+        Some(strings)
+    }
+
+    // Arrays say how long they are, so one of anything can be stepped over as a whole
+    fn skip_array(&mut self, element: &str) -> Option<()> {
+        let len = self.u32()? as usize;
+        let alignment = match element.as_bytes().first() {
+            Some(b'(' | b'{' | b'x' | b't' | b'd') => 8,
+            Some(b'y' | b'g' | b'v') => 1,
+            Some(b'n' | b'q') => 2,
+            _ => 4,
+        };
+        self.align(alignment);
+        self.pos += len;
+        (self.pos <= self.data.len()).then_some(())
+    }
+
+    // One complete type, a basic one, the array of string pairs GetButtons answers with or
+    // an array of strings. Other arrays are skipped over so what follows them can be read.
+    fn value(&mut self, signature: &str) -> Option<Value> {
+        match signature {
+            // polkit's answer, only whether the caller is authorized is looked at
+            "(bba{ss})" => {
+                self.align(8);
+                self.u32().map(Value::U32)
+            }
+            "g" => self.signature().map(Value::Str),
+            "s" | "o" => self.string().map(Value::Str),
+            "u" | "i" | "b" => self.u32().map(Value::U32),
+            "a(ss)" => self.pairs().map(Value::Pairs),
+            "as" => self.strings().map(Value::Strings),
+            _ => self.skip_array(signature.strip_prefix('a')?).map(|_| Value::Skipped),
+        }
+    }
+}
+
+// Length of the complete type the signature starts with, arrays and structs included
+fn complete_type_len(signature: &[u8]) -> Option<usize> {
+    match signature.first()? {
+        b'a' => complete_type_len(&signature[1..]).map(|len| len + 1),
+        b'(' | b'{' => {
+            let mut depth = 0;
+            for (i, c) in signature.iter().enumerate() {
+                match c {
+                    b'(' | b'{' => depth += 1,
+                    b')' | b'}' => depth -= 1,
+                    _ => {}
+                }
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            None
+        }
+        _ => Some(1),
+    }
+}
+
+// Splits a signature into its complete types, stopping at anything that doesn't add up
+fn complete_types(signature: &str) -> Vec<&str> {
+    let mut types = Vec::new();
+    let mut rest = signature;
+    while rest.is_ascii() {
+        let Some(len) = complete_type_len(rest.as_bytes()) else {
+            break;
+        };
+        types.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+    types
+}
+
+// What a call waits for before it's answered
+enum Pending {
+    Caller(Message),
+    Authorization(Message),
+}
+
+struct Message {
+    kind: u8,
+    flags: u8,
+    serial: u32,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    reply_serial: Option<u32>,
+    sender: Option<String>,
+    body: Vec<Value>,
+}
+
+fn encode(kind: u8, serial: u32, fields: &[(u8, Field)], body: &[Value]) -> Vec<u8> {
+    let mut body_writer = Writer::new();
+    body.iter().for_each(|value| body_writer.value(value));
+    let signature: String = body.iter().map(Value::signature).collect();
+
+    let mut w = Writer::new();
+    w.u8(b'l');
+    w.u8(kind);
+    w.u8(0);
+    w.u8(1);
+    w.u32(body_writer.buf.len() as u32);
+    w.u32(serial);
+    w.u32(0);
+    let fields_start = w.buf.len();
+    let signature_field = (!signature.is_empty()).then(|| (FIELD_SIGNATURE, Field::Signature(&signature)));
+    let all_fields = fields.iter().map(|(code, field)| (*code, field)).chain(signature_field.as_ref().map(|(code, field)| (*code, field)));
+    for (code, field) in all_fields {
+        w.align(8);
+        w.u8(code);
+        match field {
+            Field::Path(path) => {
+                w.signature("o");
+                w.string(path);
+            }
+            Field::Str(s) => {
+                w.signature("s");
+                w.string(s);
+            }
+            Field::Signature(s) => {
+                w.signature("g");
+                w.signature(s);
+            }
+            Field::U32(n) => {
+                w.signature("u");
+                w.u32(*n);
+            }
+        }
+    }
+    let fields_len = (w.buf.len() - fields_start) as u32;
+    w.buf[fields_start - 4..fields_start].copy_from_slice(&fields_len.to_le_bytes());
+    w.align(8);
+    w.buf.extend_from_slice(&body_writer.buf);
+    w.buf
+}
+
+fn read_message(stream: &mut impl Read) -> std::io::Result<Option<Message>> {
+    let mut fixed = [0u8; 16];
+    stream.read_exact(&mut fixed)?;
+    // Only little endian messages are expected from a bus on the same machine
+    if fixed[0] != b'l' {
+        return Err(std::io::Error::other("big endian message"));
+    }
+    let body_len = u32::from_le_bytes(fixed[4..8].try_into().unwrap()) as usize;
+    let fields_len = u32::from_le_bytes(fixed[12..16].try_into().unwrap()) as usize;
+    let header_len = (16 + fields_len).next_multiple_of(8);
+    // Nothing bigger may be sent over the bus, a bogus length isn't allocated
+    if header_len + body_len > MAX_MESSAGE_LEN {
+        return Err(std::io::Error::other("message too long"));
+    }
+    let mut data = fixed.to_vec();
+    data.resize(header_len + body_len, 0);
+    stream.read_exact(&mut data[16..])?;
+
+    let mut message = Message {
+        kind: fixed[1],
+        flags: fixed[2],
+        serial: u32::from_le_bytes(fixed[8..12].try_into().unwrap()),
+        path: None,
+        interface: None,
+        member: None,
+        error_name: None,
+        reply_serial: None,
+        sender: None,
+        body: Vec::new(),
+    };
+    let mut signature = String::new();
+    let mut r = Reader { data: &data, pos: 16 };
+    while r.pos < 16 + fields_len {
+        r.align(8);
+        let Some(code) = r.u8() else {
+            break;
+        };
+        let Some(value) = r.signature().and_then(|sig| r.value(&sig)) else {
+            return Ok(None);
+        };
+        match (code, value) {
+            (FIELD_PATH, Value::Str(s)) => message.path = Some(s),
+            (FIELD_INTERFACE, Value::Str(s)) => message.interface = Some(s),
+            (FIELD_MEMBER, Value::Str(s)) => message.member = Some(s),
+            (FIELD_ERROR_NAME, Value::Str(s)) => message.error_name = Some(s),
+            (FIELD_REPLY_SERIAL, Value::U32(n)) => message.reply_serial = Some(n),
+            (FIELD_SENDER, Value::Str(s)) => message.sender = Some(s),
+            (FIELD_SIGNATURE, Value::Str(s)) => signature = s,
+            _ => {}
+        }
+    }
+    let mut r = Reader { data: &data, pos: header_len };
+    for typ in complete_types(&signature) {
+        match r.value(typ) {
+            Some(value) => message.body.push(value),
+            // Nothing we answer takes anything else
+            None => return Ok(Some(message)),
+        }
+    }
+    Ok(Some(message))
+}
+
+fn next_serial() -> u32 {
+    SERIAL.fetch_add(1, Ordering::Relaxed)
+}
+
+// Writes go through the shared connection so signals from the main thread don't interleave with replies
+fn send(message: &[u8]) -> std::io::Result<()> {
+    let Ok(mut connection) = CONNECTION.lock() else {
+        return Ok(());
+    };
+    match connection.as_mut() {
+        Some(stream) => stream.write_all(message),
+        None => Err(std::io::Error::from(std::io::ErrorKind::NotConnected)),
+    }
+}
+
+// A method call to the bus itself
+fn bus_method(serial: u32, member: &str, body: &[Value]) -> Vec<u8> {
+    bus_interface_method(serial, "org.freedesktop.DBus", member, body)
+}
+
+fn bus_interface_method(serial: u32, interface: &str, member: &str, body: &[Value]) -> Vec<u8> {
+    encode(
+        METHOD_CALL,
+        serial,
+        &[
+            (FIELD_PATH, Field::Path("/org/freedesktop/DBus")),
+            (FIELD_INTERFACE, Field::Str(interface)),
+            (FIELD_MEMBER, Field::Str(member)),
+            (FIELD_DESTINATION, Field::Str("org.freedesktop.DBus")),
+        ],
+        body,
+    )
+}
+
+fn call(member: &str, body: &[Value]) -> std::io::Result<u32> {
+    let serial = next_serial();
+    send(&bus_method(serial, member, body))?;
+    Ok(serial)
+}
+
+fn reply(to: &Message, body: &[Value]) -> std::io::Result<()> {
+    if to.flags & NO_REPLY_EXPECTED != 0 {
+        return Ok(());
+    }
+    let mut fields = vec![(FIELD_REPLY_SERIAL, Field::U32(to.serial))];
+    if let Some(sender) = &to.sender {
+        fields.push((FIELD_DESTINATION, Field::Str(sender)));
+    }
+    send(&encode(METHOD_RETURN, next_serial(), &fields, body))
+}
+
+fn reply_error(to: &Message, name: &str, text: &str) -> std::io::Result<()> {
+    if to.flags & NO_REPLY_EXPECTED != 0 {
+        return Ok(());
+    }
+    let mut fields = vec![
+        (FIELD_ERROR_NAME, Field::Str(name)),
+        (FIELD_REPLY_SERIAL, Field::U32(to.serial)),
+    ];
+    if let Some(sender) = &to.sender {
+        fields.push((FIELD_DESTINATION, Field::Str(sender)));
+    }
+    send(&encode(ERROR, next_serial(), &fields, &[Value::Str(text.to_string())]))
+}
+
+// The main loop picks commands up once it's woken
+fn queue(command: ControlCommand) {
+    if let Ok(mut commands) = COMMANDS.lock() {
+        commands.push(command);
+    }
+    media_state::wake();
+}
+
+// Root, or the user whose desktop the bar belongs to, and nobody else
+fn caller_allowed(uid: u32, session_uid: Option<u32>) -> bool {
+    uid == 0 || session_uid == Some(uid)
+}
+
+// Introspection and pings tell nothing about the bar and change nothing on it
+fn needs_caller_check(message: &Message) -> bool {
+    !matches!(
+        (message.interface.as_deref(), message.member.as_deref()),
+        (Some("org.freedesktop.DBus.Introspectable") | None, Some("Introspect"))
+            | (Some("org.freedesktop.DBus.Peer") | None, Some("Ping"))
+    )
+}
+
+// Layout edits can end up in /etc/tiny-dfr/config.toml, the session's user needs polkit's word for them
+fn needs_authorization(message: &Message) -> bool {
+    matches!(message.interface.as_deref(), Some(INTERFACE) | None)
+        && matches!(message.member.as_deref(), Some("MoveButton" | "AddButton" | "RemoveButton"))
+}
+
+// Asks polkit whether the caller may edit the layout, the answer comes with the serial returned
+fn check_authorization(sender: &str) -> std::io::Result<u32> {
+    let serial = next_serial();
+    let subject = Value::Subject("system-bus-name".to_string(), vec![("name".to_string(), sender.to_string())]);
+    let body = [
+        subject,
+        Value::Str(EDIT_LAYOUT_ACTION.to_string()),
+        Value::Dict(Vec::new()),
+        Value::U32(ALLOW_USER_INTERACTION),
+        Value::Str(String::new()),
+    ];
+    let fields = [
+        (FIELD_PATH, Field::Path("/org/freedesktop/PolicyKit1/Authority")),
+        (FIELD_INTERFACE, Field::Str("org.freedesktop.PolicyKit1.Authority")),
+        (FIELD_MEMBER, Field::Str("CheckAuthorization")),
+        (FIELD_DESTINATION, Field::Str("org.freedesktop.PolicyKit1")),
+    ];
+    send(&encode(METHOD_CALL, serial, &fields, &body))?;
+    Ok(serial)
+}
+
+// Answers a call once the bus told who made it, as a uid or an error. Calls that need polkit's
+// word too are left waiting on the serial returned.
+fn handle_checked_call(message: &Message, caller: &Message) -> std::io::Result<Option<u32>> {
+    let session_uid = user_cache::get_cached_user_environment().map(|env| env.uid);
+    match (caller.kind, caller.body.first(), message.sender.as_deref()) {
+        (METHOD_RETURN, Some(Value::U32(uid)), Some(sender))
+            if *uid != 0 && caller_allowed(*uid, session_uid) && needs_authorization(message) =>
+        {
+            check_authorization(sender).map(Some)
+        }
+        (METHOD_RETURN, Some(Value::U32(uid)), _) if caller_allowed(*uid, session_uid) => handle_call(message).map(|_| None),
+        (METHOD_RETURN, Some(Value::U32(uid)), _) => {
+            eprintln!("Refused D-Bus call to {} from uid {}", message.member.as_deref().unwrap_or_default(), uid);
+            reply_error(message, "org.freedesktop.DBus.Error.AccessDenied", "Only the desktop session's user may do that").map(|_| None)
+        }
+        _ => reply_error(message, "org.freedesktop.DBus.Error.AccessDenied", "The caller couldn't be identified").map(|_| None),
+    }
+}
+
+// Answers a layout edit once polkit said whether the caller may make it
+fn handle_authorized_call(message: &Message, authorization: &Message) -> std::io::Result<()> {
+    match (authorization.kind, authorization.body.first()) {
+        (METHOD_RETURN, Some(Value::U32(1))) => handle_call(message),
+        (METHOD_RETURN, _) => reply_error(message, "org.freedesktop.DBus.Error.AccessDenied", "Not authorized to edit the layout"),
+        _ => {
+            eprintln!("Failed to ask polkit about a layout edit: {}", authorization.error_name.as_deref().unwrap_or_default());
+            reply_error(message, "org.freedesktop.DBus.Error.AccessDenied", "polkit couldn't be asked")
+        }
+    }
+}
+
+fn handle_call(message: &Message) -> std::io::Result<()> {
+    let interface = message.interface.as_deref();
+    let member = message.member.as_deref().unwrap_or_default();
+    if message.path.as_deref() != Some(OBJECT_PATH) {
+        return reply_error(message, "org.freedesktop.DBus.Error.UnknownObject", "No such object");
+    }
+    let command = match (interface, member, message.body.as_slice()) {
+        (Some("org.freedesktop.DBus.Introspectable") | None, "Introspect", []) => {
+            return reply(message, &[Value::Str(INTROSPECTION.to_string())]);
+        }
+        (Some("org.freedesktop.DBus.Peer") | None, "Ping", []) => return reply(message, &[]),
+        (Some(INTERFACE) | None, "SetLayer", [Value::Str(name)]) => ControlCommand::SetLayer(name.clone()),
+        (Some(INTERFACE) | None, "ShowMessage", [Value::Str(text), Value::U32(seconds)]) => {
+            ControlCommand::ShowMessage(text.clone(), *seconds)
+        }
+        (Some(INTERFACE) | None, "SetButtonText", [Value::U32(index), Value::Str(text)]) => {
+            ControlCommand::SetButtonText(*index, text.clone())
+        }
+        (Some(INTERFACE) | None, "ReloadConfig", []) => ControlCommand::ReloadConfig,
+        (Some(INTERFACE) | None, "MoveButton", [Value::U32(from), Value::U32(to), Value::U32(persist)]) => {
+            ControlCommand::EditLayout(LayoutEdit::Move(*from as usize, *to as usize), *persist != 0)
+        }
+        (Some(INTERFACE) | None, "AddButton", [Value::U32(index), Value::Str(button), Value::U32(persist)]) => {
+            // Mistakes in the button are the caller's to hear about
+            if let Err(e) = config::parse_button(button) {
+                return reply_error(message, "org.freedesktop.DBus.Error.InvalidArgs", &format!("Invalid button: {}", e));
+            }
+            ControlCommand::EditLayout(LayoutEdit::Add(*index as usize, button.clone()), *persist != 0)
+        }
+        (Some(INTERFACE) | None, "RemoveButton", [Value::U32(index), Value::U32(persist)]) => {
+            // The edit is made after the reply, so the layer shown is checked here to tell the caller
+            if BUTTONS.lock().is_ok_and(|buttons| buttons.len() <= 1) {
+                return reply_error(message, "org.freedesktop.DBus.Error.InvalidArgs", "The layer's last button can't be removed");
+            }
+            ControlCommand::EditLayout(LayoutEdit::Remove(*index as usize), *persist != 0)
+        }
+        (Some(INTERFACE) | None, "GetButtons", []) => {
+            let buttons = BUTTONS.lock().map(|buttons| buttons.clone()).unwrap_or_default();
+            return reply(message, &[Value::Pairs(buttons)]);
+        }
+        (Some(INTERFACE) | None, "Subscribe", []) => {
+            return match message.sender.as_deref() {
+                Some(sender) if subscribe(sender)? => reply(message, &[]),
+                _ => reply_error(message, "org.freedesktop.DBus.Error.LimitsExceeded", "Too many subscribers"),
+            };
+        }
+        (
+            Some(INTERFACE) | None,
+            "SetLayer" | "ShowMessage" | "SetButtonText" | "ReloadConfig" | "MoveButton" | "AddButton" | "RemoveButton"
+            | "Subscribe" | "GetButtons",
+            _,
+        ) => {
+            return reply_error(message, "org.freedesktop.DBus.Error.InvalidArgs", "Wrong arguments");
+        }
+        _ => {
+            return reply_error(message, "org.freedesktop.DBus.Error.UnknownMethod", &format!("No method {}", member));
+        }
+    };
+    queue(command);
+    reply(message, &[])
+}
+
+// Signals go to the connections asking for them only, the bus says when one of them goes away
+fn subscribe(sender: &str) -> std::io::Result<bool> {
+    let Ok(mut subscribers) = SUBSCRIBERS.lock() else {
+        return Ok(false);
+    };
+    if subscribers.iter().any(|subscriber| subscriber == sender) {
+        return Ok(true);
+    }
+    if subscribers.len() >= MAX_SUBSCRIBERS {
+        return Ok(false);
+    }
+    subscribers.push(sender.to_string());
+    drop(subscribers);
+    let rule = format!(
+        "type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus',member='NameOwnerChanged',arg0='{}'",
+        sender
+    );
+    call("AddMatch", &[Value::Str(rule)])?;
+    Ok(true)
+}
+
+// NameOwnerChanged with no new owner for a unique name means the connection is gone
+fn forget_subscriber(message: &Message) {
+    if let [Value::Str(name), _, Value::Str(new_owner)] = message.body.as_slice() {
+        if new_owner.is_empty() {
+            if let Ok(mut subscribers) = SUBSCRIBERS.lock() {
+                subscribers.retain(|subscriber| subscriber != name);
+            }
+        }
+    }
+}
+
+// SASL EXTERNAL, the bus knows who connected the socket and that has to be the uid given
+fn authenticate(stream: &mut UnixStream, uid: u32) -> std::io::Result<()> {
+    let uid = uid.to_string();
+    let hex_uid: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+    stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex_uid).as_bytes())?;
+    // Read byte by byte, the binary protocol follows right after
+    let mut line = Vec::new();
+    let mut byte = [0u8];
+    while !line.ends_with(b"\r\n") {
+        stream.read_exact(&mut byte)?;
+        line.push(byte[0]);
+    }
+    if !line.starts_with(b"OK ") {
+        return Err(std::io::Error::other(format!("authentication refused: {}", String::from_utf8_lossy(&line).trim())));
+    }
+    stream.write_all(b"BEGIN\r\n")
+}
+
+// Returns once the name can't be had, errors when the connection breaks
+fn serve() -> std::io::Result<()> {
+    let mut stream = UnixStream::connect(SYSTEM_BUS_SOCKET)?;
+    authenticate(&mut stream, unsafe { libc::geteuid() })?;
+    if let Ok(mut connection) = CONNECTION.lock() {
+        *connection = Some(stream.try_clone()?);
+    }
+    call("Hello", &[])?;
+    // Don't queue up behind another instance
+    let request_serial = call("RequestName", &[Value::Str(BUS_NAME.to_string()), Value::U32(4)])?;
+    // Calls by the serial of the GetConnectionUnixUser asking who made them, or of the
+    // CheckAuthorization asking polkit whether they may be made
+    let mut pending: HashMap<u32, Pending> = HashMap::new();
+    loop {
+        let Some(message) = read_message(&mut stream)? else {
+            continue;
+        };
+        match message.reply_serial.and_then(|serial| pending.remove(&serial)) {
+            Some(Pending::Caller(call)) => {
+                if let Some(serial) = handle_checked_call(&call, &message)? {
+                    pending.insert(serial, Pending::Authorization(call));
+                }
+                continue;
+            }
+            Some(Pending::Authorization(call)) => {
+                handle_authorized_call(&call, &message)?;
+                continue;
+            }
+            None => {}
+        }
+        match message.kind {
+            METHOD_CALL if !needs_caller_check(&message) => handle_call(&message)?,
+            METHOD_CALL => match message.sender.clone() {
+                Some(sender) if pending.len() < MAX_PENDING_CALLS => {
+                    let serial = call("GetConnectionUnixUser", &[Value::Str(sender)])?;
+                    pending.insert(serial, Pending::Caller(message));
+                }
+                Some(_) => reply_error(&message, "org.freedesktop.DBus.Error.LimitsExceeded", "Too many calls at once")?,
+                None => reply_error(&message, "org.freedesktop.DBus.Error.AccessDenied", "The caller couldn't be identified")?,
+            },
+            SIGNAL if message.member.as_deref() == Some("NameOwnerChanged") => forget_subscriber(&message),
+            METHOD_RETURN if message.reply_serial == Some(request_serial) => {
+                // 1 is the primary owner, anything else means someone else has it
+                if !matches!(message.body.first(), Some(Value::U32(1))) {
+                    eprintln!("{} is owned by another process, not providing the D-Bus interface", BUS_NAME);
+                    return Ok(());
+                }
+                println!("Providing {} on the system bus", BUS_NAME);
+            }
+            ERROR if message.reply_serial == Some(request_serial) => {
+                eprintln!(
+                    "Not allowed to own {} on the system bus ({}), is its D-Bus policy installed?",
+                    BUS_NAME,
+                    message.error_name.unwrap_or_default()
+                );
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+}
+
+fn service_loop() {
+    loop {
+        match serve() {
+            Ok(()) => return,
+            Err(e) => eprintln!("D-Bus connection failed: {}", e),
+        }
+        if let Ok(mut connection) = CONNECTION.lock() {
+            *connection = None;
+        }
+        // Names from the old connection mean nothing on the next one
+        if let Ok(mut subscribers) = SUBSCRIBERS.lock() {
+            subscribers.clear();
+        }
+        thread::sleep(RECONNECT_DELAY);
+    }
+}
+
+// Public API
+
+pub fn initialize_control_service() {
+    std::sync::LazyLock::force(&CONTROL_SERVICE);
+}
+
+pub fn take_commands() -> Vec<ControlCommand> {
+    COMMANDS.lock().map(|mut commands| std::mem::take(&mut *commands)).unwrap_or_default()
+}
+
+// Signals go to each subscriber rather than to everyone on the bus
+fn signal_subscribers(member: &str, body: &[Value]) {
+    let subscribers = SUBSCRIBERS.lock().map(|subscribers| subscribers.clone()).unwrap_or_default();
+    for subscriber in subscribers {
+        let message = encode(
+            SIGNAL,
+            next_serial(),
+            &[
+                (FIELD_PATH, Field::Path(OBJECT_PATH)),
+                (FIELD_INTERFACE, Field::Str(INTERFACE)),
+                (FIELD_MEMBER, Field::Str(member)),
+                (FIELD_DESTINATION, Field::Str(&subscriber)),
+            ],
+            body,
+        );
+        match send(&message) {
+            // Nobody to tell before the bus is connected
+            Err(e) if e.kind() != std::io::ErrorKind::NotConnected => {
+                eprintln!("Failed to send the {} signal: {}", member, e);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Sends the ButtonPressed signal with what the button does, e.g. "VolumeUp" or "Command_Lock"
+pub fn button_pressed(action: &str) {
+    signal_subscribers("ButtonPressed", &[Value::Str(action.to_string())]);
+}
+
+// Sends ButtonTouched as soon as a finger lands on a button, before it does anything, so
+// screen readers can say what's under the finger
+pub fn button_touched(index: usize, label: &str) {
+    signal_subscribers("ButtonTouched", &[Value::U32(index as u32), Value::Str(label.to_string())]);
+}
+
+// What GetButtons answers with, the action and the spoken label of each button shown
+pub fn set_buttons(buttons: Vec<(String, String)>) {
+    if let Ok(mut shown) = BUTTONS.lock() {
+        *shown = buttons;
+    }
+}
+
+// Calls on_signal for every signal matching the rule, e.g. "type='signal',sender='org.freedesktop.login1'".
+// Uses a connection of its own so it works without the control service, errors once it breaks.
+pub fn watch_signals(rule: &str, mut on_signal: impl FnMut()) -> std::io::Result<()> {
+    let mut stream = UnixStream::connect(SYSTEM_BUS_SOCKET)?;
+    authenticate(&mut stream, unsafe { libc::geteuid() })?;
+    stream.write_all(&bus_method(next_serial(), "Hello", &[]))?;
+    stream.write_all(&bus_method(next_serial(), "AddMatch", &[Value::Str(rule.to_string())]))?;
+    loop {
+        let Some(message) = read_message(&mut stream)? else {
+            continue;
+        };
+        // The bus tells us about our own name with signals too
+        if message.kind == SIGNAL && message.sender.as_deref() != Some("org.freedesktop.DBus") {
+            on_signal();
+        }
+    }
+}
+
+// Turns a connection to a bus, e.g. from privileged::connect_session_bus, into a monitor and
+// calls on_call with the member and arguments of every method call matching the rule.
+// Errors once the connection breaks or the bus won't have us monitor it.
+pub fn monitor_calls(mut stream: UnixStream, uid: u32, rule: &str, mut on_call: impl FnMut(&str, &[Value])) -> std::io::Result<()> {
+    authenticate(&mut stream, uid)?;
+    stream.write_all(&bus_method(next_serial(), "Hello", &[]))?;
+    let become_serial = next_serial();
+    let rules = Value::Strings(vec![rule.to_string()]);
+    stream.write_all(&bus_interface_method(become_serial, "org.freedesktop.DBus.Monitoring", "BecomeMonitor", &[rules, Value::U32(0)]))?;
+    loop {
+        let Some(message) = read_message(&mut stream)? else {
+            continue;
+        };
+        match message.kind {
+            METHOD_CALL => on_call(message.member.as_deref().unwrap_or_default(), &message.body),
+            ERROR if message.reply_serial == Some(become_serial) => {
+                return Err(std::io::Error::other(format!(
+                    "the bus refused BecomeMonitor ({})",
+                    message.error_name.unwrap_or_default()
+                )));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reader_writer_round_trip() {
+        let mut w = Writer::new();
+        w.u8(7);
+        w.u32(0xdeadbeef);
+        w.string("héllo");
+        w.signature("su");
+        w.u8(1);
+        w.value(&Value::U32(42));
+        // Values are aligned to their size, strings end with a nul
+        assert_eq!(&w.buf[..8], &[7, 0, 0, 0, 0xef, 0xbe, 0xad, 0xde]);
+        let mut r = Reader { data: &w.buf, pos: 0 };
+        assert_eq!(r.u8(), Some(7));
+        assert_eq!(r.u32(), Some(0xdeadbeef));
+        assert_eq!(r.string().as_deref(), Some("héllo"));
+        assert_eq!(r.signature().as_deref(), Some("su"));
+        assert_eq!(r.u8(), Some(1));
+        assert_eq!(r.value("u"), Some(Value::U32(42)));
+        assert_eq!(r.pos, w.buf.len());
+        // Past the end there's nothing, rather than a panic
+        assert_eq!(r.u32(), None);
+        let mut r = Reader { data: &[5, 0, 0, 0, b'a'], pos: 0 };
+        assert_eq!(r.string(), None);
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let body = [Value::Str("Build done".to_string()), Value::U32(3)];
+        let data = encode(
+            METHOD_CALL,
+            9,
+            &[
+                (FIELD_PATH, Field::Path(OBJECT_PATH)),
+                (FIELD_INTERFACE, Field::Str(INTERFACE)),
+                (FIELD_MEMBER, Field::Str("ShowMessage")),
+                (FIELD_SENDER, Field::Str(":1.42")),
+                (FIELD_REPLY_SERIAL, Field::U32(4)),
+            ],
+            &body,
+        );
+        // The body starts 8-aligned after the header fields
+        let body_len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+        assert_eq!(body_len, 20);
+        assert_eq!((data.len() - body_len) % 8, 0);
+        let message = read_message(&mut &data[..]).unwrap().unwrap();
+        assert_eq!(message.kind, METHOD_CALL);
+        assert_eq!(message.serial, 9);
+        assert_eq!(message.path.as_deref(), Some(OBJECT_PATH));
+        assert_eq!(message.interface.as_deref(), Some(INTERFACE));
+        assert_eq!(message.member.as_deref(), Some("ShowMessage"));
+        assert_eq!(message.sender.as_deref(), Some(":1.42"));
+        assert_eq!(message.reply_serial, Some(4));
+        assert_eq!(message.body, body);
+
+        // A body with no arguments has no signature field
+        let data = encode(METHOD_RETURN, 10, &[(FIELD_REPLY_SERIAL, Field::U32(9))], &[]);
+        let message = read_message(&mut &data[..]).unwrap().unwrap();
+        assert_eq!(message.kind, METHOD_RETURN);
+        assert!(message.body.is_empty());
+    }
+
+    #[test]
+    fn test_pairs_round_trip() {
+        let buttons = vec![
+            ("VolumeUp".to_string(), "Volume 45%".to_string()),
+            ("Command_Lock".to_string(), "Lock".to_string()),
+        ];
+        let body = [Value::U32(1), Value::Pairs(buttons.clone()), Value::Pairs(Vec::new()), Value::Str("end".to_string())];
+        let data = encode(METHOD_RETURN, 3, &[(FIELD_REPLY_SERIAL, Field::U32(2))], &body);
+        let message = read_message(&mut &data[..]).unwrap().unwrap();
+        assert_eq!(message.body, body);
+        assert_eq!(complete_types("ua(ss)s"), vec!["u", "a(ss)", "s"]);
+    }
+
+    #[test]
+    fn test_notify_arguments() {
+        // Notify's arguments, with hints carrying a variant that's only stepped over
+        let mut w = Writer::new();
+        for value in [
+            Value::Str("notify-send".to_string()),
+            Value::U32(0),
+            Value::Str(String::new()),
+            Value::Str("Build done".to_string()),
+            Value::Str("line one\n   int32 5".to_string()),
+            Value::Strings(vec!["default".to_string(), "Open".to_string()]),
+        ] {
+            w.value(&value);
+        }
+        w.u32(0);
+        let len_at = w.buf.len() - 4;
+        w.align(8);
+        let start = w.buf.len();
+        w.string("urgency");
+        w.signature("y");
+        w.u8(1);
+        let len = (w.buf.len() - start) as u32;
+        w.buf[len_at..len_at + 4].copy_from_slice(&len.to_le_bytes());
+        w.u32(-1i32 as u32);
+
+        let types = complete_types("susssasa{sv}i");
+        assert_eq!(types, vec!["s", "u", "s", "s", "s", "as", "a{sv}", "i"]);
+        let mut r = Reader { data: &w.buf, pos: 0 };
+        let values: Vec<Value> = types.iter().map_while(|typ| r.value(typ)).collect();
+        assert_eq!(values.len(), 8);
+        assert_eq!(values[4], Value::Str("line one\n   int32 5".to_string()));
+        assert_eq!(values[5], Value::Strings(vec!["default".to_string(), "Open".to_string()]));
+        assert_eq!(values[6], Value::Skipped);
+        assert_eq!(values[7], Value::U32(-1i32 as u32));
+        assert_eq!(r.pos, w.buf.len());
+
+        // Signatures that don't add up give what could be made out of them
+        assert_eq!(complete_types("sa(su"), vec!["s"]);
+        assert!(complete_types("sé").is_empty());
+        assert!(complete_types("a").is_empty());
+    }
+
+    #[test]
+    fn test_caller_allowed() {
+        assert!(caller_allowed(0, None));
+        assert!(caller_allowed(1000, Some(1000)));
+        assert!(!caller_allowed(1001, Some(1000)));
+        assert!(!caller_allowed(1000, None));
+        let mut message = read_message(&mut &encode(METHOD_CALL, 1, &[(FIELD_MEMBER, Field::Str("Introspect"))], &[])[..])
+            .unwrap()
+            .unwrap();
+        assert!(!needs_caller_check(&message));
+        message.member = Some("SetLayer".to_string());
+        assert!(needs_caller_check(&message));
+        message.interface = Some("org.freedesktop.DBus.Peer".to_string());
+        message.member = Some("Introspect".to_string());
+        assert!(needs_caller_check(&message));
+    }
+
+    #[test]
+    fn test_polkit_marshalling() {
+        let mut w = Writer::new();
+        w.value(&Value::Subject("system-bus-name".to_string(), vec![("name".to_string(), ":1.42".to_string())]));
+        // The kind, then the array of dict entries starting 8-aligned, the name in a variant
+        assert_eq!(&w.buf[..4], &[15, 0, 0, 0]);
+        assert_eq!(&w.buf[20..24], &[22, 0, 0, 0]);
+        assert_eq!(&w.buf[24..33], b"\x04\0\0\0name\0");
+        assert_eq!(&w.buf[33..36], b"\x01s\0");
+        assert_eq!(&w.buf[36..], b"\x05\0\0\0:1.42\0");
+        let mut w = Writer::new();
+        w.value(&Value::Dict(Vec::new()));
+        // Padded to where the first entry would start even when there is none
+        assert_eq!(w.buf, [0; 8]);
+        // Only is_authorized is read from the answer
+        assert_eq!(complete_types("(bba{ss})"), ["(bba{ss})"]);
+        let mut r = Reader { data: &[1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], pos: 0 };
+        assert_eq!(r.value("(bba{ss})"), Some(Value::U32(1)));
+        let mut message = read_message(&mut &encode(METHOD_CALL, 1, &[(FIELD_MEMBER, Field::Str("AddButton"))], &[])[..])
+            .unwrap()
+            .unwrap();
+        assert!(needs_authorization(&message));
+        message.member = Some("SetLayer".to_string());
+        assert!(!needs_authorization(&message));
+    }
+
+    #[test]
+    fn test_malformed_messages() {
+        let data = encode(SIGNAL, 1, &[(FIELD_MEMBER, Field::Str("ButtonPressed"))], &[Value::Str("F1".to_string())]);
+        assert!(read_message(&mut &data[..data.len() - 1]).is_err());
+        let mut big_endian = data.clone();
+        big_endian[0] = b'B';
+        assert!(read_message(&mut &big_endian[..]).is_err());
+        // A header field whose value is cut short is dropped rather than misread
+        let mut broken = data.clone();
+        broken[16 + 1] = 9;
+        assert!(read_message(&mut &broken[..]).unwrap().is_none());
+        let mut huge = data.clone();
+        huge[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_message(&mut &huge[..]).is_err());
+    }
+}
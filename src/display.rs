@@ -9,10 +9,11 @@ use drm::{
     },
     ClientCapability, Device as DrmDevice,
 };
+use crate::config::DisplayConfig;
 use std::{
     fs::{self, File, OpenOptions},
     os::unix::io::{AsFd, BorrowedFd},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 struct Card(File);
@@ -64,7 +65,7 @@ fn find_prop_id<T: ResourceHandle>(
     Err(anyhow!("Property not found"))
 }
 
-fn try_open_card(path: &Path) -> Result<DrmBackend> {
+fn try_open_card(path: &Path, display_cfg: &DisplayConfig) -> Result<DrmBackend> {
     let card = Card::open(path)?;
     card.set_client_capability(ClientCapability::UniversalPlanes, true)?;
     card.set_client_capability(ClientCapability::Atomic, true)?;
@@ -84,19 +85,43 @@ fn try_open_card(path: &Path) -> Result<DrmBackend> {
 
     let con = coninfo
         .iter()
-        .find(|&i| i.state() == connector::State::Connected)
-        .ok_or(anyhow!("No connected connectors found"))?;
+        .filter(|&i| i.state() == connector::State::Connected)
+        .find(|&i| match &display_cfg.connector {
+            Some(name) => i.to_string() == *name,
+            None => true,
+        })
+        .ok_or(match &display_cfg.connector {
+            Some(name) => anyhow!("Connector {} not found or not connected", name),
+            None => anyhow!("No connected connectors found"),
+        })?;
 
-    let &mode = con.modes().first().ok_or(anyhow!("No modes found"))?;
+    let &mode = match display_cfg.mode {
+        Some(size) => con
+            .modes()
+            .iter()
+            .find(|m| m.size() == size || m.size() == (size.1, size.0))
+            .ok_or(anyhow!("Mode {}x{} not supported by {}", size.0, size.1, con))?,
+        None => con.modes().first().ok_or(anyhow!("No modes found"))?,
+    };
     let (disp_width, disp_height) = mode.size();
-    if disp_height / disp_width < 30 {
+    // Without an explicitly selected connector only accept panels shaped like a touchbar
+    let (long_side, short_side) = (disp_width.max(disp_height), disp_width.min(disp_height));
+    if display_cfg.connector.is_none() && long_side / short_side < 30 {
         return Err(anyhow!("This does not look like a touchbar"));
     }
     let crtc = crtcinfo.first().ok_or(anyhow!("No crtcs found"))?;
-    let fmt = DrmFourcc::Xrgb8888;
-    let db = card.create_dumb_buffer((64, disp_height.into()), fmt, 32)?;
+    let fmt = display_cfg.format;
+    let (depth, bpp) = match fmt {
+        DrmFourcc::Rgb565 => (16, 16),
+        _ => (24, 32),
+    };
+    let db = card.create_dumb_buffer(
+        ((disp_width as u32).next_multiple_of(64), disp_height.into()),
+        fmt,
+        bpp,
+    )?;
 
-    let fb = card.add_framebuffer(&db, 24, 32)?;
+    let fb = card.add_framebuffer(&db, depth, bpp)?;
     let plane = *card
         .plane_handles()?
         .first()
@@ -177,18 +202,27 @@ fn try_open_card(path: &Path) -> Result<DrmBackend> {
 }
 
 impl DrmBackend {
-    pub fn open_card() -> Result<DrmBackend> {
-        let mut errors = Vec::new();
-        for entry in fs::read_dir("/dev/dri/")? {
-            let entry = entry?;
-            if !entry.file_name().to_string_lossy().starts_with("card") {
-                continue;
+    pub fn open_card(display_cfg: &DisplayConfig) -> Result<DrmBackend> {
+        let candidates: Vec<PathBuf> = match &display_cfg.card {
+            Some(card) => vec![PathBuf::from(card)],
+            None => {
+                let mut cards = Vec::new();
+                for entry in fs::read_dir("/dev/dri/")? {
+                    let entry = entry?;
+                    if entry.file_name().to_string_lossy().starts_with("card") {
+                        cards.push(entry.path());
+                    }
+                }
+                cards
             }
-            match try_open_card(&entry.path()) {
+        };
+        let mut errors = Vec::new();
+        for path in candidates {
+            match try_open_card(&path, display_cfg) {
                 Ok(card) => return Ok(card),
                 Err(err) => errors.push(format!(
                     "{}: {}",
-                    entry.path().as_os_str().to_string_lossy(),
+                    path.as_os_str().to_string_lossy(),
                     err
                 )),
             }
@@ -204,6 +238,18 @@ impl DrmBackend {
     pub fn fb_info(&self) -> Result<framebuffer::Info> {
         Ok(self.card.get_framebuffer(self.fb)?)
     }
+    pub fn pixel_format(&self) -> Result<DrmFourcc> {
+        // Older kernels lack GETFB2, fall back to guessing from depth and bpp
+        if let Ok(info) = self.card.get_planar_framebuffer(self.fb) {
+            return Ok(info.pixel_format());
+        }
+        let info = self.fb_info()?;
+        match (info.depth(), info.bpp()) {
+            (16, 16) => Ok(DrmFourcc::Rgb565),
+            (24, 32) => Ok(DrmFourcc::Xrgb8888),
+            (depth, bpp) => Err(anyhow!("Unsupported framebuffer depth {} / bpp {}", depth, bpp)),
+        }
+    }
     pub fn dirty(&self, clips: &[ClipRect]) -> Result<()> {
         Ok(self.card.dirty_framebuffer(self.fb, clips)?)
     }
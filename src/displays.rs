@@ -0,0 +1,41 @@
+use std::fs;
+
+// Connector types of the built-in panel and of the Touch Bar itself, never an external monitor
+const INTERNAL_CONNECTORS: [&str; 6] = ["eDP", "LVDS", "DSI", "USB", "Virtual", "Writeback"];
+
+// Names of the connected external monitors, e.g. HDMI-A-1 and DP-2, sorted
+pub fn connected_external() -> Vec<String> {
+    let Ok(entries) = fs::read_dir("/sys/class/drm") else {
+        return Vec::new();
+    };
+    let mut connected: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            // card0-HDMI-A-1, the card itself has no connector part
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let (_, connector) = name.split_once('-')?;
+            let connector_type = connector.rsplit_once('-').map_or(connector, |(connector_type, _)| connector_type);
+            if INTERNAL_CONNECTORS.contains(&connector_type) {
+                return None;
+            }
+            let status = fs::read_to_string(entry.path().join("status")).ok()?;
+            (status.trim() == "connected").then(|| connector.to_string())
+        })
+        .collect();
+    connected.sort();
+    connected.dedup();
+    connected
+}
+
+// What the indicator shows once the set of monitors changed from `before` to `after`
+pub fn change_message(before: &[String], after: &[String]) -> Option<String> {
+    let added: Vec<&str> = after.iter().filter(|name| !before.contains(name)).map(String::as_str).collect();
+    let removed: Vec<&str> = before.iter().filter(|name| !after.contains(name)).map(String::as_str).collect();
+    if !added.is_empty() {
+        Some(format!("Display connected · {}", added.join(", ")))
+    } else if !removed.is_empty() {
+        Some(format!("Display disconnected · {}", removed.join(", ")))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,80 @@
+use input_linux::{Key, UInputHandle};
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::sync::{Arc, Mutex};
+
+// Always there so games and udev take the device for a gamepad, whichever buttons are configured
+const STANDARD_BUTTONS: [Key; 11] = [
+    Key::ButtonSouth, Key::ButtonEast, Key::ButtonNorth, Key::ButtonWest,
+    Key::ButtonTL, Key::ButtonTR, Key::ButtonSelect, Key::ButtonStart,
+    Key::ButtonMode, Key::ButtonThumbl, Key::ButtonThumbr,
+];
+
+struct Gamepad {
+    buttons: HashSet<Key>,
+    device: Option<UInputHandle<File>>,
+    pressed: HashSet<Key>,
+}
+
+// Only made once a gamepad button is first pressed
+static GAMEPAD: std::sync::LazyLock<Arc<Mutex<Gamepad>>> = std::sync::LazyLock::new(|| {
+    Arc::new(Mutex::new(Gamepad {
+        buttons: STANDARD_BUTTONS.into_iter().collect(),
+        device: None,
+        pressed: HashSet::new(),
+    }))
+});
+
+fn create_gamepad(buttons: &HashSet<Key>) -> UInputHandle<File> {
+    let uinput = UInputHandle::new(OpenOptions::new().write(true).open("/dev/uinput").unwrap());
+    uinput.set_evbit(input_linux::EventKind::Key).unwrap();
+    for button in buttons {
+        uinput.set_keybit(*button).unwrap();
+    }
+    crate::finish_uinput(uinput, "Dynamic Function Row Virtual Gamepad")
+}
+
+// Public API
+
+// The buttons the gamepad expandables press. Like the keyboard's, they're fixed once
+// the device is made, so it's made again when new ones show up.
+pub fn set_buttons(buttons: HashSet<Key>) {
+    let Ok(mut gamepad) = GAMEPAD.lock() else {
+        return;
+    };
+    if buttons.is_subset(&gamepad.buttons) {
+        return;
+    }
+    gamepad.buttons.extend(buttons);
+    gamepad.pressed.clear();
+    if let Some(device) = gamepad.device.take() {
+        println!("Recreating the virtual gamepad for newly configured buttons");
+        if let Err(e) = device.dev_destroy() {
+            eprintln!("Failed to remove the previous virtual gamepad: {}", e);
+        }
+    }
+}
+
+pub fn press(button: Key) {
+    let Ok(mut gamepad) = GAMEPAD.lock() else {
+        return;
+    };
+    let gamepad = &mut *gamepad;
+    let device = gamepad.device.get_or_insert_with(|| create_gamepad(&gamepad.buttons));
+    crate::toggle_key(device, button, 1);
+    gamepad.pressed.insert(button);
+}
+
+// Lets go of `button` if the gamepad has it pressed, whichever layer is shown by now
+pub fn release(button: Key) -> bool {
+    let Ok(mut gamepad) = GAMEPAD.lock() else {
+        return false;
+    };
+    if !gamepad.pressed.remove(&button) {
+        return false;
+    }
+    if let Some(device) = &mut gamepad.device {
+        crate::toggle_key(device, button, 0);
+    }
+    true
+}
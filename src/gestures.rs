@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Swipes up have to start in the bottom part of the strip, swipes down in the top part,
+// given as a fraction of its height
+const SWIPE_UP_EDGE_FRACTION: f64 = 0.35;
+// Sideways swipes have to be quick, slower drags across the bar are left to the buttons
+const SWIPE_SIDEWAYS_TIME: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Swipe {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TouchStart {
+    x: f64,
+    y: f64,
+    at: Instant,
+    fired: bool,
+    // Position the last scrub step was counted from, once the finger moved far enough to scrub
+    scrub_x: Option<f64>,
+    // Where the finger is now
+    current_x: f64,
+    // Set for both fingers once they pinched, their taps don't count anymore
+    pinched: bool,
+}
+
+pub struct GestureTracker {
+    starts: HashMap<u32, TouchStart>,
+    // Fingers pinching and the distance between them the last step was counted from
+    pinch: Option<(u32, u32, f64)>,
+}
+
+impl GestureTracker {
+    pub fn new() -> GestureTracker {
+        GestureTracker {
+            starts: HashMap::new(),
+            pinch: None,
+        }
+    }
+
+    pub fn touch_down(&mut self, slot: u32, x: f64, y: f64) {
+        self.starts.insert(slot, TouchStart { x, y, at: Instant::now(), fired: false, scrub_x: None, current_x: x, pinched: false });
+    }
+
+    // `distance` is the fraction of the strip height the finger has to travel up or down,
+    // `width` how many pixels it has to travel sideways
+    pub fn touch_motion(&mut self, slot: u32, x: f64, y: f64, height: f64, distance: f64, width: f64) -> Option<Swipe> {
+        let start = self.starts.get_mut(&slot)?;
+        start.current_x = x;
+        if start.fired || start.pinched {
+            return None;
+        }
+        let (dx, dy) = (x - start.x, y - start.y);
+        let gesture = if start.y >= height * (1.0 - SWIPE_UP_EDGE_FRACTION) && -dy >= height * distance {
+            Swipe::Up
+        } else if start.y <= height * SWIPE_UP_EDGE_FRACTION && dy >= height * distance {
+            Swipe::Down
+        } else if dx.abs() >= width && start.at.elapsed() <= SWIPE_SIDEWAYS_TIME {
+            if dx < 0.0 { Swipe::Left } else { Swipe::Right }
+        } else {
+            return None;
+        };
+        // Only fire once per touch
+        start.fired = true;
+        Some(gesture)
+    }
+
+    // Number of whole `step` sized horizontal moves since the last call, negative when
+    // moving left. Scrubbing only starts once the finger moved a full step from where it went down.
+    pub fn scrub_motion(&mut self, slot: u32, x: f64, step: f64) -> i32 {
+        let Some(start) = self.starts.get_mut(&slot) else {
+            return 0;
+        };
+        let from = match start.scrub_x {
+            Some(from) => from,
+            None if (x - start.x).abs() >= step => start.x,
+            None => return 0,
+        };
+        let steps = ((x - from) / step).trunc();
+        start.scrub_x = Some(from + steps * step);
+        steps as i32
+    }
+
+    pub fn scrubbing(&self, slot: u32) -> bool {
+        self.starts.get(&slot).is_some_and(|start| start.scrub_x.is_some())
+    }
+
+    pub fn start_pinch(&mut self, first: u32, second: u32) {
+        let (Some(a), Some(b)) = (self.starts.get(&first), self.starts.get(&second)) else {
+            return;
+        };
+        self.pinch = Some((first, second, (a.current_x - b.current_x).abs()));
+        for slot in [first, second] {
+            if let Some(start) = self.starts.get_mut(&slot) {
+                start.pinched = true;
+            }
+        }
+    }
+
+    // Number of whole `step` sized changes of the distance between the pinching fingers
+    // since the last call, positive when they spread apart
+    pub fn pinch_motion(&mut self, step: f64) -> i32 {
+        let Some((first, second, from)) = self.pinch else {
+            return 0;
+        };
+        let (Some(a), Some(b)) = (self.starts.get(&first), self.starts.get(&second)) else {
+            return 0;
+        };
+        let steps = (((a.current_x - b.current_x).abs() - from) / step).trunc();
+        self.pinch = Some((first, second, from + steps * step));
+        steps as i32
+    }
+
+    pub fn touching(&self, slot: u32) -> bool {
+        self.starts.contains_key(&slot)
+    }
+
+    pub fn pinching(&self, slot: u32) -> bool {
+        self.pinch.is_some_and(|(first, second, _)| slot == first || slot == second)
+    }
+
+    pub fn pinched(&self, slot: u32) -> bool {
+        self.starts.get(&slot).is_some_and(|start| start.pinched)
+    }
+
+    pub fn touch_up(&mut self, slot: u32) {
+        self.starts.remove(&slot);
+        if self.pinching(slot) {
+            self.pinch = None;
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::privileged;
+use input_linux::{EventKind, EvdevHandle, ForceFeedbackKind};
+use input_linux_sys::{ff_effect, input_event, timeval, FF_PERIODIC, FF_RUMBLE, FF_SINE};
+use std::fs::{self, File};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{LazyLock, Mutex};
+use std::thread;
+
+// Short enough to feel like a click rather than a buzz
+const CLICK_MS: u16 = 20;
+const CLICK_STRENGTH: u16 = 0xc000;
+// Actuators that only play waveforms get a single period of this one
+const CLICK_PERIOD_MS: u16 = 10;
+// The Touch Bar's own actuator is preferred, then the trackpad's
+const PREFERRED_DEVICES: [&str; 2] = ["Touch Bar", "Trackpad"];
+
+// Clicks waiting to be played, the worker drops the ones it can't keep up with
+static CLICKS: LazyLock<Mutex<Sender<()>>> = LazyLock::new(|| {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || worker(receiver));
+    Mutex::new(sender)
+});
+
+struct Actuator {
+    device: EvdevHandle<File>,
+    effect: i16,
+}
+
+impl Actuator {
+    fn open(path: &str) -> Option<(String, Actuator)> {
+        let device = EvdevHandle::new(privileged::open(path, true).ok()?);
+        if !device.event_bits().ok()?.get(EventKind::ForceFeedback) {
+            return None;
+        }
+        let kinds = device.force_feedback_bits().ok()?;
+        // Zeroed is a valid effect, the fields that matter are filled in below
+        let mut effect: ff_effect = unsafe { std::mem::zeroed() };
+        effect.id = -1;
+        effect.replay.length = CLICK_MS;
+        if kinds.get(ForceFeedbackKind::Rumble) {
+            effect.type_ = FF_RUMBLE;
+            let rumble = <&mut input_linux_sys::ff_effect_union>::from(&mut effect).rumble_mut();
+            rumble.strong_magnitude = CLICK_STRENGTH;
+            rumble.weak_magnitude = CLICK_STRENGTH;
+        } else if kinds.get(ForceFeedbackKind::Periodic) && kinds.get(ForceFeedbackKind::Sine) {
+            effect.type_ = FF_PERIODIC;
+            let periodic = <&mut input_linux_sys::ff_effect_union>::from(&mut effect).periodic_mut();
+            periodic.waveform = FF_SINE;
+            periodic.period = CLICK_PERIOD_MS;
+            periodic.magnitude = (CLICK_STRENGTH / 2) as i16;
+        } else {
+            return None;
+        }
+        device.send_force_feedback(&mut effect).ok()?;
+        let name = String::from_utf8_lossy(&device.device_name().ok()?).trim_end_matches('\0').to_string();
+        Some((name, Actuator { device, effect: effect.id }))
+    }
+
+    fn play(&self) -> std::io::Result<()> {
+        let event = input_event {
+            time: timeval { tv_sec: 0, tv_usec: 0 },
+            type_: EventKind::ForceFeedback as u16,
+            code: self.effect as u16,
+            value: 1,
+        };
+        self.device.write(&[event]).map(|_| ())
+    }
+}
+
+// Every input device that can play a click, preferred ones first
+fn find_actuator() -> Option<Actuator> {
+    let mut found: Vec<(String, Actuator)> = fs::read_dir("/dev/input")
+        .ok()?
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let name = path.file_name()?.to_str()?;
+            name.starts_with("event").then(|| Actuator::open(path.to_str()?))?
+        })
+        .collect();
+    found.sort_by_key(|(name, _)| {
+        PREFERRED_DEVICES.iter().position(|preferred| name.contains(preferred)).unwrap_or(PREFERRED_DEVICES.len())
+    });
+    let (name, actuator) = found.into_iter().next()?;
+    println!("Haptic feedback through {}", name);
+    Some(actuator)
+}
+
+fn worker(clicks: Receiver<()>) {
+    let mut actuator = find_actuator();
+    if actuator.is_none() {
+        eprintln!("No input device with force feedback found, HapticFeedback has no effect");
+    }
+    while clicks.recv().is_ok() {
+        // A burst of touches plays as one click
+        while clicks.try_recv().is_ok() {}
+        // Devices come and go with suspend and unbinding, look again once it's gone
+        if actuator.as_ref().is_some_and(|actuator| actuator.play().is_err()) {
+            actuator = find_actuator();
+        }
+    }
+}
+
+// Public API
+
+// Plays a click on the haptic actuator, found on first use
+pub fn click() {
+    if let Ok(clicks) = CLICKS.lock() {
+        let _ = clicks.send(());
+    }
+}
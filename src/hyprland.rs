@@ -1,9 +1,23 @@
+use crate::metrics;
+use crate::privileged;
+use crate::user_cache;
 use anyhow::{anyhow, Result};
+use nix::sys::eventfd::{EfdFlags, EventFd};
 use serde::{Deserialize, Serialize};
 use std::io::{BufRead, BufReader, Read, Write};
+use std::os::fd::{AsFd, BorrowedFd};
 use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+
+// Focus changes are coalesced until events stop arriving for this long
+const UPDATE_DEBOUNCE: Duration = Duration::from_millis(80);
+// Minimum time between two button refreshes while cycling through windows
+const MIN_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+// How often the watchdog compares what the event listener saw with what Hyprland reports
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(30);
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct HyprlandWindow {
@@ -58,11 +72,72 @@ pub struct HyprlandIpc {
 static CACHED_WINDOW_INFO: std::sync::LazyLock<Arc<Mutex<Option<ActiveWindowInfo>>>> =
     std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
 
+// Workspace switched to since the main loop last looked
+static WORKSPACE_CHANGE: std::sync::LazyLock<Arc<Mutex<Option<HyprlandWorkspace>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
+
+static ACTIVE_WORKSPACE: std::sync::LazyLock<Arc<Mutex<Option<HyprlandWorkspace>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
+
+// Every workspace in order, only kept up to date once a Workspaces button asked for them
+static WORKSPACES: Mutex<Option<Vec<HyprlandWorkspace>>> = Mutex::new(None);
+// Bumped whenever a workspace is created, destroyed, renamed or switched to
+static WORKSPACES_GENERATION: AtomicU64 = AtomicU64::new(0);
+
 static EVENT_LISTENER_STARTED: std::sync::LazyLock<Arc<Mutex<bool>>> =
     std::sync::LazyLock::new(|| Arc::new(Mutex::new(false)));
 
-static CACHE_UPDATED: std::sync::LazyLock<Arc<Mutex<bool>>> =
-    std::sync::LazyLock::new(|| Arc::new(Mutex::new(false)));
+// Event socket the listener connects to, changes when Hyprland is restarted
+static EVENT_SOCKET_PATH: std::sync::LazyLock<Arc<Mutex<String>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(String::new())));
+
+// Connection the listener is currently reading from, shut down by the watchdog to restart it
+static EVENT_STREAM: std::sync::LazyLock<Arc<Mutex<Option<UnixStream>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
+
+static LISTENER_THREAD: std::sync::LazyLock<Arc<Mutex<Option<thread::JoinHandle<()>>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
+
+static LISTENER_RESTARTS: std::sync::LazyLock<Arc<Mutex<u32>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(0)));
+
+#[derive(Default)]
+struct UpdateState {
+    // Time of the most recent change that hasn't been picked up yet
+    pending_since: Option<Instant>,
+    last_applied: Option<Instant>,
+}
+
+impl UpdateState {
+    fn ready_in(&self, now: Instant) -> Option<Duration> {
+        let pending_since = self.pending_since?;
+        let debounce_left = UPDATE_DEBOUNCE.saturating_sub(now.duration_since(pending_since));
+        let interval_left = self
+            .last_applied
+            .map(|t| MIN_UPDATE_INTERVAL.saturating_sub(now.duration_since(t)))
+            .unwrap_or(Duration::ZERO);
+        Some(debounce_left.max(interval_left))
+    }
+}
+
+static UPDATE_STATE: std::sync::LazyLock<Arc<Mutex<UpdateState>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(UpdateState::default())));
+
+// Lets the event listener thread wake up the main loop when the focus changes
+static WAKEUP: std::sync::LazyLock<EventFd> = std::sync::LazyLock::new(|| {
+    EventFd::from_value_and_flags(0, EfdFlags::EFD_NONBLOCK | EfdFlags::EFD_CLOEXEC).unwrap()
+});
+
+fn mark_cache_updated() {
+    if let Ok(mut state) = UPDATE_STATE.lock() {
+        state.pending_since = Some(Instant::now());
+    }
+    let _ = WAKEUP.write(1);
+}
+
+pub fn wakeup_fd() -> BorrowedFd<'static> {
+    WAKEUP.as_fd()
+}
 
 impl HyprlandIpc {
     pub fn new() -> Result<Self> {
@@ -92,33 +167,22 @@ impl HyprlandIpc {
             }
         }
 
-        // Then try /run/user/*/hypr/ (user session location)
+        // Then try /run/user/*/hypr/ (user session location), only the user may look inside
+        // so the privileged helper lists it once privileges are dropped
         if let Ok(run_user_entries) = std::fs::read_dir("/run/user") {
             for user_entry in run_user_entries.flatten() {
-                if let Ok(file_type) = user_entry.file_type() {
-                    if file_type.is_dir() {
-                        let hypr_path = user_entry.path().join("hypr");
-                        if hypr_path.exists() {
-                            if let Ok(hypr_entries) = std::fs::read_dir(&hypr_path) {
-                                for hypr_entry in hypr_entries.flatten() {
-                                    if let Ok(hypr_file_type) = hypr_entry.file_type() {
-                                        if hypr_file_type.is_dir() {
-                                            let socket_path = hypr_entry.path().join(".socket.sock");
-                                            if socket_path.exists() {
-                                                let path_str = socket_path.to_string_lossy().to_string();
-                                                let socket2_path = hypr_entry.path().join(".socket2.sock");
-                                                let socket2_path_str = socket2_path.to_string_lossy().to_string();
-                                                println!("Found Hyprland socket at: {}", path_str);
-                                                return Ok(HyprlandIpc {
-                                                    socket_path: path_str,
-                                                    socket2_path: socket2_path_str
-                                                });
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
+                let hypr_path = format!("{}/hypr", user_entry.path().to_string_lossy());
+                for instance in privileged::read_session_dir(&hypr_path).unwrap_or_default() {
+                    let instance_path = format!("{}/{}", hypr_path, instance);
+                    let has_socket = privileged::read_session_dir(&instance_path)
+                        .is_ok_and(|names| names.iter().any(|name| name == ".socket.sock"));
+                    if has_socket {
+                        let path_str = format!("{}/.socket.sock", instance_path);
+                        println!("Found Hyprland socket at: {}", path_str);
+                        return Ok(HyprlandIpc {
+                            socket_path: path_str,
+                            socket2_path: format!("{}/.socket2.sock", instance_path)
+                        });
                     }
                 }
             }
@@ -128,7 +192,7 @@ impl HyprlandIpc {
     }
 
     pub fn send_command(&self, command: &str) -> Result<String> {
-        let mut stream = UnixStream::connect(&self.socket_path)
+        let mut stream = privileged::connect_session_socket(&self.socket_path)
             .map_err(|e| anyhow!("Failed to connect to Hyprland socket: {}", e))?;
 
         stream.write_all(command.as_bytes())
@@ -148,6 +212,20 @@ impl HyprlandIpc {
         Ok(window)
     }
 
+    pub fn get_active_workspace(&self) -> Result<HyprlandWorkspace> {
+        let response = self.send_command("j/activeworkspace")?;
+        let workspace: HyprlandWorkspace = serde_json::from_str(&response)
+            .map_err(|e| anyhow!("Failed to parse active workspace response: {}", e))?;
+        Ok(workspace)
+    }
+
+    pub fn get_workspaces(&self) -> Result<Vec<HyprlandWorkspace>> {
+        let response = self.send_command("j/workspaces")?;
+        let workspaces: Vec<HyprlandWorkspace> = serde_json::from_str(&response)
+            .map_err(|e| anyhow!("Failed to parse workspaces response: {}", e))?;
+        Ok(workspaces)
+    }
+
     pub fn get_clients(&self) -> Result<Vec<HyprlandWindow>> {
         let response = self.send_command("j/clients")?;
         let clients: Vec<HyprlandWindow> = serde_json::from_str(&response)
@@ -156,23 +234,22 @@ impl HyprlandIpc {
     }
 
     pub fn start_event_listener(&self) -> Result<()> {
-        let socket2_path = self.socket2_path.clone();
-
-        thread::spawn(move || {
-            if let Err(e) = Self::event_listener_loop(&socket2_path) {
-                println!("Hyprland event listener error: {}", e);
-            }
-        });
-
+        *EVENT_SOCKET_PATH.lock().map_err(|_| anyhow!("Event socket path lock poisoned"))? = self.socket2_path.clone();
+        spawn_event_listener();
+        thread::spawn(watchdog_loop);
         Ok(())
     }
 
-    fn event_listener_loop(socket2_path: &str) -> Result<()> {
-        println!("Starting Hyprland event listener on: {}", socket2_path);
-
+    fn event_listener_loop() -> Result<()> {
         loop {
-            match UnixStream::connect(socket2_path) {
+            let socket2_path = EVENT_SOCKET_PATH.lock().map_err(|_| anyhow!("Event socket path lock poisoned"))?.clone();
+            println!("Starting Hyprland event listener on: {}", socket2_path);
+            match privileged::connect_session_socket(&socket2_path) {
                 Ok(stream) => {
+                    metrics::hyprland_connected();
+                    if let Ok(mut current) = EVENT_STREAM.lock() {
+                        *current = stream.try_clone().ok();
+                    }
                     let reader = BufReader::new(stream);
                     for line in reader.lines() {
                         match line {
@@ -195,6 +272,24 @@ impl HyprlandIpc {
     }
 
     fn handle_event(event_line: &str) {
+        // The list is asked for again rather than pieced together from the events
+        let (event, _) = event_line.split_once(">>").unwrap_or_default();
+        if matches!(event, "createworkspacev2" | "destroyworkspacev2" | "renameworkspace" | "moveworkspacev2") {
+            refresh_workspaces();
+            return;
+        }
+        // Format: workspacev2>>ID,NAME, sent along with workspace>>NAME
+        if let Some(data) = event_line.strip_prefix("workspacev2>>") {
+            if let Some((id, name)) = data.split_once(',') {
+                if let Ok(id) = id.parse() {
+                    set_active_workspace(HyprlandWorkspace {
+                        id,
+                        name: name.to_string(),
+                    });
+                }
+            }
+            return;
+        }
         if event_line.starts_with("activewindow>>") {
             // Parse the activewindow event and update cache
             // Format: activewindow>>CLASS,TITLE
@@ -204,22 +299,12 @@ impl HyprlandIpc {
                     let class = parts[0].to_string();
                     let title = parts[1].to_string();
 
-                    let window_info = ActiveWindowInfo {
+                    set_active_window(ActiveWindowInfo {
                         title: title.clone(),
                         class: class.clone(),
-                        initial_title: title.clone(), // We don't have this from events
-                        initial_class: class.clone(), // We don't have this from events
-                    };
-
-                    if let Ok(mut cache) = CACHED_WINDOW_INFO.lock() {
-                        *cache = Some(window_info);
-                        println!("Updated cached window: {} - {}", class, title);
-
-                        // Mark cache as updated
-                        if let Ok(mut updated) = CACHE_UPDATED.lock() {
-                            *updated = true;
-                        }
-                    }
+                        initial_title: title, // We don't have this from events
+                        initial_class: class, // We don't have this from events
+                    });
                 }
             }
         }
@@ -259,32 +344,259 @@ impl ActiveWindowInfo {
     }
 }
 
-pub fn get_active_window_info() -> Result<ActiveWindowInfo> {
-    // Try to create IPC connection - if it fails, Hyprland isn't ready yet
-    let ipc = match HyprlandIpc::new() {
-        Ok(ipc) => ipc,
-        Err(_) => {
-            // Hyprland not available - return a default "waiting" state
-            return Err(anyhow!("Hyprland not available"));
+// Updates the cache from a focus or title change, whichever provider saw it
+pub fn set_active_window(window_info: ActiveWindowInfo) {
+    if let Ok(mut cache) = CACHED_WINDOW_INFO.lock() {
+        // Hyprland repeats the event for title-less focus churn, skip no-op changes
+        if cache.as_ref().is_some_and(|c| c.class == window_info.class && c.title == window_info.title) {
+            return;
         }
+        *cache = Some(window_info);
+        mark_cache_updated();
+    }
+}
+
+pub fn set_active_workspace(workspace: HyprlandWorkspace) {
+    if let Ok(mut active) = ACTIVE_WORKSPACE.lock() {
+        *active = Some(workspace.clone());
+    }
+    if let Ok(mut change) = WORKSPACE_CHANGE.lock() {
+        *change = Some(workspace);
+    }
+    WORKSPACES_GENERATION.fetch_add(1, Ordering::Relaxed);
+    let _ = WAKEUP.write(1);
+}
+
+// Asks Hyprland for the workspaces if a Workspaces button is following them. Special
+// workspaces are left out, they're toggled rather than switched to.
+fn refresh_workspaces() {
+    let Ok(mut workspaces) = WORKSPACES.lock() else {
+        return;
     };
+    if workspaces.is_none() {
+        return;
+    }
+    let mut fetched: Vec<HyprlandWorkspace> = HyprlandIpc::new()
+        .and_then(|ipc| ipc.get_workspaces())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|workspace| !workspace.name.starts_with("special:"))
+        .collect();
+    // Numbered ones first, named ones have negative ids
+    fetched.sort_by_key(|workspace| (workspace.id < 0, workspace.id));
+    *workspaces = Some(fetched);
+    WORKSPACES_GENERATION.fetch_add(1, Ordering::Relaxed);
+    let _ = WAKEUP.write(1);
+}
 
-    // Start event listener if not already started
-    {
-        let mut started = EVENT_LISTENER_STARTED.lock().unwrap();
-        if !*started {
-            if let Ok(()) = ipc.start_event_listener() {
-                *started = true;
+pub fn cached_active_window() -> Option<ActiveWindowInfo> {
+    CACHED_WINDOW_INFO.lock().ok()?.clone()
+}
+
+fn spawn_event_listener() {
+    let handle = thread::spawn(|| {
+        if let Err(e) = HyprlandIpc::event_listener_loop() {
+            println!("Hyprland event listener error: {}", e);
+        }
+    });
+    if let Ok(mut listener) = LISTENER_THREAD.lock() {
+        *listener = Some(handle);
+    }
+}
+
+// Makes the listener reconnect, or starts a new one if it died
+fn restart_event_listener(reason: &str) {
+    let restarts = match LISTENER_RESTARTS.lock() {
+        Ok(mut restarts) => {
+            *restarts += 1;
+            *restarts
+        }
+        Err(_) => 0,
+    };
+    println!("Restarting Hyprland event listener: {} (restart #{})", reason, restarts);
+    if let Some(stream) = EVENT_STREAM.lock().ok().and_then(|mut current| current.take()) {
+        let _ = stream.shutdown(std::net::Shutdown::Both);
+    }
+    let finished = LISTENER_THREAD
+        .lock()
+        .map(|listener| listener.as_ref().is_none_or(|handle| handle.is_finished()))
+        .unwrap_or(false);
+    if finished {
+        spawn_event_listener();
+    }
+}
+
+// Puts the state Hyprland reports into the caches, as if the missed events had arrived
+fn resync(window: Option<ActiveWindowInfo>, workspace: Option<HyprlandWorkspace>) {
+    // Same as the event Hyprland sends when focus moves to an empty workspace
+    let window = window.unwrap_or_else(|| ActiveWindowInfo {
+        title: String::new(),
+        class: String::new(),
+        initial_title: String::new(),
+        initial_class: String::new(),
+    });
+    if let Ok(mut cache) = CACHED_WINDOW_INFO.lock() {
+        *cache = Some(window);
+    }
+    if let Some(workspace) = workspace {
+        if let Ok(mut active) = ACTIVE_WORKSPACE.lock() {
+            *active = Some(workspace.clone());
+        }
+        if let Ok(mut change) = WORKSPACE_CHANGE.lock() {
+            *change = Some(workspace);
+        }
+    }
+    refresh_workspaces();
+    mark_cache_updated();
+}
+
+// Hyprland sends no events while nothing happens, so silence alone means nothing. Instead
+// the watchdog asks the command socket for the focused window and workspace, and restarts the
+// listener when the cached state disagrees twice in a row.
+fn watchdog_loop() {
+    let mut out_of_sync = false;
+    loop {
+        thread::sleep(WATCHDOG_INTERVAL);
+        let Ok(ipc) = HyprlandIpc::new() else {
+            // Not running, the listener keeps trying to connect on its own
+            continue;
+        };
+        let listening_on = EVENT_SOCKET_PATH.lock().map(|path| path.clone()).unwrap_or_default();
+        if ipc.socket2_path != listening_on {
+            if let Ok(mut path) = EVENT_SOCKET_PATH.lock() {
+                *path = ipc.socket2_path.clone();
             }
+            restart_event_listener("Hyprland was restarted");
+            out_of_sync = false;
+            continue;
         }
+        let response = match ipc.send_command("j/activewindow") {
+            Ok(response) => response,
+            Err(e) => {
+                println!("Hyprland command socket is not responding: {}", e);
+                continue;
+            }
+        };
+        // Hyprland answers with an empty object while no window is focused
+        let window = serde_json::from_str::<HyprlandWindow>(&response)
+            .ok()
+            .map(ActiveWindowInfo::from_hyprland_window);
+        let workspace = ipc.get_active_workspace().ok();
+        let window_matches = CACHED_WINDOW_INFO
+            .lock()
+            .map(|cache| match (cache.as_ref(), window.as_ref()) {
+                (Some(cached), Some(window)) => cached.class == window.class && cached.title == window.title,
+                (Some(cached), None) => cached.class.is_empty() && cached.title.is_empty(),
+                // Nothing was asked for yet
+                (None, _) => true,
+            })
+            .unwrap_or(true);
+        let workspace_matches = ACTIVE_WORKSPACE
+            .lock()
+            .map(|active| match (active.as_ref(), workspace.as_ref()) {
+                (Some(active), Some(workspace)) => active.id == workspace.id,
+                _ => true,
+            })
+            .unwrap_or(true);
+        if window_matches && workspace_matches {
+            out_of_sync = false;
+        } else if out_of_sync {
+            restart_event_listener("events stopped arriving");
+            resync(window, workspace);
+            out_of_sync = false;
+        } else {
+            // Might just be an event on its way, look again next time
+            out_of_sync = true;
+        }
+    }
+}
+
+// Where the active window and workspace come from, all of them feed the same cache
+pub trait WindowInfoProvider: Sync {
+    // Errors out when its compositor isn't running, the next provider is asked then
+    fn active_window(&self) -> Result<ActiveWindowInfo>;
+    // Not every compositor has workspaces to tell about
+    fn active_workspace(&self) -> Option<HyprlandWorkspace> {
+        None
     }
+}
+
+pub struct HyprlandProvider;
+
+// Asked in this order, the first one that answers wins
+static PROVIDERS: [&dyn WindowInfoProvider; 3] = [
+    &HyprlandProvider,
+    &crate::sway::SwayProvider,
+    &crate::toplevel::ForeignToplevelProvider,
+];
+
+// The provider that answered for the session as the user cache last found it, or when it turned
+// out none would. Finding the compositor means scanning /run/user for sockets, so the others are
+// only asked again once the session changes.
+struct ActiveProvider {
+    session: Option<Instant>,
+    index: Result<usize, Instant>,
+}
+
+static ACTIVE_PROVIDER: Mutex<Option<ActiveProvider>> = Mutex::new(None);
+// A session that just started may not have its compositor up yet
+const NO_PROVIDER_RETRY: Duration = Duration::from_secs(10);
 
-    // Try to get from cache first
-    if let Ok(cache) = CACHED_WINDOW_INFO.lock() {
-        if let Some(ref cached_info) = *cache {
-            return Ok(cached_info.clone());
+pub fn get_active_window_info() -> Result<ActiveWindowInfo> {
+    let session = user_cache::get_cached_user_environment().map(|env| env.last_updated);
+    let mut active = ACTIVE_PROVIDER.lock().map_err(|_| anyhow!("Provider lock poisoned"))?;
+    match active.as_ref().filter(|active| active.session == session).map(|active| active.index) {
+        Some(Ok(index)) => return PROVIDERS[index].active_window(),
+        Some(Err(looked_at)) if looked_at.elapsed() < NO_PROVIDER_RETRY => {
+            return Err(anyhow!("No Hyprland, sway or wlroots compositor available"));
+        }
+        _ => {}
+    }
+    // What the last session's compositor said doesn't count for the new one
+    if let Ok(mut cache) = CACHED_WINDOW_INFO.lock() {
+        *cache = None;
+    }
+    for (index, provider) in PROVIDERS.iter().enumerate() {
+        if let Ok(window_info) = provider.active_window() {
+            *active = Some(ActiveProvider { session, index: Ok(index) });
+            return Ok(window_info);
         }
     }
+    *active = Some(ActiveProvider { session, index: Err(Instant::now()) });
+    Err(anyhow!("No Hyprland, sway or wlroots compositor available"))
+}
+
+impl WindowInfoProvider for HyprlandProvider {
+    fn active_window(&self) -> Result<ActiveWindowInfo> {
+        active_hyprland_window()
+    }
+
+    fn active_workspace(&self) -> Option<HyprlandWorkspace> {
+        HyprlandIpc::new().ok()?.get_active_workspace().ok()
+    }
+}
+
+fn ensure_event_listener(ipc: &HyprlandIpc) {
+    let mut started = EVENT_LISTENER_STARTED.lock().unwrap();
+    if !*started {
+        if let Ok(()) = ipc.start_event_listener() {
+            *started = true;
+        }
+    }
+}
+
+fn active_hyprland_window() -> Result<ActiveWindowInfo> {
+    // Once the event listener runs it keeps the cache current, no need to find the socket again
+    if *EVENT_LISTENER_STARTED.lock().unwrap() {
+        if let Some(cached_info) = cached_active_window() {
+            return Ok(cached_info);
+        }
+    }
+
+    // Try to create IPC connection - if it fails, Hyprland isn't ready yet
+    let ipc = HyprlandIpc::new()?;
+
+    ensure_event_listener(&ipc);
 
     // If no cache, get it directly and update cache
     let window = ipc.get_active_window()
@@ -295,17 +607,81 @@ pub fn get_active_window_info() -> Result<ActiveWindowInfo> {
     if let Ok(mut cache) = CACHED_WINDOW_INFO.lock() {
         *cache = Some(window_info.clone());
     }
+    mark_cache_updated();
 
     Ok(window_info)
 }
 
+// Returns true once the active window has settled after a change and enough
+// time has passed since the last refresh
 pub fn check_and_reset_cache_updated() -> bool {
-    if let Ok(mut updated) = CACHE_UPDATED.lock() {
-        let was_updated = *updated;
-        *updated = false;
-        was_updated
-    } else {
-        false
+    // Drain the wakeup counter, the pending timestamp carries the actual state
+    let _ = WAKEUP.read();
+    let Ok(mut state) = UPDATE_STATE.lock() else {
+        return false;
+    };
+    let now = Instant::now();
+    if state.ready_in(now) != Some(Duration::ZERO) {
+        return false;
+    }
+    state.pending_since = None;
+    state.last_applied = Some(now);
+    true
+}
+
+// Time until a pending window change should be picked up, used to wake the main loop
+pub fn pending_update_timeout() -> Option<Duration> {
+    UPDATE_STATE.lock().ok()?.ready_in(Instant::now())
+}
+
+// Name of the focused workspace, asked for once and then kept up to date by the event listener
+pub fn get_active_workspace_name() -> Option<String> {
+    if let Some(workspace) = ACTIVE_WORKSPACE.lock().ok()?.as_ref() {
+        return Some(workspace.name.clone());
+    }
+    let workspace = PROVIDERS.iter().find_map(|provider| provider.active_workspace())?;
+    let name = workspace.name.clone();
+    *ACTIVE_WORKSPACE.lock().ok()? = Some(workspace);
+    Some(name)
+}
+
+// How often the event listener had to be restarted, for diagnostics
+pub fn listener_restarts() -> u32 {
+    LISTENER_RESTARTS.lock().map(|restarts| *restarts).unwrap_or(0)
+}
+
+// The workspace switched to since the last call, if any
+pub fn take_workspace_change() -> Option<HyprlandWorkspace> {
+    WORKSPACE_CHANGE.lock().ok()?.take()
+}
+
+// Every workspace but the special ones, asked for on first use and then kept up to date
+// by the event listener. Empty while Hyprland isn't running.
+pub fn workspaces() -> Vec<HyprlandWorkspace> {
+    if let Some(workspaces) = WORKSPACES.lock().ok().and_then(|workspaces| workspaces.clone()) {
+        return workspaces;
+    }
+    if let Ok(ipc) = HyprlandIpc::new() {
+        ensure_event_listener(&ipc);
+    }
+    if let Ok(mut workspaces) = WORKSPACES.lock() {
+        *workspaces = Some(Vec::new());
+    }
+    refresh_workspaces();
+    WORKSPACES.lock().ok().and_then(|workspaces| workspaces.clone()).unwrap_or_default()
+}
+
+pub fn workspaces_generation() -> u64 {
+    WORKSPACES_GENERATION.load(Ordering::Relaxed)
+}
+
+// The event listener sees the switch happen
+pub fn switch_workspace(id: i32) {
+    let result = HyprlandIpc::new().and_then(|ipc| ipc.send_command(&format!("dispatch workspace {}", id)));
+    match result {
+        Ok(response) if response.trim() == "ok" => {}
+        Ok(response) => eprintln!("Hyprland refused to switch to workspace {}: {}", id, response.trim()),
+        Err(e) => eprintln!("Failed to switch to workspace {}: {}", id, e),
     }
 }
 
@@ -325,6 +701,7 @@ pub fn parse_key_combos(action: &str) -> Vec<input_linux::Key> {
             "SHIFT" => input_linux::Key::LeftShift,
             "ALT" => input_linux::Key::LeftAlt,
             "META" | "CMD" | "SUPER" => input_linux::Key::LeftMeta,
+            "FN" => input_linux::Key::Fn,
             "A" => input_linux::Key::A,
             "B" => input_linux::Key::B,
             "C" => input_linux::Key::C,
@@ -387,6 +764,17 @@ pub fn parse_key_combos(action: &str) -> Vec<input_linux::Key> {
             "8" => input_linux::Key::Num8,
             "9" => input_linux::Key::Num9,
             "0" => input_linux::Key::Num0,
+            "MINUS" => input_linux::Key::Minus,
+            "EQUAL" => input_linux::Key::Equal,
+            "LEFTBRACE" => input_linux::Key::LeftBrace,
+            "RIGHTBRACE" => input_linux::Key::RightBrace,
+            "SEMICOLON" => input_linux::Key::Semicolon,
+            "APOSTROPHE" => input_linux::Key::Apostrophe,
+            "GRAVE" => input_linux::Key::Grave,
+            "BACKSLASH" => input_linux::Key::Backslash,
+            "COMMA" => input_linux::Key::Comma,
+            "DOT" => input_linux::Key::Dot,
+            "SLASH" => input_linux::Key::Slash,
             _ => continue, // Skip unknown keys
         };
         keys.push(key);
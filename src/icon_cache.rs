@@ -1,8 +1,12 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::Read;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use freedesktop_icons::lookup;
 
 use crate::ICON_SIZE;
@@ -11,6 +15,74 @@ use crate::ICON_SIZE;
 static ICON_CACHE: std::sync::LazyLock<Arc<Mutex<IconCache>>> =
     std::sync::LazyLock::new(|| Arc::new(Mutex::new(IconCache::new())));
 
+// Lookups that found nothing, until the icon is asked for again
+static MISSING: std::sync::LazyLock<Mutex<HashSet<String>>> =
+    std::sync::LazyLock::new(|| Mutex::new(HashSet::new()));
+
+// Resolved lookups survive restarts in here, one "name:theme<TAB>path<TAB>mtime" per line.
+// Unlike the in-memory cache nothing expires, an entry only goes once its file changed.
+const PERSISTED_PATH: &str = "/var/cache/tiny-dfr/icon-paths";
+const PERSIST_INTERVAL: Duration = Duration::from_secs(10);
+type PersistedPaths = HashMap<String, (PathBuf, u64)>;
+static PERSISTED: std::sync::LazyLock<Arc<Mutex<PersistedPaths>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
+static PERSISTED_DIRTY: AtomicBool = AtomicBool::new(false);
+// Opened while still root, the directory is out of reach once privileges are dropped
+static PERSISTED_FILE: Mutex<Option<File>> = Mutex::new(None);
+static PERSISTER: std::sync::LazyLock<thread::JoinHandle<()>> = std::sync::LazyLock::new(|| {
+    thread::spawn(|| loop {
+        thread::sleep(PERSIST_INTERVAL);
+        if PERSISTED_DIRTY.swap(false, Ordering::Relaxed) {
+            save_persisted();
+        }
+    })
+});
+
+fn modified_secs(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs())
+}
+
+fn remember(key: &str, path: &Path) {
+    let Some(mtime) = modified_secs(path) else {
+        return;
+    };
+    let entry = (path.to_path_buf(), mtime);
+    if let Ok(mut persisted) = PERSISTED.lock() {
+        if persisted.get(key) != Some(&entry) {
+            persisted.insert(key.to_string(), entry);
+            PERSISTED_DIRTY.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+fn save_persisted() {
+    let contents: String = match PERSISTED.lock() {
+        Ok(persisted) => persisted
+            .iter()
+            .map(|(key, (path, mtime))| format!("{}\t{}\t{}\n", key, path.display(), mtime))
+            .collect(),
+        Err(_) => return,
+    };
+    let Ok(file) = PERSISTED_FILE.lock() else {
+        return;
+    };
+    let Some(file) = file.as_ref() else {
+        return;
+    };
+    // Rewritten in place, a line cut short by a crash doesn't parse and is dropped on the next load
+    if let Err(e) = file.set_len(0).and_then(|_| file.write_all_at(contents.as_bytes(), 0)) {
+        eprintln!("Failed to save the icon cache to {}: {}", PERSISTED_PATH, e);
+    }
+}
+
+fn open_persisted() -> std::io::Result<File> {
+    if let Some(parent) = Path::new(PERSISTED_PATH).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new().read(true).write(true).create(true).truncate(false).open(PERSISTED_PATH)
+}
+
 #[derive(Debug, Clone)]
 struct CacheEntry {
     path: PathBuf,
@@ -102,9 +174,12 @@ impl IconLoader {
 
             // Update cache
             if let Some(ref p) = path {
+                remember(&request.cache_key, p);
                 if let Ok(mut cache) = ICON_CACHE.lock() {
                     cache.insert(request.cache_key, p.clone());
                 }
+            } else if let Ok(mut missing) = MISSING.lock() {
+                missing.insert(request.cache_key);
             }
 
             // Send response
@@ -164,6 +239,9 @@ impl IconLoader {
         }
 
         let (response_sender, response_receiver) = std::sync::mpsc::channel();
+        if let Ok(mut missing) = MISSING.lock() {
+            missing.remove(&cache_key);
+        }
 
         // Add to pending requests in cache
         {
@@ -205,6 +283,64 @@ pub fn get_icon_cached(name: String, theme: Option<String>) -> Option<PathBuf> {
     }
 }
 
+// Fills the cache with the lookups of previous runs whose files haven't changed since,
+// then keeps saving new ones in the background
+pub fn load_persisted_cache() {
+    let started = Instant::now();
+    let mut loaded = 0;
+    let mut contents = String::new();
+    let file = match open_persisted() {
+        Ok(mut file) => file.read_to_string(&mut contents).map(|_| file),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = &file {
+        eprintln!("Failed to open the icon cache {}: {}", PERSISTED_PATH, e);
+    }
+    if let Ok(mut persisted_file) = PERSISTED_FILE.lock() {
+        *persisted_file = file.ok();
+    }
+    for line in contents.lines() {
+        let mut fields = line.split('\t');
+        let (Some(key), Some(path), Some(mtime)) = (fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        let path = PathBuf::from(path);
+        let Ok(mtime) = mtime.parse::<u64>() else {
+            continue;
+        };
+        if modified_secs(&path) != Some(mtime) {
+            PERSISTED_DIRTY.store(true, Ordering::Relaxed);
+            continue;
+        }
+        if let Ok(mut persisted) = PERSISTED.lock() {
+            persisted.insert(key.to_string(), (path.clone(), mtime));
+        }
+        if let Ok(mut cache) = ICON_CACHE.lock() {
+            cache.insert(key.to_string(), path);
+        }
+        loaded += 1;
+    }
+    if loaded > 0 {
+        println!("Loaded {} icon paths from {} in {:?}", loaded, PERSISTED_PATH, started.elapsed());
+    }
+    std::sync::LazyLock::force(&PERSISTER);
+}
+
+// Records where an icon loaded outside of the background loader was found
+pub fn icon_resolved(name: &str, theme: Option<&str>, path: &Path) {
+    let cache_key = format!("{}:{}", name, theme.unwrap_or(""));
+    remember(&cache_key, path);
+    if let Ok(mut cache) = ICON_CACHE.lock() {
+        cache.insert(cache_key, path.to_path_buf());
+    }
+}
+
+// Whether the background loader looked for the icon and didn't find it anywhere
+pub fn icon_missing(name: &str, theme: Option<&str>) -> bool {
+    let cache_key = format!("{}:{}", name, theme.unwrap_or(""));
+    MISSING.lock().is_ok_and(|missing| missing.contains(&cache_key))
+}
+
 pub fn load_icon_async(name: String, theme: Option<String>) -> std::sync::mpsc::Receiver<Option<PathBuf>> {
     ICON_LOADER.load_async(name, theme)
 }
@@ -0,0 +1,123 @@
+use cairo::{Antialias, Context, Format, ImageSurface};
+use librsvg_rebind::{prelude::HandleExt, Handle, Rectangle};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::process::{Command, Stdio};
+
+// Icons from icon themes can come from any package or the user's home, they're parsed and
+// drawn by a short-lived process of their own that can't do much if one of them exploits
+// librsvg or libpng. It gets the file on stdin and answers with the pixels.
+pub const RENDER_ARG: &str = "--render-icon";
+const UNPRIVILEGED_USER: &str = "nobody";
+// Icons are a few kilobytes, anything this large isn't one
+const MAX_ICON: u64 = 4 * 1024 * 1024;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+// Plenty for an icon, a renderer taking more is stuck on a hostile file
+const MEMORY_LIMIT: u64 = 512 * 1024 * 1024;
+const CPU_SECONDS: u64 = 5;
+
+fn limit(resource: libc::__rlimit_resource_t, value: u64) -> io::Result<()> {
+    let limit = libc::rlimit { rlim_cur: value, rlim_max: value };
+    if unsafe { libc::setrlimit(resource, &limit) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Gives up everything the renderer doesn't need before it looks at the file
+fn confine() -> io::Result<()> {
+    if unsafe { libc::geteuid() } == 0 {
+        privdrop::PrivDrop::default()
+            .user(UNPRIVILEGED_USER)
+            .apply()
+            .map_err(io::Error::other)?;
+    }
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    limit(libc::RLIMIT_AS, MEMORY_LIMIT)?;
+    limit(libc::RLIMIT_CPU, CPU_SECONDS)?;
+    // Nothing is written but the pixels on stdout
+    limit(libc::RLIMIT_FSIZE, 0)?;
+    limit(libc::RLIMIT_CORE, 0)
+}
+
+fn render_stdin(size: i32) -> Result<Vec<u8>, String> {
+    let mut icon = Vec::new();
+    io::stdin().take(MAX_ICON).read_to_end(&mut icon).map_err(|e| e.to_string())?;
+    let mut surface = ImageSurface::create(Format::ARgb32, size, size).map_err(|e| e.to_string())?;
+    {
+        let c = Context::new(&surface).map_err(|e| e.to_string())?;
+        if icon.starts_with(PNG_SIGNATURE) {
+            let png = ImageSurface::create_from_png(&mut &icon[..]).map_err(|e| e.to_string())?;
+            c.scale(size as f64 / png.width() as f64, size as f64 / png.height() as f64);
+            c.set_source_surface(png, 0.0, 0.0).map_err(|e| e.to_string())?;
+            c.set_antialias(Antialias::Best);
+            c.paint().map_err(|e| e.to_string())?;
+        } else {
+            // Without a file to resolve them against, references to other files aren't followed
+            let handle = Handle::from_data(&icon).map_err(|e| e.to_string())?.ok_or("neither a PNG nor an SVG")?;
+            handle
+                .render_document(&c, &Rectangle::new(0.0, 0.0, size as f64, size as f64))
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    surface.flush();
+    let pixels = surface.data().map_err(|e| e.to_string())?.to_vec();
+    Ok(pixels)
+}
+
+// Public API
+
+// Entry point of tiny-dfr --render-icon <size>
+pub fn run_renderer() {
+    if let Err(e) = confine() {
+        eprintln!("Icon renderer failed to confine itself: {}", e);
+        std::process::exit(1);
+    }
+    let size = std::env::args().nth(2).and_then(|size| size.parse().ok()).unwrap_or(0);
+    if !(1..=1024).contains(&size) {
+        eprintln!("Icon renderer got no valid size");
+        std::process::exit(1);
+    }
+    match render_stdin(size) {
+        Ok(pixels) => {
+            if io::stdout().write_all(&pixels).is_err() {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("Icon renderer failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Draws the PNG or SVG at `path` into a square of `size` pixels in the renderer process
+pub fn render(path: &str, size: i32) -> io::Result<ImageSurface> {
+    if fs::metadata(path)?.len() > MAX_ICON {
+        return Err(io::Error::other("too large for an icon"));
+    }
+    let icon = fs::read(path)?;
+    let stride = Format::ARgb32.stride_for_width(size as u32).map_err(io::Error::other)?;
+    let expected = stride as usize * size as usize;
+    let mut child = Command::new("/proc/self/exe")
+        .arg(RENDER_ARG)
+        .arg(size.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()?;
+    // The renderer reads all of it before answering, and quits on its own when stuck
+    let written = child.stdin.take().map_or(Ok(()), |mut stdin| stdin.write_all(&icon));
+    let mut pixels = Vec::with_capacity(expected);
+    if let Some(stdout) = child.stdout.take() {
+        stdout.take(expected as u64).read_to_end(&mut pixels)?;
+    }
+    let status = child.wait()?;
+    written?;
+    if !status.success() || pixels.len() != expected {
+        return Err(io::Error::other(format!("the icon renderer failed ({})", status)));
+    }
+    ImageSurface::create_for_data(pixels, Format::ARgb32, size, size, stride).map_err(io::Error::other)
+}
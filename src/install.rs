@@ -0,0 +1,91 @@
+use anyhow::{anyhow, Result};
+use std::fs::{self, Permissions};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+
+// Everything needed to run the daemon is built into the binary, so a plain
+// `cargo build` is enough to set it up on distros without a package
+const FILES: [(&str, &str); 9] = [
+    ("/etc/systemd/system/tiny-dfr.service", include_str!("../etc/systemd/system/tiny-dfr.service")),
+    ("/etc/udev/rules.d/99-touchbar-seat.rules", include_str!("../etc/udev/rules.d/99-touchbar-seat.rules")),
+    ("/etc/udev/rules.d/99-touchbar-tiny-dfr.rules", include_str!("../etc/udev/rules.d/99-touchbar-tiny-dfr.rules")),
+    ("/usr/share/dbus-1/system.d/org.tinydfr.Control.conf", include_str!("../etc/dbus-1/system.d/org.tinydfr.Control.conf")),
+    ("/usr/share/polkit-1/actions/org.tinydfr.Control.policy", include_str!("../etc/polkit-1/actions/org.tinydfr.Control.policy")),
+    ("/usr/share/tiny-dfr/config.toml", include_str!("../share/tiny-dfr/config.toml")),
+    ("/usr/share/tiny-dfr/commands.toml", include_str!("../share/tiny-dfr/commands.toml")),
+    ("/usr/share/tiny-dfr/expandables.toml", include_str!("../share/tiny-dfr/expandables.toml")),
+    ("/usr/share/tiny-dfr/hyprland.toml", include_str!("../share/tiny-dfr/hyprland.toml")),
+];
+
+// Copies of the defaults meant to be edited, never overwritten once they exist
+const USER_FILES: [(&str, &str); 2] = [
+    ("/etc/tiny-dfr/config.toml", include_str!("../share/tiny-dfr/config.toml")),
+    ("/etc/tiny-dfr/commands.toml", include_str!("../share/tiny-dfr/commands.toml")),
+];
+
+fn require_root() -> Result<()> {
+    if unsafe { libc::geteuid() } != 0 {
+        return Err(anyhow!("this needs to be run as root"));
+    }
+    Ok(())
+}
+
+fn write_file(path: &str, contents: &str, mode: u32) -> Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents).map_err(|e| anyhow!("failed to write {}: {}", path, e))?;
+    fs::set_permissions(path, Permissions::from_mode(mode))?;
+    println!("Wrote {}", path);
+    Ok(())
+}
+
+// Failures are reported but don't stop the rest, e.g. udevadm is missing in containers
+fn run(program: &str, args: &[&str]) {
+    match Command::new(program).args(args).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => eprintln!("{} {} exited with {}", program, args.join(" "), status),
+        Err(e) => eprintln!("Failed to run {} {}: {}", program, args.join(" "), e),
+    }
+}
+
+pub fn install_service() -> Result<()> {
+    require_root()?;
+    for (path, contents) in FILES {
+        write_file(path, contents, 0o644)?;
+    }
+    for (path, contents) in USER_FILES {
+        if Path::new(path).exists() {
+            println!("Keeping existing {}", path);
+        } else {
+            write_file(path, contents, 0o644)?;
+        }
+    }
+    if !Path::new("/usr/share/tiny-dfr/back.svg").exists() {
+        println!("Icons are not installed, copy share/tiny-dfr/*.svg to /usr/share/tiny-dfr to get them");
+    }
+    run("udevadm", &["control", "--reload-rules"]);
+    run("udevadm", &["trigger"]);
+    run("systemctl", &["daemon-reload"]);
+    run("systemctl", &["enable", "tiny-dfr.service"]);
+    println!("tiny-dfr is installed, it starts once the Touch Bar shows up on the next boot");
+    println!("Edit /etc/tiny-dfr/config.toml to customize it");
+    Ok(())
+}
+
+pub fn uninstall_service() -> Result<()> {
+    require_root()?;
+    run("systemctl", &["disable", "--now", "tiny-dfr.service"]);
+    for (path, _) in FILES {
+        match fs::remove_file(path) {
+            Ok(()) => println!("Removed {}", path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("Failed to remove {}: {}", path, e),
+        }
+    }
+    run("systemctl", &["daemon-reload"]);
+    run("udevadm", &["control", "--reload-rules"]);
+    println!("tiny-dfr is uninstalled, your configuration in /etc/tiny-dfr was kept");
+    Ok(())
+}
@@ -3,11 +3,31 @@ use std::{
     fs::{self, File, OpenOptions},
     io::Write,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
 };
 
 const DEFAULT_KEYBOARD_BRIGHTNESS: u32 = 128;
 const KEYBOARD_BRIGHTNESS_STEP: u32 = 1466;
 
+// Whether the backlight is at full, shown on Torch buttons however it got there
+static TORCH_ON: AtomicBool = AtomicBool::new(false);
+// Bumped whenever that changes so the main loop knows to redraw
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn set_torch_on(on: bool) {
+    if TORCH_ON.swap(on, Ordering::Relaxed) != on {
+        GENERATION.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn torch_on() -> bool {
+    TORCH_ON.load(Ordering::Relaxed)
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
 pub struct KeyboardBacklightManager {
     kbd_bl_file: Option<File>,
     max_brightness: u32,
@@ -37,6 +57,7 @@ impl KeyboardBacklightManager {
                 let current_bl = read_attr(&path, "brightness").unwrap_or(0);
                 
                 println!("Keyboard backlight - Max: {}, Current: {}", max_bl, current_bl);
+                set_torch_on(file.is_some() && current_bl >= max_bl);
                 
                 (file, max_bl, current_bl)
             } else {
@@ -91,6 +112,20 @@ impl KeyboardBacklightManager {
         false
     }
 
+    // Straight to full in one tap, or off again when it already is
+    pub fn toggle_torch(&mut self) -> bool {
+        if self.kbd_bl_file.is_none() {
+            eprintln!("No keyboard backlight to turn into a torch");
+            return false;
+        }
+        let target = if self.current_brightness >= self.max_brightness { 0 } else { self.max_brightness };
+        if self.set_brightness(target) {
+            println!("Keyboard backlight {}", if target == 0 { "off" } else { "at full" });
+            return true;
+        }
+        false
+    }
+
     pub fn set_brightness(&mut self, brightness: u32) -> bool {
         if let Some(ref mut file) = self.kbd_bl_file {
             let clamped_brightness = brightness.min(self.max_brightness);
@@ -101,6 +136,7 @@ impl KeyboardBacklightManager {
                     match file.flush() {
                         Ok(()) => {
                             self.current_brightness = clamped_brightness;
+                            set_torch_on(clamped_brightness >= self.max_brightness);
                             return true;
                         }
                         Err(e) => {
@@ -141,7 +177,7 @@ impl KeyboardBacklightManager {
     }
 }
 
-fn find_keyboard_backlight() -> Result<PathBuf> {
+pub fn find_keyboard_backlight() -> Result<PathBuf> {
     // Priority 1: T2 Mac specific path
     let t2_path = PathBuf::from("/sys/class/leds/:white:kbd_backlight");
     if t2_path.exists() && t2_path.join("brightness").exists() {
@@ -210,4 +246,17 @@ mod tests {
 
         assert_eq!(manager.brightness_percentage(), 50.0);
     }
+
+    #[test]
+    fn test_torch_without_backlight() {
+        let mut manager = KeyboardBacklightManager {
+            kbd_bl_file: None,
+            max_brightness: 200,
+            current_brightness: 100,
+            brightness_step: 25,
+        };
+
+        assert!(!manager.toggle_torch());
+        assert_eq!(manager.current_brightness(), 100);
+    }
 }
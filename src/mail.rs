@@ -0,0 +1,140 @@
+use crate::poller::Poller;
+use crate::privileged::Command;
+use crate::user_cache;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_REFRESH: Duration = Duration::from_secs(60);
+
+#[derive(Clone, PartialEq)]
+enum Source {
+    Command(String),
+    Maildir(String), // may start with ~/ for the logged in user's home
+}
+
+impl Source {
+    fn key(&self) -> &str {
+        match self {
+            Source::Command(command) => command,
+            Source::Maildir(dir) => dir,
+        }
+    }
+}
+
+static WATCHED: Poller<Source> = Poller::new();
+// Unread count of every source, keyed by its command or Maildir
+static COUNTS: std::sync::LazyLock<Arc<Mutex<HashMap<String, u32>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
+// Bumped whenever a count changes so the main loop knows to redraw
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// Only started once a mail widget is created
+static MAIL_MONITOR: std::sync::LazyLock<thread::JoinHandle<()>> =
+    std::sync::LazyLock::new(|| thread::spawn(monitor_loop));
+
+fn resolve_maildir(dir: &str) -> Option<PathBuf> {
+    match dir.strip_prefix("~/") {
+        Some(rest) => Some(PathBuf::from(user_cache::get_cached_user_environment()?.home_dir).join(rest)),
+        None => Some(PathBuf::from(dir)),
+    }
+}
+
+// New mail is delivered into new/ and moved to cur/ once a client has seen it
+fn count_maildir(dir: &str) -> Option<u32> {
+    let entries = fs::read_dir(resolve_maildir(dir)?.join("new")).ok()?;
+    let count = entries
+        .flatten()
+        .filter(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .count();
+    Some(count as u32)
+}
+
+// Runs the mail check as the logged in user, it prints the number of unread messages
+fn count_command(command: &str) -> Option<u32> {
+    let env = user_cache::get_cached_user_environment()?;
+    let output = match Command::login_shell(&env.username, command).output() {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("Failed to run mail check '{}': {}", command, e);
+            return None;
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.split_whitespace().next().and_then(|count| count.parse().ok()) {
+        Some(count) => Some(count),
+        None => {
+            eprintln!("Mail check '{}' did not print a count", command);
+            None
+        }
+    }
+}
+
+fn monitor_loop() {
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK)
+        .map_err(|e| eprintln!("Failed to watch Maildirs, checking them every minute instead: {}", e))
+        .ok();
+    let flags = AddWatchFlags::IN_CREATE
+        | AddWatchFlags::IN_DELETE
+        | AddWatchFlags::IN_MOVED_TO
+        | AddWatchFlags::IN_MOVED_FROM;
+    // Maildirs whose new/ is watched
+    let mut watching = HashSet::new();
+    loop {
+        let delivered = inotify.as_ref().is_some_and(|inotify| inotify.read_events().is_ok_and(|events| !events.is_empty()));
+        // The home directory is only known once someone logged in
+        if let Some(inotify) = &inotify {
+            for source in WATCHED.keys() {
+                if let Source::Maildir(dir) = source {
+                    if !watching.contains(&dir) && resolve_maildir(&dir).is_some_and(|path| inotify.add_watch(&path.join("new"), flags).is_ok()) {
+                        watching.insert(dir);
+                    }
+                }
+            }
+        }
+        for source in WATCHED.due(|source| delivered && matches!(source, Source::Maildir(_))) {
+            let count = match &source {
+                Source::Maildir(dir) => count_maildir(dir),
+                Source::Command(command) => count_command(command),
+            };
+            let Some(count) = count else {
+                continue;
+            };
+            if let Ok(mut counts) = COUNTS.lock() {
+                if counts.insert(source.key().to_string(), count) != Some(count) {
+                    GENERATION.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+fn watch(source: Source, refresh_seconds: Option<u64>) {
+    let refresh = refresh_seconds.map_or(DEFAULT_REFRESH, |seconds| Duration::from_secs(seconds.max(5)));
+    WATCHED.watch(source, refresh);
+    std::sync::LazyLock::force(&MAIL_MONITOR);
+}
+
+// Public API
+
+pub fn watch_command(command: &str, refresh_seconds: Option<u64>) {
+    watch(Source::Command(command.to_string()), refresh_seconds);
+}
+
+pub fn watch_maildir(dir: &str) {
+    watch(Source::Maildir(dir.to_string()), None);
+}
+
+pub fn unread(key: &str) -> Option<u32> {
+    COUNTS.lock().ok()?.get(key).copied()
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
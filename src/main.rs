@@ -1,7 +1,7 @@
 use anyhow::{anyhow, Result};
-use cairo::{Antialias, Context, Format, ImageSurface, Surface};
+use cairo::{Antialias, Context, FontSlant, FontWeight, Format, ImageSurface, Operator, Surface};
 use chrono::{Local, Locale, Timelike, format::{StrftimeItems, Item as ChronoItem}};
-use drm::control::ClipRect;
+use drm::{buffer::DrmFourcc, control::ClipRect};
 use freedesktop_icons::lookup;
 use input::{
     event::{
@@ -12,7 +12,7 @@ use input::{
     },
     Device as InputDevice, Libinput, LibinputInterface,
 };
-use input_linux::{uinput::UInputHandle, EventKind, Key, SynchronizeKind};
+use input_linux::{uinput::UInputHandle, EventKind, Key, RelativeAxis, SynchronizeKind};
 use input_linux_sys::{input_event, input_id, timeval, uinput_setup};
 use libc::{c_char, O_ACCMODE, O_RDONLY, O_RDWR, O_WRONLY};
 use librsvg_rebind::{prelude::HandleExt, Handle, Rectangle};
@@ -21,15 +21,16 @@ use nix::{
     sys::{
         epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags},
         signal::{SigSet, Signal},
+        signalfd::{SfdFlags, SignalFd},
     },
 };
 use std::{
     cmp::min,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs::{self, File, OpenOptions},
     os::{
         fd::{AsFd, AsRawFd},
-        unix::{fs::OpenOptionsExt, io::OwnedFd},
+        unix::{fs::{FileExt, OpenOptionsExt}, io::OwnedFd},
     },
     panic::{self, AssertUnwindSafe},
     path::{Path, PathBuf},
@@ -39,34 +40,115 @@ use udev::MonitorBuilder;
 mod backlight;
 mod battery_monitor;
 mod config;
+mod consumer;
+mod dbus;
 mod display;
+mod displays;
 mod fonts;
+mod gamepad;
+mod gestures;
+mod haptics;
 mod hyprland;
 mod icon_cache;
+mod icon_sandbox;
+mod install;
 mod keyboard_backlight;
+mod mail;
+mod media_state;
+mod metrics;
+mod midi;
+mod mpris;
+mod notifications;
+mod ambient_light;
+mod audio;
+mod burn_in;
+mod caffeine;
+mod calculator;
+mod osd;
+mod overlay;
 mod pixel_shift;
+mod poller;
+mod privileged;
+mod probe;
+mod rfkill;
+mod session_lock;
+mod status;
+mod script;
+mod sway;
 mod system_monitor;
+mod template;
+mod toplevel;
+mod slider;
+mod ticker;
 mod user_cache;
+mod world_clock;
 
 use crate::config::ConfigManager;
 use crate::battery_monitor::BatteryState;
+use crate::media_state::PlaybackStatus;
 use backlight::BacklightManager;
-use config::{ButtonConfig, Config, ButtonAction, ButtonColor};
+use calculator::CalculatorKey;
+use dbus::ControlCommand;
+use config::{Alarm, ButtonConfig, Config, ButtonAction, ButtonColor, BatteryStateStyle, DimmedContentStyle, FnTap, LayoutEdit, PinchConfig, ScrollAxis, SliderKind, WorkspaceTarget, CONFIG_ERRORS_EXPANDABLE};
 use display::DrmBackend;
+use gestures::{GestureTracker, Swipe};
 use keyboard_backlight::KeyboardBacklightManager;
-use pixel_shift::{PixelShiftManager, PIXEL_SHIFT_WIDTH_PX};
+use burn_in::BurnInTracker;
+use osd::OsdManager;
+use overlay::OverlayLayer;
+use pixel_shift::PixelShiftManager;
+use status::StatusItem;
+use template::TextTemplate;
 
 const BUTTON_SPACING_PX: i32 = 16;
 const BUTTON_COLOR_INACTIVE: f64 = 0.200;
 const BUTTON_COLOR_ACTIVE: f64 = 0.400;
 const ICON_SIZE: i32 = 48;
+// Icons drawn by the icon sandbox get enough pixels to stay sharp up to Scale = 2
+const SANDBOXED_ICON_SIZE: i32 = ICON_SIZE * 2;
 const TIMEOUT_MS: i32 = 10 * 1000;
+const AMBIENT_LIGHT_INTERVAL_MS: i32 = 2 * 1000;
+const CONFIRM_TIMEOUT_MS: i32 = 5 * 1000;
+const LONG_PRESS_DURATION: std::time::Duration = std::time::Duration::from_millis(500);
+// An alarm nobody answers stops on its own after a while
+const ALARM_RING_DURATION: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+const ALARM_FLASH_MS: u128 = 500;
+// How long to look for the new timezone after /etc/localtime changed, and how often
+const LOCALTIME_SETTLE: std::time::Duration = std::time::Duration::from_secs(2);
+const LOCALTIME_POLL_MS: i32 = 250;
+// How often a shown mail widget looks for changed unread counts
+const MAIL_POLL_MS: i32 = 1000;
+const ICON_POLL_MS: i32 = 20;
+// How far a finger has to quickly move sideways to swipe, before scaling
+const SWIPE_SIDEWAYS_PX: f64 = 150.0;
+// How often a shown slider looks for levels changed elsewhere
+const SLIDER_POLL_MS: i32 = 250;
+// Second tap on an explored button within this long activates it
+const EXPLORE_DOUBLE_TAP: std::time::Duration = std::time::Duration::from_millis(400);
+// Crashes after which the configuration is ignored in favour of plain function keys
+const SAFE_MODE_AFTER_CRASHES: u32 = 3;
+// Crashes are counted here across restarts, tiny-dfr can't start over in-process once it runs as nobody
+const CRASH_COUNT_PATH: &str = "/run/tiny-dfr/crashes";
+// Crashes longer ago than this are forgotten
+const CRASH_COUNT_EXPIRY: std::time::Duration = std::time::Duration::from_secs(600);
+// How much less often periodically refreshed buttons update in battery saver
+const BATTERY_SAVER_REFRESH_FACTOR: u32 = 4;
 
 #[derive(Clone, Debug)]
 struct NavigationState {
     navigation_stack: Vec<String>,
     current_expandable: Option<String>,
     last_interaction_time: std::time::Instant,
+    // Set while a confirmation prompt replaces the active layer
+    confirming: Option<std::time::Instant>,
+    // Expandable brought up by a workspace binding, kept open until the workspace changes
+    pinned_expandable: Option<String>,
+    // Set by a LockLayer button, keeps Fn, timeouts and Hyprland from switching away
+    locked: bool,
+    // Layer showing the prompt of an alarm going off, and since when
+    alarm: Option<(usize, std::time::Instant)>,
+    // Alarms to go off again once their snooze is up
+    snoozed_alarms: Vec<(usize, std::time::Instant)>,
 }
 
 #[derive(Clone, Debug)]
@@ -83,6 +165,11 @@ impl NavigationState {
             navigation_stack: Vec::new(),
             current_expandable: None,
             last_interaction_time: std::time::Instant::now(),
+            confirming: None,
+            pinned_expandable: None,
+            locked: false,
+            alarm: None,
+            snoozed_alarms: Vec::new(),
         }
     }
 
@@ -108,9 +195,27 @@ impl NavigationState {
         }
     }
 
+    // Jump back to an ancestor level: 0 is the main layer, 1 the first expandable opened, ...
+    fn jump_to_level(&mut self, level: usize) -> bool {
+        if level > self.navigation_stack.len() {
+            return false;
+        }
+        if level == 0 {
+            self.reset_to_main();
+        } else {
+            self.current_expandable = Some(self.navigation_stack[level - 1].clone());
+            self.navigation_stack.truncate(level - 1);
+            self.last_interaction_time = std::time::Instant::now();
+        }
+        true
+    }
+
     fn reset_to_main(&mut self) {
         self.navigation_stack.clear();
         self.current_expandable = None;
+        self.confirming = None;
+        self.alarm = None;
+        self.pinned_expandable = None;
         self.last_interaction_time = std::time::Instant::now();
     }
 
@@ -120,23 +225,46 @@ impl NavigationState {
 
     fn should_timeout(&self, timeout_seconds: u32) -> bool {
         timeout_seconds > 0 &&
+        !self.locked &&
         self.current_expandable.is_some() &&
+        self.current_expandable != self.pinned_expandable &&
         self.last_interaction_time.elapsed().as_secs() >= timeout_seconds as u64
     }
 }
 
-#[derive(Clone)]
-struct BatteryImages {
-    plain: Vec<Handle>,
-    charging: Vec<Handle>,
-    bolt: Handle,
+// Battery glyphs are only parsed once a battery button first shows them, and shared by
+// every battery button using the same theme. Handles can't leave the main thread.
+thread_local! {
+    static BATTERY_ICONS: std::cell::RefCell<HashMap<String, Option<Icon>>> = std::cell::RefCell::new(HashMap::new());
+}
+
+fn battery_icon(name: &str, theme: Option<&str>) -> Option<Icon> {
+    let key = format!("{}:{}", name, theme.unwrap_or(""));
+    BATTERY_ICONS.with(|icons| {
+        icons
+            .borrow_mut()
+            .entry(key)
+            .or_insert_with(|| match try_load_image(name, theme).map(ButtonImage::into_icon) {
+                Ok(Some(icon)) => Some(icon),
+                Ok(None) => {
+                    eprintln!("failed to load battery icon {}: not an image", name);
+                    None
+                }
+                Err(err) => {
+                    eprintln!("failed to load battery icon {}: {:?}", name, err);
+                    None
+                }
+            })
+            .clone()
+    })
 }
 
 #[derive(Eq, PartialEq, Copy, Clone)]
 enum BatteryIconMode {
     Percentage,
     Icon,
-    Both
+    Both,
+    Remaining,
 }
 
 impl BatteryIconMode {
@@ -154,8 +282,136 @@ enum ButtonImage {
     Svg(Handle),
     Bitmap(ImageSurface),
     Time(Vec<ChronoItem<'static>>, Locale),
-    Battery(String, BatteryIconMode, BatteryImages),
-    TextWithIcon(String, Handle),
+    Battery(String, BatteryIconMode, Option<String>, BatteryStateStyle), // device, mode, icon theme and style
+    TextWithIcon(String, Icon),
+    AmbientLight(AmbientLightMode),
+    BatteryHistory(u32), // hours shown
+    Playback(Icon, Icon, Icon), // configured icon, play and pause glyphs
+    Template(TextTemplate),
+    Status(Vec<StatusItem>), // one item per tap zone
+    WorldClock(String, String), // city and zone
+    Ticker(config::TickerConfig, usize), // index of the symbol shown
+    Mail(Icon, String), // envelope and the command or Maildir counted
+    Script(String, String), // command and what's shown until it printed something
+    Pending(String, String, Option<String>), // placeholder text until the icon and theme are found
+    MediaPlayer(String), // shown while nothing plays
+    Slider(SliderKind),
+    Calculator,
+    Volume(Icon, Icon), // volume and muted icons
+    Workspaces(Vec<hyprland::HyprlandWorkspace>), // one pill and tap zone each
+}
+
+impl ButtonImage {
+    // For widgets drawing an icon along with something else
+    fn into_icon(self) -> Option<Icon> {
+        match self {
+            ButtonImage::Svg(svg) => Some(Icon::Svg(svg)),
+            ButtonImage::Bitmap(surface) => Some(Icon::Pixels(surface)),
+            _ => None,
+        }
+    }
+}
+
+// An icon parsed here, or drawn into pixels beforehand by the icon sandbox
+#[derive(Clone)]
+enum Icon {
+    Svg(Handle),
+    Pixels(ImageSurface),
+}
+
+impl Icon {
+    // Fills the square like Handle::render_document does
+    fn render_document(&self, c: &Context, viewport: &Rectangle) -> Result<()> {
+        match self {
+            Icon::Svg(svg) => svg.render_document(c, viewport)?,
+            Icon::Pixels(surface) => draw_pixels(c, surface, viewport.x(), viewport.y(), viewport.width())?,
+        }
+        Ok(())
+    }
+}
+
+// Bitmaps come in whatever size they were loaded at, stretched to a square of `size`
+fn draw_pixels(c: &Context, surface: &ImageSurface, x: f64, y: f64, size: f64) -> Result<()> {
+    c.save()?;
+    c.translate(x, y);
+    c.scale(size / surface.width() as f64, size / surface.height() as f64);
+    c.set_source_surface(surface, 0.0, 0.0)?;
+    c.rectangle(0.0, 0.0, surface.width() as f64, surface.height() as f64);
+    c.fill()?;
+    c.restore()?;
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum AmbientLightMode {
+    Lux,
+    Brightness,
+    Both,
+}
+
+#[derive(Clone)]
+enum Background {
+    Svg(Handle),
+    Bitmap(ImageSurface),
+}
+
+impl Background {
+    fn load(name: &str) -> Result<Background> {
+        let locations = if name.starts_with('/') {
+            vec![PathBuf::from(name)]
+        } else {
+            vec![
+                PathBuf::from(format!("/etc/tiny-dfr/{name}.svg")),
+                PathBuf::from(format!("/etc/tiny-dfr/{name}.png")),
+                PathBuf::from(format!("/usr/share/tiny-dfr/{name}.svg")),
+                PathBuf::from(format!("/usr/share/tiny-dfr/{name}.png")),
+            ]
+        };
+
+        for location in locations {
+            if !location.exists() {
+                continue;
+            }
+            return match location.extension().and_then(|s| s.to_str()) {
+                Some("png") => {
+                    let mut file = File::open(&location)?;
+                    Ok(Background::Bitmap(ImageSurface::create_from_png(&mut file)?))
+                }
+                Some("svg") => Ok(Background::Svg(
+                    Handle::from_file(location.to_str().ok_or(anyhow!("image path is not unicode"))?)?
+                        .ok_or(anyhow!("failed to load image"))?,
+                )),
+                _ => Err(anyhow!("invalid file extension")),
+            };
+        }
+        Err(anyhow!("background image {name} not found"))
+    }
+
+    fn paint(&self, c: &Context, width: f64, height: f64) {
+        match self {
+            Background::Svg(svg) => {
+                svg.render_document(c, &Rectangle::new(0.0, 0.0, width, height))
+                    .unwrap();
+            }
+            Background::Bitmap(surf) => {
+                c.save().unwrap();
+                c.scale(width / surf.width() as f64, height / surf.height() as f64);
+                c.set_source_surface(surf, 0.0, 0.0).unwrap();
+                c.paint().unwrap();
+                c.restore().unwrap();
+            }
+        }
+    }
+}
+
+// Where a button's content comes from, so dynamic buttons can be refreshed
+// without guessing from what they currently display
+#[derive(Clone, Copy, PartialEq)]
+enum ButtonSource {
+    Static,
+    ActiveWindowTitle, // Text = "plugin-hyprland"
+    ActiveWindowIcon,  // Icon = "plugin-hyprland" or "plugin-hyprland-icon"
+    Template,          // Text with placeholders, filled in when drawn
 }
 
 #[derive(Clone)]
@@ -166,15 +422,69 @@ struct Button {
     action: ButtonAction,
     show_outline: Option<bool>,
     outline_color: Option<ButtonColor>,
+    source: ButtonSource,
+    confirm: bool,
+    touch_sound: Option<bool>, // overrides the global TouchSound
+    // Actions of equally wide tap zones across the button, empty for a single zone
+    zones: Vec<ButtonAction>,
+    active_zone: Option<usize>,
+    // Keys sent for every step of a horizontal drag to the left and to the right
+    scrub: Option<(Key, Key)>,
+    // Seconds counted down on the button before its action fires
+    countdown: Option<u32>,
+    countdown_until: Option<std::time::Instant>,
+    // How long a tap keeps the button's key pressed, released by a pending action
+    hold_for: Option<std::time::Duration>,
+    // Done instead of the action when held for LONG_PRESS_DURATION, the tap then fires on release
+    long_press: Option<ButtonAction>,
+    pressed_at: Option<std::time::Instant>,
+    scroll: Option<ScrollAxis>,
+    pinch: Option<PinchConfig>,
+}
+
+// Parsed SVGs and sandbox-drawn icons by path, so buttons on both layers and the layers of a
+// reloaded config share one image per file for as long as the file isn't modified
+thread_local! {
+    static IMAGES: std::cell::RefCell<HashMap<String, (std::time::SystemTime, ButtonImage)>> = std::cell::RefCell::new(HashMap::new());
+}
+
+// tiny-dfr's own icons are decoded here, the ones of icon themes could come from anywhere
+fn trusted_icon(path: &str) -> bool {
+    (path.starts_with("/etc/tiny-dfr/") || path.starts_with("/usr/share/tiny-dfr/")) && !path.contains("/../")
+}
+
+fn cached_image(path: &str, load: impl FnOnce() -> Result<ButtonImage>) -> Result<ButtonImage> {
+    let modified = fs::metadata(path)?.modified()?;
+    let cached = IMAGES.with(|images| {
+        images
+            .borrow()
+            .get(path)
+            .filter(|(cached_modified, _)| *cached_modified == modified)
+            .map(|(_, image)| image.clone())
+    });
+    if let Some(image) = cached {
+        return Ok(image);
+    }
+    let image = load()?;
+    IMAGES.with(|images| images.borrow_mut().insert(path.to_string(), (modified, image.clone())));
+    Ok(image)
 }
 
 fn try_load_svg(path: &str) -> Result<ButtonImage> {
-    Ok(ButtonImage::Svg(
-        Handle::from_file(path)?.ok_or(anyhow!("failed to load image"))?,
-    ))
+    cached_image(path, || {
+        Ok(if trusted_icon(path) {
+            ButtonImage::Svg(Handle::from_file(path)?.ok_or(anyhow!("failed to load image"))?)
+        } else {
+            ButtonImage::Bitmap(icon_sandbox::render(path, SANDBOXED_ICON_SIZE)?)
+        })
+    })
 }
 
 fn try_load_png(path: impl AsRef<Path>) -> Result<ButtonImage> {
+    let path = path.as_ref().to_str().ok_or(anyhow!("icon path isn't UTF-8"))?;
+    if !trusted_icon(path) {
+        return cached_image(path, || Ok(ButtonImage::Bitmap(icon_sandbox::render(path, ICON_SIZE)?)));
+    }
     let mut file = File::open(path)?;
     let surf = ImageSurface::create_from_png(&mut file)?;
     if surf.height() == ICON_SIZE && surf.width() == ICON_SIZE {
@@ -257,7 +567,11 @@ fn try_load_image_sync(name: &str, theme: Option<&str>) -> Result<ButtonImage> {
         };
 
         match result {
-            Ok(image) => return Ok(image),
+            Ok(image) => {
+                // Spares the next lookup, and the next start, from searching again
+                icon_cache::icon_resolved(name, theme, &location);
+                return Ok(image);
+            }
             Err(err) => {
                 last_err = err.context(format!("while loading path {}", location.display()));
             }
@@ -268,6 +582,35 @@ fn try_load_image_sync(name: &str, theme: Option<&str>) -> Result<ButtonImage> {
     Err(last_err.context(format!("failed loading all possible paths for icon {name}")))
 }
 
+// Swaps in the icons the background loader has found since, returns whether any are still missing
+fn resolve_pending_icons(layers: &mut [FunctionLayer]) -> bool {
+    let mut pending = false;
+    for layer in layers.iter_mut() {
+        for (_, button) in &mut layer.buttons {
+            let ButtonImage::Pending(_, name, theme) = &button.image else {
+                continue;
+            };
+            if icon_cache::get_icon_cached(name.clone(), theme.clone()).is_some() {
+                button.image = match try_load_image(name, theme.as_deref()) {
+                    Ok(image) => image,
+                    Err(err) => {
+                        eprintln!("failed to load icon: {:?}", err);
+                        ButtonImage::Text(name.clone())
+                    }
+                };
+                button.changed = true;
+            } else if icon_cache::icon_missing(name, theme.as_deref()) {
+                eprintln!("failed to load icon: no icon named {} was found", name);
+                button.image = ButtonImage::Text(name.clone());
+                button.changed = true;
+            } else {
+                pending = true;
+            }
+        }
+    }
+    pending
+}
+
 fn find_battery_device() -> Option<String> {
     let power_supply_path = "/sys/class/power_supply";
     if let Ok(entries) = fs::read_dir(power_supply_path) {
@@ -286,57 +629,117 @@ fn find_battery_device() -> Option<String> {
     None
 }
 
-fn get_battery_state(battery: &str) -> (u32, BatteryState) {
-    // Try to get cached battery state first
-    if let Some(cached_state) = battery_monitor::get_cached_battery_state() {
-        return cached_state;
-    }
-
-    // Fallback to direct reading if cache is not available
-    get_battery_state_direct(battery)
-}
-
-fn get_battery_state_direct(battery: &str) -> (u32, BatteryState) {
-    let status_path = format!("/sys/class/power_supply/{}/status", battery);
-    let status = fs::read_to_string(&status_path)
-        .unwrap_or_else(|_| "Unknown".to_string());
-
-    #[cfg(target_arch = "x86_64")]
-    let capacity = {
-        let charge_now_path = format!("/sys/class/power_supply/{}/charge_now", battery);
-        let charge_full_path = format!("/sys/class/power_supply/{}/charge_full", battery);
-        let charge_now = fs::read_to_string(&charge_now_path)
-            .ok()
-            .and_then(|s| s.trim().parse::<f64>().ok());
-        let charge_full = fs::read_to_string(&charge_full_path)
-            .ok()
-            .and_then(|s| s.trim().parse::<f64>().ok());
-        match (charge_now, charge_full) {
-            (Some(now), Some(full)) if full > 0.0 => ((now / full) * 100.0).round() as u32,
-            _ => 100,
+// Whether any mains power supply, i.e. the charger, is connected
+// An external monitor on its own might just be a projector, the charger too makes a dock
+fn is_docked(external_displays: &[String], charger: Option<bool>) -> bool {
+    !external_displays.is_empty() && charger == Some(true)
+}
+
+// Brings up the [Docked] layers, or the usual ones again, when docking or undocking
+fn update_docked(cfg: &Config, cfg_mgr: &mut ConfigManager, external_displays: &[String], charger: Option<bool>) {
+    let docked = is_docked(external_displays, charger);
+    if docked == config::docked() {
+        return;
+    }
+    println!("{}", if docked { "Docked" } else { "Undocked" });
+    config::set_docked(docked);
+    if cfg.has_docked_profile {
+        cfg_mgr.request_reload();
+        media_state::wake();
+    }
+}
+
+fn charger_online() -> Option<bool> {
+    let entries = fs::read_dir("/sys/class/power_supply").ok()?;
+    let mut found = None;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if fs::read_to_string(path.join("type")).is_ok_and(|typ| typ.trim() == "Mains") {
+            let online = fs::read_to_string(path.join("online")).is_ok_and(|online| online.trim() == "1");
+            found = Some(found.unwrap_or(false) || online);
         }
-    };
+    }
+    found
+}
 
-    #[cfg(target_arch = "aarch64")]
-    let capacity = {
-        let capacity_path = format!("/sys/class/power_supply/{}/capacity", battery);
-        fs::read_to_string(&capacity_path)
-            .ok()
-            .and_then(|s| s.trim().parse::<u32>().ok())
-            .unwrap_or(100)
-    };
+// From the battery monitor, read here only when there's none for this machine's battery
+fn get_battery_state(battery: &str) -> (u32, BatteryState) {
+    battery_monitor::get_cached_battery_state().unwrap_or_else(|| {
+        let info = battery_monitor::read_battery_state(battery);
+        (info.capacity, info.state)
+    })
+}
 
-    let status = match status.trim() {
-        "Charging" | "Full" => BatteryState::Charging,
-        "Discharging" if capacity < 10 => BatteryState::Low,
-        _ => BatteryState::NotCharging,
+// Whether the battery is discharging at or below BatterySaverThreshold
+fn battery_saver_wanted(cfg: &Config, battery: Option<&str>) -> bool {
+    let Some(battery) = battery else {
+        return false;
     };
-    (capacity, status)
+    if cfg.battery_saver_threshold == 0 {
+        return false;
+    }
+    // "Not charging" is also reported on AC once a charge limit is reached
+    let discharging = fs::read_to_string(format!("/sys/class/power_supply/{}/status", battery))
+        .is_ok_and(|status| status.trim() == "Discharging");
+    discharging && get_battery_state(battery).0 <= cfg.battery_saver_threshold
 }
 
 impl Button {
-    fn with_config(cfg: ButtonConfig) -> Button {
-        let mut button = if let Some(text) = cfg.text {
+    // Everything else is filled in by with_config or the constructors below
+    fn new(image: ButtonImage, action: ButtonAction) -> Button {
+        Button {
+            image,
+            changed: false,
+            active: false,
+            action,
+            show_outline: None,
+            outline_color: None,
+            source: ButtonSource::Static,
+            confirm: false,
+            touch_sound: None,
+            zones: Vec::new(),
+            active_zone: None,
+            scrub: None,
+            countdown: None,
+            countdown_until: None,
+            hold_for: None,
+            long_press: None,
+            pressed_at: None,
+            scroll: None,
+            pinch: None,
+        }
+    }
+    fn with_config(cfg: ButtonConfig) -> Result<Button, String> {
+        let mut button = if let Some(zone) = cfg.world_clock {
+            let city = cfg.text.unwrap_or_else(|| zone.rsplit('/').next().unwrap_or(&zone).replace('_', " "));
+            let mut button = Button::new_text(city.clone(), cfg.action);
+            if world_clock::watch_zone(&zone) {
+                button.image = ButtonImage::WorldClock(city, zone);
+            }
+            button
+        } else if let Some(ticker) = cfg.ticker {
+            Button::new_ticker(cfg.action, ticker)
+        } else if let Some(mail) = cfg.mail {
+            Button::new_mail(cfg.action, mail, cfg.icon, cfg.theme)
+        } else if let Some(script) = cfg.script {
+            script::watch(&script.command, script.interval);
+            Button::new(ButtonImage::Script(script.command, cfg.text.unwrap_or_else(|| "…".to_string())), cfg.action)
+        } else if cfg.now_playing == Some(true) {
+            Button::new_now_playing(cfg.action, cfg.text)
+        } else if cfg.volume == Some(true) {
+            Button::new_volume(cfg.action, cfg.theme)
+        } else if cfg.workspaces == Some(true) {
+            Button::new_workspaces(cfg.action)
+        } else if cfg.calculator == Some(true) {
+            let mut button = Button::new_text(String::new(), cfg.action);
+            button.image = ButtonImage::Calculator;
+            button
+        } else if let Some(kind) = cfg.slider {
+            slider::initialize_slider_monitor();
+            let mut button = Button::new_text(String::new(), cfg.action);
+            button.image = ButtonImage::Slider(kind);
+            button
+        } else if let Some(text) = cfg.text {
             if text == "plugin-hyprland" {
                 // Get Hyprland active window text - use "title" as default button title
                 let (window_text, window_class) = match hyprland::get_active_window_info() {
@@ -352,7 +755,7 @@ impl Button {
                 };
 
                 // For Hyprland buttons, always try to show app icon if available
-                if !window_class.is_empty() {
+                let mut button = if !window_class.is_empty() {
                     // Try custom app_icon first, then auto-detect from class
                     let icon_name = if let Some(app_icon) = &cfg.app_icon {
                         app_icon.clone()
@@ -368,7 +771,11 @@ impl Button {
                     }
                 } else {
                     Button::new_text(window_text, cfg.action)
-                }
+                };
+                button.source = ButtonSource::ActiveWindowTitle;
+                button
+            } else if let Some(template) = TextTemplate::parse(&text, cfg.locale.as_deref()) {
+                Button::new_template(template, cfg.action)
             } else {
                 Button::new_text(text, cfg.action)
             }
@@ -416,40 +823,167 @@ impl Button {
                 });
 
                 Button {
-                    action: cfg.action,
-                    image: final_icon,
-                    active: false,
-                    changed: false,
-                    show_outline: None,
-                    outline_color: None,
+                    source: ButtonSource::ActiveWindowIcon,
+                    ..Button::new(final_icon, cfg.action)
                 }
             } else {
-                Button::new_icon(&icon, cfg.theme, cfg.action)
+                Button::new_icon(&icon, cfg.theme, cfg.action)?
             }
         } else if let Some(time) = cfg.time {
-            Button::new_time(cfg.action, &time, cfg.locale.as_deref())
+            Button::new_time(cfg.action, &time, cfg.locale.as_deref())?
         } else if let Some(battery_mode) = cfg.battery {
             if let Some(battery) = find_battery_device() {
-                Button::new_battery(cfg.action, battery, battery_mode, cfg.theme)
+                Button::new_battery(cfg.action, battery, battery_mode, cfg.battery_state_style.unwrap_or_default(), cfg.theme)?
             } else {
                 Button::new_text("Battery N/A".to_string(), cfg.action)
             }
+        } else if let Some(hours) = cfg.battery_history {
+            Button::new_battery_history(cfg.action, hours)
+        } else if let Some(items) = cfg.status {
+            Button::new_status(cfg.action, items)
+        } else if let Some(mode) = cfg.ambient_light {
+            Button::new_ambient_light(cfg.action, &mode)?
         } else {
-            panic!("Invalid config, a button must have either Text, Icon or Time")
+            return Err(format!("{} has no Text, Icon or Time to show", cfg.action.label()));
         };
 
+        // Play/pause buttons follow the player, keeping the configured icon while there is none
+        if button.action == ButtonAction::Key(Key::PlayPause) {
+            if let Some(idle) = button.image.clone().into_icon() {
+                if let (Some(play), Some(pause)) = (
+                    try_load_image("play", None::<&str>).ok().and_then(ButtonImage::into_icon),
+                    try_load_image("pause", None::<&str>).ok().and_then(ButtonImage::into_icon),
+                ) {
+                    button.image = ButtonImage::Playback(idle, play, pause);
+                    media_state::initialize_media_monitor();
+                }
+            }
+        }
+
+        if button.action == ButtonAction::AirplaneMode {
+            rfkill::initialize_rfkill_monitor();
+        }
+        if button.action == ButtonAction::LockSession {
+            session_lock::initialize_lock_monitor();
+        }
+
         button.show_outline = cfg.show_button_outlines;
         button.outline_color = cfg.button_outlines_color;
-        button
+        button.confirm = cfg.confirm.unwrap_or(false);
+        button.touch_sound = cfg.touch_sound;
+        button.long_press = cfg.long_press;
+        if let Some(zones) = cfg.zones.filter(|zones| zones.len() > 1) {
+            button.zones = zones;
+        }
+        button.countdown = cfg.countdown.filter(|seconds| *seconds > 0);
+        button.hold_for = match (cfg.hold_seconds, &button.action) {
+            (Some(seconds), ButtonAction::Key(_) | ButtonAction::KeyCombos(_)) if seconds > 0.0 => {
+                Some(std::time::Duration::from_secs_f64(seconds.min(60.0)))
+            }
+            (Some(_), _) => {
+                eprintln!("HoldSeconds needs a positive number of seconds and a key action, ignoring it");
+                None
+            }
+            (None, _) => None,
+        };
+        button.scroll = cfg.scroll;
+        button.pinch = cfg.pinch.filter(|pinch| *pinch != PinchConfig::Wheel(false));
+        button.scrub = match cfg.scrub {
+            Some([ButtonAction::Key(left), ButtonAction::Key(right)]) => Some((left, right)),
+            Some(_) => {
+                eprintln!("Scrub only accepts two keys, ignoring it");
+                None
+            }
+            None => None,
+        };
+        Ok(button)
     }
     fn new_text(text: String, action: ButtonAction) -> Button {
+        Button::new(ButtonImage::Text(text), action)
+    }
+    fn new_status(action: ButtonAction, items: Vec<config::StatusItemConfig>) -> Button {
+        let (zones, items): (Vec<_>, Vec<_>) = items
+            .into_iter()
+            .filter_map(|item| {
+                let status_item = StatusItem::parse(&item.item, item.format.as_deref())?;
+                Some((item.action.unwrap_or_else(|| action.clone()), status_item))
+            })
+            .unzip();
+        if items.is_empty() {
+            return Button::new_text("No status".to_string(), action);
+        }
         Button {
-            action,
-            active: false,
-            changed: false,
-            image: ButtonImage::Text(text),
-            show_outline: None,
-            outline_color: None,
+            zones,
+            ..Button::new(ButtonImage::Status(items), action)
+        }
+    }
+    fn new_mail(action: ButtonAction, mail: config::MailConfig, icon: Option<String>, theme: Option<String>) -> Button {
+        let key = match (mail.command, mail.maildir) {
+            (Some(command), None) => {
+                mail::watch_command(&command, mail.interval);
+                command
+            }
+            (None, Some(maildir)) => {
+                mail::watch_maildir(&maildir);
+                maildir
+            }
+            _ => {
+                eprintln!("Mail needs either a Command or a Maildir");
+                return Button::new_text("Mail".to_string(), action);
+            }
+        };
+        let mut button = Button::new_text("Mail".to_string(), action);
+        match try_load_image(icon.as_deref().unwrap_or("mail"), theme).ok().and_then(ButtonImage::into_icon) {
+            Some(envelope) => button.image = ButtonImage::Mail(envelope, key),
+            None => eprintln!("Mail widget icon couldn't be loaded, showing a label instead"),
+        }
+        button
+    }
+    fn new_ticker(action: ButtonAction, ticker: config::TickerConfig) -> Button {
+        if ticker.symbols.is_empty() {
+            return Button::new_text("No symbols".to_string(), action);
+        }
+        ticker::watch(&ticker.command, &ticker.symbols, ticker.interval);
+        Button::new(ButtonImage::Ticker(ticker, 0), action)
+    }
+    // Follows the active player, showing `idle` while nothing plays
+    fn new_now_playing(action: ButtonAction, idle: Option<String>) -> Button {
+        mpris::initialize_mpris_monitor();
+        Button::new(ButtonImage::MediaPlayer(idle.unwrap_or_else(|| "Not playing".to_string())), action)
+    }
+    // Percentage of the default output, crossed out speaker while muted
+    fn new_volume(action: ButtonAction, theme: Option<String>) -> Button {
+        audio::initialize_audio_monitor();
+        let mut button = Button::new_text("Volume".to_string(), action);
+        let icon = |name| try_load_image(name, theme.as_deref()).ok().and_then(ButtonImage::into_icon);
+        match (icon("volume_up"), icon("volume_off")) {
+            (Some(up), Some(off)) => button.image = ButtonImage::Volume(up, off),
+            _ => eprintln!("Volume widget icons couldn't be loaded, showing a label instead"),
+        }
+        button
+    }
+    fn new_workspaces(action: ButtonAction) -> Button {
+        let mut button = Button::new_text(String::new(), action);
+        button.refresh_workspaces();
+        button
+    }
+    // Takes the workspaces as they are now, the button's own action is left for when there are none
+    fn refresh_workspaces(&mut self) {
+        let workspaces = hyprland::workspaces();
+        self.zones = workspaces.iter().map(|workspace| ButtonAction::SwitchWorkspace(workspace.id)).collect();
+        self.image = ButtonImage::Workspaces(workspaces);
+        self.changed = true;
+    }
+    // The action of the zone being touched, or the button's own one
+    fn current_action(&self) -> &ButtonAction {
+        self.active_zone
+            .and_then(|zone| self.zones.get(zone))
+            .unwrap_or(&self.action)
+    }
+    fn new_template(template: TextTemplate, action: ButtonAction) -> Button {
+        Button {
+            source: ButtonSource::Template,
+            ..Button::new(ButtonImage::Template(template), action)
         }
     }
     fn new_text_with_icon(text: String, icon_name: String, theme: Option<impl AsRef<str>>, action: ButtonAction) -> Button {
@@ -460,76 +994,55 @@ impl Button {
             })
         });
 
-        let icon_handle = match icon {
-            ButtonImage::Svg(handle) => handle,
-            _ => {
-                // If not SVG, create a text button instead
-                return Button::new_text(text, action);
-            }
+        let Some(icon_handle) = icon.into_icon() else {
+            // If not an image, create a text button instead
+            return Button::new_text(text, action);
         };
 
-        Button {
-            action,
-            active: false,
-            changed: false,
-            image: ButtonImage::TextWithIcon(format!(" {}", text), icon_handle), // Add space before text
-            show_outline: None,
-            outline_color: None,
-        }
+        Button::new(ButtonImage::TextWithIcon(format!(" {}", text), icon_handle), action) // Add space before text
     }
-    fn new_icon(path: impl AsRef<str>, theme: Option<impl AsRef<str>>, action: ButtonAction) -> Button {
-        let image = try_load_image(path, theme).expect("failed to load icon");
-        Button {
-            action,
-            image,
-            active: false,
-            changed: false,
-            show_outline: None,
-            outline_color: None,
-        }
+    // Icons that weren't found before are looked up in the background, the name stands in until then
+    fn new_icon(path: impl AsRef<str>, theme: Option<impl AsRef<str>>, action: ButtonAction) -> Result<Button, String> {
+        let name = path.as_ref().to_string();
+        let theme = theme.map(|theme| theme.as_ref().to_string());
+        let image = if icon_cache::get_icon_cached(name.clone(), theme.clone()).is_some() {
+            try_load_image(&name, theme.as_deref()).map_err(|e| format!("Failed to load icon {}: {}", name, e))?
+        } else {
+            let _ = icon_cache::load_icon_async(name.clone(), theme.clone());
+            ButtonImage::Pending(name.clone(), name, theme)
+        };
+        Ok(Button::new(image, action))
     }
-    fn load_battery_image(icon: &str, theme: Option<impl AsRef<str>>) -> Handle {
-        if let ButtonImage::Svg(svg) = try_load_image(icon, theme).unwrap() {
-            return svg;
-        }
-        panic!("failed to load icon");
+    fn new_ambient_light(action: ButtonAction, mode: &str) -> Result<Button, String> {
+        let mode = match mode {
+            "lux" => AmbientLightMode::Lux,
+            "brightness" => AmbientLightMode::Brightness,
+            "both" => AmbientLightMode::Both,
+            _ => return Err(format!("Invalid ambient light mode {}, accepted modes: lux, brightness, both", mode)),
+        };
+        Ok(Button::new(ButtonImage::AmbientLight(mode), action))
     }
-    fn new_battery(action: ButtonAction, battery: String, battery_mode: String, theme: Option<impl AsRef<str>>) -> Button {
-        let bolt = Self::load_battery_image("bolt", theme.as_ref());
-        let mut plain = Vec::new();
-        let mut charging = Vec::new();
-        for icon in [
-            "battery_0_bar", "battery_1_bar", "battery_2_bar", "battery_3_bar",
-            "battery_4_bar", "battery_5_bar", "battery_6_bar", "battery_full",
-        ] {
-            plain.push(Self::load_battery_image(icon, theme.as_ref()));
-        }
-        for icon in [
-            "battery_charging_20", "battery_charging_30", "battery_charging_50",
-            "battery_charging_60", "battery_charging_80",
-            "battery_charging_90", "battery_charging_full",
-        ] {
-            charging.push(Self::load_battery_image(icon, theme.as_ref()));
-        }
+    fn new_battery_history(action: ButtonAction, hours: u32) -> Button {
+        Button::new(ButtonImage::BatteryHistory(hours.max(1)), action)
+    }
+    fn new_battery(
+        action: ButtonAction,
+        battery: String,
+        battery_mode: String,
+        state_style: BatteryStateStyle,
+        theme: Option<impl AsRef<str>>,
+    ) -> Result<Button, String> {
         let battery_mode = match battery_mode.as_str() {
             "icon" => BatteryIconMode::Icon,
             "percentage" => BatteryIconMode::Percentage,
             "both" => BatteryIconMode::Both,
-            _ => panic!("invalid battery mode, accepted modes: icon, percentage, both"),
+            "remaining" => BatteryIconMode::Remaining,
+            other => return Err(format!("Invalid battery mode {}, accepted modes: icon, percentage, both, remaining", other)),
         };
-        Button {
-            action,
-            active: false,
-            changed: false,
-            image: ButtonImage::Battery(battery, battery_mode, BatteryImages {
-                plain, bolt, charging
-            }),
-            show_outline: None,
-            outline_color: None,
-        }
+        Ok(Button::new(ButtonImage::Battery(battery, battery_mode, theme.map(|theme| theme.as_ref().to_string()), state_style), action))
     }
 
-    fn new_time(action: ButtonAction, format: &str, locale_str: Option<&str>) -> Button {
+    fn new_time(action: ButtonAction, format: &str, locale_str: Option<&str>) -> Result<Button, String> {
         let format_str = if format == "24hr" {
             "%H:%M    %a %-e %b"
         } else if format == "12hr" {
@@ -540,18 +1053,11 @@ impl Button {
 
         let format_items = match StrftimeItems::new(format_str).parse_to_owned() {
             Ok(s) => s,
-            Err(e) => panic!("Invalid time format, consult the configuration file for examples of correct ones: {e:?}"),
+            Err(e) => return Err(format!("Invalid time format {}, consult the configuration file for examples of correct ones: {e:?}", format)),
         };
 
         let locale = locale_str.and_then(|l| Locale::try_from(l).ok()).unwrap_or(Locale::POSIX);
-        Button {
-            action,
-            active: false,
-            changed: false,
-            image: ButtonImage::Time(format_items, locale),
-            show_outline: None,
-            outline_color: None,
-        }
+        Ok(Button::new(ButtonImage::Time(format_items, locale), action))
     }
     fn render(
         &self,
@@ -560,9 +1066,22 @@ impl Button {
         button_left_edge: f64,
         button_width: u64,
         y_shift: f64,
+        scale: f64,
     ) {
+        let icon_size = ICON_SIZE as f64 * scale;
+        if let Some(until) = self.countdown_until {
+            let remaining = until.saturating_duration_since(std::time::Instant::now()).as_secs_f64().ceil().max(1.0);
+            let text = format!("{}", remaining as u32);
+            let extents = c.text_extents(&text).unwrap();
+            c.move_to(
+                button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+            );
+            c.show_text(&text).unwrap();
+            return;
+        }
         match &self.image {
-            ButtonImage::Text(text) => {
+            ButtonImage::Text(text) | ButtonImage::Pending(text, _, _) => {
                 let extents = c.text_extents(text).unwrap();
                 c.move_to(
                     button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
@@ -570,76 +1089,313 @@ impl Button {
                 );
                 c.show_text(text).unwrap();
             }
-            ButtonImage::TextWithIcon(text, svg) => {
-                // Make icon fit button height with some padding, keeping aspect ratio
-                let padding = 4.0;
-                let icon_size = height as f64 - (padding * 2.0);
-                let available_text_width = button_width as f64 - icon_size - 8.0; // Reserve some padding
-
-                // Trim text if it's too wide for the button
-                let (display_text, text_extents) = {
-                    let original_extents = c.text_extents(text).unwrap();
-                    if original_extents.width() <= available_text_width {
-                        (text.clone(), original_extents)
+            ButtonImage::Status(items) => {
+                let zone_width = button_width as f64 / items.len() as f64;
+                for (i, item) in items.iter().enumerate() {
+                    let zone_left = button_left_edge + i as f64 * zone_width;
+                    let text = item.text();
+                    let extents = c.text_extents(&text).unwrap();
+                    c.move_to(
+                        zone_left + (zone_width / 2.0 - extents.width() / 2.0).round(),
+                        y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                    );
+                    c.show_text(&text).unwrap();
+                    // Faint separator between neighbouring items
+                    if i > 0 {
+                        c.save().unwrap();
+                        c.set_source_rgba(1.0, 1.0, 1.0, 0.25);
+                        c.rectangle(zone_left.round(), y_shift + height as f64 * 0.3, 1.0, height as f64 * 0.4);
+                        c.fill().unwrap();
+                        c.restore().unwrap();
+                    }
+                }
+            }
+            ButtonImage::Workspaces(workspaces) => {
+                let active = hyprland::get_active_workspace_name();
+                let zone_width = button_width as f64 / workspaces.len().max(1) as f64;
+                let pill_height = (height as f64 * 0.55).round();
+                let radius = pill_height / 2.0;
+                let top = y_shift + ((height as f64 - pill_height) / 2.0).round();
+                for (i, workspace) in workspaces.iter().enumerate() {
+                    let zone_left = button_left_edge + i as f64 * zone_width;
+                    let pill_width = (zone_width - 12.0 * scale).max(pill_height).min(zone_width);
+                    let left = (zone_left + (zone_width - pill_width) / 2.0).round();
+                    c.save().unwrap();
+                    c.new_sub_path();
+                    c.arc(left + radius, top + radius, radius, std::f64::consts::FRAC_PI_2, 3.0 * std::f64::consts::FRAC_PI_2);
+                    c.arc(left + pill_width - radius, top + radius, radius, -std::f64::consts::FRAC_PI_2, std::f64::consts::FRAC_PI_2);
+                    c.close_path();
+                    // The workspace shown is filled in, the others only outlined
+                    if active.as_ref() == Some(&workspace.name) {
+                        c.set_source_rgba(1.0, 1.0, 1.0, 0.35);
+                        c.fill().unwrap();
                     } else {
-                        // Trim text to fit with ellipsis
-                        let trimmed = text.clone();
-                        let ellipsis = "...";
-                        let ellipsis_width = c.text_extents(ellipsis).unwrap().width();
-                        let target_width = available_text_width - ellipsis_width;
-
-                        // Binary search to find optimal text length
-                        let mut left = 0;
-                        let mut right = trimmed.chars().count();
-                        while left < right {
-                            let mid = (left + right + 1) / 2;
-                            let test_text: String = trimmed.chars().take(mid).collect();
-                            let test_width = c.text_extents(&test_text).unwrap().width();
-                            if test_width <= target_width {
-                                left = mid;
-                            } else {
-                                right = mid - 1;
-                            }
-                        }
-
-                        let final_text: String = trimmed.chars().take(left).collect::<String>() + ellipsis;
-                        let final_extents = c.text_extents(&final_text).unwrap();
-                        (final_text, final_extents)
+                        c.set_source_rgba(1.0, 1.0, 1.0, 0.25);
+                        c.set_line_width(1.5 * scale);
+                        c.stroke().unwrap();
                     }
-                };
-
-                let total_width = icon_size + text_extents.width();
-
-                // Center the combined icon+text in the button
-                let start_x = button_left_edge + (button_width as f64 / 2.0 - total_width / 2.0).round();
-
-                // Draw icon
-                let icon_x = start_x;
-                let icon_y = y_shift + padding;
-                svg.render_document(c, &Rectangle::new(icon_x, icon_y, icon_size, icon_size))
-                    .unwrap();
-
-                // Draw text
-                let text_x = start_x + icon_size;
-                let text_y = y_shift + (height as f64 / 2.0 + text_extents.height() / 2.0).round();
-                c.move_to(text_x, text_y);
-                c.show_text(&display_text).unwrap();
+                    c.restore().unwrap();
+                    let extents = c.text_extents(&workspace.name).unwrap();
+                    c.move_to(
+                        zone_left + (zone_width / 2.0 - extents.width() / 2.0).round(),
+                        y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                    );
+                    c.show_text(&workspace.name).unwrap();
+                }
+                // Hyprland isn't running
+                if workspaces.is_empty() {
+                    let extents = c.text_extents("--").unwrap();
+                    c.move_to(
+                        button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                        y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                    );
+                    c.show_text("--").unwrap();
+                }
             }
-            ButtonImage::Svg(svg) => {
+            ButtonImage::WorldClock(city, zone) => {
+                let time = world_clock::zone_time(zone).unwrap_or_else(|| "--:--".to_string());
+                let text = format!("{} {}", city, time);
+                let extents = c.text_extents(&text).unwrap();
+                c.move_to(
+                    button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                    y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                );
+                c.show_text(&text).unwrap();
+            }
+            ButtonImage::Calculator => {
+                // Long calculations lose their start, the end is what's being typed
+                let mut text = calculator::display();
+                let room = button_width as f64 - icon_size / 2.0;
+                while c.text_extents(&text).unwrap().width() > room && text.chars().count() > 2 {
+                    let shorter: String = text.trim_start_matches('…').chars().skip(1).collect();
+                    text = format!("…{}", shorter.trim_start());
+                }
+                let extents = c.text_extents(&text).unwrap();
+                c.move_to(
+                    button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                    y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                );
+                c.show_text(&text).unwrap();
+            }
+            ButtonImage::Slider(kind) => {
+                let glyph = match kind {
+                    SliderKind::Brightness => "☀",
+                    SliderKind::Volume => "🔊",
+                };
+                let text = match slider::level(*kind) {
+                    Some(level) => format!("{} {:.0}%", glyph, level * 100.0),
+                    None => glyph.to_string(),
+                };
+                let extents = c.text_extents(&text).unwrap();
+                c.move_to(
+                    button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                    y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                );
+                c.show_text(&text).unwrap();
+            }
+            ButtonImage::MediaPlayer(idle) => {
+                let text = match mpris::now_playing() {
+                    Some(track) if !track.title.is_empty() => {
+                        let glyph = if track.playing { "▶" } else { "⏸" };
+                        if track.artist.is_empty() {
+                            format!("{} {}", glyph, track.title)
+                        } else {
+                            format!("{} {} – {}", glyph, track.artist, track.title)
+                        }
+                    }
+                    _ => idle.clone(),
+                };
+                // Long titles lose their end rather than running over the neighbours
+                let text = truncate_to_width(c, &text, button_width as f64 - icon_size / 2.0);
+                let extents = c.text_extents(&text).unwrap();
+                c.move_to(
+                    button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                    y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                );
+                c.show_text(&text).unwrap();
+            }
+            ButtonImage::Script(command, placeholder) => {
+                let text = script::output(command).unwrap_or_else(|| placeholder.clone());
+                let text = truncate_to_width(c, &text, button_width as f64 - icon_size / 2.0);
+                let extents = c.text_extents(&text).unwrap();
+                c.move_to(
+                    button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                    y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                );
+                c.show_text(&text).unwrap();
+            }
+            ButtonImage::Mail(envelope, key) => {
+                let x = button_left_edge + (button_width as f64 / 2.0 - icon_size / 2.0).round();
+                let y = y_shift + ((height as f64 - icon_size) / 2.0).round();
+                envelope.render_document(c, &Rectangle::new(x, y, icon_size, icon_size)).unwrap();
+                let unread = mail::unread(key).unwrap_or(0);
+                if unread > 0 {
+                    // Count badge over the top right corner of the envelope
+                    let text = if unread > 99 { "99+".to_string() } else { unread.to_string() };
+                    c.save().unwrap();
+                    c.set_font_size(18.0 * scale);
+                    let extents = c.text_extents(&text).unwrap();
+                    let radius = (extents.height() / 2.0 + 4.0 * scale).max(extents.width() / 2.0 + 3.0 * scale);
+                    let (badge_x, badge_y) = (x + icon_size - radius / 2.0, y + radius / 2.0);
+                    c.set_source_rgb(0.9, 0.2, 0.2);
+                    c.arc(badge_x, badge_y, radius, 0.0, 2.0 * std::f64::consts::PI);
+                    c.fill().unwrap();
+                    c.set_source_rgb(1.0, 1.0, 1.0);
+                    c.move_to(
+                        (badge_x - extents.width() / 2.0 - extents.x_bearing()).round(),
+                        (badge_y + extents.height() / 2.0).round(),
+                    );
+                    c.show_text(&text).unwrap();
+                    c.restore().unwrap();
+                }
+            }
+            ButtonImage::Volume(up, off) => {
+                let (icon, text) = match audio::volume() {
+                    Some((_, true)) => (off, "Muted".to_string()),
+                    Some((level, false)) => (up, format!("{}%", (level * 100.0).round())),
+                    None => (off, "--".to_string()),
+                };
+                let extents = c.text_extents(&text).unwrap();
+                let gap = 6.0 * scale;
+                let left = button_left_edge + ((button_width as f64 - icon_size - gap - extents.x_advance()) / 2.0).round();
+                let y = y_shift + ((height as f64 - icon_size) / 2.0).round();
+                icon.render_document(c, &Rectangle::new(left, y, icon_size, icon_size)).unwrap();
+                c.move_to(
+                    left + icon_size + gap,
+                    y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                );
+                c.show_text(&text).unwrap();
+            }
+            ButtonImage::Ticker(ticker, shown) => {
+                let symbol = &ticker.symbols[*shown];
+                let quote = ticker::quote(&ticker.command, symbol);
+                let text = match &quote {
+                    Some(quote) => format!("{} {}", symbol, quote.price),
+                    None => format!("{} …", symbol),
+                };
+                let delta = quote.as_ref().and_then(|quote| Some((format!(" {}", quote.delta.as_ref()?), quote.rising)));
+                let extents = c.text_extents(&text).unwrap();
+                let delta_width = delta.as_ref().map_or(0.0, |(delta, _)| c.text_extents(delta).unwrap().x_advance());
+                c.move_to(
+                    button_left_edge + (button_width as f64 / 2.0 - (extents.x_advance() + delta_width) / 2.0).round(),
+                    y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                );
+                c.show_text(&text).unwrap();
+                if let Some((delta, rising)) = delta {
+                    c.save().unwrap();
+                    match rising {
+                        Some(true) => c.set_source_rgb(0.3, 0.85, 0.3),
+                        Some(false) => c.set_source_rgb(0.95, 0.3, 0.3),
+                        None => {}
+                    }
+                    c.show_text(&delta).unwrap();
+                    c.restore().unwrap();
+                }
+            }
+            ButtonImage::Template(template) => {
+                let text = template.render();
+                let extents = c.text_extents(&text).unwrap();
+                c.move_to(
+                    button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                    y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                );
+                c.show_text(&text).unwrap();
+            }
+            ButtonImage::TextWithIcon(text, svg) => {
+                // Make icon fit button height with some padding, keeping aspect ratio
+                let padding = 4.0 * scale;
+                let icon_size = height as f64 - (padding * 2.0);
+                let available_text_width = button_width as f64 - icon_size - 8.0 * scale; // Reserve some padding
+
+                // Trim text if it's too wide for the button
+                let (display_text, text_extents) = {
+                    let original_extents = c.text_extents(text).unwrap();
+                    if original_extents.width() <= available_text_width {
+                        (text.clone(), original_extents)
+                    } else {
+                        // Trim text to fit with ellipsis
+                        let trimmed = text.clone();
+                        let ellipsis = "...";
+                        let ellipsis_width = c.text_extents(ellipsis).unwrap().width();
+                        let target_width = available_text_width - ellipsis_width;
+
+                        // Binary search to find optimal text length
+                        let mut left = 0;
+                        let mut right = trimmed.chars().count();
+                        while left < right {
+                            let mid = (left + right + 1) / 2;
+                            let test_text: String = trimmed.chars().take(mid).collect();
+                            let test_width = c.text_extents(&test_text).unwrap().width();
+                            if test_width <= target_width {
+                                left = mid;
+                            } else {
+                                right = mid - 1;
+                            }
+                        }
+
+                        let final_text: String = trimmed.chars().take(left).collect::<String>() + ellipsis;
+                        let final_extents = c.text_extents(&final_text).unwrap();
+                        (final_text, final_extents)
+                    }
+                };
+
+                let total_width = icon_size + text_extents.width();
+
+                // Center the combined icon+text in the button
+                let start_x = button_left_edge + (button_width as f64 / 2.0 - total_width / 2.0).round();
+
+                // Draw icon
+                let icon_x = start_x;
+                let icon_y = y_shift + padding;
+                svg.render_document(c, &Rectangle::new(icon_x, icon_y, icon_size, icon_size))
+                    .unwrap();
+
+                // Draw text
+                let text_x = start_x + icon_size;
+                let text_y = y_shift + (height as f64 / 2.0 + text_extents.height() / 2.0).round();
+                c.move_to(text_x, text_y);
+                c.show_text(&display_text).unwrap();
+            }
+            ButtonImage::Svg(svg) => {
+                let x =
+                    button_left_edge + (button_width as f64 / 2.0 - icon_size / 2.0).round();
+                let y = y_shift + ((height as f64 - icon_size) / 2.0).round();
+
+                svg.render_document(c, &Rectangle::new(x, y, icon_size, icon_size))
+                    .unwrap();
+            }
+            ButtonImage::Playback(idle, play, pause) => {
+                let status = media_state::playback_status();
+                let svg = match status {
+                    PlaybackStatus::NoPlayer => idle,
+                    PlaybackStatus::Playing => pause,
+                    PlaybackStatus::Paused | PlaybackStatus::Stopped => play,
+                };
                 let x =
-                    button_left_edge + (button_width as f64 / 2.0 - (ICON_SIZE / 2) as f64).round();
-                let y = y_shift + ((height as f64 - ICON_SIZE as f64) / 2.0).round();
+                    button_left_edge + (button_width as f64 / 2.0 - icon_size / 2.0).round();
+                let y = y_shift + ((height as f64 - icon_size) / 2.0).round();
 
-                svg.render_document(c, &Rectangle::new(x, y, ICON_SIZE as f64, ICON_SIZE as f64))
+                svg.render_document(c, &Rectangle::new(x, y, icon_size, icon_size))
                     .unwrap();
+                // Subtle underline below the glyph while some player is around
+                if status != PlaybackStatus::NoPlayer {
+                    c.save().unwrap();
+                    c.set_source_rgba(1.0, 1.0, 1.0, 0.5);
+                    c.rectangle(
+                        x + (icon_size * 0.3).round(),
+                        y + (icon_size * 0.85).round(),
+                        (icon_size * 0.4).round(),
+                        (2.0 * scale).round(),
+                    );
+                    c.fill().unwrap();
+                    c.restore().unwrap();
+                }
             }
             ButtonImage::Bitmap(surf) => {
                 let x =
-                    button_left_edge + (button_width as f64 / 2.0 - (ICON_SIZE / 2) as f64).round();
-                let y = y_shift + ((height as f64 - ICON_SIZE as f64) / 2.0).round();
-                c.set_source_surface(surf, x, y).unwrap();
-                c.rectangle(x, y, ICON_SIZE as f64, ICON_SIZE as f64);
-                c.fill().unwrap();
+                    button_left_edge + (button_width as f64 / 2.0 - icon_size / 2.0).round();
+                let y = y_shift + ((height as f64 - icon_size) / 2.0).round();
+                draw_pixels(c, surf, x, y, icon_size).unwrap();
             }
             ButtonImage::Time(format, locale) => {
                 let current_time = Local::now();
@@ -651,66 +1407,246 @@ impl Button {
                 );
                 c.show_text(&formatted_time).unwrap();
             }
-            ButtonImage::Battery(battery, battery_mode, icons) => {
+            ButtonImage::AmbientLight(mode) => {
+                let lux = match ambient_light::read_lux() {
+                    Some(lux) => format!("{:.0} lx", lux),
+                    None => "No ALS".to_string(),
+                };
+                let brightness = match ambient_light::get_target_brightness() {
+                    Some(target) => format!("☀ {}", target),
+                    None => "☀ -".to_string(),
+                };
+                let text = match mode {
+                    AmbientLightMode::Lux => lux,
+                    AmbientLightMode::Brightness => brightness,
+                    AmbientLightMode::Both => format!("{} {}", lux, brightness),
+                };
+                let extents = c.text_extents(&text).unwrap();
+                c.move_to(
+                    button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+                    y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                );
+                c.show_text(&text).unwrap();
+            }
+            ButtonImage::BatteryHistory(hours) => {
+                let max_age = std::time::Duration::from_secs(*hours as u64 * 3600);
+                let history = battery_monitor::get_battery_history(max_age);
+                let padding = 12.0 * scale;
+                let label = format!("{}h", hours);
+                let label_extents = c.text_extents(&label).unwrap();
+                let left = button_left_edge + padding;
+                let right = button_left_edge + button_width as f64 - padding;
+                let graph_left = left + label_extents.width() + padding;
+                let graph_top = y_shift + height as f64 * 0.25;
+                let graph_bottom = y_shift + height as f64 * 0.75;
+
+                c.move_to(
+                    left,
+                    y_shift + (height as f64 / 2.0 + label_extents.height() / 2.0).round(),
+                );
+                c.show_text(&label).unwrap();
+
+                // 50% guide line
+                c.save().unwrap();
+                c.set_source_rgba(1.0, 1.0, 1.0, 0.25);
+                c.set_line_width(1.0);
+                c.move_to(graph_left, (graph_top + graph_bottom) / 2.0);
+                c.line_to(right, (graph_top + graph_bottom) / 2.0);
+                c.stroke().unwrap();
+                c.restore().unwrap();
+
+                // Newest sample on the right edge, older ones towards the left
+                let to_point = |age: std::time::Duration, capacity: u32| {
+                    let x = right - (right - graph_left) * (age.as_secs_f64() / max_age.as_secs_f64());
+                    let y = graph_bottom - (graph_bottom - graph_top) * (capacity.min(100) as f64 / 100.0);
+                    (x, y)
+                };
+                if history.len() > 1 {
+                    c.set_line_width(2.0 * scale);
+                    for (i, (age, capacity)) in history.iter().enumerate() {
+                        let (x, y) = to_point(*age, *capacity);
+                        if i == 0 {
+                            c.move_to(x, y);
+                        } else {
+                            c.line_to(x, y);
+                        }
+                    }
+                    c.stroke().unwrap();
+                } else {
+                    let text = "Collecting…";
+                    let extents = c.text_extents(text).unwrap();
+                    c.move_to(
+                        graph_left + ((right - graph_left) / 2.0 - extents.width() / 2.0).round(),
+                        y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+                    );
+                    c.show_text(text).unwrap();
+                }
+            }
+            ButtonImage::Battery(battery, battery_mode, theme, state_style) => {
                 let (capacity, state) = get_battery_state(battery);
                 let icon = if battery_mode.should_draw_icon() {
                     Some(match state {
                         BatteryState::Charging => match capacity {
-                            0..=20 => &icons.charging[0],
-                            21..=30 => &icons.charging[1],
-                            31..=50 => &icons.charging[2],
-                            51..=60 => &icons.charging[3],
-                            61..=80 => &icons.charging[4],
-                            81..=99 => &icons.charging[5],
-                            _ => &icons.charging[6],
+                            0..=20 => "battery_charging_20",
+                            21..=30 => "battery_charging_30",
+                            31..=50 => "battery_charging_50",
+                            51..=60 => "battery_charging_60",
+                            61..=80 => "battery_charging_80",
+                            81..=99 => "battery_charging_90",
+                            _ => "battery_charging_full",
                         },
                         _ => match capacity {
-                            0 => &icons.plain[0],
-                            1..=20 => &icons.plain[1],
-                            21..=30 => &icons.plain[2],
-                            31..=50 => &icons.plain[3],
-                            51..=60 => &icons.plain[4],
-                            61..=80 => &icons.plain[5],
-                            81..=99 => &icons.plain[6],
-                            _ => &icons.plain[7],
+                            0 => "battery_0_bar",
+                            1..=20 => "battery_1_bar",
+                            21..=30 => "battery_2_bar",
+                            31..=50 => "battery_3_bar",
+                            51..=60 => "battery_4_bar",
+                            61..=80 => "battery_5_bar",
+                            81..=99 => "battery_6_bar",
+                            _ => "battery_full",
                         },
                     })
                 } else if state == BatteryState::Charging {
-                    Some(&icons.bolt)
+                    Some("bolt")
                 } else {
                     None
                 };
-                let percent_str = format!("{:.0}%", capacity);
+                let icon = icon.and_then(|name| battery_icon(name, theme.as_deref()));
+                // Low battery shown without relying on the red tint
+                let warn = *state_style == BatteryStateStyle::Glyph && state == BatteryState::Low;
+                let draw_text = battery_mode.should_draw_text() || warn;
+                let mut text = format!("{:.0}%", capacity);
+                if *battery_mode == BatteryIconMode::Remaining {
+                    if let Some(remaining) = battery_monitor::get_cached_battery_info().and_then(|info| info.remaining_text()) {
+                        text = remaining;
+                    }
+                }
+                let percent_str = match (warn, battery_mode.should_draw_text()) {
+                    (true, true) => format!("!{}", text),
+                    (true, false) => "!".to_string(),
+                    _ => text,
+                };
                 let extents = c.text_extents(&percent_str).unwrap();
                 let mut width = extents.width();
-                let mut text_offset = 0;
+                let mut text_offset = 0.0;
                 if let Some(svg) = icon {
-                    if !battery_mode.should_draw_text() {
-                        width = ICON_SIZE as f64;
+                    if !draw_text {
+                        width = icon_size;
                     } else {
-                        width += ICON_SIZE as f64;
+                        width += icon_size;
                     }
-                    text_offset = ICON_SIZE;
+                    text_offset = icon_size;
                     let x =
                         button_left_edge + (button_width as f64 / 2.0 - width / 2.0).round();
-                    let y = y_shift + ((height as f64 - ICON_SIZE as f64) / 2.0).round();
+                    let y = y_shift + ((height as f64 - icon_size) / 2.0).round();
 
-                    svg.render_document(c, &Rectangle::new(x, y, ICON_SIZE as f64, ICON_SIZE as f64))
+                    svg.render_document(c, &Rectangle::new(x, y, icon_size, icon_size))
                         .unwrap();
                 }
-                if battery_mode.should_draw_text() {
+                if draw_text {
                     c.move_to(
-                        button_left_edge + (button_width as f64 / 2.0 - width / 2.0 + text_offset as f64).round(),
+                        button_left_edge + (button_width as f64 / 2.0 - width / 2.0 + text_offset).round(),
                         y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
                     );
                     c.show_text(&percent_str).unwrap();
                 }
             }
         }
+        // Bar under the button's content while what it toggles is on
+        if self.toggled_on() {
+            c.save().unwrap();
+            c.set_source_rgba(1.0, 1.0, 1.0, 0.8);
+            c.rectangle(
+                button_left_edge + (button_width as f64 / 2.0 - icon_size * 0.2).round(),
+                y_shift + ((height as f64 + icon_size) / 2.0 - icon_size * 0.1).round(),
+                (icon_size * 0.4).round(),
+                (2.0 * scale).round(),
+            );
+            c.fill().unwrap();
+            c.restore().unwrap();
+        }
+    }
+    // Airplane mode, caffeine or the torch being on, or the session being locked, for the buttons toggling them
+    fn toggled_on(&self) -> bool {
+        match self.action {
+            ButtonAction::Torch => keyboard_backlight::torch_on(),
+            ButtonAction::AirplaneMode => rfkill::airplane_mode(),
+            ButtonAction::Caffeine => caffeine::active(),
+            ButtonAction::LockSession => session_lock::locked(),
+            _ => false,
+        }
+    }
+    // What touch exploration announces for the button
+    fn spoken_label(&self) -> String {
+        match &self.image {
+            ButtonImage::Text(text) | ButtonImage::TextWithIcon(text, _) => text.clone(),
+            ButtonImage::Template(template) => template.render(),
+            ButtonImage::Calculator => calculator::display(),
+            ButtonImage::Workspaces(_) => match hyprland::get_active_workspace_name() {
+                Some(name) => format!("Workspace {}", name),
+                None => self.action.label(),
+            },
+            ButtonImage::Volume(_, _) => match audio::volume() {
+                Some((_, true)) => "Volume muted".to_string(),
+                Some((level, false)) => format!("Volume {}%", (level * 100.0).round()),
+                None => self.action.label(),
+            },
+            ButtonImage::MediaPlayer(idle) => match mpris::now_playing() {
+                Some(track) if !track.title.is_empty() => format!("{} {}", track.artist, track.title),
+                _ => idle.clone(),
+            },
+            ButtonImage::Script(command, placeholder) => script::output(command).unwrap_or_else(|| placeholder.clone()),
+            ButtonImage::WorldClock(city, zone) => {
+                format!("{} {}", city, world_clock::zone_time(zone).unwrap_or_default())
+            }
+            _ => self.action.label(),
+        }
+    }
+    // What CheatSheet shows instead of the button, one label per tap zone
+    fn render_action_label(&self, c: &Context, height: i32, button_left_edge: f64, button_width: u64, y_shift: f64, scale: f64) {
+        let label = if self.zones.is_empty() {
+            self.action.label()
+        } else {
+            self.zones.iter().map(|action| action.label()).collect::<Vec<_>>().join(" | ")
+        };
+        let label = match &self.long_press {
+            Some(long_press) => format!("{} / {}", label, long_press.label()),
+            None => label,
+        };
+        c.save().unwrap();
+        // Shrink long labels to fit rather than cutting them off
+        let available = button_width as f64 - 8.0 * scale;
+        let extents = c.text_extents(&label).unwrap();
+        if extents.width() > available {
+            c.set_font_size((32.0 * scale * available / extents.width()).max(12.0 * scale));
+        }
+        let extents = c.text_extents(&label).unwrap();
+        c.move_to(
+            button_left_edge + (button_width as f64 / 2.0 - extents.width() / 2.0).round(),
+            y_shift + (height as f64 / 2.0 + extents.height() / 2.0).round(),
+        );
+        c.show_text(&label).unwrap();
+        c.restore().unwrap();
+    }
+    // Charging or low state of a battery button that wants it drawn as a pattern
+    // How far a slider's fill reaches, from 0 to 1
+    fn slider_level(&self) -> Option<f64> {
+        match &self.image {
+            ButtonImage::Slider(kind) => slider::level(*kind),
+            _ => None,
+        }
+    }
+    fn state_pattern(&self) -> Option<BatteryState> {
+        match &self.image {
+            ButtonImage::Battery(battery, _, _, BatteryStateStyle::Pattern) => {
+                Some(get_battery_state(battery).1).filter(|state| *state != BatteryState::NotCharging)
+            }
+            _ => None,
+        }
     }
     fn set_backround_color(&self, c: &Context, color: f64) {
         match &self.image {
-            ButtonImage::Battery(battery, _, _) => {
+            ButtonImage::Battery(battery, _, _, _) => {
                 let (_, state) = get_battery_state(battery);
                 match state {
                     BatteryState::NotCharging => c.set_source_rgb(color, color, color),
@@ -727,23 +1663,113 @@ impl Button {
 pub struct FunctionLayer {
     displays_time: bool,
     displays_battery: bool,
+    displays_ambient_light: bool,
+    displays_playback: bool,
+    displays_status: bool,
+    displays_ticker: bool,
+    displays_mail: bool,
+    displays_slider: bool,
     buttons: Vec<(usize, Button)>,
     virtual_button_count: usize,
+    background: Option<Background>,
+    // Horizontal pixel shift the buttons were last drawn with
+    drawn_shift_x: f64,
+    // How many virtual buttons fit on the bar as last drawn, fewer than there are when it scrolls
+    drawn_visible_count: usize,
+    // First virtual button shown while scrolled
+    scroll: usize,
+    // Set by a CheatSheet button, buttons show their action instead of their content
+    cheat_sheet: bool,
+    // What each button was built from, buttons with an unchanged configuration survive a reload
+    fingerprints: Vec<String>,
+    // Set while PipTitles controls are added, the button count and playback flag to go back to
+    pip_controls: Option<(usize, bool)>,
+}
+
+// Buttons of the layers a reload replaces, waiting to be taken by an identical configuration
+thread_local! {
+    static KEPT_BUTTONS: std::cell::RefCell<HashMap<String, Vec<Button>>> = std::cell::RefCell::new(HashMap::new());
+}
+
+fn keep_buttons_for_reload(layers: &[FunctionLayer]) {
+    KEPT_BUTTONS.with(|kept| {
+        let mut kept = kept.borrow_mut();
+        for layer in layers {
+            for (fingerprint, (_, button)) in layer.fingerprints.iter().zip(&layer.buttons) {
+                kept.entry(fingerprint.clone()).or_default().push(button.clone());
+            }
+        }
+    });
+}
+
+fn drop_kept_buttons() -> usize {
+    KEPT_BUTTONS.with(|kept| kept.borrow_mut().drain().map(|(_, buttons)| buttons.len()).sum())
+}
+
+// Kept buttons aren't built again, what they poll is watched for the new configuration all the same
+fn watch_polled(cfg: &ButtonConfig) {
+    if let Some(ticker) = cfg.ticker.as_ref().filter(|ticker| !ticker.symbols.is_empty()) {
+        ticker::watch(&ticker.command, &ticker.symbols, ticker.interval);
+    }
+    if let Some(script) = &cfg.script {
+        script::watch(&script.command, script.interval);
+    }
+    if let Some(mail) = &cfg.mail {
+        match (&mail.command, &mail.maildir) {
+            (Some(command), None) => mail::watch_command(command, mail.interval),
+            (None, Some(maildir)) => mail::watch_maildir(maildir),
+            _ => {}
+        }
+    }
+}
+
+fn take_kept_button(fingerprint: &str) -> Option<Button> {
+    let mut button = KEPT_BUTTONS.with(|kept| kept.borrow_mut().get_mut(fingerprint)?.pop())?;
+    button.active = false;
+    button.active_zone = None;
+    button.pressed_at = None;
+    button.changed = true;
+    Some(button)
 }
 
 impl FunctionLayer {
-    fn with_config(cfg: Vec<ButtonConfig>) -> FunctionLayer {
+    // Every button that couldn't be built is listed, not just the first one
+    fn with_config(cfg: Vec<ButtonConfig>) -> Result<FunctionLayer, Vec<String>> {
         if cfg.is_empty() {
-            panic!("Invalid configuration, layer has 0 buttons");
+            return Err(vec!["Layer has 0 buttons".to_string()]);
         }
-
+        let (layer, errors) = FunctionLayer::build(cfg);
+        if errors.is_empty() {
+            Ok(layer)
+        } else {
+            Err(errors)
+        }
+    }
+    // For layers built while running, buttons that fail show a warning sign in their place
+    fn with_config_or_warnings(cfg: Vec<ButtonConfig>) -> FunctionLayer {
+        let (layer, errors) = FunctionLayer::build(cfg);
+        for error in errors {
+            eprintln!("{}", error);
+        }
+        layer
+    }
+    fn build(cfg: Vec<ButtonConfig>) -> (FunctionLayer, Vec<String>) {
+        let mut errors = Vec::new();
+        let fingerprints: Vec<String> = cfg.iter().map(|cfg| format!("{:?}", cfg)).collect();
         let mut virtual_button_count = 0;
-        FunctionLayer {
-            displays_time: cfg.iter().any(|cfg| cfg.time.is_some()),
-            displays_battery: cfg.iter().any(|cfg| cfg.battery.is_some()),
+        let mut layer = FunctionLayer {
+            displays_time: cfg.iter().any(|cfg| cfg.time.is_some() || cfg.world_clock.is_some()),
+            displays_battery: cfg.iter().any(|cfg| cfg.battery.is_some() || cfg.battery_history.is_some()),
+            displays_ambient_light: cfg.iter().any(|cfg| cfg.ambient_light.is_some()),
+            displays_playback: cfg.iter().any(|cfg| cfg.action == ButtonAction::Key(Key::PlayPause) || cfg.now_playing == Some(true)),
+            displays_status: cfg.iter().any(|cfg| cfg.status.is_some()),
+            displays_ticker: cfg.iter().any(|cfg| cfg.ticker.is_some()),
+            displays_mail: cfg.iter().any(|cfg| cfg.mail.is_some()),
+            displays_slider: cfg.iter().any(|cfg| cfg.slider.is_some()),
             buttons: cfg
                 .into_iter()
-                .scan(&mut virtual_button_count, |state, cfg| {
+                .zip(&fingerprints)
+                .scan(&mut virtual_button_count, |state, (cfg, fingerprint)| {
                     let i = **state;
                     let mut stretch = cfg.stretch.unwrap_or(1);
                     if stretch < 1 {
@@ -751,11 +1777,110 @@ impl FunctionLayer {
                         stretch = 1;
                     }
                     **state += stretch;
-                    Some((i, Button::with_config(cfg)))
+                    let action = cfg.action.clone();
+                    let kept = take_kept_button(fingerprint).inspect(|_| watch_polled(&cfg));
+                    let button = match kept.map_or_else(|| Button::with_config(cfg), Ok) {
+                        Ok(button) => button,
+                        Err(e) => {
+                            errors.push(e);
+                            Button::new_text("⚠".to_string(), action)
+                        }
+                    };
+                    Some((i, button))
                 })
                 .collect(),
             virtual_button_count,
+            background: None,
+            drawn_shift_x: 0.0,
+            drawn_visible_count: virtual_button_count,
+            scroll: 0,
+            cheat_sheet: false,
+            fingerprints,
+            pip_controls: None,
+        };
+        // Templates only know what they show once parsed
+        for (_, button) in &layer.buttons {
+            if let ButtonImage::Template(template) = &button.image {
+                layer.displays_time |= template.uses_time();
+                layer.displays_battery |= template.uses_battery();
+            }
+            if let ButtonImage::Status(items) = &button.image {
+                layer.displays_time |= items.iter().any(|item| matches!(item, StatusItem::Clock(_)));
+                layer.displays_battery |= items.iter().any(|item| matches!(item, StatusItem::Battery(_)));
+            }
+        }
+        (layer, errors)
+    }
+    // Adds or takes away the playback controls at the end, true when the layer changed
+    fn set_pip_controls(&mut self, shown: bool) -> bool {
+        match (self.pip_controls, shown) {
+            (None, true) => {
+                let controls = FunctionLayer::with_config_or_warnings(config::pip_controls());
+                self.pip_controls = Some((self.virtual_button_count, self.displays_playback));
+                let offset = self.virtual_button_count;
+                self.buttons.extend(controls.buttons.into_iter().map(|(i, button)| (offset + i, button)));
+                self.virtual_button_count += controls.virtual_button_count;
+                self.displays_playback = true;
+                true
+            }
+            (Some((count, displays_playback)), false) => {
+                self.buttons.retain(|(i, _)| *i < count);
+                self.virtual_button_count = count;
+                self.displays_playback = displays_playback;
+                self.pip_controls = None;
+                true
+            }
+            _ => false,
+        }
+    }
+    // Moves, adds or removes a button, the others keep their stretch
+    fn edit(&mut self, edit: &LayoutEdit) -> Result<(), String> {
+        let ends: Vec<usize> = self.buttons.iter().skip(1).map(|(start, _)| *start).chain([self.virtual_button_count]).collect();
+        let mut entries: Vec<(usize, Button, String)> = std::mem::take(&mut self.buttons)
+            .into_iter()
+            .zip(ends)
+            .zip(std::mem::take(&mut self.fingerprints))
+            .map(|(((start, button), end), fingerprint)| (end - start, button, fingerprint))
+            .collect();
+        let result = edit.apply(&mut entries, |text| {
+            let added = FunctionLayer::with_config(vec![config::parse_button(text)?]).map_err(|errors| errors.join(", "))?;
+            self.displays_time |= added.displays_time;
+            self.displays_battery |= added.displays_battery;
+            self.displays_ambient_light |= added.displays_ambient_light;
+            self.displays_playback |= added.displays_playback;
+            self.displays_status |= added.displays_status;
+            self.displays_ticker |= added.displays_ticker;
+            self.displays_mail |= added.displays_mail;
+            self.displays_slider |= added.displays_slider;
+            let (_, button) = added.buttons.into_iter().next().ok_or("no button given")?;
+            Ok((added.virtual_button_count, button, added.fingerprints.into_iter().next().unwrap_or_default()))
+        });
+        let mut start = 0;
+        for (stretch, button, fingerprint) in entries {
+            self.buttons.push((start, button));
+            self.fingerprints.push(fingerprint);
+            start += stretch;
         }
+        self.virtual_button_count = start;
+        self.drawn_visible_count = self.drawn_visible_count.min(start);
+        result
+    }
+    // Whether the layer has more buttons than the bar shows at once
+    fn scrollable(&self) -> bool {
+        self.drawn_visible_count < self.virtual_button_count
+    }
+    // Moves a bar's width worth of buttons on, or back when `forward` is false
+    fn scroll_page(&mut self, forward: bool) {
+        let page = self.drawn_visible_count;
+        self.scroll = if forward {
+            (self.scroll + page).min(self.virtual_button_count - page)
+        } else {
+            self.scroll.saturating_sub(page)
+        };
+    }
+    fn with_background(mut self, background: Option<Background>) -> FunctionLayer {
+        self.background = background;
+        self
     }
     fn draw(
         &mut self,
@@ -765,35 +1890,47 @@ impl FunctionLayer {
         surface: &Surface,
         pixel_shift: (f64, f64),
         complete_redraw: bool,
+        dimmed: bool,
     ) -> Vec<ClipRect> {
         let c = Context::new(surface).unwrap();
         let mut modified_regions = if complete_redraw {
-            vec![ClipRect::new(0, 0, height as u16, width as u16)]
+            vec![bar_rect_to_clip(config.rotation, width, height, 0.0, 0.0, width as f64, height as f64)]
         } else {
             Vec::new()
         };
-        c.translate(height as f64, 0.0);
-        c.rotate((90.0f64).to_radians());
+        apply_rotation(&c, config.rotation, width, height);
         let pixel_shift_width = if config.enable_pixel_shift {
-            PIXEL_SHIFT_WIDTH_PX
+            config.pixel_shift_width
         } else {
             0
         };
-        let virtual_button_width = ((width - pixel_shift_width as i32)
-            - (BUTTON_SPACING_PX * (self.virtual_button_count - 1) as i32))
-            as f64
-            / self.virtual_button_count as f64;
-        let radius = 8.0f64;
+        let spacing = BUTTON_SPACING_PX as f64 * config.scale;
+        let visible_count = match config.max_visible_buttons {
+            0 => self.virtual_button_count,
+            max => max.min(self.virtual_button_count),
+        };
+        self.drawn_visible_count = visible_count;
+        self.scroll = self.scroll.min(self.virtual_button_count - visible_count);
+        let virtual_button_width = ((width - pixel_shift_width as i32) as f64
+            - (spacing * (visible_count - 1) as f64))
+            / visible_count as f64;
+        let radius = 8.0 * config.scale;
         let bot = (height as f64) * 0.15;
         let top = (height as f64) * 0.85;
         let (pixel_shift_x, pixel_shift_y) = pixel_shift;
+        // A button moved by pixel shift has to be cleared where it was drawn before
+        let shift_delta = pixel_shift_x - self.drawn_shift_x;
+        self.drawn_shift_x = pixel_shift_x;
 
         if complete_redraw {
             c.set_source_rgb(0.0, 0.0, 0.0);
             c.paint().unwrap();
+            if let Some(background) = &self.background {
+                background.paint(&c, width as f64, height as f64);
+            }
         }
         c.set_font_face(&config.font_face);
-        c.set_font_size(32.0);
+        c.set_font_size(32.0 * config.scale);
 
         for i in 0..self.buttons.len() {
             let end = if i + 1 < self.buttons.len() {
@@ -807,39 +1944,62 @@ impl FunctionLayer {
             if !button.changed && !complete_redraw {
                 continue;
             };
+            // Scrolled out of sight, drawn once it comes back
+            if end <= self.scroll || start >= self.scroll + visible_count {
+                continue;
+            }
 
-            let left_edge = (start as f64 * (virtual_button_width + BUTTON_SPACING_PX as f64))
+            let left_edge = ((start as f64 - self.scroll as f64) * (virtual_button_width + spacing))
                 .floor()
                 + pixel_shift_x
                 + (pixel_shift_width / 2) as f64;
 
             let button_width = virtual_button_width
-                + ((end - start - 1) as f64 * (virtual_button_width + BUTTON_SPACING_PX as f64))
+                + ((end - start - 1) as f64 * (virtual_button_width + spacing))
                     .floor();
 
-            let show_outline = button.show_outline.unwrap_or(config.show_button_outlines);
+            // Union of the old and new position of the button
+            let dirty_left = (left_edge - shift_delta.max(0.0)).floor();
+            let dirty_width = (button_width + shift_delta.abs()).ceil() + 1.0;
+
+            let show_outline = config.high_contrast || button.show_outline.unwrap_or(config.show_button_outlines);
             if !complete_redraw {
-                c.set_source_rgb(0.0, 0.0, 0.0);
+                // Restore whatever was underneath the button before repainting it
+                c.save().unwrap();
                 c.rectangle(
-                    left_edge,
+                    dirty_left,
                     bot - radius,
-                    button_width,
+                    dirty_width,
                     top - bot + radius * 2.0,
                 );
-                c.fill().unwrap();
+                c.clip();
+                c.set_source_rgb(0.0, 0.0, 0.0);
+                c.paint().unwrap();
+                if let Some(background) = &self.background {
+                    background.paint(&c, width as f64, height as f64);
+                }
+                c.restore().unwrap();
             }
 
-            if button.active {
+            // A button with tap zones only lights up the zone being touched
+            let active_zone = button.active_zone.filter(|_| button.active && !button.zones.is_empty());
+            // Outline-less buttons stay transparent so the background shows through
+            let fill_button = if button.active && active_zone.is_none() {
                 button.set_backround_color(&c, BUTTON_COLOR_ACTIVE);
+                true
             } else if show_outline {
                 if let Some(custom_color) = &button.outline_color {
                     custom_color.set_cairo_source(&c);
                 } else {
                     button.set_backround_color(&c, BUTTON_COLOR_INACTIVE);
                 }
+                true
+            } else if self.background.is_some() {
+                false
             } else {
                 button.set_backround_color(&c, 0.0);
-            }
+                true
+            };
             // draw box with rounded corners
             c.new_sub_path();
             let left = left_edge + radius;
@@ -874,47 +2034,145 @@ impl FunctionLayer {
             );
             c.close_path();
 
-            c.fill().unwrap();
+            let state_pattern = button.state_pattern();
+            let slider_level = button.slider_level();
+            let outline = (active_zone.is_some() || state_pattern.is_some() || slider_level.is_some()).then(|| c.copy_path().unwrap());
+            if fill_button && config.high_contrast {
+                // A thick white border keeps every button's edges visible, drawn inside
+                // the button so nothing is left behind when it moves
+                c.fill_preserve().unwrap();
+                c.save().unwrap();
+                c.clip_preserve();
+                c.set_source_rgb(1.0, 1.0, 1.0);
+                c.set_line_width(6.0 * config.scale);
+                c.stroke().unwrap();
+                c.restore().unwrap();
+            } else if fill_button {
+                c.fill().unwrap();
+            } else {
+                c.new_path();
+            }
+            if let (Some(zone), Some(outline)) = (active_zone, &outline) {
+                let zone_width = button_width / button.zones.len() as f64;
+                c.save().unwrap();
+                c.rectangle(
+                    (left_edge + zone as f64 * zone_width).floor(),
+                    bot - radius,
+                    zone_width.ceil(),
+                    top - bot + radius * 2.0,
+                );
+                c.clip();
+                c.append_path(outline);
+                button.set_backround_color(&c, BUTTON_COLOR_ACTIVE);
+                c.fill().unwrap();
+                c.restore().unwrap();
+            }
+            if let (Some(level), Some(outline)) = (slider_level, &outline) {
+                c.save().unwrap();
+                c.rectangle(
+                    left_edge.floor(),
+                    bot - radius,
+                    (button_width * level).ceil(),
+                    top - bot + radius * 2.0,
+                );
+                c.clip();
+                c.append_path(outline);
+                button.set_backround_color(&c, BUTTON_COLOR_ACTIVE);
+                c.fill().unwrap();
+                c.restore().unwrap();
+            }
+            // Stripes while charging, crosshatch when low, for those who can't tell the tints apart
+            if let (Some(state), Some(outline)) = (state_pattern, &outline) {
+                let (pattern_top, pattern_height) = (bot - radius, top - bot + radius * 2.0);
+                c.save().unwrap();
+                c.append_path(outline);
+                c.clip();
+                c.set_source_rgba(1.0, 1.0, 1.0, 0.3);
+                c.set_line_width(2.0 * config.scale);
+                let mut x = left_edge - pattern_height;
+                while x < left_edge + button_width {
+                    c.move_to(x, pattern_top + pattern_height);
+                    c.line_to(x + pattern_height, pattern_top);
+                    if state == BatteryState::Low {
+                        c.move_to(x, pattern_top);
+                        c.line_to(x + pattern_height, pattern_top + pattern_height);
+                    }
+                    x += 12.0 * config.scale;
+                }
+                c.stroke().unwrap();
+                c.restore().unwrap();
+            }
             c.set_source_rgb(1.0, 1.0, 1.0);
-            button.render(
-                &c,
-                height,
-                left_edge,
-                button_width.ceil() as u64,
-                pixel_shift_y,
-            );
+            if self.cheat_sheet {
+                button.render_action_label(&c, height, left_edge, button_width.ceil() as u64, pixel_shift_y, config.scale);
+            } else {
+                button.render(
+                    &c,
+                    height,
+                    left_edge,
+                    button_width.ceil() as u64,
+                    pixel_shift_y,
+                    config.scale,
+                );
+            }
 
             button.changed = false;
 
+            // Everything repainted above, including the background the button moved off of
+            if dimmed && !complete_redraw {
+                c.save().unwrap();
+                c.rectangle(
+                    dirty_left,
+                    bot - radius,
+                    dirty_width,
+                    top - bot + radius * 2.0,
+                );
+                c.clip();
+                apply_dimmed_style(&c, config.dimmed_content_style);
+                c.restore().unwrap();
+            }
+
             if !complete_redraw {
-                modified_regions.push(ClipRect::new(
-                    height as u16 - top as u16 - radius as u16,
-                    left_edge as u16,
-                    height as u16 - bot as u16 + radius as u16,
-                    left_edge as u16 + button_width as u16,
+                modified_regions.push(bar_rect_to_clip(
+                    config.rotation,
+                    width,
+                    height,
+                    dirty_left,
+                    bot - radius,
+                    (dirty_left + dirty_width).min(width as f64),
+                    top + radius,
                 ));
             }
         }
 
+        // Where the shown buttons are among all of them, under the buttons of a scrolling layer
+        if complete_redraw && visible_count < self.virtual_button_count {
+            let track = width as f64 - pixel_shift_width as f64;
+            let thumb_left = track * self.scroll as f64 / self.virtual_button_count as f64;
+            let thumb_width = track * visible_count as f64 / self.virtual_button_count as f64;
+            c.set_source_rgba(1.0, 1.0, 1.0, 0.4);
+            c.rectangle(
+                (thumb_left + pixel_shift_x + (pixel_shift_width / 2) as f64).round(),
+                (top + radius + 2.0 * config.scale).round(),
+                thumb_width.round(),
+                (2.0 * config.scale).ceil(),
+            );
+            c.fill().unwrap();
+        }
+
+        if dimmed && complete_redraw {
+            apply_dimmed_style(&c, config.dimmed_content_style);
+        }
+
         modified_regions
     }
 
-    fn hit(&self, width: u16, height: u16, x: f64, y: f64, i: Option<usize>) -> Option<usize> {
+    // Left edge and width of a button as used for hit testing
+    fn button_extent(&self, width: u16, scale: f64, i: usize) -> (f64, f64) {
+        let spacing = BUTTON_SPACING_PX as f64 * scale;
         let virtual_button_width =
-            (width as i32 - (BUTTON_SPACING_PX * (self.virtual_button_count - 1) as i32)) as f64
-                / self.virtual_button_count as f64;
-
-        let i = i.unwrap_or_else(|| {
-            let virtual_i = (x / (width as f64 / self.virtual_button_count as f64)) as usize;
-            self.buttons
-                .iter()
-                .position(|(start, _)| *start > virtual_i)
-                .unwrap_or(self.buttons.len())
-                - 1
-        });
-        if i >= self.buttons.len() {
-            return None;
-        }
+            (width as f64 - (spacing * (self.drawn_visible_count - 1) as f64))
+                / self.drawn_visible_count as f64;
 
         let start = self.buttons[i].0;
         let end = if i + 1 < self.buttons.len() {
@@ -923,11 +2181,43 @@ impl FunctionLayer {
             self.virtual_button_count
         };
 
-        let left_edge = (start as f64 * (virtual_button_width + BUTTON_SPACING_PX as f64)).floor();
+        let left_edge = ((start as f64 - self.scroll as f64) * (virtual_button_width + spacing)).floor();
 
         let button_width = virtual_button_width
-            + ((end - start - 1) as f64 * (virtual_button_width + BUTTON_SPACING_PX as f64))
+            + ((end - start - 1) as f64 * (virtual_button_width + spacing))
                 .floor();
+        (left_edge, button_width)
+    }
+    // Where `x` is across button `i`, from 0 at its left edge to 1 at its right one
+    fn fraction_at(&self, width: u16, scale: f64, x: f64, i: usize) -> f64 {
+        let (left_edge, button_width) = self.button_extent(width, scale, i);
+        ((x - left_edge) / button_width).clamp(0.0, 1.0)
+    }
+    // Which tap zone of button `i` is under `x`, None for buttons with a single zone
+    fn zone_at(&self, width: u16, scale: f64, x: f64, i: usize) -> Option<usize> {
+        let zones = self.buttons[i].1.zones.len();
+        if zones == 0 {
+            return None;
+        }
+        let (left_edge, button_width) = self.button_extent(width, scale, i);
+        let zone = ((x - left_edge) / button_width * zones as f64).floor().max(0.0) as usize;
+        Some(zone.min(zones - 1))
+    }
+    fn hit(&self, width: u16, height: u16, scale: f64, x: f64, y: f64, i: Option<usize>) -> Option<usize> {
+
+        let i = i.unwrap_or_else(|| {
+            let virtual_i = (x / (width as f64 / self.drawn_visible_count as f64)) as usize + self.scroll;
+            self.buttons
+                .iter()
+                .position(|(start, _)| *start > virtual_i)
+                .unwrap_or(self.buttons.len())
+                - 1
+        });
+        if i >= self.buttons.len() {
+            return None;
+        }
+
+        let (left_edge, button_width) = self.button_extent(width, scale, i);
 
         if x < left_edge
             || x > (left_edge + button_width)
@@ -941,6 +2231,102 @@ impl FunctionLayer {
     }
 }
 
+// Maps the landscape bar coordinate space onto the framebuffer, whose scanout
+// orientation depends on how the panel is mounted
+fn apply_rotation(c: &Context, rotation: u32, width: i32, height: i32) {
+    match rotation {
+        0 => {}
+        180 => {
+            c.translate(width as f64, height as f64);
+            c.rotate((180.0f64).to_radians());
+        }
+        270 => {
+            c.translate(0.0, width as f64);
+            c.rotate((-90.0f64).to_radians());
+        }
+        _ => {
+            c.translate(height as f64, 0.0);
+            c.rotate((90.0f64).to_radians());
+        }
+    }
+}
+
+fn bar_point_to_fb(rotation: u32, width: i32, height: i32, x: f64, y: f64) -> (f64, f64) {
+    match rotation {
+        0 => (x, y),
+        180 => (width as f64 - x, height as f64 - y),
+        270 => (y, width as f64 - x),
+        _ => (height as f64 - y, x),
+    }
+}
+
+// The digitizer reports landscape coordinates that are upright at Rotation 0 and 90, a panel
+// mounted the other way round has its touches turned half a turn along with its drawing
+fn touch_point_to_bar(rotation: u32, width: i32, height: i32, x: f64, y: f64) -> (f64, f64) {
+    match rotation {
+        180 | 270 => bar_point_to_fb(180, width, height, x, y),
+        _ => (x, y),
+    }
+}
+
+// Small padlock in the top right corner of the bar while the layer is locked
+fn draw_lock_glyph(config: &Config, width: i32, height: i32, surface: &Surface) -> ClipRect {
+    let c = Context::new(surface).unwrap();
+    apply_rotation(&c, config.rotation, width, height);
+    let size = 10.0 * config.scale;
+    let left = width as f64 - size * 2.0;
+    let top = size * 0.5;
+    c.set_source_rgb(1.0, 1.0, 1.0);
+    c.set_line_width(1.5 * config.scale);
+    c.arc(left + size / 2.0, top + size * 0.45, size * 0.3, std::f64::consts::PI, 2.0 * std::f64::consts::PI);
+    c.stroke().unwrap();
+    c.rectangle(left, top + size * 0.45, size, size * 0.55);
+    c.fill().unwrap();
+    let margin = 2.0 * config.scale;
+    bar_rect_to_clip(config.rotation, width, height, left - margin, top - margin, left + size + margin, top + size + margin)
+}
+
+fn bar_rect_to_clip(rotation: u32, width: i32, height: i32, x1: f64, y1: f64, x2: f64, y2: f64) -> ClipRect {
+    let (ax, ay) = bar_point_to_fb(rotation, width, height, x1, y1);
+    let (bx, by) = bar_point_to_fb(rotation, width, height, x2, y2);
+    ClipRect::new(
+        ax.min(bx).max(0.0) as u16,
+        ay.min(by).max(0.0) as u16,
+        ax.max(bx).max(0.0) as u16,
+        ay.max(by).max(0.0) as u16,
+    )
+}
+
+// Cuts the end off text wider than room, marking the cut with an ellipsis
+fn truncate_to_width(c: &Context, text: &str, room: f64) -> String {
+    let mut text = text.to_string();
+    while c.text_extents(&text).unwrap().width() > room && text.chars().count() > 2 {
+        let mut shorter = text.trim_end_matches('…').to_string();
+        shorter.pop();
+        text = format!("{}…", shorter.trim_end());
+    }
+    text
+}
+
+fn apply_dimmed_style(c: &Context, style: DimmedContentStyle) {
+    match style {
+        DimmedContentStyle::None => {}
+        DimmedContentStyle::Grayscale => {
+            c.save().unwrap();
+            c.set_operator(Operator::HslSaturation);
+            c.set_source_rgb(0.5, 0.5, 0.5);
+            c.paint().unwrap();
+            c.restore().unwrap();
+        }
+        DimmedContentStyle::Fade => {
+            c.save().unwrap();
+            c.set_source_rgba(0.0, 0.0, 0.0, 0.5);
+            c.paint().unwrap();
+            c.restore().unwrap();
+        }
+    }
+}
+
 struct Interface;
 
 impl LibinputInterface for Interface {
@@ -977,6 +2363,170 @@ where
         .unwrap();
 }
 
+fn action_keys(action: &ButtonAction, keys: &mut HashSet<Key>) {
+    match action {
+        ButtonAction::Key(key) => {
+            keys.insert(*key);
+        }
+        ButtonAction::KeyCombos(combo) => keys.extend(combo),
+        ButtonAction::KeySequence(combos) => keys.extend(combos.iter().flatten()),
+        ButtonAction::Calculator(CalculatorKey::Type) => keys.extend(calculator::TYPED_KEYS),
+        _ => {}
+    }
+}
+
+fn pinch_keys(pinch: Option<&PinchConfig>, keys: &mut HashSet<Key>) {
+    match pinch {
+        Some(PinchConfig::Wheel(true)) => {
+            keys.insert(Key::LeftCtrl);
+        }
+        Some(PinchConfig::Actions(actions)) => actions.iter().for_each(|action| action_keys(action, keys)),
+        _ => {}
+    }
+}
+
+// Every key the layers, expandables, Hyprland expandables, Fn tap and swipes of the configuration can send
+fn configured_keys(cfg: &Config, layers: &[FunctionLayer]) -> HashSet<Key> {
+    let mut keys = HashSet::new();
+    for layer in layers {
+        for (_, button) in &layer.buttons {
+            action_keys(&button.action, &mut keys);
+            pinch_keys(button.pinch.as_ref(), &mut keys);
+            if let Some(long_press) = &button.long_press {
+                action_keys(long_press, &mut keys);
+            }
+        }
+    }
+    for (name, expandable_buttons) in &cfg.expandables {
+        let gamepad = cfg.gamepad_expandables.contains(name);
+        for button in expandable_buttons {
+            // Their keys are pressed on the virtual gamepad
+            if !gamepad || !matches!(button.action, ButtonAction::Key(_)) {
+                action_keys(&button.action, &mut keys);
+            }
+            pinch_keys(button.pinch.as_ref(), &mut keys);
+            if let Some(long_press) = &button.long_press {
+                action_keys(long_press, &mut keys);
+            }
+        }
+    }
+    for hyprland_expandable_configs in cfg.hyprland_expandables.values() {
+        for hyprland_config in hyprland_expandable_configs {
+            for button in &hyprland_config.layer_keys {
+                action_keys(&button.action, &mut keys);
+            }
+        }
+    }
+    if let Some(FnTap::Action(action)) = &cfg.fn_tap {
+        action_keys(action, &mut keys);
+    }
+    for action in [&cfg.swipe_left, &cfg.swipe_right, &cfg.swipe_up, &cfg.swipe_down].into_iter().flatten() {
+        action_keys(action, &mut keys);
+    }
+    if cfg.separate_media_device {
+        keys.retain(|key| !consumer::is_media_key(*key));
+    }
+    keys
+}
+
+// The buttons the gamepad expandables press
+fn gamepad_buttons(cfg: &Config) -> HashSet<Key> {
+    let mut buttons = HashSet::new();
+    for name in &cfg.gamepad_expandables {
+        for button in cfg.expandables.get(name).into_iter().flatten() {
+            if let ButtonAction::Key(key) = button.action {
+                buttons.insert(key);
+            }
+        }
+    }
+    buttons
+}
+
+// The keys a virtual keyboard can send are fixed once it's created, new ones need another
+fn register_keys(uinput: &mut UInputHandle<File>, registered_keys: &mut HashSet<Key>, keys: HashSet<Key>) {
+    if keys.is_subset(registered_keys) {
+        return;
+    }
+    println!("Recreating the virtual keyboard for {} newly configured keys", keys.difference(registered_keys).count());
+    registered_keys.extend(keys);
+    if let Err(e) = uinput.dev_destroy() {
+        eprintln!("Failed to remove the previous virtual keyboard: {}", e);
+    }
+    *uinput = create_uinput(registered_keys);
+}
+
+fn create_uinput(keys: &HashSet<Key>) -> UInputHandle<File> {
+    let uinput = UInputHandle::new(privileged::open("/dev/uinput", true).unwrap());
+    uinput.set_evbit(EventKind::Key).unwrap();
+    for key in keys {
+        uinput.set_keybit(*key).unwrap();
+    }
+    finish_uinput(uinput, "Dynamic Function Row Virtual Input Device")
+}
+
+// Only made once a scroll strip is first dragged. The pointer axes and button are
+// there so it's taken for a mouse, the wheels are all it ever sends.
+fn create_scroll_wheel() -> UInputHandle<File> {
+    let uinput = UInputHandle::new(privileged::open("/dev/uinput", true).unwrap());
+    uinput.set_evbit(EventKind::Key).unwrap();
+    uinput.set_keybit(Key::ButtonLeft).unwrap();
+    uinput.set_evbit(EventKind::Relative).unwrap();
+    for axis in [
+        RelativeAxis::X, RelativeAxis::Y,
+        RelativeAxis::Wheel, RelativeAxis::WheelHiRes,
+        RelativeAxis::HorizontalWheel, RelativeAxis::HorizontalWheelHiRes,
+    ] {
+        uinput.set_relbit(axis).unwrap();
+    }
+    finish_uinput(uinput, "Dynamic Function Row Virtual Scroll Wheel")
+}
+
+fn finish_uinput(uinput: UInputHandle<File>, name: &str) -> UInputHandle<File> {
+    let mut dev_name_c = [0 as c_char; 80];
+    let dev_name = name.as_bytes();
+    for i in 0..dev_name.len() {
+        dev_name_c[i] = dev_name[i] as c_char;
+    }
+    uinput
+        .dev_setup(&uinput_setup {
+            id: input_id {
+                bustype: 0x19,
+                vendor: 0x1209,
+                product: 0x316E,
+                version: 1,
+            },
+            ff_effects_max: 0,
+            name: dev_name_c,
+        })
+        .unwrap();
+    uinput.dev_create().unwrap();
+    uinput
+}
+
+// Turns the wheel by `amount` 120ths of a notch, whole notches are also sent the old way
+fn scroll_wheel<F>(uinput: &mut UInputHandle<F>, axis: ScrollAxis, amount: i32, remainder: &mut i32)
+where
+    F: AsRawFd,
+{
+    let (hi_res, notches) = match axis {
+        ScrollAxis::Vertical => (RelativeAxis::WheelHiRes, RelativeAxis::Wheel),
+        ScrollAxis::Horizontal => (RelativeAxis::HorizontalWheelHiRes, RelativeAxis::HorizontalWheel),
+    };
+    emit(uinput, EventKind::Relative, hi_res as u16, amount);
+    *remainder += amount;
+    let whole = *remainder / 120;
+    if whole != 0 {
+        emit(uinput, EventKind::Relative, notches as u16, whole);
+        *remainder -= whole * 120;
+    }
+    emit(
+        uinput,
+        EventKind::Synchronize,
+        SynchronizeKind::Report as u16,
+        0,
+    );
+}
+
 fn toggle_key<F>(uinput: &mut UInputHandle<F>, code: Key, value: i32)
 where
     F: AsRawFd,
@@ -990,31 +2540,55 @@ where
     );
 }
 
-fn update_layer_for_navigation(navigation_state: &NavigationState, config: &Config, layers: &mut [FunctionLayer; 2], active_layer: &mut usize, needs_complete_redraw: &mut bool, original_layers: &[FunctionLayer; 2], touches: &mut HashMap<u32, (usize, usize)>) {
+// Keys of gamepad expandables are pressed on the virtual gamepad instead of the keyboard
+fn press_key<F>(uinput: &mut UInputHandle<F>, key: Key, pressed: bool, config: &Config, navigation_state: &NavigationState)
+where
+    F: AsRawFd,
+{
+    if !pressed && gamepad::release(key) {
+        return;
+    }
+    let gamepad_layer = navigation_state
+        .current_expandable
+        .as_ref()
+        .is_some_and(|name| config.gamepad_expandables.contains(name));
+    if pressed && gamepad_layer {
+        gamepad::press(key);
+    } else {
+        send_key(uinput, key, pressed as i32);
+    }
+}
+
+// Presses or lets go of a key on the virtual keyboard, or on the consumer control device for media keys
+fn send_key<F>(uinput: &mut UInputHandle<F>, key: Key, value: i32)
+where
+    F: AsRawFd,
+{
+    if !consumer::toggle(key, value) {
+        toggle_key(uinput, key, value);
+    }
+}
+
+fn update_layer_for_navigation(navigation_state: &NavigationState, config: &Config, layers: &mut [FunctionLayer], active_layer: &mut usize, needs_complete_redraw: &mut bool, original_layers: &[FunctionLayer], touches: &mut HashMap<u32, (usize, usize)>) {
     if let Some(expandable_name) = &navigation_state.current_expandable {
         if let Some(expandable_buttons) = config.expandables.get(expandable_name) {
             // Create back button
             let back_button = ButtonConfig {
                 icon: Some("back".to_string()),
                 text: Some("Back".to_string()),
-                theme: None,
-                time: None,
-                battery: None,
-                locale: None,
-                action: ButtonAction::Command("Back".to_string()),
-                stretch: None,
                 show_button_outlines: Some(config.back_button_show_outlines),
                 button_outlines_color: config.back_button_outline_color.clone(),
-                show_app_icon_alongside_text: None,
-                app_icon: None,
+                ..ButtonConfig::new(ButtonAction::Command("Back".to_string()))
             };
 
-            // Combine back button with expandable buttons
+            // Combine back button and breadcrumb trail with expandable buttons
             let mut combined_buttons = vec![back_button];
+            combined_buttons.extend(breadcrumb_buttons(navigation_state, config));
             combined_buttons.extend_from_slice(expandable_buttons);
 
             // Replace the current layer with the expandable
-            layers[*active_layer] = FunctionLayer::with_config(combined_buttons);
+            layers[*active_layer] = FunctionLayer::with_config_or_warnings(combined_buttons)
+                .with_background(config.expandable_backgrounds.get(expandable_name).or(config.background.as_ref()).cloned());
             *needs_complete_redraw = true;
 
             // Clear all active touches to prevent accidental triggering in new layout
@@ -1022,8 +2596,7 @@ fn update_layer_for_navigation(navigation_state: &NavigationState, config: &Conf
         }
     } else {
         // Return to original configuration
-        layers[0] = original_layers[0].clone();
-        layers[1] = original_layers[1].clone();
+        layers.clone_from_slice(original_layers);
         *needs_complete_redraw = true;
 
         // Clear all active touches to prevent accidental triggering in new layout
@@ -1031,20 +2604,87 @@ fn update_layer_for_navigation(navigation_state: &NavigationState, config: &Conf
 
         // Force update hyprland buttons with current window state after back button
         if let Ok(window_info) = hyprland::get_active_window_info() {
-            for button in &mut layers[*active_layer].buttons {
-                match &button.1.action {
-                    config::ButtonAction::HyprlandExpand(_expand_name) => {
-                        // Update hyprland button with current window regardless of cache state
-                        update_hyprland_button_content(button, &window_info);
-                    }
-                    _ => {}
-                }
-            }
+            refresh_active_window_buttons(&mut layers[*active_layer], &window_info);
         }
     }
 }
 
-fn clear_all_touches(layers: &mut [FunctionLayer; 2], touches: &mut HashMap<u32, (usize, usize)>) {
+fn breadcrumb_label(expandable_name: &str) -> String {
+    let label = if expandable_name.starts_with("hyprland_") {
+        "App"
+    } else {
+        expandable_name.strip_prefix("Expand_").unwrap_or(expandable_name)
+    };
+    if label.chars().count() > 8 {
+        format!("{}…", label.chars().take(7).collect::<String>())
+    } else {
+        label.to_string()
+    }
+}
+
+// Breadcrumb trail shown next to the back button once expandables are nested
+fn breadcrumb_buttons(navigation_state: &NavigationState, config: &Config) -> Vec<ButtonConfig> {
+    if navigation_state.navigation_stack.is_empty() {
+        return Vec::new();
+    }
+    let mut crumbs = vec!["Main".to_string()];
+    crumbs.extend(navigation_state.navigation_stack.iter().map(|name| breadcrumb_label(name)));
+    if let Some(current) = &navigation_state.current_expandable {
+        crumbs.push(breadcrumb_label(current));
+    }
+    let last = crumbs.len() - 1;
+    crumbs
+        .into_iter()
+        .enumerate()
+        .map(|(level, label)| ButtonConfig {
+            text: Some(if level == last { label } else { format!("{} ▸", label) }),
+            show_button_outlines: Some(config.back_button_show_outlines),
+            button_outlines_color: config.back_button_outline_color.clone(),
+            ..ButtonConfig::new(ButtonAction::NavigateTo(level))
+        })
+        .collect()
+}
+
+fn navigate_to_level(level: usize, config: &Config, navigation_state: &mut NavigationState, layers: &mut [FunctionLayer], active_layer: &mut usize, needs_complete_redraw: &mut bool, original_layers: &[FunctionLayer], touches: &mut HashMap<u32, (usize, usize)>) {
+    if !navigation_state.jump_to_level(level) {
+        return;
+    }
+    // Hyprland expandables are rebuilt from the active window instead of the expandables table
+    let hyprland_name = navigation_state
+        .current_expandable
+        .as_ref()
+        .and_then(|name| name.strip_prefix("hyprland_"))
+        .map(|name| name.to_string());
+    if let Some(hyprland_name) = hyprland_name {
+        navigation_state.pop_expandable();
+        handle_hyprland_expand(&hyprland_name, config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+    } else {
+        update_layer_for_navigation(navigation_state, config, layers, active_layer, needs_complete_redraw, original_layers, touches);
+    }
+}
+
+// LayerSwitch_<Name> brings that layer up, or the default one back if it's already shown.
+// Next and Previous go through all of them in turn
+fn switch_layer(name: &str, config: &Config, navigation_state: &mut NavigationState, layers: &mut [FunctionLayer], active_layer: &mut usize, needs_complete_redraw: &mut bool, original_layers: &[FunctionLayer], touches: &mut HashMap<u32, (usize, usize)>) {
+    if navigation_state.locked {
+        return;
+    }
+    let count = layers.len();
+    let target = match name {
+        "Next" => (*active_layer + 1) % count,
+        "Previous" => (*active_layer + count - 1) % count,
+        name => match config.layer_target(name) {
+            Some(WorkspaceTarget::Layer(layer)) if layer == *active_layer && navigation_state.current_expandable.is_none() => 0,
+            Some(WorkspaceTarget::Layer(layer)) => layer,
+            _ => return,
+        },
+    };
+    navigation_state.reset_to_main();
+    *active_layer = target;
+    update_layer_for_navigation(navigation_state, config, layers, active_layer, needs_complete_redraw, original_layers, touches);
+}
+
+fn clear_all_touches(layers: &mut [FunctionLayer], touches: &mut HashMap<u32, (usize, usize)>) {
     // Only clear if there are actually touches to clear
     if touches.is_empty() {
         return;
@@ -1064,7 +2704,7 @@ fn clear_all_touches(layers: &mut [FunctionLayer; 2], touches: &mut HashMap<u32,
     touches.clear();
 }
 
-fn handle_hyprland_expand(hyprland_expand_name: &str, config: &Config, navigation_state: &mut NavigationState, layers: &mut [FunctionLayer; 2], active_layer: &mut usize, needs_complete_redraw: &mut bool, _original_layers: &[FunctionLayer; 2], touches: &mut HashMap<u32, (usize, usize)>) {
+fn handle_hyprland_expand(hyprland_expand_name: &str, config: &Config, navigation_state: &mut NavigationState, layers: &mut [FunctionLayer], active_layer: &mut usize, needs_complete_redraw: &mut bool, _original_layers: &[FunctionLayer], touches: &mut HashMap<u32, (usize, usize)>) {
     // Get the active window information
     let active_window_info = match hyprland::get_active_window_info() {
         Ok(info) => info,
@@ -1076,9 +2716,9 @@ fn handle_hyprland_expand(hyprland_expand_name: &str, config: &Config, navigatio
 
     // Check if we have a Hyprland expandable configuration for this action
     if let Some(hyprland_configs) = config.hyprland_expandables.get(hyprland_expand_name) {
-        // Find a matching configuration based on the active window class
+        // The first configuration matching the active window's class and title wins
         let matching_config = hyprland_configs.iter().find(|config| {
-            config.class == active_window_info.class
+            config.matches(&active_window_info.class, &active_window_info.title)
         });
 
         if let Some(matched_config) = matching_config {
@@ -1090,29 +2730,26 @@ fn handle_hyprland_expand(hyprland_expand_name: &str, config: &Config, navigatio
             let window_button_config = ButtonConfig {
                 icon: Some("back".to_string()), // Show back arrow icon
                 text: Some("Back".to_string()), // Fixed: Show "Back" text instead of window title
-                theme: None,
-                time: None,
-                battery: None,
-                locale: None,
-                action: ButtonAction::Command("Back".to_string()),
-                stretch: None,
                 show_button_outlines: Some(config.back_button_show_outlines),
                 button_outlines_color: config.back_button_outline_color.clone(),
                 show_app_icon_alongside_text: Some(true), // Show icon alongside text
                 app_icon: Some("back".to_string()), // Use back icon
+                ..ButtonConfig::new(ButtonAction::Command("Back".to_string()))
             };
 
+            // Push to navigation state to track this expansion
+            navigation_state.push_expandable(format!("hyprland_{}", hyprland_expand_name));
+
             // Combine window button with expandable layer keys
             let mut combined_buttons = vec![window_button_config];
+            combined_buttons.extend(breadcrumb_buttons(navigation_state, config));
             combined_buttons.extend_from_slice(&matched_config.layer_keys);
 
             // Replace the current layer with the expandable
-            layers[*active_layer] = FunctionLayer::with_config(combined_buttons);
+            layers[*active_layer] = FunctionLayer::with_config_or_warnings(combined_buttons)
+                .with_background(config.background.clone());
             *needs_complete_redraw = true;
 
-            // Push to navigation state to track this expansion
-            navigation_state.push_expandable(format!("hyprland_{}", hyprland_expand_name));
-
             // Clear all active touches to prevent accidental triggering in new layout
             clear_all_touches(layers, touches);
         }
@@ -1121,24 +2758,124 @@ fn handle_hyprland_expand(hyprland_expand_name: &str, config: &Config, navigatio
     // If no Hyprland expandable configuration found, ignore the button press
 }
 
-fn handle_button_action<F>(uinput: &mut UInputHandle<F>, action: &ButtonAction, config: &Config, active: bool, navigation_state: &mut NavigationState, layers: &mut [FunctionLayer; 2], active_layer: &mut usize, needs_complete_redraw: &mut bool, original_layers: &[FunctionLayer; 2], touches: &mut HashMap<u32, (usize, usize)>, pending_actions: &mut Vec<PendingAction>, button_index: Option<usize>)
+fn needs_confirmation(command_id: &str, config: &Config, layer: &FunctionLayer, button_index: usize) -> bool {
+    let button_confirm = layer.buttons.get(button_index).is_some_and(|(_, button)| button.confirm);
+    let command_confirm = config.commands.get(command_id).and_then(|spec| spec.confirm).unwrap_or(false);
+    button_confirm || command_confirm
+}
+
+// What the prompt asks about, the button's own text when it has one
+fn confirm_label(command_id: &str, layer: &FunctionLayer, button_index: usize) -> String {
+    let text = layer.buttons.get(button_index).and_then(|(_, button)| match &button.image {
+        ButtonImage::Text(text) | ButtonImage::TextWithIcon(text, _) => Some(text.trim().to_string()),
+        _ => None,
+    });
+    text.filter(|text| !text.is_empty())
+        .unwrap_or_else(|| command_id.strip_prefix("Command_").unwrap_or(command_id).to_string())
+}
+
+// Replaces the active layer with a prompt, the command only runs on a second tap
+fn show_confirm_prompt(command_id: &str, label: &str, config: &Config, layers: &mut [FunctionLayer], active_layer: &mut usize, needs_complete_redraw: &mut bool, touches: &mut HashMap<u32, (usize, usize)>) {
+    let cancel_button = ButtonConfig {
+        icon: Some("back".to_string()),
+        text: Some("Cancel".to_string()),
+        show_button_outlines: Some(config.back_button_show_outlines),
+        button_outlines_color: config.back_button_outline_color.clone(),
+        ..ButtonConfig::new(ButtonAction::CancelConfirm)
+    };
+    let confirm_button = ButtonConfig {
+        text: Some(format!("{}? Tap again to confirm", label)),
+        stretch: Some(4),
+        ..ButtonConfig::new(ButtonAction::ConfirmCommand(command_id.to_string()))
+    };
+    layers[*active_layer] = FunctionLayer::with_config_or_warnings(vec![cancel_button, confirm_button])
+        .with_background(config.background.clone());
+    *needs_complete_redraw = true;
+    clear_all_touches(layers, touches);
+}
+
+fn dismiss_confirm_prompt(config: &Config, navigation_state: &mut NavigationState, layers: &mut [FunctionLayer], active_layer: &mut usize, needs_complete_redraw: &mut bool, original_layers: &[FunctionLayer], touches: &mut HashMap<u32, (usize, usize)>) {
+    if navigation_state.confirming.take().is_none() {
+        return;
+    }
+    restore_layer_after_prompt(config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+}
+
+// Replaces the active layer with the label of an alarm going off, to be dismissed or snoozed
+fn show_alarm_prompt(alarm: &Alarm, index: usize, config: &Config, layers: &mut [FunctionLayer], active_layer: &mut usize, needs_complete_redraw: &mut bool, touches: &mut HashMap<u32, (usize, usize)>) {
+    let dismiss_button = ButtonConfig {
+        text: Some(format!("{} · Dismiss", alarm.label)),
+        stretch: Some(3),
+        ..ButtonConfig::new(ButtonAction::DismissAlarm)
+    };
+    let snooze_button = ButtonConfig {
+        text: Some(format!("Snooze {} min", config.alarm_snooze_minutes)),
+        stretch: Some(2),
+        ..ButtonConfig::new(ButtonAction::SnoozeAlarm(index))
+    };
+    layers[*active_layer] = FunctionLayer::with_config_or_warnings(vec![dismiss_button, snooze_button])
+        .with_background(config.background.clone());
+    *needs_complete_redraw = true;
+    clear_all_touches(layers, touches);
+}
+
+fn dismiss_alarm_prompt(config: &Config, navigation_state: &mut NavigationState, layers: &mut [FunctionLayer], active_layer: &mut usize, needs_complete_redraw: &mut bool, original_layers: &[FunctionLayer], touches: &mut HashMap<u32, (usize, usize)>) {
+    if navigation_state.alarm.take().is_none() {
+        return;
+    }
+    restore_layer_after_prompt(config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+}
+
+// Puts back whatever a confirmation or alarm prompt replaced
+fn restore_layer_after_prompt(config: &Config, navigation_state: &mut NavigationState, layers: &mut [FunctionLayer], active_layer: &mut usize, needs_complete_redraw: &mut bool, original_layers: &[FunctionLayer], touches: &mut HashMap<u32, (usize, usize)>) {
+    // Hyprland expandables are rebuilt from the active window instead of the expandables table
+    let hyprland_name = navigation_state
+        .current_expandable
+        .as_ref()
+        .and_then(|name| name.strip_prefix("hyprland_"))
+        .map(|name| name.to_string());
+    if let Some(hyprland_name) = hyprland_name {
+        navigation_state.pop_expandable();
+        handle_hyprland_expand(&hyprland_name, config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+    } else {
+        update_layer_for_navigation(navigation_state, config, layers, active_layer, needs_complete_redraw, original_layers, touches);
+    }
+}
+
+fn handle_button_action<F>(uinput: &mut UInputHandle<F>, action: &ButtonAction, config: &Config, active: bool, navigation_state: &mut NavigationState, layers: &mut [FunctionLayer], active_layer: &mut usize, needs_complete_redraw: &mut bool, original_layers: &[FunctionLayer], touches: &mut HashMap<u32, (usize, usize)>, pending_actions: &mut Vec<PendingAction>, button_index: Option<usize>)
 where
     F: AsRawFd,
 {
+    if active && config.dbus_control {
+        dbus::button_pressed(&action.label());
+    }
     match action {
         ButtonAction::Key(key) => {
-            toggle_key(uinput, *key, active as i32);
+            press_key(uinput, *key, active, config, navigation_state);
         }
         ButtonAction::KeyCombos(keys) => {
             if active {
                 // Press all keys in the combination
                 for key in keys {
-                    toggle_key(uinput, *key, 1);
+                    send_key(uinput, *key, 1);
                 }
             } else {
                 // Release all keys in reverse order
                 for key in keys.iter().rev() {
-                    toggle_key(uinput, *key, 0);
+                    send_key(uinput, *key, 0);
+                }
+            }
+        }
+        // Typed in full on touch, each combination let go of before the next one
+        ButtonAction::KeySequence(combos) => {
+            if active {
+                for keys in combos {
+                    for key in keys {
+                        send_key(uinput, *key, 1);
+                    }
+                    for key in keys.iter().rev() {
+                        send_key(uinput, *key, 0);
+                    }
                 }
             }
         }
@@ -1181,9 +2918,8 @@ where
                 }
             }
         }
-        ButtonAction::HyprlandExpand(hyprland_expand_name) => {
+        ButtonAction::NavigateTo(level) => {
             if active {
-                // Add visual feedback delay for HyprlandExpand actions
                 if let Some(btn_idx) = button_index {
                     pending_actions.push(PendingAction {
                         action: action.clone(),
@@ -1192,49 +2928,182 @@ where
                         layer_index: *active_layer,
                     });
                 } else {
-                    // Fallback to immediate execution if no button index provided
-                    handle_hyprland_expand(hyprland_expand_name, config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+                    navigate_to_level(*level, config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
                 }
             }
         }
-    }
-}
-
-fn execute_pending_actions<F>(
-    pending_actions: &mut Vec<PendingAction>,
-    uinput: &mut UInputHandle<F>,
-    config: &Config,
-    navigation_state: &mut NavigationState,
-    layers: &mut [FunctionLayer; 2],
-    active_layer: &mut usize,
-    needs_complete_redraw: &mut bool,
-    original_layers: &[FunctionLayer; 2],
-    touches: &mut HashMap<u32, (usize, usize)>,
-) where
-    F: AsRawFd,
-{
-    let now = std::time::Instant::now();
-    let mut actions_to_remove = Vec::new();
-
-    for (index, pending_action) in pending_actions.iter().enumerate() {
-        if now >= pending_action.execution_time {
-            // Reset button visual state
-            if pending_action.layer_index < layers.len() && pending_action.button_index < layers[pending_action.layer_index].buttons.len() {
-                layers[pending_action.layer_index].buttons[pending_action.button_index].1.active = false;
-                layers[pending_action.layer_index].buttons[pending_action.button_index].1.changed = true;
+        ButtonAction::LayerSwitch(name) => {
+            if active {
+                if let Some(btn_idx) = button_index {
+                    pending_actions.push(PendingAction {
+                        action: action.clone(),
+                        execution_time: std::time::Instant::now() + std::time::Duration::from_millis(150),
+                        button_index: btn_idx,
+                        layer_index: *active_layer,
+                    });
+                } else {
+                    switch_layer(name, config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+                }
             }
-
-            // Execute the action
-            match &pending_action.action {
-                ButtonAction::Command(command_id) => {
-                    if command_id == "Back" {
-                        if navigation_state.pop_expandable() {
-                            update_layer_for_navigation(navigation_state, config, layers, active_layer, needs_complete_redraw, original_layers, touches);
+        }
+        ButtonAction::ConfirmCommand(_) | ButtonAction::CancelConfirm => {
+            if active {
+                if let Some(btn_idx) = button_index {
+                    pending_actions.push(PendingAction {
+                        action: action.clone(),
+                        execution_time: std::time::Instant::now() + std::time::Duration::from_millis(150),
+                        button_index: btn_idx,
+                        layer_index: *active_layer,
+                    });
+                } else {
+                    if let ButtonAction::ConfirmCommand(command_id) = action {
+                        execute_command(command_id, config);
+                    }
+                    dismiss_confirm_prompt(config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+                }
+            }
+        }
+        ButtonAction::SnoozeAlarm(_) | ButtonAction::DismissAlarm => {
+            if active {
+                if let Some(btn_idx) = button_index {
+                    pending_actions.push(PendingAction {
+                        action: action.clone(),
+                        execution_time: std::time::Instant::now() + std::time::Duration::from_millis(150),
+                        button_index: btn_idx,
+                        layer_index: *active_layer,
+                    });
+                } else {
+                    if let ButtonAction::SnoozeAlarm(index) = action {
+                        snooze_alarm(*index, config, navigation_state);
+                    }
+                    dismiss_alarm_prompt(config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+                }
+            }
+        }
+        ButtonAction::LockLayer => {
+            if active {
+                navigation_state.locked = !navigation_state.locked;
+                println!("Layer {}", if navigation_state.locked { "locked" } else { "unlocked" });
+                // Draws or clears the lock glyph
+                *needs_complete_redraw = true;
+            }
+        }
+        ButtonAction::Midi(message) => {
+            midi::send(config.midi_device.as_deref(), config.midi_channel, *message, active);
+        }
+        ButtonAction::AirplaneMode => {
+            if active {
+                rfkill::toggle_airplane_mode();
+            }
+        }
+        ButtonAction::Caffeine => {
+            if active {
+                caffeine::toggle();
+            }
+        }
+        ButtonAction::LockSession => {
+            if active {
+                session_lock::lock_session();
+            }
+        }
+        // Handled in the main loop, which has the keyboard backlight
+        ButtonAction::Torch => {}
+        ButtonAction::SwitchWorkspace(id) => {
+            if active {
+                hyprland::switch_workspace(*id);
+            }
+        }
+        ButtonAction::Calculator(key) => {
+            if active {
+                match key {
+                    CalculatorKey::Type => {
+                        for key in calculator::typed_keys(&calculator::result()) {
+                            send_key(uinput, key, 1);
+                            send_key(uinput, key, 0);
                         }
+                    }
+                    CalculatorKey::Copy => calculator::copy_to_clipboard(calculator::result()),
+                    _ => calculator::press(*key),
+                }
+                for button in &mut layers[*active_layer].buttons {
+                    if let ButtonImage::Calculator = button.1.image {
+                        button.1.changed = true;
+                    }
+                }
+            }
+        }
+        ButtonAction::CheatSheet => {
+            if active {
+                let layer = &mut layers[*active_layer];
+                layer.cheat_sheet = !layer.cheat_sheet;
+                *needs_complete_redraw = true;
+            }
+        }
+        ButtonAction::HyprlandExpand(hyprland_expand_name) => {
+            if active {
+                // Add visual feedback delay for HyprlandExpand actions
+                if let Some(btn_idx) = button_index {
+                    pending_actions.push(PendingAction {
+                        action: action.clone(),
+                        execution_time: std::time::Instant::now() + std::time::Duration::from_millis(150),
+                        button_index: btn_idx,
+                        layer_index: *active_layer,
+                    });
+                } else {
+                    // Fallback to immediate execution if no button index provided
+                    handle_hyprland_expand(hyprland_expand_name, config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+                }
+            }
+        }
+    }
+}
+
+fn execute_pending_actions<F>(
+    pending_actions: &mut Vec<PendingAction>,
+    uinput: &mut UInputHandle<F>,
+    config: &Config,
+    navigation_state: &mut NavigationState,
+    layers: &mut [FunctionLayer],
+    active_layer: &mut usize,
+    needs_complete_redraw: &mut bool,
+    original_layers: &[FunctionLayer],
+    touches: &mut HashMap<u32, (usize, usize)>,
+) where
+    F: AsRawFd,
+{
+    let now = std::time::Instant::now();
+    let mut actions_to_remove = Vec::new();
+
+    for (index, pending_action) in pending_actions.iter().enumerate() {
+        if now >= pending_action.execution_time {
+            // Reset button visual state
+            if pending_action.layer_index < layers.len() && pending_action.button_index < layers[pending_action.layer_index].buttons.len() {
+                layers[pending_action.layer_index].buttons[pending_action.button_index].1.active = false;
+                layers[pending_action.layer_index].buttons[pending_action.button_index].1.changed = true;
+            }
+
+            // Execute the action
+            match &pending_action.action {
+                ButtonAction::Command(command_id) => {
+                    if command_id == "Back" {
+                        if navigation_state.pop_expandable() {
+                            update_layer_for_navigation(navigation_state, config, layers, active_layer, needs_complete_redraw, original_layers, touches);
+                        }
+                    } else if needs_confirmation(command_id, config, &layers[pending_action.layer_index], pending_action.button_index) {
+                        let label = confirm_label(command_id, &layers[pending_action.layer_index], pending_action.button_index);
+                        show_confirm_prompt(command_id, &label, config, layers, active_layer, needs_complete_redraw, touches);
+                        navigation_state.confirming = Some(std::time::Instant::now());
                     } else {
                         execute_command(command_id, config);
                     }
                 }
+                ButtonAction::ConfirmCommand(command_id) => {
+                    execute_command(command_id, config);
+                    dismiss_confirm_prompt(config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+                }
+                ButtonAction::CancelConfirm => {
+                    dismiss_confirm_prompt(config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+                }
                 ButtonAction::Expand(expandable_name) => {
                     navigation_state.push_expandable(expandable_name.clone());
                     update_layer_for_navigation(navigation_state, config, layers, active_layer, needs_complete_redraw, original_layers, touches);
@@ -1242,7 +3111,29 @@ fn execute_pending_actions<F>(
                 ButtonAction::HyprlandExpand(hyprland_expand_name) => {
                     handle_hyprland_expand(hyprland_expand_name, config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
                 }
-                _ => {} // Other actions are handled immediately
+                ButtonAction::NavigateTo(level) => {
+                    navigate_to_level(*level, config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+                }
+                ButtonAction::LayerSwitch(name) => {
+                    switch_layer(name, config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+                }
+                ButtonAction::LockLayer | ButtonAction::CheatSheet | ButtonAction::Midi(_) | ButtonAction::Calculator(_) | ButtonAction::AirplaneMode | ButtonAction::Caffeine | ButtonAction::LockSession | ButtonAction::Torch | ButtonAction::KeySequence(_) | ButtonAction::SwitchWorkspace(_) => {}
+                ButtonAction::SnoozeAlarm(index) => {
+                    snooze_alarm(*index, config, navigation_state);
+                    dismiss_alarm_prompt(config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+                }
+                ButtonAction::DismissAlarm => {
+                    dismiss_alarm_prompt(config, navigation_state, layers, active_layer, needs_complete_redraw, original_layers, touches);
+                }
+                // A key pressed by a tap on a holding button, its time is up
+                ButtonAction::Key(key) => {
+                    press_key(uinput, *key, false, config, navigation_state);
+                }
+                ButtonAction::KeyCombos(keys) => {
+                    for key in keys.iter().rev() {
+                        send_key(uinput, *key, 0);
+                    }
+                }
             }
 
             actions_to_remove.push(index);
@@ -1255,21 +3146,116 @@ fn execute_pending_actions<F>(
     }
 }
 
+fn snooze_alarm(index: usize, config: &Config, navigation_state: &mut NavigationState) {
+    let until = std::time::Instant::now() + std::time::Duration::from_secs(config.alarm_snooze_minutes as u64 * 60);
+    navigation_state.snoozed_alarms.push((index, until));
+}
+
+// Plays the click through the logged in user's PipeWire, there is no audio access as root
+fn play_touch_sound(config: &Config) {
+    let Some(env) = user_cache::get_cached_user_environment() else {
+        return;
+    };
+    let file = config.touch_sound_file.clone();
+    std::thread::spawn(move || {
+        let result = crate::privileged::Command::session_user(&env.username, "env")
+            .arg(format!("XDG_RUNTIME_DIR={}", env.runtime_dir))
+            .args(["pw-play", &file])
+            .stdout(crate::privileged::Stdio::null())
+            .stderr(crate::privileged::Stdio::null())
+            .status();
+        if let Err(e) = result {
+            eprintln!("Failed to play touch sound {}: {}", file, e);
+        }
+    });
+}
+
+// Moves a slider's level to where the finger is on it
+fn slide_to(backlight: &mut BacklightManager, kind: SliderKind, level: f64) {
+    if kind == SliderKind::Brightness {
+        backlight.set_display_brightness(level);
+    }
+    slider::set_level(kind, level);
+}
+
+// Lights up the button touch exploration landed on and has it read out
+fn explore_button(config: &Config, button: &mut Button) {
+    button.active = true;
+    button.changed = true;
+    if let Some(command_id) = &config.touch_explore_speech {
+        execute_command_with(command_id, config, vec![("BUTTON_LABEL", button.spoken_label())]);
+    }
+}
+
 fn execute_command(command_id: &str, config: &Config) {
-    if let Some(command) = config.commands.get(command_id) {
+    execute_command_with(command_id, config, Vec::new());
+}
+
+// Runs the command with extra variables on top of the usual context
+fn execute_command_with(command_id: &str, config: &Config, extra_context: Vec<(&'static str, String)>) {
+    // Nobody can see what the command does, most likely a stray touch
+    if backlight::bar_off() {
+        eprintln!("Not running '{}' since the Touch Bar is off", command_id);
+        return;
+    }
+    run_command(command_id, config, extra_context);
+}
+
+// Runs the command whether or not the Touch Bar is on, for ones no touch started
+fn run_command(command_id: &str, config: &Config, extra_context: Vec<(&'static str, String)>) {
+    if let Some(spec) = config.commands.get(command_id) {
+        let allow_root = config.allow_commands_as_root;
+        let allow_locked = config.allow_commands_while_locked;
+        if spec.run_as.as_deref() == Some("root") && !allow_root {
+            eprintln!(
+                "Refusing to run '{}' as root, set AllowCommandsAsRoot = true in config.toml to allow it",
+                command_id
+            );
+            return;
+        }
         // Execute command in the background with cached user environment
         std::thread::spawn({
-            let command = command.clone();
+            let command = spec.run.clone();
+            let run_as = spec.run_as.clone();
             let user_env = config.user_env.clone();
             move || {
                 println!("Executing command: {}", command);
+                metrics::command_executed();
 
-                // Use cached user environment for instant execution
-                if let Some(cached_env) = user_cache::get_cached_user_environment() {
-                    // Use runuser with login shell - no password required, reads .bash_profile, .bashrc, etc.
-                    let mut cmd = std::process::Command::new("/usr/bin/runuser");
-                    cmd.args(["-l", &cached_env.username, "-c", &command]);
+                let cached_env = user_cache::get_cached_user_environment();
+                if !allow_locked && cached_env.as_ref().is_some_and(|env| user_cache::session_locked(&env.username)) {
+                    eprintln!(
+                        "Not running '{}' while the screen is locked, set AllowCommandsWhileLocked = true in config.toml to allow it",
+                        command
+                    );
+                    return;
+                }
+                let mut context = command_context();
+                context.extend(extra_context);
+                match run_as.as_deref() {
+                    Some("root") => {
+                        fallback_execution(&command, &context);
+                        return;
+                    }
+                    // Some other account than the session user, without its session environment
+                    Some(user) if cached_env.as_ref().is_none_or(|env| env.username != user) => {
+                        // The privileged helper only runs commands as the session user
+                        if privileged::dropped() {
+                            eprintln!("Not running '{}' as user '{}', RunAs other users needs DropPrivileges = false", command, user);
+                            return;
+                        }
+                        let mut cmd = crate::privileged::Command::new("/usr/bin/runuser");
+                        cmd.args(["-l", user, "-c", &format!("{}{}", export_context(&context), command)]);
+                        if let Err(e) = cmd.spawn() {
+                            eprintln!("Failed to execute command '{}' as user '{}': {}", command, user, e);
+                        }
+                        return;
+                    }
+                    _ => {}
+                }
 
+                // Use cached user environment for instant execution
+                if let Some(cached_env) = cached_env {
                     // Use user environment config if available, otherwise use cached detection
                     let wayland_display = if let Some(user_env) = &user_env {
                         user_env.wayland_display.clone()
@@ -1279,24 +3265,29 @@ fn execute_command(command_id: &str, config: &Config) {
 
                     // Build command with environment variables embedded (to work with runuser -l)
                     let env_command = format!(
-                        "export PATH='{}' DISPLAY=':0' WAYLAND_DISPLAY='{}' XDG_RUNTIME_DIR='{}'; {}",
-                        cached_env.enhanced_path, wayland_display, cached_env.runtime_dir, command
+                        "export PATH='{}' DISPLAY=':0' WAYLAND_DISPLAY='{}' XDG_RUNTIME_DIR='{}'; {}{}",
+                        cached_env.enhanced_path, wayland_display, cached_env.runtime_dir, export_context(&context), command
                     );
 
-                    // Update command to use embedded environment
-                    cmd = std::process::Command::new("/usr/bin/runuser");
-                    cmd.args(["-l", &cached_env.username, "-c", &env_command]);
+                    // Use runuser with login shell - no password required, reads .bash_profile, .bashrc, etc.
+                    let mut cmd = crate::privileged::Command::login_shell(&cached_env.username, &env_command);
 
                     if let Err(e) = cmd.spawn() {
                         eprintln!("Failed to execute command '{}' as user '{}': {}", command, cached_env.username, e);
 
-                        // Fallback to basic execution
-                        fallback_execution(&command);
+                        if allow_root {
+                            fallback_execution(&command, &context);
+                        }
                     }
-                } else {
+                } else if allow_root {
                     // Fallback if cache is not available
-                    eprintln!("User environment cache not available, using fallback execution");
-                    fallback_execution(&command);
+                    eprintln!("User environment cache not available, running '{}' as root", command);
+                    fallback_execution(&command, &context);
+                } else {
+                    eprintln!(
+                        "No logged in user to run '{}' as, not running it as root since AllowCommandsAsRoot is disabled",
+                        command
+                    );
                 }
             }
         });
@@ -1305,9 +3296,40 @@ fn execute_command(command_id: &str, config: &Config) {
     }
 }
 
-fn fallback_execution(command: &str) {
-    let mut cmd = std::process::Command::new("sh");
+// What the bar knows about the desktop, handed to commands so scripts don't have to ask hyprctl
+fn command_context() -> Vec<(&'static str, String)> {
+    let mut context = Vec::new();
+    if let Ok(window) = hyprland::get_active_window_info() {
+        context.push(("ACTIVE_CLASS", window.class));
+        context.push(("ACTIVE_TITLE", window.title));
+    }
+    if let Some(workspace) = hyprland::get_active_workspace_name() {
+        context.push(("WORKSPACE", workspace));
+    }
+    if let Some(battery) = find_battery_device() {
+        let (capacity, _) = get_battery_state(&battery);
+        context.push(("BATTERY_PCT", capacity.to_string()));
+    }
+    context
+}
+
+// The context as an export statement to put in front of a command run through a login shell
+fn export_context(context: &[(&str, String)]) -> String {
+    if context.is_empty() {
+        return String::new();
+    }
+    let assignments: Vec<String> = context
+        .iter()
+        .map(|(name, value)| format!("{}='{}'", name, value.replace('\'', "'\\''")))
+        .collect();
+    format!("export {}; ", assignments.join(" "))
+}
+
+// Runs the command as the daemon itself, i.e. as root
+fn fallback_execution(command: &str, context: &[(&str, String)]) {
+    let mut cmd = crate::privileged::Command::new("sh");
     cmd.arg("-c").arg(command);
+    cmd.envs(context.iter().map(|(name, value)| (name, value)));
     if let Err(e) = cmd.spawn() {
         eprintln!("Failed to execute command '{}': {}", command, e);
     }
@@ -1357,10 +3379,102 @@ fn expand_user_path(username: &str) -> Result<String, std::io::Error> {
 
 
 fn main() {
-    let mut drm = DrmBackend::open_card().unwrap();
+    let setup = match std::env::args().nth(1).as_deref() {
+        Some("--install-service") => Some(install::install_service()),
+        Some("--uninstall-service") => Some(install::uninstall_service()),
+        Some("--probe") => std::process::exit(if probe::run_probe() { 0 } else { 1 }),
+        Some("--migrate-config") => Some(config::migrate_config().map_err(|e| anyhow!(e))),
+        Some(privileged::HELPER_ARG) => return privileged::run_helper(),
+        Some(icon_sandbox::RENDER_ARG) => return icon_sandbox::run_renderer(),
+        _ => None,
+    };
+    if let Some(result) = setup {
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let mut drm = DrmBackend::open_card(&config::load_display_config()).unwrap();
+    // Nothing can be drawn into a framebuffer Cairo can't handle, whatever is configured
+    match surface_format(&drm) {
+        Ok(format) => {
+            // Opened while still root, so it can be written to after privileges are dropped
+            let (mut crash_file, mut crashes) = match open_crash_count() {
+                Ok((file, crashes)) => (Some(file), crashes),
+                Err(e) => {
+                    eprintln!("Failed to open {}: {}", CRASH_COUNT_PATH, e);
+                    (None, 0)
+                }
+            };
+            if crashes >= SAFE_MODE_AFTER_CRASHES {
+                eprintln!("Crashed {} times, falling back to the built-in function keys", crashes);
+                config::enter_safe_mode();
+            }
+            loop {
+                if let Ok(Err(errors)) = panic::catch_unwind(AssertUnwindSafe(|| real_main(&mut drm, format))) {
+                    eprintln!("Unable to show the configuration:");
+                    for error in &errors {
+                        eprintln!("  {}", error);
+                    }
+                    draw_error_screen(&mut drm, format, &errors);
+                    wait_for_config_change();
+                    continue;
+                }
+                // Not even the built-in layout works
+                if config::safe_mode() {
+                    break;
+                }
+                crashes += 1;
+                if let Some(file) = crash_file.as_mut() {
+                    if let Err(e) = write_crash_count(file, crashes) {
+                        eprintln!("Failed to write {}: {}", CRASH_COUNT_PATH, e);
+                    }
+                }
+                // The devices can't be opened again without root, systemd starts tiny-dfr over instead
+                if privileged::dropped() {
+                    eprintln!("Crashed after dropping privileges, exiting to be restarted");
+                    std::process::exit(1);
+                }
+                if crashes >= SAFE_MODE_AFTER_CRASHES {
+                    eprintln!("Crashed {} times, falling back to the built-in function keys", crashes);
+                    config::enter_safe_mode();
+                } else {
+                    eprintln!("Crashed, starting over");
+                }
+                std::thread::sleep(std::time::Duration::from_secs(1));
+            }
+        }
+        Err(e) => eprintln!("{}, tiny-dfr can't draw on this display", e),
+    }
+    draw_crash_bitmap(&mut drm);
+    let mut sigset = SigSet::empty();
+    sigset.add(Signal::SIGTERM);
+    sigset.wait().unwrap();
+}
+
+// Crashes of earlier runs count unless they are old or the configuration changed since
+fn open_crash_count() -> std::io::Result<(File, u32)> {
+    fs::create_dir_all(Path::new(CRASH_COUNT_PATH).parent().unwrap())?;
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).mode(0o600).open(CRASH_COUNT_PATH)?;
+    let crashed_at = file.metadata()?.modified()?;
+    let config_changed = fs::metadata(config::USER_CFG_PATH).and_then(|m| m.modified()).is_ok_and(|changed_at| changed_at > crashed_at);
+    let expired = crashed_at.elapsed().is_ok_and(|elapsed| elapsed > CRASH_COUNT_EXPIRY);
+    let mut contents = String::new();
+    std::io::Read::read_to_string(&mut file, &mut contents)?;
+    let crashes = if config_changed || expired { 0 } else { contents.trim().parse().unwrap_or(0) };
+    Ok((file, crashes))
+}
+
+fn write_crash_count(file: &mut File, crashes: u32) -> std::io::Result<()> {
+    file.set_len(0)?;
+    file.write_all_at(crashes.to_string().as_bytes(), 0)
+}
+
+fn draw_crash_bitmap(drm: &mut DrmBackend) {
     let (height, width) = drm.mode().size();
-    let _ = panic::catch_unwind(AssertUnwindSafe(|| real_main(&mut drm)));
     let crash_bitmap = include_bytes!("crash_bitmap.raw");
+    let bytes_per_pixel = (drm.fb_info().unwrap().bpp() / 8) as usize;
     let mut map = drm.map().unwrap();
     let data = map.as_mut();
     let mut wptr = 0;
@@ -1368,29 +3482,114 @@ fn main() {
         for i in 0..8 {
             let bit = ((byte >> i) & 0x1) == 0;
             let color = if bit { 0xFF } else { 0x0 };
-            data[wptr] = color;
-            data[wptr + 1] = color;
-            data[wptr + 2] = color;
-            data[wptr + 3] = color;
-            wptr += 4;
+            data[wptr..wptr + bytes_per_pixel].fill(color);
+            wptr += bytes_per_pixel;
         }
     }
     drop(map);
     drm.dirty(&[ClipRect::new(0, 0, height, width)]).unwrap();
-    let mut sigset = SigSet::empty();
-    sigset.add(Signal::SIGTERM);
-    sigset.wait().unwrap();
 }
 
-fn real_main(drm: &mut DrmBackend) {
-    let (height, width) = drm.mode().size();
+// What kept the configuration from loading, a line saying so and the errors after it
+fn draw_error_screen(drm: &mut DrmBackend, format: Format, errors: &[String]) {
+    let Ok(info) = drm.fb_info() else {
+        return draw_crash_bitmap(drm);
+    };
+    let (db_width, db_height) = info.size();
+    let (width, height) = (db_width.max(db_height) as i32, db_width.min(db_height) as i32);
+    let mut surface = ImageSurface::create(format, db_width as i32, db_height as i32).unwrap();
+    {
+        let c = Context::new(&surface).unwrap();
+        c.set_source_rgb(0.0, 0.0, 0.0);
+        c.paint().unwrap();
+        apply_rotation(&c, if db_width > db_height { 0 } else { 90 }, width, height);
+        c.select_font_face("sans", FontSlant::Normal, FontWeight::Normal);
+        c.set_font_size(height as f64 * 0.3);
+        c.set_source_rgb(1.0, 0.4, 0.4);
+        c.move_to(height as f64 * 0.3, height as f64 * 0.42);
+        c.show_text("tiny-dfr can't show its configuration, fix it and save to try again").unwrap();
+        c.set_source_rgb(1.0, 1.0, 1.0);
+        c.move_to(height as f64 * 0.3, height as f64 * 0.82);
+        c.show_text(&errors.join(" · ")).unwrap();
+    }
+    copy_to_framebuffer(&mut surface, drm, info.pitch() as usize);
+    drm.dirty(&[ClipRect::new(0, 0, db_width as u16, db_height as u16)]).unwrap();
+}
+
+// Returns once the configuration was edited, or after a minute in case a missing font or icon was installed
+fn wait_for_config_change() {
+    let cfg_mgr = ConfigManager::new();
+    let epoll = Epoll::new(EpollCreateFlags::empty()).unwrap();
+    epoll.add(cfg_mgr.fd(), EpollEvent::new(EpollFlags::EPOLLIN, 0)).unwrap();
+    let _ = epoll.wait(&mut [EpollEvent::new(EpollFlags::EPOLLIN, 0)], 60000u16);
+}
+
+// The framebuffer formats Cairo can draw into directly
+fn surface_format(drm: &DrmBackend) -> Result<Format> {
+    match drm.pixel_format()? {
+        DrmFourcc::Rgb565 => Ok(Format::Rgb16_565),
+        DrmFourcc::Xrgb8888 | DrmFourcc::Argb8888 => Ok(Format::ARgb32),
+        fmt => Err(anyhow!("Unsupported framebuffer format {fmt}")),
+    }
+}
+
+fn copy_to_framebuffer(surface: &mut ImageSurface, drm: &mut DrmBackend, pitch: usize) {
+    let stride = surface.stride() as usize;
+    let rows = surface.height() as usize;
+    let data = surface.data().unwrap();
+    let mut map = drm.map().unwrap();
+    let fb = map.as_mut();
+    if stride == pitch {
+        fb[..data.len()].copy_from_slice(&data);
+    } else {
+        // Cairo and the kernel may pad rows differently
+        let row_len = stride.min(pitch);
+        for row in 0..rows {
+            fb[row * pitch..row * pitch + row_len]
+                .copy_from_slice(&data[row * stride..row * stride + row_len]);
+        }
+    }
+}
+
+fn real_main(drm: &mut DrmBackend, surface_format: Format) -> Result<(), Vec<String>> {
+    // SIGUSR1 prints a state dump, blocked before any thread is started so only the main loop sees it
+    let mut state_dump_mask = SigSet::empty();
+    state_dump_mask.add(Signal::SIGUSR1);
+    state_dump_mask.thread_block().unwrap();
+    let state_dump_signal = SignalFd::with_flags(&state_dump_mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC).unwrap();
+    // The bar is always long and thin, whichever way the panel scans out
+    let (mode_width, mode_height) = drm.mode().size();
+    let (width, height) = (mode_width.max(mode_height), mode_width.min(mode_height));
     let (db_width, db_height) = drm.fb_info().unwrap().size();
-    let mut uinput = UInputHandle::new(OpenOptions::new().write(true).open("/dev/uinput").unwrap());
     let mut backlight = BacklightManager::new();
     let mut last_redraw_minute = Local::now().minute();
     let mut last_battery_update_minute = Local::now().minute();
+    let mut last_battery_generation = battery_monitor::generation();
+    let mut last_dimmed = false;
+    let mut last_frame = std::time::Instant::now();
+    let mut last_ambient_light_update = std::time::Instant::now();
+    let mut last_status_update = std::time::Instant::now();
+    let mut last_ticker_update = std::time::Instant::now();
+    let mut last_mail_generation = mail::generation();
+    let mut last_script_generation = script::generation();
+    let mut last_slider_generation = slider::generation();
+    let mut last_rfkill_generation = rfkill::generation();
+    let mut last_lock_generation = session_lock::generation();
+    let mut last_torch_generation = keyboard_backlight::generation();
+    let mut last_volume_generation = audio::generation();
+    let mut last_workspaces_generation = hyprland::workspaces_generation();
     let mut cfg_mgr = ConfigManager::new();
-    let (mut cfg, mut layers) = cfg_mgr.load_config(width);
+    config::set_docked(is_docked(&displays::connected_external(), charger_online()));
+    let (mut cfg, mut layers) = cfg_mgr.load_config(width)?;
+    if let Some(path) = &cfg.metrics_socket {
+        metrics::start_metrics_server(path);
+    }
+    if cfg.dbus_control {
+        dbus::initialize_control_service();
+    }
+    if cfg.notification_banner {
+        notifications::initialize_notification_monitor();
+    }
     
     // Initialize keyboard backlight BEFORE dropping privileges
     let mut kbd_backlight = KeyboardBacklightManager::new_with_config(
@@ -1405,20 +3604,32 @@ fn real_main(drm: &mut DrmBackend) {
         println!("Keyboard backlight control disabled - falling back to key events");
     }
     
-    let mut pixel_shift = PixelShiftManager::new();
+    let mut pixel_shift = PixelShiftManager::new(&cfg);
+    let mut burn_in = BurnInTracker::new();
+    let mut care_active = false;
+    let mut quiet_active = false;
+    let battery_device = find_battery_device();
+    let mut saver_active = false;
+    let mut last_saver_check_minute = None;
+    let mut osd = OsdManager::new();
+    let mut overlay: Option<OverlayLayer> = None;
+    let mut charger_was_online = charger_online();
 
     // Initialize performance optimizations
     user_cache::initialize_user_environment_cache();
+    icon_cache::load_persisted_cache();
     icon_cache::preload_common_icons();
-    let _battery_monitor = battery_monitor::BatteryMonitor::new("BAT0".to_string());
-    let _system_monitor = system_monitor::SystemMonitor::new();
+    system_monitor::initialize_system_monitor();
     icon_cache::start_background_preloader();
 
-    // Keep running as root to allow command execution
-    // Note: Privilege dropping disabled to allow access to user files for command execution
+    // Everything that needs root to open is open by now, commands go through the privileged helper
+    if cfg.drop_privileges {
+        privileged::drop_privileges();
+    }
 
+    let fb_pitch = drm.fb_info().unwrap().pitch() as usize;
     let mut surface =
-        ImageSurface::create(Format::ARgb32, db_width as i32, db_height as i32).unwrap();
+        ImageSurface::create(surface_format, db_width as i32, db_height as i32).unwrap();
     let mut active_layer = 0;
     let mut needs_complete_redraw = true;
     let mut navigation_state = NavigationState::new();
@@ -1434,15 +3645,12 @@ fn real_main(drm: &mut DrmBackend) {
         None
     };
 
-    // Start system state monitoring
-    let _system_monitor = system_monitor::SystemMonitor::new();
-
     // Start background icon preloader (after initial setup)
     icon_cache::start_background_preloader();
 
     let mut input_tb = Libinput::new_with_udev(Interface);
     let mut input_main = Libinput::new_with_udev(Interface);
-    input_tb.udev_assign_seat("seat-touchbar").unwrap();
+    input_tb.udev_assign_seat(&cfg.touch_seat).unwrap();
     input_main.udev_assign_seat("seat0").unwrap();
     let udev_monitor = MonitorBuilder::new()
         .unwrap()
@@ -1463,124 +3671,242 @@ fn real_main(drm: &mut DrmBackend) {
     epoll
         .add(&udev_monitor, EpollEvent::new(EpollFlags::EPOLLIN, 3))
         .unwrap();
-    uinput.set_evbit(EventKind::Key).unwrap();
-    for layer in &layers {
-        for button in &layer.buttons {
-            match &button.1.action {
-                ButtonAction::Key(key) => {
-                    uinput.set_keybit(*key).unwrap();
-                }
-                ButtonAction::KeyCombos(keys) => {
-                    for key in keys {
-                        uinput.set_keybit(*key).unwrap();
-                    }
-                }
-                _ => {}
-            }
-        }
+    let drm_monitor = MonitorBuilder::new()
+        .unwrap()
+        .match_subsystem("drm")
+        .unwrap()
+        .listen()
+        .unwrap();
+    epoll
+        .add(&drm_monitor, EpollEvent::new(EpollFlags::EPOLLIN, 8))
+        .unwrap();
+    let mut external_displays = displays::connected_external();
+    epoll
+        .add(hyprland::wakeup_fd(), EpollEvent::new(EpollFlags::EPOLLIN, 4))
+        .unwrap();
+    epoll
+        .add(media_state::wakeup_fd(), EpollEvent::new(EpollFlags::EPOLLIN, 5))
+        .unwrap();
+    epoll
+        .add(state_dump_signal.as_fd(), EpollEvent::new(EpollFlags::EPOLLIN, 6))
+        .unwrap();
+    let localtime_watch = system_monitor::LocaltimeWatch::new();
+    if let Some(fd) = localtime_watch.fd() {
+        epoll.add(fd, EpollEvent::new(EpollFlags::EPOLLIN, 7)).unwrap();
     }
+    let mut utc_offset = Local::now().offset().local_minus_utc();
+    let mut localtime_changed: Option<std::time::Instant> = None;
+    let mut last_alarm_minute = None;
+    let mut registered_keys = configured_keys(&cfg, &layers);
+    consumer::set_enabled(cfg.separate_media_device);
+    let mut uinput = create_uinput(&registered_keys);
+    gamepad::set_buttons(gamepad_buttons(&cfg));
 
-    // Also register keys from expandables
-    for expandable_buttons in cfg.expandables.values() {
-        for button in expandable_buttons {
-            match &button.action {
-                ButtonAction::Key(key) => {
-                    uinput.set_keybit(*key).unwrap();
+    let mut digitizer: Option<InputDevice> = None;
+    let mut touches: HashMap<u32, (usize, usize)> = HashMap::new();
+    let mut pending_actions: Vec<PendingAction> = Vec::new();
+    let mut gestures = GestureTracker::new();
+    let mut show_config_errors = !cfg.config_errors.is_empty();
+    let mut workspace_bound = false;
+    let mut was_locked = false;
+    // Touch exploration: the button last let go of and when, and the touch that activated one
+    let mut explored_tap: Option<(usize, usize, std::time::Instant)> = None;
+    let mut explore_activated: Option<u32> = None;
+    // Keys held on the physical keyboard, and whether a shortcut went off while Fn was held
+    let mut held_keys: HashSet<u32> = HashSet::new();
+    let mut shortcut_during_fn = false;
+    // Virtual mouse of scroll strips and pinch zooming, and what's left of a notch from the current drag
+    let mut scroll_device: Option<UInputHandle<File>> = None;
+    let mut scroll_remainder = 0;
+    // When Fn went down and which layer was shown before, until it turns out not to be a tap
+    let mut fn_pressed: Option<(std::time::Instant, usize)> = None;
+    // What letting go of Fn goes back to, and what a tap of it starts from
+    let mut layer_before_fn = 0;
+    // The layer of Layers brought up by the focused window's App pattern
+    let mut app_layer_shown: Option<usize> = None;
+    loop {
+        // Requests from other programs over D-Bus
+        for command in dbus::take_commands() {
+            match command {
+                ControlCommand::SetLayer(_) if navigation_state.locked => {
+                    println!("Ignoring SetLayer while the layer is locked");
                 }
-                ButtonAction::KeyCombos(keys) => {
-                    for key in keys {
-                        uinput.set_keybit(*key).unwrap();
+                ControlCommand::SetLayer(name) if name == "Back" => {
+                    if navigation_state.pop_expandable() {
+                        update_layer_for_navigation(&navigation_state, &cfg, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches);
                     }
                 }
-                _ => {}
-            }
-        }
-    }
-
-    // Also register keys from hyprland expandables
-    for hyprland_expandable_configs in cfg.hyprland_expandables.values() {
-        for hyprland_config in hyprland_expandable_configs {
-            for button in &hyprland_config.layer_keys {
-                match &button.action {
-                    ButtonAction::Key(key) => {
-                        uinput.set_keybit(*key).unwrap();
-                    }
-                    ButtonAction::KeyCombos(keys) => {
-                        for key in keys {
-                            uinput.set_keybit(*key).unwrap();
+                ControlCommand::SetLayer(name) => match cfg.layer_target(&name) {
+                    Some(target) => {
+                        navigation_state.reset_to_main();
+                        layers.clone_from_slice(&original_layers);
+                        active_layer = 0;
+                        match target {
+                            WorkspaceTarget::Layer(layer) => active_layer = layer,
+                            WorkspaceTarget::Expandable(name) => navigation_state.push_expandable(name),
                         }
+                        update_layer_for_navigation(&navigation_state, &cfg, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches);
                     }
-                    _ => {}
+                    None => eprintln!("SetLayer: there is no layer or expandable called {}", name),
+                },
+                ControlCommand::ShowMessage(text, seconds) => {
+                    osd.show_message(text, std::time::Duration::from_secs(seconds.clamp(1, 60) as u64));
+                    backlight.wake();
+                    needs_complete_redraw = true;
+                }
+                ControlCommand::SetButtonText(index, text) => match layers[active_layer].buttons.get_mut(index as usize) {
+                    Some(button) => {
+                        button.1.image = ButtonImage::Text(text);
+                        button.1.changed = true;
+                    }
+                    None => eprintln!("SetButtonText: the shown layer has no button {}", index),
+                },
+                ControlCommand::ReloadConfig => cfg_mgr.request_reload(),
+                ControlCommand::EditLayout(..) if navigation_state.current_expandable.is_some() || navigation_state.locked => {
+                    println!("Ignoring a layout edit while an expandable or locked layer is shown");
                 }
+                ControlCommand::EditLayout(edit, persist) => match original_layers[active_layer].edit(&edit) {
+                    Ok(()) => {
+                        layers[active_layer] = original_layers[active_layer].clone();
+                        register_keys(&mut uinput, &mut registered_keys, configured_keys(&cfg, &original_layers));
+                        needs_complete_redraw = true;
+                        clear_all_touches(&mut layers, &mut touches);
+                        // Saving reloads the configuration, which keeps the buttons as edited
+                        if persist {
+                            if let Err(e) = config::save_layout_edit(&cfg, active_layer, &edit) {
+                                eprintln!("Layout edited but not saved: {}", e);
+                            }
+                        }
+                    }
+                    Err(e) => eprintln!("Failed to edit the layout: {}", e),
+                },
             }
         }
-    }
-    let mut dev_name_c = [0 as c_char; 80];
-    let dev_name = "Dynamic Function Row Virtual Input Device".as_bytes();
-    for i in 0..dev_name.len() {
-        dev_name_c[i] = dev_name[i] as c_char;
-    }
-    uinput
-        .dev_setup(&uinput_setup {
-            id: input_id {
-                bustype: 0x19,
-                vendor: 0x1209,
-                product: 0x316E,
-                version: 1,
-            },
-            ff_effects_max: 0,
-            name: dev_name_c,
-        })
-        .unwrap();
-    uinput.dev_create().unwrap();
 
-    let mut digitizer: Option<InputDevice> = None;
-    let mut touches: HashMap<u32, (usize, usize)> = HashMap::new();
-    let mut pending_actions: Vec<PendingAction> = Vec::new();
-    loop {
-        if cfg_mgr.update_config(&mut cfg, &mut layers, width) {
-            active_layer = 0;
+        if cfg_mgr.update_config(&mut cfg, &mut original_layers, width) {
+            register_keys(&mut uinput, &mut registered_keys, configured_keys(&cfg, &original_layers));
+            gamepad::set_buttons(gamepad_buttons(&cfg));
+            consumer::set_enabled(cfg.separate_media_device);
+            if cfg.notification_banner {
+                notifications::initialize_notification_monitor();
+            }
+            layers = original_layers.clone();
             needs_complete_redraw = true;
-            original_layers = layers.clone(); // Update original layers
-            navigation_state.reset_to_main(); // Reset navigation on config update
+            clear_all_touches(&mut layers, &mut touches);
+            show_config_errors = !cfg.config_errors.is_empty();
+            // Stay in the open expandable if the new configuration still has it, and the layer it
+            // was opened on too, custom layers can go away with a reload
+            let still_configured = active_layer < layers.len()
+                && navigation_state.current_expandable.as_ref().is_some_and(|name| {
+                    name != CONFIG_ERRORS_EXPANDABLE && cfg.expandables.contains_key(name)
+                })
+                && navigation_state.confirming.is_none()
+                && navigation_state.alarm.is_none()
+                && navigation_state.navigation_stack.iter().all(|name| cfg.expandables.contains_key(name));
+            if still_configured {
+                update_layer_for_navigation(&navigation_state, &cfg, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches);
+            } else {
+                active_layer = 0;
+                navigation_state.reset_to_main(); // Reset navigation on config update
+                navigation_state.locked = false;
+            }
 
             // Update keyboard backlight step size only (can't recreate manager after privilege drop)
             kbd_backlight.update_brightness_step(cfg.keyboard_brightness_step);
         }
 
+        // List unresolved references on the bar itself, tapping any of them goes back
+        if show_config_errors {
+            show_config_errors = false;
+            navigation_state.push_expandable(CONFIG_ERRORS_EXPANDABLE.to_string());
+            update_layer_for_navigation(&navigation_state, &cfg, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches);
+        }
+
         // Check for timeout and return to main layer (only if we're actually in an expandable)
         if navigation_state.current_expandable.is_some() && navigation_state.should_timeout(cfg.expandable_timeout_seconds) {
             navigation_state.reset_to_main();
-            layers[0] = original_layers[0].clone();
-            layers[1] = original_layers[1].clone();
+            layers.clone_from_slice(&original_layers);
             needs_complete_redraw = true;
             // Clear touches to prevent accidental triggering after timeout
             clear_all_touches(&mut layers, &mut touches);
 
             // Force update hyprland buttons with current window state after timeout
             if let Ok(window_info) = hyprland::get_active_window_info() {
-                for button in &mut layers[active_layer].buttons {
-                    match &button.1.action {
-                        config::ButtonAction::HyprlandExpand(_expand_name) => {
-                            // Update hyprland button with current window regardless of cache state
-                            update_hyprland_button_content(button, &window_info);
-                        }
-                        _ => {}
-                    }
-                }
+                refresh_active_window_buttons(&mut layers[active_layer], &window_info);
+            }
+        }
+
+        // An unanswered confirmation prompt counts as cancelled
+        if let Some(started) = navigation_state.confirming {
+            let remaining_ms = CONFIRM_TIMEOUT_MS - started.elapsed().as_millis() as i32;
+            if remaining_ms <= 0 {
+                dismiss_confirm_prompt(&cfg, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches);
             }
         }
 
         let now = Local::now();
         let ms_left = ((60 - now.second()) * 1000) as i32;
         let mut next_timeout_ms = min(ms_left, TIMEOUT_MS);
+        // Nobody is looking closely at a dimmed bar or the care pattern, polls wait longer
+        let throttle_ms = if backlight.is_dimmed() || care_active {
+            cfg.idle_refresh_seconds as i32 * 1000
+        } else {
+            0
+        };
 
-        if cfg.enable_pixel_shift {
-            let (pixel_shift_needs_redraw, pixel_shift_next_timeout_ms) = pixel_shift.update();
+        // Alarms go off once during their minute, snoozed ones once their snooze is up
+        let mut due_alarm = None;
+        if last_alarm_minute != Some((now.hour(), now.minute())) {
+            last_alarm_minute = Some((now.hour(), now.minute()));
+            due_alarm = cfg.alarms.iter().position(|alarm| alarm.hour == now.hour() && alarm.minute == now.minute());
+        }
+        if let Some(pos) = navigation_state.snoozed_alarms.iter().position(|(_, until)| *until <= std::time::Instant::now()) {
+            let (index, _) = navigation_state.snoozed_alarms.remove(pos);
+            due_alarm = due_alarm.or(Some(index));
+        }
+        for (_, until) in &navigation_state.snoozed_alarms {
+            let remaining = until.saturating_duration_since(std::time::Instant::now());
+            next_timeout_ms = min(next_timeout_ms, remaining.as_millis() as i32 + 1);
+        }
+        if let Some((index, alarm)) = due_alarm.and_then(|index| Some((index, cfg.alarms.get(index)?.clone()))) {
+            println!("Alarm '{}' going off", alarm.label);
+            if let Some(action) = &alarm.action {
+                handle_button_action(&mut uinput, action, &cfg, true, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, None);
+                handle_button_action(&mut uinput, action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, None);
+            }
+            navigation_state.confirming = None;
+            show_alarm_prompt(&alarm, index, &cfg, &mut layers, &mut active_layer, &mut needs_complete_redraw, &mut touches);
+            navigation_state.alarm = Some((active_layer, std::time::Instant::now()));
+        }
+
+        // Flash the prompt of a ringing alarm and keep the bar lit until it is answered
+        if let Some((layer, since)) = navigation_state.alarm {
+            if since.elapsed() >= ALARM_RING_DURATION {
+                dismiss_alarm_prompt(&cfg, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches);
+            } else {
+                let elapsed_ms = since.elapsed().as_millis();
+                let lit = (elapsed_ms / ALARM_FLASH_MS).is_multiple_of(2);
+                let label = &mut layers[layer].buttons[0].1;
+                if label.active != lit {
+                    label.active = lit;
+                    label.changed = true;
+                }
+                backlight.wake();
+                next_timeout_ms = min(next_timeout_ms, (ALARM_FLASH_MS - elapsed_ms % ALARM_FLASH_MS) as i32 + 1);
+            }
+        }
+
+        // Jumping under a finger is distracting, optionally hold the position until it's lifted
+        let pixel_shift_paused = cfg.pixel_shift_pause_on_touch && !touches.is_empty();
+        if cfg.enable_pixel_shift && !pixel_shift_paused {
+            let (pixel_shift_needs_redraw, pixel_shift_next_timeout_ms) = pixel_shift.update(&cfg);
             if pixel_shift_needs_redraw {
-                needs_complete_redraw = true;
+                // Only the buttons move, so redrawing them over their old and new area is enough
+                for button in &mut layers[active_layer].buttons {
+                    button.1.changed = true;
+                }
             }
-            next_timeout_ms = min(next_timeout_ms, pixel_shift_next_timeout_ms);
+            next_timeout_ms = min(next_timeout_ms, pixel_shift_next_timeout_ms.max(throttle_ms));
         }
 
         // Add expandable timeout to the calculation if we're in an expandable
@@ -1593,45 +3919,289 @@ fn real_main(drm: &mut DrmBackend) {
             }
         }
 
-        // Process pending actions (for visual feedback delay)
-        if !pending_actions.is_empty() {
-            execute_pending_actions(
-                &mut pending_actions,
-                &mut uinput,
-                &cfg,
-                &mut navigation_state,
-                &mut layers,
-                &mut active_layer,
-                &mut needs_complete_redraw,
-                &original_layers,
-                &mut touches,
-            );
+        if let Some(started) = navigation_state.confirming {
+            let remaining_ms = CONFIRM_TIMEOUT_MS - started.elapsed().as_millis() as i32;
+            next_timeout_ms = min(next_timeout_ms, remaining_ms.max(1));
+        }
+
+        // Process pending actions (for visual feedback delay)
+        if !pending_actions.is_empty() {
+            execute_pending_actions(
+                &mut pending_actions,
+                &mut uinput,
+                &cfg,
+                &mut navigation_state,
+                &mut layers,
+                &mut active_layer,
+                &mut needs_complete_redraw,
+                &original_layers,
+                &mut touches,
+            );
+        }
+
+        // Calculate next timeout for pending actions
+        if !pending_actions.is_empty() {
+            let now = std::time::Instant::now();
+            for pending_action in &pending_actions {
+                let remaining_ms = pending_action.execution_time.saturating_duration_since(now).as_millis() as i32;
+                if remaining_ms > 0 {
+                    next_timeout_ms = min(next_timeout_ms, remaining_ms);
+                }
+            }
+        }
+
+        if let Some(remaining) = hyprland::pending_update_timeout() {
+            // Wake up again once a pending focus change has settled, rounding up so we don't spin
+            next_timeout_ms = min(next_timeout_ms, remaining.as_millis() as i32 + 1);
+        }
+
+        // Timezone and DST changes move the clock without waiting for the next minute. chrono
+        // looks at /etc/localtime at most once a second, so keep checking for a moment after it changed
+        if localtime_watch.changed() {
+            localtime_changed = Some(std::time::Instant::now());
+        }
+        if localtime_changed.is_some_and(|changed| changed.elapsed() < LOCALTIME_SETTLE) {
+            next_timeout_ms = min(next_timeout_ms, LOCALTIME_POLL_MS);
+        } else {
+            localtime_changed = None;
+        }
+        let offset = Local::now().offset().local_minus_utc();
+        if offset != utc_offset {
+            println!("UTC offset changed from {}s to {}s, refreshing the clock", utc_offset, offset);
+            utc_offset = offset;
+            if layers[active_layer].displays_time {
+                needs_complete_redraw = true;
+            }
+        }
+
+        // Use system monitor for time updates (more efficient)
+        let current_minute = system_monitor::get_current_minute();
+        if layers[active_layer].displays_time && (current_minute != last_redraw_minute) {
+            needs_complete_redraw = true;
+            last_redraw_minute = current_minute;
+        }
+        // The history graph moves with time, the rest with the battery monitor's readings
+        let battery_generation = battery_monitor::generation();
+        if layers[active_layer].displays_battery
+            && (current_minute != last_battery_update_minute || battery_generation != last_battery_generation)
+        {
+            for button in &mut layers[active_layer].buttons {
+                match &button.1.image {
+                    ButtonImage::Battery(_, _, _, _) | ButtonImage::BatteryHistory(_) => button.1.changed = true,
+                    ButtonImage::Template(template) if template.uses_battery() => button.1.changed = true,
+                    ButtonImage::Status(_) => button.1.changed = true,
+                    _ => {}
+                }
+            }
+            last_battery_update_minute = current_minute;
+            last_battery_generation = battery_generation;
+        }
+
+        if last_saver_check_minute != Some(current_minute) {
+            let saver = battery_saver_wanted(&cfg, battery_device.as_deref());
+            if saver != saver_active {
+                saver_active = saver;
+                println!("{} battery saver", if saver_active { "Entering" } else { "Leaving" });
+                backlight.set_battery_saver(saver_active);
+            }
+            last_saver_check_minute = Some(current_minute);
+        }
+        let refresh_factor = if saver_active && cfg.battery_saver_slow_refresh {
+            BATTERY_SAVER_REFRESH_FACTOR
+        } else {
+            1
+        };
+
+        if layers[active_layer].displays_ambient_light {
+            let interval_ms = (AMBIENT_LIGHT_INTERVAL_MS * refresh_factor as i32).max(throttle_ms);
+            if last_ambient_light_update.elapsed().as_millis() as i32 >= interval_ms {
+                for button in &mut layers[active_layer].buttons {
+                    if let ButtonImage::AmbientLight(_) = button.1.image {
+                        button.1.changed = true;
+                    }
+                }
+                last_ambient_light_update = std::time::Instant::now();
+            }
+            next_timeout_ms = min(next_timeout_ms, interval_ms);
+        }
+
+        if layers[active_layer].displays_status {
+            let interval = (status::STATUS_INTERVAL * refresh_factor).max(std::time::Duration::from_millis(throttle_ms as u64));
+            if last_status_update.elapsed() >= interval {
+                for button in &mut layers[active_layer].buttons {
+                    if let ButtonImage::Status(_) = button.1.image {
+                        button.1.changed = true;
+                    }
+                }
+                last_status_update = std::time::Instant::now();
+            }
+            next_timeout_ms = min(next_timeout_ms, interval.as_millis() as i32);
+        }
+
+        // Picks up new quotes and moves on to the next symbol
+        if layers[active_layer].displays_ticker {
+            let interval = (ticker::CYCLE_INTERVAL * refresh_factor).max(std::time::Duration::from_millis(throttle_ms as u64));
+            if last_ticker_update.elapsed() >= interval {
+                for button in &mut layers[active_layer].buttons {
+                    if let ButtonImage::Ticker(ticker, shown) = &mut button.1.image {
+                        *shown = (*shown + 1) % ticker.symbols.len();
+                        button.1.changed = true;
+                    }
+                }
+                last_ticker_update = std::time::Instant::now();
+            }
+            next_timeout_ms = min(next_timeout_ms, interval.as_millis() as i32);
+        }
+
+        if layers[active_layer].displays_mail {
+            let generation = mail::generation();
+            if generation != last_mail_generation {
+                for button in &mut layers[active_layer].buttons {
+                    if let ButtonImage::Mail(_, _) = button.1.image {
+                        button.1.changed = true;
+                    }
+                }
+                last_mail_generation = generation;
+            }
+            next_timeout_ms = min(next_timeout_ms, MAIL_POLL_MS.max(throttle_ms));
+        }
+
+        // Levels changed by dragging or elsewhere, e.g. by the keyboard's keys
+        if layers[active_layer].displays_slider {
+            let generation = slider::generation();
+            if generation != last_slider_generation {
+                for button in &mut layers[active_layer].buttons {
+                    if let ButtonImage::Slider(_) = button.1.image {
+                        button.1.changed = true;
+                    }
+                }
+                last_slider_generation = generation;
+            }
+            next_timeout_ms = min(next_timeout_ms, SLIDER_POLL_MS.max(throttle_ms));
+        }
+
+        // Airplane mode turned on or off, here or by anything else using rfkill
+        let generation = rfkill::generation();
+        if generation != last_rfkill_generation {
+            for button in &mut layers[active_layer].buttons {
+                if button.1.action == ButtonAction::AirplaneMode {
+                    button.1.changed = true;
+                }
+            }
+            last_rfkill_generation = generation;
+        }
+
+        // The session locked or unlocked, from the button or from anywhere else
+        let generation = session_lock::generation();
+        if generation != last_lock_generation {
+            for button in &mut layers[active_layer].buttons {
+                if button.1.action == ButtonAction::LockSession {
+                    button.1.changed = true;
+                }
+            }
+            last_lock_generation = generation;
+        }
+
+        // The keyboard backlight reached full or left it, with Torch or with IllumUp and IllumDown
+        let generation = keyboard_backlight::generation();
+        if generation != last_torch_generation {
+            for button in &mut layers[active_layer].buttons {
+                if button.1.action == ButtonAction::Torch {
+                    button.1.changed = true;
+                }
+            }
+            last_torch_generation = generation;
+        }
+
+        // Volume or mute changed, by the keys, the sound settings or any other client
+        let generation = audio::generation();
+        if generation != last_volume_generation {
+            for button in &mut layers[active_layer].buttons {
+                if let ButtonImage::Volume(_, _) = button.1.image {
+                    button.1.changed = true;
+                }
+            }
+            last_volume_generation = generation;
+        }
+
+        // Scripts printed something new
+        let generation = script::generation();
+        if generation != last_script_generation {
+            for button in &mut layers[active_layer].buttons {
+                if let ButtonImage::Script(_, _) = button.1.image {
+                    button.1.changed = true;
+                }
+            }
+            last_script_generation = generation;
+        }
+
+        // Workspaces came, went, were renamed or switched to
+        let generation = hyprland::workspaces_generation();
+        if generation != last_workspaces_generation {
+            for layer in layers.iter_mut().chain(original_layers.iter_mut()) {
+                for button in &mut layer.buttons {
+                    if let ButtonImage::Workspaces(_) = button.1.image {
+                        button.1.refresh_workspaces();
+                    }
+                }
+            }
+            last_workspaces_generation = generation;
+        }
+
+        // Icons still being looked up show their name until they're found
+        if resolve_pending_icons(&mut layers) | resolve_pending_icons(&mut original_layers) {
+            next_timeout_ms = min(next_timeout_ms, ICON_POLL_MS);
         }
 
-        // Calculate next timeout for pending actions
-        if !pending_actions.is_empty() {
-            let now = std::time::Instant::now();
-            for pending_action in &pending_actions {
-                let remaining_ms = pending_action.execution_time.saturating_duration_since(now).as_millis() as i32;
-                if remaining_ms > 0 {
-                    next_timeout_ms = min(next_timeout_ms, remaining_ms);
-                }
+        // Fire countdowns that ran out and keep the remaining seconds shown on the others up to date
+        let now = std::time::Instant::now();
+        for btn in 0..layers[active_layer].buttons.len() {
+            let Some(until) = layers[active_layer].buttons[btn].1.countdown_until else {
+                continue;
+            };
+            layers[active_layer].buttons[btn].1.changed = true;
+            if now >= until {
+                layers[active_layer].buttons[btn].1.countdown_until = None;
+                let action = layers[active_layer].buttons[btn].1.current_action().clone();
+                handle_button_action(&mut uinput, &action, &cfg, true, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, None);
+                handle_button_action(&mut uinput, &action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, None);
+                // The action may have replaced the layer
+                break;
             }
+            // Don't let the expandable time out underneath a running countdown
+            navigation_state.update_interaction_time();
+            let to_next_second = (until - now).as_millis() % 1000;
+            next_timeout_ms = min(next_timeout_ms, to_next_second as i32 + 1);
         }
 
-        // Use system monitor for time updates (more efficient)
-        let current_minute = system_monitor::get_current_minute();
-        if layers[active_layer].displays_time && (current_minute != last_redraw_minute) {
-            needs_complete_redraw = true;
-            last_redraw_minute = current_minute;
+        // Fire the long press of buttons held down long enough, the tap is dropped then
+        for btn in 0..layers[active_layer].buttons.len() {
+            let Some(pressed_at) = layers[active_layer].buttons[btn].1.pressed_at else {
+                continue;
+            };
+            let remaining = LONG_PRESS_DURATION.saturating_sub(pressed_at.elapsed());
+            if remaining.is_zero() {
+                let button = &mut layers[active_layer].buttons[btn].1;
+                button.pressed_at = None;
+                button.active = false;
+                button.changed = true;
+                let Some(action) = button.long_press.clone() else {
+                    continue;
+                };
+                handle_button_action(&mut uinput, &action, &cfg, true, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, None);
+                handle_button_action(&mut uinput, &action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, None);
+                // The action may have replaced the layer
+                break;
+            }
+            next_timeout_ms = min(next_timeout_ms, remaining.as_millis() as i32 + 1);
         }
-        if layers[active_layer].displays_battery && (current_minute != last_battery_update_minute) {
+
+        if media_state::check_and_reset_updated() && layers[active_layer].displays_playback {
             for button in &mut layers[active_layer].buttons {
-                if let ButtonImage::Battery(_, _, _) = button.1.image {
+                if let ButtonImage::Playback(_, _, _) | ButtonImage::MediaPlayer(_) = button.1.image {
                     button.1.changed = true;
                 }
             }
-            last_battery_update_minute = current_minute;
         }
 
         // Periodic cache cleanup
@@ -1644,170 +4214,200 @@ fn real_main(drm: &mut DrmBackend) {
         let hyprland_cache_updated = hyprland::check_and_reset_cache_updated();
         let window_info_result = hyprland::get_active_window_info();
 
-        // Update buttons if cache was updated OR if Hyprland connection succeeded (for recovery)
-        if hyprland_cache_updated || window_info_result.is_ok() {
-            if let Ok(window_info) = window_info_result {
-                for button in &mut layers[active_layer].buttons {
-                    // Check if this is a hyprland plugin button and update its content
-                    match &button.1.action {
-                        config::ButtonAction::HyprlandExpand(expand_name) => {
-                            // Check if this is an icon-only button (plugin-hyprland-icon) or text button (plugin-hyprland)
-                            match &button.1.image {
-                                ButtonImage::Svg(_) | ButtonImage::Bitmap(_) => {
-                                    // This is an Icon plugin-hyprland-icon button - keep it as icon only
-                                    let app_icon_name = window_info.get_app_icon_name();
-
-                                    // Use efficient cache-based loading
-                                    let new_icon = if let Some(path) = icon_cache::get_icon_cached(app_icon_name.clone(), None) {
-                                        match path.extension().and_then(|s| s.to_str()) {
-                                            Some("png") => try_load_png(&path).ok(),
-                                            Some("svg") => try_load_svg(path.to_str().unwrap_or("")).ok(),
-                                            _ => None,
-                                        }
-                                    } else {
-                                        // Start async loading for next time
-                                        let _ = icon_cache::load_icon_async(app_icon_name, None);
-                                        None
-                                    }.unwrap_or_else(|| {
-                                        // Use pre-loaded fallbacks
-                                        icon_cache::get_icon_cached("application-default-icon".to_string(), None)
-                                            .and_then(|path| match path.extension().and_then(|s| s.to_str()) {
-                                                Some("png") => try_load_png(&path).ok(),
-                                                Some("svg") => try_load_svg(path.to_str().unwrap_or("")).ok(),
-                                                _ => None,
-                                            })
-                                            .or_else(|| {
-                                                icon_cache::get_icon_cached("plugin-hyprland".to_string(), None)
-                                                    .and_then(|path| match path.extension().and_then(|s| s.to_str()) {
-                                                        Some("png") => try_load_png(&path).ok(),
-                                                        Some("svg") => try_load_svg(path.to_str().unwrap_or("")).ok(),
-                                                        _ => None,
-                                                    })
-                                            })
-                                            .unwrap_or_else(|| ButtonImage::Text("🔗".to_string()))
-                                    });
+        if let Some(workspace) = hyprland::take_workspace_change() {
+            let target = cfg
+                .workspace_bindings
+                .get(&workspace.name)
+                .or_else(|| cfg.workspace_bindings.get(&workspace.id.to_string()))
+                .cloned();
+            // Leaving a bound workspace for an unbound one goes back to the default layer
+            if (target.is_some() || workspace_bound) && !navigation_state.locked {
+                navigation_state.reset_to_main();
+                layers.clone_from_slice(&original_layers);
+                active_layer = 0;
+                match &target {
+                    Some(WorkspaceTarget::Layer(layer)) => active_layer = *layer,
+                    Some(WorkspaceTarget::Expandable(name)) => {
+                        navigation_state.push_expandable(name.clone());
+                        navigation_state.pinned_expandable = Some(name.clone());
+                    }
+                    None => {}
+                }
+                update_layer_for_navigation(&navigation_state, &cfg, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches);
+            }
+            workspace_bound = target.is_some();
+            for button in &mut layers[active_layer].buttons {
+                if button.1.source == ButtonSource::Template {
+                    button.1.changed = true;
+                }
+            }
+        }
 
-                                    button.1.image = new_icon;
-                                    button.1.changed = true;
-                                }
-                                _ => {
-                                    // This is a Text plugin-hyprland button - show with app icon and text using cache
-                                    let app_icon_name = window_info.get_app_icon_name();
-                                    let window_title = window_info.get_text_by_button_title("title");
-
-                                    // Use cache-based approach for consistent performance
-                                    if let Some(path) = icon_cache::get_icon_cached(app_icon_name.clone(), None) {
-                                        // Load icon from cached path
-                                        if let Ok(icon) = match path.extension().and_then(|s| s.to_str()) {
-                                            Some("png") => try_load_png(&path),
-                                            Some("svg") => try_load_svg(path.to_str().unwrap_or("")),
-                                            _ => {
-                                                // Start async loading for next time
-                                                let _ = icon_cache::load_icon_async(app_icon_name, None);
-                                                button.1.image = ButtonImage::Text(window_title);
-                                                button.1.changed = true;
-                                                continue;
-                                            },
-                                        } {
-                                            if let ButtonImage::Svg(icon_handle) = icon {
-                                                // Show with app icon and text
-                                                button.1.image = ButtonImage::TextWithIcon(
-                                                    format!(" {}", window_title),
-                                                    icon_handle
-                                                );
-                                                button.1.changed = true;
-                                            } else {
-                                                // Non-SVG icon, fallback to text only
-                                                button.1.image = ButtonImage::Text(window_title);
-                                                button.1.changed = true;
-                                            }
-                                        } else {
-                                            // Failed to load cached icon, fallback to text
-                                            button.1.image = ButtonImage::Text(window_title);
-                                            button.1.changed = true;
-                                        }
-                                    } else {
-                                        // Icon not in cache, start async loading and try fallbacks
-                                        let _ = icon_cache::load_icon_async(app_icon_name, None);
-
-                                        // Try pre-loaded fallback icons for text+icon mode
-                                        if let Some(fallback_path) = icon_cache::get_icon_cached("application-default-icon".to_string(), None) {
-                                            if let Ok(fallback_icon) = match fallback_path.extension().and_then(|s| s.to_str()) {
-                                                Some("png") => try_load_png(&fallback_path),
-                                                Some("svg") => try_load_svg(fallback_path.to_str().unwrap_or("")),
-                                                _ => {
-                                                    button.1.image = ButtonImage::Text(window_title);
-                                                    button.1.changed = true;
-                                                    continue;
-                                                },
-                                            } {
-                                                if let ButtonImage::Svg(fallback_handle) = fallback_icon {
-                                                    // Use fallback icon with text
-                                                    button.1.image = ButtonImage::TextWithIcon(
-                                                        format!(" {}", window_title),
-                                                        fallback_handle
-                                                    );
-                                                    button.1.changed = true;
-                                                } else {
-                                                    // Fallback to text only
-                                                    button.1.image = ButtonImage::Text(window_title);
-                                                    button.1.changed = true;
-                                                }
-                                            } else {
-                                                // Fallback icon failed to load, text only
-                                                button.1.image = ButtonImage::Text(window_title);
-                                                button.1.changed = true;
-                                            }
-                                        } else {
-                                            // No fallback available, text only
-                                            button.1.image = ButtonImage::Text(window_title);
-                                            button.1.changed = true;
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        _ => {
-                            // For other buttons, check if they might be plugin-hyprland buttons
-                            // We need to identify Icon plugin-hyprland buttons somehow
-                            // One way is to check if this is a button that shows app-specific icons
-                            match &button.1.image {
-                                ButtonImage::Text(text) if text.contains("Alacritty") || text.contains("code") || text.contains("Visual Studio") => {
-                                    // This is a Text plugin-hyprland button
-                                    button.1.image = ButtonImage::Text(window_info.get_text_by_button_title("title"));
-                                    button.1.changed = true;
-                                }
-                                ButtonImage::TextWithIcon(text, _) if text.contains("Alacritty") || text.contains("code") || text.contains("Visual Studio") => {
-                                    // For non-Hyprland expand buttons, keep as text only
-                                    button.1.image = ButtonImage::Text(window_info.get_text_by_button_title("title"));
-                                    button.1.changed = true;
-                                }
-                                _ => {}
-                            }
-                        }
+        // Catch up on focus changes that happened while the layer was locked
+        let unlocked = was_locked && !navigation_state.locked;
+        was_locked = navigation_state.locked;
+
+        // Layers with an App pattern come up while a matching window is focused, and make way once it isn't
+        if hyprland_cache_updated && !navigation_state.locked && navigation_state.current_expandable.is_none() {
+            let app_layer = window_info_result.as_ref().ok().and_then(|window| cfg.app_layer(&window.class));
+            if app_layer != app_layer_shown {
+                let target = match app_layer {
+                    Some(layer) => Some(layer),
+                    None if app_layer_shown == Some(active_layer) => Some(0),
+                    None => None,
+                };
+                if let Some(layer) = target.filter(|layer| *layer != active_layer && *layer < layers.len()) {
+                    active_layer = layer;
+                    needs_complete_redraw = true;
+                    clear_all_touches(&mut layers, &mut touches);
+                }
+                app_layer_shown = app_layer;
+            }
+        }
+
+        // Only rebuild the buttons once a focus change has settled, layer switches refresh them on their own
+        if (hyprland_cache_updated || unlocked) && !navigation_state.locked {
+            if let Ok(window_info) = &window_info_result {
+                refresh_active_window_buttons(&mut layers[active_layer], window_info);
+            }
+        }
+
+        // Playback controls follow a focused Picture-in-Picture window onto whichever layer shows
+        if !navigation_state.locked {
+            let pip_focused = window_info_result.as_ref().is_ok_and(|window| cfg.is_pip_window(&window.title));
+            let mut changed = false;
+            for (i, layer) in layers.iter_mut().enumerate() {
+                changed |= layer.set_pip_controls(pip_focused && i == active_layer);
+            }
+            if changed {
+                needs_complete_redraw = true;
+                clear_all_touches(&mut layers, &mut touches);
+            }
+        }
+
+        // Re-render everything when entering or leaving the dimmed state so the content style follows
+        let dimmed = cfg.dimmed_content_style != DimmedContentStyle::None && backlight.is_dimmed();
+        if dimmed != last_dimmed {
+            needs_complete_redraw = true;
+            last_dimmed = dimmed;
+        }
+
+        burn_in.tick(backlight.brightness_fraction());
+        let care = burn_in.care_mode_active(&cfg, backlight.idle_for(), backlight.lid_closed());
+        if care != care_active {
+            care_active = care;
+            burn_in.set_care_mode(care_active);
+            backlight.set_care_mode(care_active);
+            needs_complete_redraw = true;
+        }
+
+        let quiet = cfg.quiet_hours.is_some_and(config::within_hours);
+        if quiet != quiet_active {
+            quiet_active = quiet;
+            println!("{} quiet hours", if quiet_active { "Entering" } else { "Leaving" });
+            backlight.set_quiet(quiet_active);
+            if let Some(name) = cfg.quiet_hours_expandable.clone() {
+                // Only leave the expandable if it is still the one quiet hours put up
+                let switch = cfg.expandables.contains_key(&name)
+                    && !navigation_state.locked
+                    && (quiet_active || navigation_state.pinned_expandable.as_ref() == Some(&name));
+                if switch {
+                    navigation_state.reset_to_main();
+                    layers.clone_from_slice(&original_layers);
+                    active_layer = 0;
+                    if quiet_active {
+                        navigation_state.push_expandable(name.clone());
+                        navigation_state.pinned_expandable = Some(name);
                     }
+                    update_layer_for_navigation(&navigation_state, &cfg, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches);
                 }
             }
         }
 
-        if needs_complete_redraw || layers[active_layer].buttons.iter().any(|b| b.1.changed) {
+        let (osd_changed, osd_next_timeout_ms) = osd.update();
+        next_timeout_ms = min(next_timeout_ms, osd_next_timeout_ms);
+        if osd_changed {
+            needs_complete_redraw = true;
+        }
+
+        // The latest notification replaces one still shown
+        if let (Some(notification), true, false) =
+            (notifications::take_notifications().pop(), cfg.notification_banner, quiet_active)
+        {
+            overlay = Some(OverlayLayer::notification(&cfg, notification));
+            backlight.wake();
+            needs_complete_redraw = true;
+        }
+        if overlay.as_ref().is_some_and(OverlayLayer::expired) {
+            overlay = None;
+            needs_complete_redraw = true;
+        }
+        if let Some(overlay) = &overlay {
+            next_timeout_ms = min(next_timeout_ms, overlay.timeout_ms());
+        }
+
+        if care_active {
+            let (pattern_moved, care_next_timeout_ms) = burn_in.care_update(&cfg);
+            next_timeout_ms = min(next_timeout_ms, care_next_timeout_ms.max(throttle_ms));
+            if pattern_moved || needs_complete_redraw {
+                let clips = burn_in.draw_care_pattern(&cfg, width as i32, height as i32, &surface);
+                burn_in.frame_drawn(&mut surface, cfg.rotation, width as i32, height as i32);
+                copy_to_framebuffer(&mut surface, drm, fb_pitch);
+                drm.dirty(&clips).unwrap();
+                needs_complete_redraw = false;
+            }
+        } else if osd.active() {
+            // Touches still reach the layer underneath, so its buttons can be tapped repeatedly
+            if needs_complete_redraw {
+                let clips = osd.draw(&cfg, width as i32, height as i32, &surface);
+                burn_in.frame_drawn(&mut surface, cfg.rotation, width as i32, height as i32);
+                copy_to_framebuffer(&mut surface, drm, fb_pitch);
+                drm.dirty(&clips).unwrap();
+                needs_complete_redraw = false;
+            }
+        } else if let Some(overlay) = &overlay {
+            if needs_complete_redraw {
+                let clips = overlay.draw(&cfg, width as i32, height as i32, &surface);
+                burn_in.frame_drawn(&mut surface, cfg.rotation, width as i32, height as i32);
+                copy_to_framebuffer(&mut surface, drm, fb_pitch);
+                drm.dirty(&clips).unwrap();
+                needs_complete_redraw = false;
+            }
+        } else if !needs_complete_redraw
+            && (last_frame.elapsed().as_millis() as i32) < throttle_ms
+            && layers[active_layer].buttons.iter().any(|b| b.1.changed)
+        {
+            // Drawn along with whatever else changes until the next frame is due
+            next_timeout_ms = min(next_timeout_ms, throttle_ms - last_frame.elapsed().as_millis() as i32 + 1);
+        } else if needs_complete_redraw || layers[active_layer].buttons.iter().any(|b| b.1.changed) {
             let shift = if cfg.enable_pixel_shift {
-                pixel_shift.get()
+                pixel_shift.get(&cfg)
             } else {
                 (0.0, 0.0)
             };
-            let clips = layers[active_layer].draw(
+            let draw_started = std::time::Instant::now();
+            let mut clips = layers[active_layer].draw(
                 &cfg,
                 width as i32,
                 height as i32,
                 &surface,
                 shift,
                 needs_complete_redraw,
+                dimmed,
             );
-            let data = surface.data().unwrap();
-            drm.map().unwrap().as_mut()[..data.len()].copy_from_slice(&data);
+            if navigation_state.locked {
+                clips.push(draw_lock_glyph(&cfg, width as i32, height as i32, &surface));
+            }
+            burn_in.frame_drawn(&mut surface, cfg.rotation, width as i32, height as i32);
+            copy_to_framebuffer(&mut surface, drm, fb_pitch);
             drm.dirty(&clips).unwrap();
+            metrics::frame_rendered(draw_started.elapsed());
+            last_frame = std::time::Instant::now();
             needs_complete_redraw = false;
+            if cfg.dbus_control {
+                let buttons = layers[active_layer].buttons.iter().map(|(_, button)| (button.current_action().label(), button.spoken_label()));
+                dbus::set_buttons(buttons.collect());
+            }
         }
 
         match epoll.wait(
@@ -1818,7 +4418,47 @@ fn real_main(drm: &mut DrmBackend) {
             e => e.unwrap(),
         };
 
-        _ = udev_monitor.iter().last();
+        if udev_monitor.iter().last().is_some() {
+            battery_monitor::refresh();
+            let online = charger_online();
+            if online != charger_was_online {
+                // Like the other overlays, not during quiet hours
+                if let (Some(_), Some(plugged), false) = (charger_was_online, online, quiet_active) {
+                    let capacity = battery_device.as_deref().map(|battery| battery_monitor::read_battery_state(battery).capacity);
+                    osd.power_changed(&cfg, plugged, capacity);
+                }
+                charger_was_online = online;
+                update_docked(&cfg, &mut cfg_mgr, &external_displays, online);
+            }
+        }
+
+        // Hotplug events come for every connector of the card, only a change in the monitors counts
+        if drm_monitor.iter().last().is_some() {
+            let connected = displays::connected_external();
+            if let Some(message) = displays::change_message(&external_displays, &connected) {
+                println!("{}", message);
+                if cfg.display_indicator && !quiet_active {
+                    osd.show_message(message, std::time::Duration::from_secs(2));
+                    backlight.wake();
+                    needs_complete_redraw = true;
+                }
+                if let Some(command_id) = &cfg.display_profile_command {
+                    let event = if connected.iter().any(|name| !external_displays.contains(name)) { "connected" } else { "disconnected" };
+                    run_command(command_id, &cfg, vec![("DISPLAYS", connected.join(" ")), ("DISPLAY_EVENT", event.to_string())]);
+                }
+            }
+            external_displays = connected;
+            update_docked(&cfg, &mut cfg_mgr, &external_displays, charger_was_online);
+        }
+
+        if let Ok(Some(_)) = state_dump_signal.read_signal() {
+            println!("State dump:");
+            println!("  Active layer: {}, expandable: {:?}", active_layer, navigation_state.current_expandable);
+            println!("  Touch Bar brightness: {}, dimmed: {}", backlight.current_bl(), backlight.is_dimmed());
+            println!("  Quiet hours: {}, battery saver: {}, safe mode: {}", quiet_active, saver_active, config::safe_mode());
+            println!("  Touches in progress: {}, pending actions: {}", touches.len(), pending_actions.len());
+            println!("  Hyprland event listener restarts: {}", hyprland::listener_restarts());
+        }
 
         input_tb.dispatch().unwrap();
         input_main.dispatch().unwrap();
@@ -1827,14 +4467,55 @@ fn real_main(drm: &mut DrmBackend) {
             match event {
                 Event::Device(DeviceEvent::Added(evt)) => {
                     let dev = evt.device();
-                    if dev.name().contains(" Touch Bar") {
+                    if dev.name().contains(cfg.touch_device.as_str()) {
                         digitizer = Some(dev);
                     }
                 }
                 Event::Keyboard(KeyboardEvent::Key(key)) => {
-                    if key.key() == Key::Fn as u32 {
+                    if key.key_state() == KeyState::Pressed && !quiet_active {
+                        osd.key_pressed(&cfg, key.key());
+                    }
+                    // Either side's modifier completes a shortcut written with the left one
+                    let code = match key.key() {
+                        code if code == Key::RightCtrl as u32 => Key::LeftCtrl as u32,
+                        code if code == Key::RightShift as u32 => Key::LeftShift as u32,
+                        code if code == Key::RightAlt as u32 => Key::LeftAlt as u32,
+                        code if code == Key::RightMeta as u32 => Key::LeftMeta as u32,
+                        code => code,
+                    };
+                    match key.key_state() {
+                        KeyState::Pressed => held_keys.insert(code),
+                        KeyState::Released => held_keys.remove(&code),
+                    };
+                    let shortcut = cfg.keyboard_shortcuts.iter().find(|(keys, _)| {
+                        key.key_state() == KeyState::Pressed
+                            && keys.iter().any(|k| *k as u32 == code)
+                            && keys.iter().all(|k| held_keys.contains(&(*k as u32)))
+                    });
+                    if let (Some((_, target)), false) = (shortcut, navigation_state.locked) {
+                        // The same shortcut closes the expandable it opened
+                        let open = matches!(target, WorkspaceTarget::Expandable(name) if navigation_state.current_expandable.as_ref() == Some(name));
+                        navigation_state.reset_to_main();
+                        layers.clone_from_slice(&original_layers);
+                        active_layer = 0;
+                        match target {
+                            WorkspaceTarget::Layer(layer) => active_layer = *layer,
+                            WorkspaceTarget::Expandable(name) if !open => navigation_state.push_expandable(name.clone()),
+                            WorkspaceTarget::Expandable(_) => {}
+                        }
+                        update_layer_for_navigation(&navigation_state, &cfg, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches);
+                        shortcut_during_fn = held_keys.contains(&(Key::Fn as u32));
+                    }
+                    // Letting go of Fn after a shortcut keeps what it brought up
+                    let after_shortcut = key.key() == Key::Fn as u32 && key.key_state() == KeyState::Released
+                        && std::mem::take(&mut shortcut_during_fn);
+                    if key.key() == Key::Fn as u32 && key.key_state() == KeyState::Pressed {
+                        layer_before_fn = active_layer;
+                    }
+                    if key.key() == Key::Fn as u32 && !navigation_state.locked && !after_shortcut {
                         let new_layer = match key.key_state() {
-                            KeyState::Pressed => 1,
+                            KeyState::Pressed => cfg.fn_layer(),
+                            KeyState::Released if layer_before_fn < layers.len() => layer_before_fn,
                             KeyState::Released => 0,
                         };
                         if active_layer != new_layer {
@@ -1842,50 +4523,195 @@ fn real_main(drm: &mut DrmBackend) {
                             needs_complete_redraw = true;
                         }
                     }
+                    // A quick press of Fn alone is a tap, anything pressed along with it makes it a hold
+                    if key.key_state() == KeyState::Pressed {
+                        fn_pressed = (key.key() == Key::Fn as u32).then(|| (std::time::Instant::now(), layer_before_fn));
+                    }
+                    let tapped_from = fn_pressed.filter(|(at, _)| {
+                        key.key() == Key::Fn as u32
+                            && key.key_state() == KeyState::Released
+                            && at.elapsed().as_secs_f64() < cfg.fn_tap_seconds
+                    });
+                    if key.key_state() == KeyState::Released && key.key() == Key::Fn as u32 {
+                        fn_pressed = None;
+                    }
+                    if let (Some((_, layer_before)), false) = (tapped_from, navigation_state.locked) {
+                        match &cfg.fn_tap {
+                            Some(FnTap::ToggleLayers) => {
+                                active_layer = if layer_before == 0 { cfg.fn_layer() } else { 0 };
+                                needs_complete_redraw = true;
+                            }
+                            Some(FnTap::CycleLayers) => {
+                                active_layer = (layer_before + 1) % layers.len();
+                                needs_complete_redraw = true;
+                            }
+                            Some(FnTap::Action(action)) => {
+                                handle_button_action(&mut uinput, action, &cfg, true, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, None);
+                                handle_button_action(&mut uinput, action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, None);
+                            }
+                            None => {
+                                if let Some(layer) = cfg.fn_lock_layer() {
+                                    active_layer = if layer_before == layer { 0 } else { layer };
+                                    needs_complete_redraw = true;
+                                }
+                            }
+                        }
+                    }
                 }
                 Event::Touch(te) => {
-                    if Some(te.device()) != digitizer || backlight.current_bl() == 0 {
+                    // Touches only wake the bar up while the care pattern is shown
+                    if Some(te.device()) != digitizer || backlight.current_bl() == 0 || care_active {
                         continue;
                     }
+                    metrics::touch_event();
                     match te {
+                        // Tapping a notification away doesn't press what's underneath
+                        TouchEvent::Down(_) if overlay.is_some() => {
+                            overlay = None;
+                            needs_complete_redraw = true;
+                        }
                         TouchEvent::Down(dn) => {
-                            let x = dn.x_transformed(width as u32);
-                            let y = dn.y_transformed(height as u32);
-                            if let Some(btn) = layers[active_layer].hit(width, height, x, y, None) {
+                            let (x, y) = touch_point_to_bar(
+                                cfg.rotation,
+                                width as i32,
+                                height as i32,
+                                dn.x_transformed(width as u32),
+                                dn.y_transformed(height as u32),
+                            );
+                            gestures.touch_down(dn.seat_slot(), x, y);
+                            if let Some(btn) = layers[active_layer].hit(width, height, cfg.scale, x, y, None) {
                                 touches.insert(dn.seat_slot(), (active_layer, btn));
-                                
-                                // Get the button action before borrowing layers mutably
-                                let button_action = &layers[active_layer].buttons[btn].1.action;
-                                
-                                // Handle keyboard backlight actions directly
-                                let handled_by_keyboard_backlight = if cfg.keyboard_brightness_enabled {
-                                    match button_action {
-                                        ButtonAction::Key(Key::IllumUp) => {
-                                            kbd_backlight.increase_brightness()
-                                        }
-                                        ButtonAction::Key(Key::IllumDown) => {
-                                            kbd_backlight.decrease_brightness()
+                                if cfg.dbus_control {
+                                    dbus::button_touched(btn, &layers[active_layer].buttons[btn].1.spoken_label());
+                                }
+                                if cfg.haptic_feedback {
+                                    haptics::click();
+                                }
+                                let zone = layers[active_layer].zone_at(width, cfg.scale, x, btn);
+                                layers[active_layer].buttons[btn].1.active_zone = zone;
+
+                                // A second finger on a pinch button starts pinching, neither finger taps it anymore
+                                if layers[active_layer].buttons[btn].1.pinch.is_some() {
+                                    let partner = touches
+                                        .iter()
+                                        .find(|(slot, target)| **slot != dn.seat_slot() && **target == (active_layer, btn) && gestures.touching(**slot))
+                                        .map(|(slot, _)| *slot);
+                                    if let Some(partner) = partner {
+                                        gestures.start_pinch(partner, dn.seat_slot());
+                                        if layers[active_layer].buttons[btn].1.active {
+                                            let action = layers[active_layer].buttons[btn].1.current_action().clone();
+                                            handle_button_action(&mut uinput, &action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
                                         }
-                                        _ => false
+                                        layers[active_layer].buttons[btn].1.active = true;
+                                        layers[active_layer].buttons[btn].1.changed = true;
+                                        navigation_state.update_interaction_time();
+                                        continue;
+                                    }
+                                }
+
+                                // Exploring only announces the button, a quick second tap on it activates it
+                                if cfg.touch_explore {
+                                    let double_tap = explored_tap.take().is_some_and(|(layer, tapped, at)| {
+                                        layer == active_layer && tapped == btn && at.elapsed() < EXPLORE_DOUBLE_TAP
+                                    });
+                                    explore_activated = double_tap.then_some(dn.seat_slot());
+                                    if double_tap {
+                                        let action = layers[active_layer].buttons[btn].1.current_action().clone();
+                                        handle_button_action(&mut uinput, &action, &cfg, true, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
+                                        handle_button_action(&mut uinput, &action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
+                                    } else {
+                                        explore_button(&cfg, &mut layers[active_layer].buttons[btn].1);
+                                    }
+                                    navigation_state.update_interaction_time();
+                                    continue;
+                                }
+
+                                // Sliders jump to where they're touched instead of acting
+                                if let ButtonImage::Slider(kind) = layers[active_layer].buttons[btn].1.image {
+                                    let level = layers[active_layer].fraction_at(width, cfg.scale, x, btn);
+                                    slide_to(&mut backlight, kind, level);
+                                    layers[active_layer].buttons[btn].1.changed = true;
+                                    navigation_state.update_interaction_time();
+                                    continue;
+                                }
+
+                                // Countdown buttons start or cancel their countdown instead of acting right away
+                                let handled_by_countdown = if let Some(seconds) = layers[active_layer].buttons[btn].1.countdown {
+                                    let button = &mut layers[active_layer].buttons[btn].1;
+                                    button.countdown_until = match button.countdown_until {
+                                        Some(_) => None,
+                                        None => Some(std::time::Instant::now() + std::time::Duration::from_secs(seconds as u64)),
+                                    };
+                                    true
+                                } else {
+                                    false
+                                };
+                                
+                                // Holding buttons press their key now and let go of it once the time is up,
+                                // tapping again while it is held lets go early
+                                let handled_by_hold = if let Some(hold_for) = layers[active_layer].buttons[btn].1.hold_for {
+                                    let action = layers[active_layer].buttons[btn].1.current_action().clone();
+                                    let held = pending_actions
+                                        .iter_mut()
+                                        .find(|pending| pending.layer_index == active_layer && pending.button_index == btn && pending.action == action);
+                                    if let Some(held) = held {
+                                        held.execution_time = std::time::Instant::now();
+                                    } else {
+                                        handle_button_action(&mut uinput, &action, &cfg, true, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
+                                        pending_actions.push(PendingAction {
+                                            action,
+                                            execution_time: std::time::Instant::now() + hold_for,
+                                            button_index: btn,
+                                            layer_index: active_layer,
+                                        });
                                     }
+                                    true
                                 } else {
                                     false
                                 };
+
+                                // Buttons with a long press wait to see how long they are held before doing anything
+                                let handled_by_long_press = layers[active_layer].buttons[btn].1.long_press.is_some()
+                                    && !handled_by_countdown
+                                    && !handled_by_hold;
+                                if handled_by_long_press {
+                                    layers[active_layer].buttons[btn].1.pressed_at = Some(std::time::Instant::now());
+                                }
+
+                                // Get the button action before borrowing layers mutably
+                                let button_action = &layers[active_layer].buttons[btn].1.current_action();
+                                
+                                // Handle keyboard backlight actions directly
+                                let handled_directly = handled_by_countdown || handled_by_hold || handled_by_long_press || (cfg.keyboard_brightness_enabled && match button_action {
+                                    ButtonAction::Key(Key::IllumUp) => {
+                                        kbd_backlight.increase_brightness()
+                                    }
+                                    ButtonAction::Key(Key::IllumDown) => {
+                                        kbd_backlight.decrease_brightness()
+                                    }
+                                    _ => false
+                                }) || (matches!(button_action, ButtonAction::Torch) && kbd_backlight.toggle_torch());
                                 
-                                // Only send key event if we didn't handle it with keyboard backlight
-                                if !handled_by_keyboard_backlight {
+                                // Only send key event if we didn't handle it directly
+                                if !handled_directly {
                                     // Extract the button action to avoid borrowing conflict
-                                    let action = layers[active_layer].buttons[btn].1.action.clone();
+                                    let action = layers[active_layer].buttons[btn].1.current_action().clone();
                                     let old_active = layers[active_layer].buttons[btn].1.active;
                                     if old_active != true {
                                         layers[active_layer].buttons[btn].1.active = true;
                                         layers[active_layer].buttons[btn].1.changed = true;
+                                        if layers[active_layer].buttons[btn].1.touch_sound.unwrap_or(cfg.touch_sound) {
+                                            play_touch_sound(&cfg);
+                                        }
                                         handle_button_action(&mut uinput, &action, &cfg, true, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
                                     }
                                 } else {
-                                    // Show visual feedback for keyboard backlight buttons (without key event)
+                                    // Show visual feedback for buttons handled directly (without key event)
                                     layers[active_layer].buttons[btn].1.active = true;
                                     layers[active_layer].buttons[btn].1.changed = true;
+                                    if layers[active_layer].buttons[btn].1.touch_sound.unwrap_or(cfg.touch_sound) {
+                                        play_touch_sound(&cfg);
+                                    }
                                 }
 
                                 // Update interaction time for any touch
@@ -1893,25 +4719,203 @@ fn real_main(drm: &mut DrmBackend) {
                             }
                         }
                         TouchEvent::Motion(mtn) => {
+                            let (x, y) = touch_point_to_bar(
+                                cfg.rotation,
+                                width as i32,
+                                height as i32,
+                                mtn.x_transformed(width as u32),
+                                mtn.y_transformed(height as u32),
+                            );
+
+                            let swipe = gestures.touch_motion(mtn.seat_slot(), x, y, height as f64, cfg.swipe_up_distance, SWIPE_SIDEWAYS_PX * cfg.scale);
+                            if let (Some(Swipe::Up), Some(expandable_name)) = (swipe, cfg.swipe_up_expandable.clone()) {
+                                if cfg.expandables.contains_key(&expandable_name)
+                                    && navigation_state.current_expandable.as_ref() != Some(&expandable_name)
+                                {
+                                    // Release the button the swipe started on before leaving the layer
+                                    if let Some(&(layer, btn)) = touches.get(&mtn.seat_slot()) {
+                                        if layers[layer].buttons[btn].1.active {
+                                            let action = layers[layer].buttons[btn].1.current_action().clone();
+                                            layers[layer].buttons[btn].1.active = false;
+                                            handle_button_action(&mut uinput, &action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
+                                        }
+                                    }
+                                    navigation_state.push_expandable(expandable_name);
+                                    update_layer_for_navigation(&navigation_state, &cfg, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches);
+                                }
+                                continue;
+                            }
+
+                            // Swipes across the bar do their own action, except on buttons that are dragged along
+                            let swipe_action = match swipe {
+                                Some(Swipe::Left) => cfg.swipe_left.clone(),
+                                Some(Swipe::Right) => cfg.swipe_right.clone(),
+                                Some(Swipe::Up) => cfg.swipe_up.clone(),
+                                Some(Swipe::Down) => cfg.swipe_down.clone(),
+                                None => None,
+                            };
+                            let dragged_along = touches.get(&mtn.seat_slot()).is_some_and(|&(layer, btn)| {
+                                let button = &layers[layer].buttons[btn].1;
+                                button.scrub.is_some() || button.scroll.is_some() || button.pinch.is_some() || matches!(button.image, ButtonImage::Slider(_))
+                            });
+                            // Layers with more buttons than fit turn sideways swipes into scrolling instead
+                            let page_forward = match swipe {
+                                Some(Swipe::Left) if layers[active_layer].scrollable() => Some(true),
+                                Some(Swipe::Right) if layers[active_layer].scrollable() => Some(false),
+                                _ => None,
+                            }.filter(|_| !dragged_along);
+                            let swipe_action = swipe_action.filter(|_| !dragged_along && page_forward.is_none());
+                            if page_forward.is_some() || swipe_action.is_some() {
+                                // The button the swipe started on lets go without doing anything more
+                                if let Some((layer, btn)) = touches.remove(&mtn.seat_slot()) {
+                                    layers[layer].buttons[btn].1.pressed_at = None;
+                                    if layers[layer].buttons[btn].1.active {
+                                        let action = layers[layer].buttons[btn].1.current_action().clone();
+                                        layers[layer].buttons[btn].1.active = false;
+                                        layers[layer].buttons[btn].1.changed = true;
+                                        handle_button_action(&mut uinput, &action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
+                                    }
+                                }
+                                if let Some(forward) = page_forward {
+                                    layers[active_layer].scroll_page(forward);
+                                    needs_complete_redraw = true;
+                                }
+                                if let Some(swipe_action) = swipe_action {
+                                    handle_button_action(&mut uinput, &swipe_action, &cfg, true, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, None);
+                                    handle_button_action(&mut uinput, &swipe_action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, None);
+                                }
+                                navigation_state.update_interaction_time();
+                                continue;
+                            }
+
                             if !touches.contains_key(&mtn.seat_slot()) {
                                 continue;
                             }
 
-                            let x = mtn.x_transformed(width as u32);
-                            let y = mtn.y_transformed(height as u32);
                             let (layer, btn) = *touches.get(&mtn.seat_slot()).unwrap();
+
+                            // Zoom a step for every scrub step's worth the fingers spread apart or pinch in
+                            if gestures.pinching(mtn.seat_slot()) {
+                                let steps = gestures.pinch_motion(cfg.scrub_step * cfg.scale);
+                                match layers[layer].buttons[btn].1.pinch.clone() {
+                                    Some(PinchConfig::Actions([pinch_in, spread])) if steps != 0 => {
+                                        let action = if steps < 0 { pinch_in } else { spread };
+                                        for _ in 0..steps.abs() {
+                                            handle_button_action(&mut uinput, &action, &cfg, true, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
+                                            handle_button_action(&mut uinput, &action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
+                                        }
+                                    }
+                                    Some(PinchConfig::Wheel(_)) if steps != 0 => {
+                                        let wheel = scroll_device.get_or_insert_with(create_scroll_wheel);
+                                        toggle_key(&mut uinput, Key::LeftCtrl, 1);
+                                        scroll_wheel(wheel, ScrollAxis::Vertical, steps * 120, &mut scroll_remainder);
+                                        toggle_key(&mut uinput, Key::LeftCtrl, 0);
+                                    }
+                                    _ => {}
+                                }
+                                navigation_state.update_interaction_time();
+                                continue;
+                            }
+
+                            // Sliding the finger along announces each button it reaches
+                            if cfg.touch_explore {
+                                if explore_activated == Some(mtn.seat_slot()) {
+                                    continue;
+                                }
+                                let under = layers[layer].hit(width, height, cfg.scale, x, y, None);
+                                if under != Some(btn) || !layers[layer].buttons[btn].1.active {
+                                    layers[layer].buttons[btn].1.active = false;
+                                    layers[layer].buttons[btn].1.changed = true;
+                                    if let Some(under) = under {
+                                        touches.insert(mtn.seat_slot(), (layer, under));
+                                        explore_button(&cfg, &mut layers[layer].buttons[under].1);
+                                    }
+                                }
+                                navigation_state.update_interaction_time();
+                                continue;
+                            }
+
+                            // Held keys are let go of by their timer, not by the finger
+                            if layers[layer].buttons[btn].1.hold_for.is_some() {
+                                continue;
+                            }
+
+                            // Sliders follow the finger, even once it slides past their ends
+                            if let ButtonImage::Slider(kind) = layers[layer].buttons[btn].1.image {
+                                let level = layers[layer].fraction_at(width, cfg.scale, x, btn);
+                                slide_to(&mut backlight, kind, level);
+                                layers[layer].buttons[btn].1.changed = true;
+                                navigation_state.update_interaction_time();
+                                continue;
+                            }
+
+                            // Dragging across a scroll strip turns the wheel in quarter notches instead of tapping it,
+                            // a scrub step's worth of movement making a whole notch
+                            if let Some(axis) = layers[layer].buttons[btn].1.scroll {
+                                let was_scrubbing = gestures.scrubbing(mtn.seat_slot());
+                                let steps = gestures.scrub_motion(mtn.seat_slot(), x, cfg.scrub_step * cfg.scale / 4.0);
+                                if gestures.scrubbing(mtn.seat_slot()) {
+                                    if !was_scrubbing {
+                                        scroll_remainder = 0;
+                                        if layers[layer].buttons[btn].1.active {
+                                            let action = layers[layer].buttons[btn].1.current_action().clone();
+                                            handle_button_action(&mut uinput, &action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
+                                        }
+                                    }
+                                    // Moving right scrolls down, like dragging a scrollbar
+                                    let amount = match axis {
+                                        ScrollAxis::Vertical => -steps * 30,
+                                        ScrollAxis::Horizontal => steps * 30,
+                                    };
+                                    if amount != 0 {
+                                        let wheel = scroll_device.get_or_insert_with(create_scroll_wheel);
+                                        scroll_wheel(wheel, axis, amount, &mut scroll_remainder);
+                                    }
+                                    navigation_state.update_interaction_time();
+                                    continue;
+                                }
+                            }
+
+                            // Dragging across a scrub button sends a key per step instead of a single tap
+                            if let Some((left, right)) = layers[layer].buttons[btn].1.scrub {
+                                let was_scrubbing = gestures.scrubbing(mtn.seat_slot());
+                                let steps = gestures.scrub_motion(mtn.seat_slot(), x, cfg.scrub_step * cfg.scale);
+                                if gestures.scrubbing(mtn.seat_slot()) {
+                                    if !was_scrubbing && layers[layer].buttons[btn].1.active {
+                                        // Let go of the tap the drag started with, the button stays lit until released
+                                        let action = layers[layer].buttons[btn].1.current_action().clone();
+                                        handle_button_action(&mut uinput, &action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
+                                    }
+                                    let key = if steps < 0 { left } else { right };
+                                    for _ in 0..steps.abs() {
+                                        send_key(&mut uinput, key, 1);
+                                        send_key(&mut uinput, key, 0);
+                                    }
+                                    navigation_state.update_interaction_time();
+                                    continue;
+                                }
+                            }
+
                             let hit = layers[active_layer]
-                                .hit(width, height, x, y, Some(btn))
+                                .hit(width, height, cfg.scale, x, y, Some(btn))
                                 .is_some();
                             
-                            // Check if this is a keyboard backlight button
-                            let button_action = &layers[layer].buttons[btn].1.action;
-                            let is_kbd_backlight_button = cfg.keyboard_brightness_enabled &&
+                            // Sliding off a long press button lets it go without doing anything
+                            if !hit {
+                                layers[layer].buttons[btn].1.pressed_at = None;
+                            }
+
+                            // Check if this is a keyboard backlight, countdown or long press button
+                            let button_action = &layers[layer].buttons[btn].1.current_action();
+                            let handled_directly = layers[layer].buttons[btn].1.countdown.is_some()
+                                || layers[layer].buttons[btn].1.long_press.is_some()
+                                || matches!(button_action, ButtonAction::Torch)
+                                || cfg.keyboard_brightness_enabled &&
                                 matches!(button_action, ButtonAction::Key(Key::IllumUp) | ButtonAction::Key(Key::IllumDown));
                             
-                            if !is_kbd_backlight_button {
+                            if !handled_directly {
                                 // Extract the button action to avoid borrowing conflict
-                                let action = layers[layer].buttons[btn].1.action.clone();
+                                let action = layers[layer].buttons[btn].1.current_action().clone();
                                 let old_active = layers[layer].buttons[btn].1.active;
                                 if old_active != hit {
                                     layers[layer].buttons[btn].1.active = hit;
@@ -1919,7 +4923,7 @@ fn real_main(drm: &mut DrmBackend) {
                                     handle_button_action(&mut uinput, &action, &cfg, hit, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
                                 }
                             } else {
-                                // Handle visual feedback for keyboard backlight buttons (without key event)
+                                // Handle visual feedback for buttons handled directly (without key event)
                                 layers[layer].buttons[btn].1.active = hit;
                                 layers[layer].buttons[btn].1.changed = true;
                             }
@@ -1928,19 +4932,62 @@ fn real_main(drm: &mut DrmBackend) {
                             navigation_state.update_interaction_time();
                         }
                         TouchEvent::Up(up) => {
+                            let scrubbed = gestures.scrubbing(up.seat_slot()) || gestures.pinched(up.seat_slot());
+                            gestures.touch_up(up.seat_slot());
                             if !touches.contains_key(&up.seat_slot()) {
                                 continue;
                             }
                             let (layer, btn) = *touches.get(&up.seat_slot()).unwrap();
+                            if cfg.touch_explore {
+                                // Lifting the finger off an announced button arms the double tap
+                                if explore_activated.take() != Some(up.seat_slot()) && layers[layer].buttons[btn].1.active {
+                                    explored_tap = Some((layer, btn, std::time::Instant::now()));
+                                }
+                                layers[layer].buttons[btn].1.active = false;
+                                layers[layer].buttons[btn].1.changed = true;
+                                navigation_state.update_interaction_time();
+                                continue;
+                            }
+                            if scrubbed {
+                                // The tap was already let go of when scrubbing started
+                                layers[layer].buttons[btn].1.active = false;
+                                layers[layer].buttons[btn].1.changed = true;
+                                navigation_state.update_interaction_time();
+                                continue;
+                            }
+                            if layers[layer].buttons[btn].1.hold_for.is_some() {
+                                // Stays lit until the held key is let go of
+                                navigation_state.update_interaction_time();
+                                continue;
+                            }
+                            if let ButtonImage::Slider(_) = layers[layer].buttons[btn].1.image {
+                                // Already set while the finger moved
+                                navigation_state.update_interaction_time();
+                                continue;
+                            }
                             
-                            // Check if this was a keyboard backlight button
-                            let button_action = &layers[layer].buttons[btn].1.action;
-                            let is_kbd_backlight_button = cfg.keyboard_brightness_enabled &&
+                            // Let go of a long press button before its long press fired, a plain tap
+                            if layers[layer].buttons[btn].1.pressed_at.take().is_some() {
+                                let action = layers[layer].buttons[btn].1.current_action().clone();
+                                layers[layer].buttons[btn].1.active = false;
+                                layers[layer].buttons[btn].1.changed = true;
+                                handle_button_action(&mut uinput, &action, &cfg, true, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
+                                handle_button_action(&mut uinput, &action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
+                                navigation_state.update_interaction_time();
+                                continue;
+                            }
+
+                            // Check if this was a keyboard backlight, countdown or long press button
+                            let button_action = &layers[layer].buttons[btn].1.current_action();
+                            let handled_directly = layers[layer].buttons[btn].1.countdown.is_some()
+                                || layers[layer].buttons[btn].1.long_press.is_some()
+                                || matches!(button_action, ButtonAction::Torch)
+                                || cfg.keyboard_brightness_enabled &&
                                 matches!(button_action, ButtonAction::Key(Key::IllumUp) | ButtonAction::Key(Key::IllumDown));
 
-                            if !is_kbd_backlight_button {
+                            if !handled_directly {
                                 // Extract the button action to avoid borrowing conflict
-                                let action = layers[layer].buttons[btn].1.action.clone();
+                                let action = layers[layer].buttons[btn].1.current_action().clone();
                                 let old_active = layers[layer].buttons[btn].1.active;
                                 if old_active != false {
                                     layers[layer].buttons[btn].1.active = false;
@@ -1948,7 +4995,7 @@ fn real_main(drm: &mut DrmBackend) {
                                     handle_button_action(&mut uinput, &action, &cfg, false, &mut navigation_state, &mut layers, &mut active_layer, &mut needs_complete_redraw, &original_layers, &mut touches, &mut pending_actions, Some(btn));
                                 }
                             } else {
-                                // Reset visual state for keyboard backlight buttons
+                                // Reset visual state for buttons handled directly
                                 layers[layer].buttons[btn].1.active = false;
                                 layers[layer].buttons[btn].1.changed = true;
                             }
@@ -1966,10 +5013,20 @@ fn real_main(drm: &mut DrmBackend) {
     }
 }
 
+// Update every active window driven button with the current window state, regardless of cache state
+fn refresh_active_window_buttons(layer: &mut FunctionLayer, window_info: &hyprland::ActiveWindowInfo) {
+    for button in &mut layer.buttons {
+        if button.1.source != ButtonSource::Static {
+            update_hyprland_button_content(button, window_info);
+        }
+    }
+}
+
 fn update_hyprland_button_content(button: &mut (usize, Button), window_info: &hyprland::ActiveWindowInfo) {
-    // Check if this is an icon-only button (plugin-hyprland-icon) or text button (plugin-hyprland)
-    match &button.1.image {
-        ButtonImage::Svg(_) | ButtonImage::Bitmap(_) => {
+    match button.1.source {
+        ButtonSource::Static => {}
+        ButtonSource::Template => button.1.changed = true,
+        ButtonSource::ActiveWindowIcon => {
             // This is an Icon plugin-hyprland-icon button - keep it as icon only
             let app_icon_name = window_info.get_app_icon_name();
 
@@ -2006,7 +5063,7 @@ fn update_hyprland_button_content(button: &mut (usize, Button), window_info: &hy
             button.1.image = new_icon;
             button.1.changed = true;
         }
-        _ => {
+        ButtonSource::ActiveWindowTitle => {
             // This is a Text plugin-hyprland button - show with app icon and text using cache
             let app_icon_name = window_info.get_app_icon_name();
             let window_title = window_info.get_text_by_button_title("title");
@@ -2025,7 +5082,7 @@ fn update_hyprland_button_content(button: &mut (usize, Button), window_info: &hy
                         return;
                     },
                 } {
-                    if let ButtonImage::Svg(icon_handle) = icon {
+                    if let Some(icon_handle) = icon.into_icon() {
                         // Show with app icon and text - maintain consistent spacing format
                         button.1.image = ButtonImage::TextWithIcon(
                             format!(" {}", window_title),
@@ -2057,7 +5114,7 @@ fn update_hyprland_button_content(button: &mut (usize, Button), window_info: &hy
                             return;
                         },
                     } {
-                        if let ButtonImage::Svg(fallback_handle) = fallback_icon {
+                        if let Some(fallback_handle) = fallback_icon.into_icon() {
                             // Use fallback icon with text
                             button.1.image = ButtonImage::TextWithIcon(
                                 format!(" {}", window_title),
@@ -2083,3 +5140,39 @@ fn update_hyprland_button_content(button: &mut (usize, Button), window_info: &hy
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Size of the bar itself, whichever way the panel is mounted
+    const WIDTH: i32 = 2008;
+    const HEIGHT: i32 = 60;
+
+    #[test]
+    fn test_bar_point_to_fb() {
+        assert_eq!(bar_point_to_fb(0, WIDTH, HEIGHT, 10.0, 5.0), (10.0, 5.0));
+        assert_eq!(bar_point_to_fb(90, WIDTH, HEIGHT, 10.0, 5.0), (55.0, 10.0));
+        assert_eq!(bar_point_to_fb(180, WIDTH, HEIGHT, 10.0, 5.0), (1998.0, 55.0));
+        assert_eq!(bar_point_to_fb(270, WIDTH, HEIGHT, 10.0, 5.0), (5.0, 1998.0));
+    }
+
+    #[test]
+    fn test_touch_point_to_bar() {
+        assert_eq!(touch_point_to_bar(0, WIDTH, HEIGHT, 10.0, 5.0), (10.0, 5.0));
+        assert_eq!(touch_point_to_bar(90, WIDTH, HEIGHT, 10.0, 5.0), (10.0, 5.0));
+        assert_eq!(touch_point_to_bar(180, WIDTH, HEIGHT, 10.0, 5.0), (1998.0, 55.0));
+        assert_eq!(touch_point_to_bar(270, WIDTH, HEIGHT, 10.0, 5.0), (1998.0, 55.0));
+    }
+
+    #[test]
+    fn test_bar_rect_to_clip() {
+        let clip = |rotation| bar_rect_to_clip(rotation, WIDTH, HEIGHT, 10.0, 5.0, 110.0, 25.0);
+        assert_eq!(clip(0), ClipRect::new(10, 5, 110, 25));
+        assert_eq!(clip(90), ClipRect::new(35, 10, 55, 110));
+        assert_eq!(clip(180), ClipRect::new(1898, 35, 1998, 55));
+        assert_eq!(clip(270), ClipRect::new(5, 1898, 25, 1998));
+        // Rects hanging over the edge of the bar are cut off there
+        assert_eq!(bar_rect_to_clip(0, WIDTH, HEIGHT, -5.0, 5.0, 110.0, 25.0), ClipRect::new(0, 5, 110, 25));
+    }
+}
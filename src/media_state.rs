@@ -0,0 +1,107 @@
+use crate::privileged::{Command, Stdio};
+use crate::user_cache;
+use nix::sys::eventfd::{EfdFlags, EventFd};
+use std::io::{BufRead, BufReader};
+use std::os::fd::{AsFd, BorrowedFd};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// How long to wait before following the player again after playerctl exited
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum PlaybackStatus {
+    #[default]
+    NoPlayer,
+    Playing,
+    Paused,
+    Stopped,
+}
+
+static STATUS: std::sync::LazyLock<Arc<Mutex<PlaybackStatus>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(PlaybackStatus::NoPlayer)));
+
+// Signalled whenever the status changes so the main loop can redraw right away
+static WAKEUP: std::sync::LazyLock<EventFd> = std::sync::LazyLock::new(|| {
+    EventFd::from_value_and_flags(0, EfdFlags::EFD_NONBLOCK | EfdFlags::EFD_CLOEXEC).unwrap()
+});
+
+// Only started once a button showing the playback status is created
+static MEDIA_MONITOR: std::sync::LazyLock<thread::JoinHandle<()>> =
+    std::sync::LazyLock::new(|| thread::spawn(monitor_loop));
+
+fn set_status(status: PlaybackStatus) {
+    if let Ok(mut current) = STATUS.lock() {
+        if *current != status {
+            *current = status;
+            let _ = WAKEUP.write(1);
+        }
+    }
+}
+
+fn monitor_loop() {
+    let mut reported_failure = false;
+    loop {
+        if let Some(env) = user_cache::get_cached_user_environment() {
+            match follow_player(&env) {
+                Ok(()) => reported_failure = false,
+                // Most likely playerctl isn't installed, don't repeat that every retry
+                Err(e) if !reported_failure => {
+                    eprintln!("Failed to follow the media player status with playerctl: {}", e);
+                    reported_failure = true;
+                }
+                Err(_) => {}
+            }
+        }
+        set_status(PlaybackStatus::NoPlayer);
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+// playerctl prints the status of the current player on every change, and an
+// empty line once no player is left. It exits when the session bus goes away.
+fn follow_player(env: &user_cache::CachedUserEnvironment) -> std::io::Result<()> {
+    let mut child = Command::session_user(&env.username, "env")
+        .arg(format!("XDG_RUNTIME_DIR={}", env.runtime_dir))
+        .arg(format!("DBUS_SESSION_BUS_ADDRESS=unix:path={}/bus", env.runtime_dir))
+        .args(["playerctl", "--follow", "status"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines() {
+            let status = match line?.trim() {
+                "Playing" => PlaybackStatus::Playing,
+                "Paused" => PlaybackStatus::Paused,
+                "Stopped" => PlaybackStatus::Stopped,
+                _ => PlaybackStatus::NoPlayer,
+            };
+            set_status(status);
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
+
+// Public API
+
+pub fn initialize_media_monitor() {
+    std::sync::LazyLock::force(&MEDIA_MONITOR);
+}
+
+pub fn playback_status() -> PlaybackStatus {
+    STATUS.lock().map(|status| *status).unwrap_or_default()
+}
+
+pub fn wakeup_fd() -> BorrowedFd<'static> {
+    WAKEUP.as_fd()
+}
+
+pub fn wake() {
+    let _ = WAKEUP.write(1);
+}
+
+pub fn check_and_reset_updated() -> bool {
+    WAKEUP.read().is_ok()
+}
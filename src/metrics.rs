@@ -0,0 +1,135 @@
+use crate::hyprland;
+use std::fmt::Write as _;
+use std::fs::{self, Permissions};
+use std::io::Write;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::sync::{Arc, Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct Counters {
+    frames_rendered: u64,
+    draw_time: Duration,
+    touch_events: u64,
+    command_executions: u64,
+    hyprland_connects: u64,
+}
+
+static COUNTERS: std::sync::LazyLock<Arc<Mutex<Counters>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(Counters::default())));
+
+static STARTED: std::sync::LazyLock<Instant> = std::sync::LazyLock::new(Instant::now);
+static SERVER: Once = Once::new();
+
+fn update(f: impl FnOnce(&mut Counters)) {
+    if let Ok(mut counters) = COUNTERS.lock() {
+        f(&mut counters);
+    }
+}
+
+// Prometheus text exposition format, one sample per metric
+fn render() -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(out, "{} {}", name, value);
+    };
+    let Ok(counters) = COUNTERS.lock() else {
+        return out;
+    };
+    let average_ms = if counters.frames_rendered > 0 {
+        counters.draw_time.as_secs_f64() * 1000.0 / counters.frames_rendered as f64
+    } else {
+        0.0
+    };
+    metric("tiny_dfr_uptime_seconds", "gauge", "Seconds since the daemon started", STARTED.elapsed().as_secs().to_string());
+    metric("tiny_dfr_frames_rendered_total", "counter", "Frames drawn to the Touch Bar", counters.frames_rendered.to_string());
+    metric("tiny_dfr_draw_seconds_total", "counter", "Time spent drawing frames", format!("{:.6}", counters.draw_time.as_secs_f64()));
+    metric("tiny_dfr_draw_milliseconds_average", "gauge", "Average time to draw a frame", format!("{:.3}", average_ms));
+    metric("tiny_dfr_touch_events_total", "counter", "Touch events received from the digitizer", counters.touch_events.to_string());
+    metric("tiny_dfr_command_executions_total", "counter", "Commands started from buttons", counters.command_executions.to_string());
+    metric(
+        "tiny_dfr_hyprland_reconnects_total",
+        "counter",
+        "Times the Hyprland event socket was connected again after the first time",
+        counters.hyprland_connects.saturating_sub(1).to_string(),
+    );
+    metric(
+        "tiny_dfr_hyprland_listener_restarts_total",
+        "counter",
+        "Times the Hyprland event listener was restarted for falling out of sync",
+        hyprland::listener_restarts().to_string(),
+    );
+    out
+}
+
+fn serve(listener: UnixListener) {
+    for stream in listener.incoming() {
+        match stream {
+            // Every connection gets the current values and is closed, e.g. `socat - UNIX-CONNECT:<path>`
+            Ok(mut stream) => {
+                let _ = stream.write_all(render().as_bytes());
+            }
+            Err(e) => eprintln!("Failed to accept metrics connection: {}", e),
+        }
+    }
+}
+
+fn bind(path: &str) {
+    std::sync::LazyLock::force(&STARTED);
+    if let Some(parent) = Path::new(path).parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    // Left behind by a previous run, anything else at the path is not ours to delete
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            let _ = fs::remove_file(path);
+        }
+        Ok(_) => {
+            eprintln!("Not serving metrics, {} exists and is not a socket", path);
+            return;
+        }
+        Err(_) => {}
+    }
+    let listener = match UnixListener::bind(path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to create metrics socket {}: {}", path, e);
+            return;
+        }
+    };
+    // Only counters are exposed, let any local user read them
+    let _ = fs::set_permissions(path, Permissions::from_mode(0o666));
+    println!("Serving metrics on {}", path);
+    thread::spawn(move || serve(listener));
+}
+
+// Public API
+
+// The socket is kept when real_main runs again, a changed MetricsSocket takes a restart
+pub fn start_metrics_server(path: &str) {
+    SERVER.call_once(|| bind(path));
+}
+
+pub fn frame_rendered(draw_time: Duration) {
+    update(|counters| {
+        counters.frames_rendered += 1;
+        counters.draw_time += draw_time;
+    });
+}
+
+pub fn touch_event() {
+    update(|counters| counters.touch_events += 1);
+}
+
+pub fn command_executed() {
+    update(|counters| counters.command_executions += 1);
+}
+
+pub fn hyprland_connected() {
+    update(|counters| counters.hyprland_connects += 1);
+}
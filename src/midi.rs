@@ -0,0 +1,72 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+// What a MIDI button sends, on its press and its release
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MidiMessage {
+    Note(u8),    // note on at full velocity, note off on release
+    Control(u8), // controller to 127, back to 0 on release
+}
+
+impl MidiMessage {
+    // "MidiNote_60" or "MidiCC_20", numbers above 127 aren't MIDI
+    pub fn parse(action: &str) -> Option<MidiMessage> {
+        if let Some(note) = action.strip_prefix("MidiNote_") {
+            return note.parse().ok().filter(|note| *note < 128).map(MidiMessage::Note);
+        }
+        let control = action.strip_prefix("MidiCC_")?;
+        control.parse().ok().filter(|control| *control < 128).map(MidiMessage::Control)
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            MidiMessage::Note(note) => format!("MidiNote_{}", note),
+            MidiMessage::Control(control) => format!("MidiCC_{}", control),
+        }
+    }
+
+    fn bytes(&self, channel: u8, pressed: bool) -> [u8; 3] {
+        match (self, pressed) {
+            (MidiMessage::Note(note), true) => [0x90 | channel, *note, 127],
+            (MidiMessage::Note(note), false) => [0x80 | channel, *note, 0],
+            (MidiMessage::Control(control), true) => [0xB0 | channel, *control, 127],
+            (MidiMessage::Control(control), false) => [0xB0 | channel, *control, 0],
+        }
+    }
+}
+
+// The raw MIDI port written to and its path, reopened when the configured one changes
+type Port = Option<(String, File)>;
+static PORT: std::sync::LazyLock<Arc<Mutex<Port>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
+
+fn write(device: &str, bytes: &[u8]) -> std::io::Result<()> {
+    let Ok(mut port) = PORT.lock() else {
+        return Ok(());
+    };
+    if port.as_ref().is_none_or(|(path, _)| path != device) {
+        let file = OpenOptions::new().write(true).open(device)?;
+        *port = Some((device.to_string(), file));
+    }
+    let result = port.as_mut().map_or(Ok(()), |(_, file)| file.write_all(bytes));
+    if result.is_err() {
+        // Try opening it again next time, the module may have been reloaded
+        *port = None;
+    }
+    result
+}
+
+// Public API
+
+// `channel` counts from 1 like MIDI software does
+pub fn send(device: Option<&str>, channel: u8, message: MidiMessage, pressed: bool) {
+    let Some(device) = device else {
+        eprintln!("{} needs MidiDevice to be set", message.label());
+        return;
+    };
+    let bytes = message.bytes(channel.clamp(1, 16) - 1, pressed);
+    if let Err(e) = write(device, &bytes) {
+        eprintln!("Failed to send {} to {}: {}", message.label(), device, e);
+    }
+}
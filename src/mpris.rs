@@ -0,0 +1,90 @@
+use crate::privileged::{Command, Stdio};
+use crate::user_cache;
+use std::io::{BufRead, BufReader};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// How long to wait before following the player again after playerctl exited
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+// The track of the player playerctl considers active, MPRIS being what players offer it over D-Bus
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NowPlaying {
+    pub playing: bool,
+    pub artist: String,
+    pub title: String,
+}
+
+static NOW_PLAYING: std::sync::LazyLock<Arc<Mutex<Option<NowPlaying>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
+
+// Only started once a now playing button is created
+static MPRIS_MONITOR: std::sync::LazyLock<thread::JoinHandle<()>> =
+    std::sync::LazyLock::new(|| thread::spawn(monitor_loop));
+
+fn set_now_playing(now_playing: Option<NowPlaying>) {
+    if let Ok(mut current) = NOW_PLAYING.lock() {
+        if *current != now_playing {
+            *current = now_playing;
+            // Shares the playback status wakeup, the main loop redraws both kinds of buttons
+            crate::media_state::wake();
+        }
+    }
+}
+
+fn monitor_loop() {
+    let mut reported_failure = false;
+    loop {
+        if let Some(env) = user_cache::get_cached_user_environment() {
+            match follow_metadata(&env) {
+                Ok(()) => reported_failure = false,
+                Err(e) if !reported_failure => {
+                    eprintln!("Failed to follow the playing track with playerctl: {}", e);
+                    reported_failure = true;
+                }
+                Err(_) => {}
+            }
+        }
+        set_now_playing(None);
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+// playerctl prints a line on every track or status change, and an empty one once no player is left
+fn follow_metadata(env: &user_cache::CachedUserEnvironment) -> std::io::Result<()> {
+    let mut child = Command::session_user(&env.username, "env")
+        .arg(format!("XDG_RUNTIME_DIR={}", env.runtime_dir))
+        .arg(format!("DBUS_SESSION_BUS_ADDRESS=unix:path={}/bus", env.runtime_dir))
+        .args(["playerctl", "--follow", "metadata", "--format", "{{status}}\t{{artist}}\t{{title}}"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(stdout) = child.stdout.take() {
+        for line in BufReader::new(stdout).lines() {
+            let line = line?;
+            let mut fields = line.splitn(3, '\t');
+            let (Some(status), Some(artist), Some(title)) = (fields.next(), fields.next(), fields.next()) else {
+                set_now_playing(None);
+                continue;
+            };
+            set_now_playing(Some(NowPlaying {
+                playing: status == "Playing",
+                artist: artist.trim().to_string(),
+                title: title.trim().to_string(),
+            }));
+        }
+    }
+    child.wait()?;
+    Ok(())
+}
+
+// Public API
+
+pub fn initialize_mpris_monitor() {
+    std::sync::LazyLock::force(&MPRIS_MONITOR);
+}
+
+pub fn now_playing() -> Option<NowPlaying> {
+    NOW_PLAYING.lock().ok()?.clone()
+}
@@ -0,0 +1,143 @@
+use crate::dbus::{self, Value};
+use crate::media_state;
+use crate::privileged;
+use crate::user_cache;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+// How long to wait before listening again after the session bus went away
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+const MATCH_RULE: &str = "type='method_call',interface='org.freedesktop.Notifications',member='Notify'";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub app_name: String,
+    pub summary: String,
+    pub body: String,
+    // In milliseconds, -1 leaves it to the server and 0 asks for it to stay
+    pub expire_timeout: i32,
+}
+
+// Notifications sent since the main loop last looked, oldest first
+static PENDING: Mutex<Vec<Notification>> = Mutex::new(Vec::new());
+
+// Only started when NotificationBanner is on
+static NOTIFICATION_MONITOR: std::sync::LazyLock<thread::JoinHandle<()>> =
+    std::sync::LazyLock::new(|| thread::spawn(monitor_loop));
+
+// Bodies may carry the little markup the specification allows, the bar shows plain text
+fn strip_markup(text: &str) -> String {
+    let mut plain = String::new();
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => plain.push(c),
+            _ => {}
+        }
+    }
+    plain
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// Notify's arguments are app_name, replaces_id, app_icon, summary, body, actions, hints and
+// expire_timeout, anything else isn't a notification
+fn notification(arguments: &[Value]) -> Option<Notification> {
+    let string = |i: usize| match arguments.get(i)? {
+        Value::Str(text) => Some(strip_markup(text).trim().to_string()),
+        _ => None,
+    };
+    let Some(Value::U32(expire_timeout)) = arguments.get(7) else {
+        return None;
+    };
+    Some(Notification {
+        app_name: string(0)?,
+        summary: string(3)?,
+        body: string(4)?,
+        expire_timeout: *expire_timeout as i32,
+    })
+}
+
+fn push(notification: Notification) {
+    if let Ok(mut pending) = PENDING.lock() {
+        pending.push(notification);
+    }
+    media_state::wake();
+}
+
+// The desktop's own notification daemon owns the name, a monitor sees the calls to it
+fn follow_notifications(env: &user_cache::CachedUserEnvironment) -> std::io::Result<()> {
+    let (bus, uid) = privileged::connect_session_bus(&env.username)?;
+    dbus::monitor_calls(bus, uid, MATCH_RULE, |member, arguments| match notification(arguments) {
+        Some(notification) if member == "Notify" => push(notification),
+        _ => {}
+    })
+}
+
+fn monitor_loop() {
+    let mut reported_failure = false;
+    loop {
+        if let Some(env) = user_cache::get_cached_user_environment() {
+            match follow_notifications(&env) {
+                Ok(()) => reported_failure = false,
+                Err(e) if !reported_failure => {
+                    eprintln!("Failed to follow notifications on the session bus: {}", e);
+                    reported_failure = true;
+                }
+                Err(_) => {}
+            }
+        }
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+// Public API
+
+pub fn initialize_notification_monitor() {
+    std::sync::LazyLock::force(&NOTIFICATION_MONITOR);
+}
+
+pub fn take_notifications() -> Vec<Notification> {
+    PENDING.lock().map(|mut pending| std::mem::take(&mut *pending)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notification() {
+        let arguments = |body: &str, expire_timeout: i32| {
+            vec![
+                Value::Str("notify-send".to_string()),
+                Value::U32(0),
+                Value::Str(String::new()),
+                Value::Str("Build done".to_string()),
+                Value::Str(body.to_string()),
+                Value::Strings(Vec::new()),
+                Value::Skipped,
+                Value::U32(expire_timeout as u32),
+            ]
+        };
+        assert_eq!(
+            notification(&arguments("<b>tiny-dfr</b> built\nin 42s &amp; passed", -1)),
+            Some(Notification {
+                app_name: "notify-send".to_string(),
+                summary: "Build done".to_string(),
+                body: "tiny-dfr built\nin 42s & passed".to_string(),
+                expire_timeout: -1,
+            })
+        );
+        // A body that looks like more arguments is just text
+        let spoofed = notification(&arguments("hi\"\n   array [\n   ]\n   int32 0", 5000)).unwrap();
+        assert_eq!(spoofed.body, "hi\"\n   array [\n   ]\n   int32 0");
+        assert_eq!(spoofed.expire_timeout, 5000);
+        assert_eq!(notification(&arguments("", -1)[..7]), None);
+    }
+}
@@ -0,0 +1,294 @@
+use crate::backlight;
+use crate::config::Config;
+use crate::status;
+use crate::{apply_rotation, bar_rect_to_clip, truncate_to_width};
+use cairo::{Context, ImageSurface};
+use drm::control::ClipRect;
+use input_linux::Key;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const OSD_DURATION: Duration = Duration::from_millis(1500);
+// Give the desktop a moment to act on the key before reading the new level
+const READ_DELAY: Duration = Duration::from_millis(100);
+// How often to look for the reading while it is being taken
+const POLL_INTERVAL_MS: i32 = 50;
+// The battery gauge of the charger overlay fills up over this long
+const CHARGER_ANIMATION: Duration = Duration::from_millis(600);
+const CHARGER_FRAME_MS: i32 = 33;
+
+#[derive(Clone, Copy, PartialEq)]
+enum OsdKind {
+    Volume,
+    Brightness,
+    Charger(bool), // plugged in or not
+}
+
+#[derive(Clone, Copy)]
+struct OsdLevel {
+    kind: OsdKind,
+    level: f64, // 0 to 1
+    muted: bool,
+}
+
+// Latest reading, written by the thread taking it
+static READING: std::sync::LazyLock<Arc<Mutex<Option<OsdLevel>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
+
+pub struct OsdManager {
+    display_bl_path: Option<PathBuf>,
+    shown: Option<(OsdLevel, Instant)>,
+    reading_since: Option<Instant>,
+    // Text shown until it expires, levels coming in meanwhile take over
+    message: Option<(String, Instant, Duration)>,
+}
+
+impl OsdManager {
+    pub fn new() -> OsdManager {
+        OsdManager {
+            display_bl_path: backlight::find_display_backlight().ok(),
+            shown: None,
+            reading_since: None,
+            message: None,
+        }
+    }
+
+    // Called for every key press seen on the main seat, including the bar's own virtual keyboard
+    pub fn key_pressed(&mut self, cfg: &Config, key: u32) {
+        if !cfg.show_osd {
+            return;
+        }
+        let kind = if key == Key::VolumeUp as u32 || key == Key::VolumeDown as u32 || key == Key::Mute as u32 {
+            OsdKind::Volume
+        } else if key == Key::BrightnessUp as u32 || key == Key::BrightnessDown as u32 {
+            OsdKind::Brightness
+        } else {
+            return;
+        };
+        self.reading_since = Some(Instant::now());
+        let display_bl_path = self.display_bl_path.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(READ_DELAY);
+            let reading = match kind {
+                OsdKind::Volume => read_volume(),
+                OsdKind::Brightness => display_bl_path.and_then(read_brightness),
+                // Shown right away by power_changed, nothing to read
+                OsdKind::Charger(_) => None,
+            };
+            if let (Some(reading), Ok(mut latest)) = (reading, READING.lock()) {
+                *latest = Some(reading);
+            }
+        });
+    }
+
+    // Returns whether the OSD appeared, changed or went away, and how long until it should be checked again
+    pub fn update(&mut self) -> (bool, i32) {
+        let mut changed = false;
+        if let Some(reading) = READING.lock().ok().and_then(|mut latest| latest.take()) {
+            self.shown = Some((reading, Instant::now()));
+            self.reading_since = None;
+            changed = true;
+        }
+        // Nothing came back, e.g. no session to ask PipeWire in
+        if self.reading_since.is_some_and(|since| since.elapsed() > OSD_DURATION) {
+            self.reading_since = None;
+        }
+        if self.shown.is_some_and(|(_, since)| since.elapsed() >= OSD_DURATION) {
+            self.shown = None;
+            changed = true;
+        }
+        if self.message.as_ref().is_some_and(|(_, since, duration)| since.elapsed() >= *duration) {
+            self.message = None;
+            changed = true;
+        }
+        let animating = self.shown.is_some_and(|(reading, since)| {
+            matches!(reading.kind, OsdKind::Charger(_)) && since.elapsed() < CHARGER_ANIMATION + Duration::from_millis(CHARGER_FRAME_MS as u64)
+        });
+        changed |= animating;
+        let next_timeout_ms = match (self.reading_since, self.shown) {
+            (Some(_), _) => POLL_INTERVAL_MS,
+            (None, Some(_)) if animating => CHARGER_FRAME_MS,
+            (None, Some((_, since))) => (OSD_DURATION.saturating_sub(since.elapsed())).as_millis() as i32 + 1,
+            (None, None) => i32::MAX,
+        };
+        let message_timeout_ms = self.message.as_ref().map_or(i32::MAX, |(_, since, duration)| {
+            duration.saturating_sub(since.elapsed()).as_millis() as i32 + 1
+        });
+        (changed, next_timeout_ms.min(message_timeout_ms))
+    }
+
+    // Called when the charger is plugged in or out, with the battery charge in percent if there is a battery
+    pub fn power_changed(&mut self, cfg: &Config, plugged: bool, capacity: Option<u32>) {
+        if !cfg.charging_animation {
+            return;
+        }
+        let reading = OsdLevel {
+            kind: OsdKind::Charger(plugged),
+            level: capacity.map_or(1.0, |capacity| capacity as f64 / 100.0),
+            // No battery to show the charge of
+            muted: capacity.is_none(),
+        };
+        self.shown = Some((reading, Instant::now()));
+    }
+
+    // Shows `text` across the bar for `duration`
+    pub fn show_message(&mut self, text: String, duration: Duration) {
+        self.message = Some((text, Instant::now(), duration));
+    }
+
+    pub fn active(&self) -> bool {
+        self.shown.is_some() || self.message.is_some()
+    }
+
+    pub fn draw(&self, cfg: &Config, width: i32, height: i32, surface: &ImageSurface) -> Vec<ClipRect> {
+        let c = Context::new(surface).unwrap();
+        apply_rotation(&c, cfg.rotation, width, height);
+        c.set_source_rgb(0.0, 0.0, 0.0);
+        c.paint().unwrap();
+        let Some((reading, _)) = self.shown else {
+            if let Some((text, _, _)) = &self.message {
+                draw_message(&c, cfg, width, height, text);
+            }
+            return vec![bar_rect_to_clip(cfg.rotation, width, height, 0.0, 0.0, width as f64, height as f64)];
+        };
+
+        let scale = cfg.scale;
+        let padding = 24.0 * scale;
+        let label = match reading.kind {
+            OsdKind::Volume if reading.muted => "Muted",
+            OsdKind::Volume => "Volume",
+            OsdKind::Brightness => "Brightness",
+            OsdKind::Charger(plugged) => {
+                let since = self.shown.map_or(Duration::ZERO, |(_, since)| since.elapsed());
+                draw_charger(&c, cfg, width, height, reading, plugged, since);
+                return vec![bar_rect_to_clip(cfg.rotation, width, height, 0.0, 0.0, width as f64, height as f64)];
+            }
+        };
+        let percent = format!("{}%", (reading.level * 100.0).round() as u32);
+        c.set_font_face(&cfg.font_face);
+        c.set_font_size(28.0 * scale);
+        c.set_source_rgb(1.0, 1.0, 1.0);
+        let label_extents = c.text_extents(label).unwrap();
+        let percent_extents = c.text_extents("100%").unwrap();
+        let baseline = (height as f64 / 2.0 + label_extents.height() / 2.0).round();
+        c.move_to(padding, baseline);
+        c.show_text(label).unwrap();
+        c.move_to(width as f64 - padding - percent_extents.width(), baseline);
+        c.show_text(&percent).unwrap();
+
+        // Level track between the two labels
+        let left = padding * 2.0 + label_extents.width().max(c.text_extents("Brightness").unwrap().width());
+        let right = width as f64 - padding * 2.0 - percent_extents.width();
+        let track_height = 12.0 * scale;
+        let top = (height as f64 - track_height) / 2.0;
+        c.set_source_rgb(0.2, 0.2, 0.2);
+        c.rectangle(left, top, right - left, track_height);
+        c.fill().unwrap();
+        if reading.muted {
+            c.set_source_rgb(0.5, 0.5, 0.5);
+        } else {
+            c.set_source_rgb(1.0, 1.0, 1.0);
+        }
+        c.rectangle(left, top, (right - left) * reading.level.clamp(0.0, 1.0), track_height);
+        c.fill().unwrap();
+
+        vec![bar_rect_to_clip(cfg.rotation, width, height, 0.0, 0.0, width as f64, height as f64)]
+    }
+}
+
+// Centered, long text loses its end
+fn draw_message(c: &Context, cfg: &Config, width: i32, height: i32, text: &str) {
+    let padding = 24.0 * cfg.scale;
+    c.set_font_face(&cfg.font_face);
+    c.set_font_size(28.0 * cfg.scale);
+    c.set_source_rgb(1.0, 1.0, 1.0);
+    let text = truncate_to_width(c, text, width as f64 - padding * 2.0);
+    let extents = c.text_extents(&text).unwrap();
+    c.move_to(
+        ((width as f64 - extents.width()) / 2.0).round(),
+        (height as f64 / 2.0 + extents.height() / 2.0).round(),
+    );
+    c.show_text(&text).unwrap();
+}
+
+// A battery gauge filling up to the charge, followed by "Charging · 64%"
+fn draw_charger(c: &Context, cfg: &Config, width: i32, height: i32, reading: OsdLevel, plugged: bool, since: Duration) {
+    let scale = cfg.scale;
+    let state = if plugged { "Charging" } else { "On battery" };
+    let label = if reading.muted {
+        state.to_string()
+    } else {
+        format!("{} · {}%", state, (reading.level * 100.0).round() as u32)
+    };
+    c.set_font_face(&cfg.font_face);
+    c.set_font_size(28.0 * scale);
+    let extents = c.text_extents(&label).unwrap();
+    let (gauge_width, gauge_height, gap) = (64.0 * scale, 28.0 * scale, 20.0 * scale);
+    let left = ((width as f64 - gauge_width - gap - extents.width()) / 2.0).round();
+    let top = ((height as f64 - gauge_height) / 2.0).round();
+
+    c.set_source_rgb(1.0, 1.0, 1.0);
+    c.set_line_width(2.0 * scale);
+    c.rectangle(left, top, gauge_width, gauge_height);
+    c.stroke().unwrap();
+    c.rectangle(left + gauge_width, top + gauge_height * 0.3, 4.0 * scale, gauge_height * 0.4);
+    c.fill().unwrap();
+    // Eases out towards the charge
+    let progress = (since.as_secs_f64() / CHARGER_ANIMATION.as_secs_f64()).min(1.0);
+    let progress = 1.0 - (1.0 - progress).powi(3);
+    let inset = 4.0 * scale;
+    if plugged {
+        c.set_source_rgb(0.3, 0.85, 0.3);
+    }
+    c.rectangle(
+        left + inset,
+        top + inset,
+        (gauge_width - inset * 2.0) * reading.level.clamp(0.0, 1.0) * progress,
+        gauge_height - inset * 2.0,
+    );
+    c.fill().unwrap();
+    if plugged {
+        // Bolt across the gauge
+        let (x, y, w, h) = (left + gauge_width / 2.0, top - 4.0 * scale, 14.0 * scale, gauge_height + 8.0 * scale);
+        c.move_to(x + w * 0.2, y);
+        c.line_to(x - w * 0.5, y + h * 0.55);
+        c.line_to(x, y + h * 0.55);
+        c.line_to(x - w * 0.2, y + h);
+        c.line_to(x + w * 0.5, y + h * 0.45);
+        c.line_to(x, y + h * 0.45);
+        c.close_path();
+        c.set_source_rgb(1.0, 1.0, 1.0);
+        c.fill_preserve().unwrap();
+        c.set_source_rgb(0.0, 0.0, 0.0);
+        c.set_line_width(1.5 * scale);
+        c.stroke().unwrap();
+    }
+
+    c.set_source_rgb(1.0, 1.0, 1.0);
+    c.move_to(left + gauge_width + gap, (height as f64 / 2.0 + extents.height() / 2.0).round());
+    c.show_text(&label).unwrap();
+}
+
+fn read_volume() -> Option<OsdLevel> {
+    let (level, muted) = status::read_volume()?;
+    Some(OsdLevel {
+        kind: OsdKind::Volume,
+        level,
+        muted,
+    })
+}
+
+fn read_brightness(path: PathBuf) -> Option<OsdLevel> {
+    let read = |attr: &str| -> Option<f64> { fs::read_to_string(path.join(attr)).ok()?.trim().parse().ok() };
+    let max = read("max_brightness")?;
+    if max <= 0.0 {
+        return None;
+    }
+    Some(OsdLevel {
+        kind: OsdKind::Brightness,
+        level: read("brightness")? / max,
+        muted: false,
+    })
+}
@@ -0,0 +1,72 @@
+use crate::config::Config;
+use crate::notifications::Notification;
+use crate::{apply_rotation, bar_rect_to_clip, truncate_to_width};
+use cairo::{Context, ImageSurface};
+use drm::control::ClipRect;
+use std::time::{Duration, Instant};
+
+// Content that takes over the whole strip for a while, the layer underneath comes back
+// once it expires or is tapped away
+pub struct OverlayLayer {
+    title: String,
+    text: String,
+    shown_at: Instant,
+    duration: Duration,
+}
+
+impl OverlayLayer {
+    // Stays for NotificationSeconds, or less if the sender asks for a shorter time
+    pub fn notification(cfg: &Config, notification: Notification) -> OverlayLayer {
+        let mut duration = Duration::from_secs(cfg.notification_seconds as u64);
+        if notification.expire_timeout > 0 {
+            duration = duration.min(Duration::from_millis(notification.expire_timeout as u64));
+        }
+        let title = if notification.summary.is_empty() { notification.app_name } else { notification.summary };
+        OverlayLayer {
+            title,
+            // One line is all there's room for
+            text: notification.body.split_whitespace().collect::<Vec<_>>().join(" "),
+            shown_at: Instant::now(),
+            duration,
+        }
+    }
+
+    pub fn expired(&self) -> bool {
+        self.shown_at.elapsed() >= self.duration
+    }
+
+    // How long until it should be checked again
+    pub fn timeout_ms(&self) -> i32 {
+        self.duration.saturating_sub(self.shown_at.elapsed()).as_millis() as i32 + 1
+    }
+
+    pub fn draw(&self, cfg: &Config, width: i32, height: i32, surface: &ImageSurface) -> Vec<ClipRect> {
+        let c = Context::new(surface).unwrap();
+        apply_rotation(&c, cfg.rotation, width, height);
+        c.set_source_rgb(0.0, 0.0, 0.0);
+        c.paint().unwrap();
+        c.set_font_face(&cfg.font_face);
+        c.set_source_rgb(1.0, 1.0, 1.0);
+        let padding = 24.0 * cfg.scale;
+        let available = width as f64 - padding * 2.0;
+        if self.text.is_empty() {
+            c.set_font_size(28.0 * cfg.scale);
+            let title = truncate_to_width(&c, &self.title, available);
+            let extents = c.text_extents(&title).unwrap();
+            c.move_to(padding, (height as f64 / 2.0 + extents.height() / 2.0).round());
+            c.show_text(&title).unwrap();
+        } else {
+            // Summary above the body, both from the left edge
+            c.set_font_size(22.0 * cfg.scale);
+            let title = truncate_to_width(&c, &self.title, available);
+            c.move_to(padding, (height as f64 * 0.45).round());
+            c.show_text(&title).unwrap();
+            c.set_font_size(18.0 * cfg.scale);
+            c.set_source_rgb(0.7, 0.7, 0.7);
+            let text = truncate_to_width(&c, &self.text, available);
+            c.move_to(padding, (height as f64 * 0.85).round());
+            c.show_text(&text).unwrap();
+        }
+        vec![bar_rect_to_clip(cfg.rotation, width, height, 0.0, 0.0, width as f64, height as f64)]
+    }
+}
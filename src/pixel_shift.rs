@@ -1,21 +1,20 @@
-use crate::TIMEOUT_MS;
+use crate::config::{Config, PixelShiftPattern};
 use rand::Rng;
 use std::time::Instant;
 
-const INTERVAL_MS: i32 = TIMEOUT_MS; // should be a multiple of TIMEOUT_MS
-const PROLONGED_INTERVAL_MS: i32 = TIMEOUT_MS * 5; // should be a multiple of TIMEOUT_MS and more than INTERVAL_MS
-const ANIMATION_INTERVAL_MS: i32 = 200; // should be less than TIMEOUT_MS
+const ANIMATION_INTERVAL_MS: i32 = 200; // should be less than the shift interval
 const ANIMATION_DURATION_MS: i32 = 4000; // should be a multiple of ANIMATION_INTERVAL_MS
+const PROLONGED_INTERVAL_FACTOR: i32 = 5; // how much longer to wait at either end of a sweep
 
-// This is the total range on the x-axis that pixels will shift by over time, ie. they will shift by
-// PIXEL_SHIFT_WIDTH_PX / 2 to the right and to the left.
+// The defaults for the total range on the x-axis that pixels will shift by over time, ie. they
+// will shift by half of it to the right and to the left.
 // To make sure that no pixel ends up being always on, the minimum value to be safe here is the
 // size of the largest continuous colored line in the x-direction. The higher this value, the less
 // strain is put on the panel.
-pub const PIXEL_SHIFT_WIDTH_PX: u64 = 22; // should be divisible by 2
-                                          // in y direction we can't really shift by a lot since icons still need to appear centered,
-                                          // 2 pixels in each direction seems to be the maximum before it gets really visible.
-const PIXEL_SHIFT_HEIGHT_PX: u64 = 4; // should be divisible by 2
+pub const DEFAULT_PIXEL_SHIFT_WIDTH_PX: u64 = 22; // should be divisible by 2
+// in y direction we can't really shift by a lot since icons still need to appear centered,
+// 2 pixels in each direction seems to be the maximum before it gets really visible.
+pub const DEFAULT_PIXEL_SHIFT_HEIGHT_PX: u64 = 4; // should be divisible by 2
 
 #[derive(Clone, Copy)]
 enum ShiftState {
@@ -31,23 +30,24 @@ pub struct PixelShiftManager {
     subpixel_progress: f64,
     direction: i64,
     state: ShiftState,
+    random_offset: (f64, f64),
 }
 
-fn wait_for_state(state: ShiftState) -> i32 {
+fn wait_for_state(state: ShiftState, interval_ms: i32) -> i32 {
     match state {
         ShiftState::ShiftingSubpixel => ANIMATION_INTERVAL_MS,
-        ShiftState::Normal => INTERVAL_MS,
-        ShiftState::WaitingAtEnd => PROLONGED_INTERVAL_MS,
+        ShiftState::Normal => interval_ms,
+        ShiftState::WaitingAtEnd => interval_ms * PROLONGED_INTERVAL_FACTOR,
     }
 }
 
 impl PixelShiftManager {
-    pub fn new() -> PixelShiftManager {
-        let pixel_progress = rand::thread_rng().gen_range(0..PIXEL_SHIFT_WIDTH_PX);
+    pub fn new(cfg: &Config) -> PixelShiftManager {
+        let pixel_progress = rand::thread_rng().gen_range(0..cfg.pixel_shift_width.max(1));
 
         // add some randomness to the relationship between shifting on the x and y axis
         // so that pixel shifting doesn't follow the same 2d pattern every time
-        let y_constant: f64 = rand::thread_rng().gen_range(0..PIXEL_SHIFT_HEIGHT_PX * 2) as f64;
+        let y_constant: f64 = rand::thread_rng().gen_range(0..(cfg.pixel_shift_height * 2).max(1)) as f64;
 
         PixelShiftManager {
             last_active: Instant::now(),
@@ -56,18 +56,36 @@ impl PixelShiftManager {
             pixel_progress,
             subpixel_progress: 0.0,
             direction: 1,
+            random_offset: (0.0, 0.0),
         }
     }
 
-    pub fn update(&mut self) -> (bool, i32) {
+    pub fn update(&mut self, cfg: &Config) -> (bool, i32) {
         let time_now = Instant::now();
         let since_last_pixel_shift = (time_now - self.last_active).as_millis() as i32;
+        let interval_ms = cfg.pixel_shift_interval_ms;
 
-        if since_last_pixel_shift < wait_for_state(self.state) {
+        if cfg.pixel_shift_pattern == PixelShiftPattern::Random {
+            if since_last_pixel_shift < interval_ms {
+                return (false, interval_ms - since_last_pixel_shift);
+            }
+            self.last_active = time_now;
+            let mut rng = rand::thread_rng();
+            self.random_offset = (
+                rng.gen_range(0..=cfg.pixel_shift_width) as f64,
+                rng.gen_range(0..=cfg.pixel_shift_height) as f64,
+            );
+            return (true, interval_ms);
+        }
+
+        if since_last_pixel_shift < wait_for_state(self.state, interval_ms) {
             return (false, i32::MAX);
         }
         self.last_active = time_now;
 
+        // The range may have shrunk after a config reload
+        self.pixel_progress = self.pixel_progress.min(cfg.pixel_shift_width);
+
         match self.state {
             ShiftState::Normal => {
                 self.state = ShiftState::ShiftingSubpixel;
@@ -76,10 +94,10 @@ impl PixelShiftManager {
                 let shift_by = ANIMATION_INTERVAL_MS as f64 / ANIMATION_DURATION_MS as f64;
                 self.subpixel_progress += shift_by * self.direction as f64;
                 if self.subpixel_progress <= -0.99 || self.subpixel_progress >= 0.99 {
-                    self.pixel_progress = (self.direction + self.pixel_progress as i64) as u64;
+                    self.pixel_progress = self.pixel_progress.saturating_add_signed(self.direction);
                     self.state = ShiftState::Normal;
                     self.subpixel_progress = 0.0;
-                    if self.pixel_progress == 0 || self.pixel_progress >= PIXEL_SHIFT_WIDTH_PX {
+                    if self.pixel_progress == 0 || self.pixel_progress >= cfg.pixel_shift_width {
                         self.state = ShiftState::WaitingAtEnd;
                         self.direction = -self.direction;
                     }
@@ -90,18 +108,30 @@ impl PixelShiftManager {
                 self.subpixel_progress = 0.0;
             }
         }
-        (true, wait_for_state(self.state))
+        (true, wait_for_state(self.state, interval_ms))
     }
 
-    pub fn get(&self) -> (f64, f64) {
-        let x_progress = self.pixel_progress as f64 + self.subpixel_progress;
-        let mut y_progress = (x_progress + self.y_constant) % (PIXEL_SHIFT_HEIGHT_PX * 2) as f64;
-        if y_progress > PIXEL_SHIFT_HEIGHT_PX as f64 {
-            y_progress = (PIXEL_SHIFT_HEIGHT_PX * 2) as f64 - y_progress;
-        }
+    pub fn get(&self, cfg: &Config) -> (f64, f64) {
+        let width = cfg.pixel_shift_width;
+        let height = cfg.pixel_shift_height;
+        let (x_progress, y_progress) = match cfg.pixel_shift_pattern {
+            PixelShiftPattern::Random => self.random_offset,
+            PixelShiftPattern::Sweep => {
+                let x_progress = self.pixel_progress as f64 + self.subpixel_progress;
+                if height == 0 {
+                    (x_progress, 0.0)
+                } else {
+                    let mut y_progress = (x_progress + self.y_constant) % (height * 2) as f64;
+                    if y_progress > height as f64 {
+                        y_progress = (height * 2) as f64 - y_progress;
+                    }
+                    (x_progress, y_progress)
+                }
+            }
+        };
         (
-            x_progress - (PIXEL_SHIFT_WIDTH_PX / 2) as f64,
-            y_progress - (PIXEL_SHIFT_HEIGHT_PX / 2) as f64,
+            x_progress - (width / 2) as f64,
+            y_progress - (height / 2) as f64,
         )
     }
 }
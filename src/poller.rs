@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Configuration loads, the one whose layers are being built and the last one that made it to the bar
+static BUILDING: AtomicU64 = AtomicU64::new(0);
+static SHOWN: AtomicU64 = AtomicU64::new(0);
+
+struct Entry<K> {
+    key: K,
+    refresh: Duration,
+    last_run: Option<Instant>,
+    // The configuration load that last watched it
+    load: u64,
+}
+
+// What the widgets of the shown configuration poll, each as often as it asks for. Sources are
+// watched while layers are built, a reload drops those no button watched again.
+pub struct Poller<K> {
+    entries: Mutex<Vec<Entry<K>>>,
+}
+
+impl<K: Clone + PartialEq> Poller<K> {
+    pub const fn new() -> Self {
+        Poller { entries: Mutex::new(Vec::new()) }
+    }
+
+    // The same source on two buttons is polled once, as often as the more eager one asks
+    pub fn watch(&self, key: K, refresh: Duration) {
+        let load = BUILDING.load(Ordering::Relaxed);
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        match entries.iter_mut().find(|entry| entry.key == key) {
+            Some(entry) if entry.load == load => entry.refresh = entry.refresh.min(refresh),
+            Some(entry) => {
+                entry.refresh = refresh;
+                entry.load = load;
+            }
+            None => entries.push(Entry { key, refresh, last_run: None, load }),
+        }
+    }
+
+    pub fn keys(&self) -> Vec<K> {
+        self.entries.lock().map(|entries| entries.iter().map(|entry| entry.key.clone()).collect()).unwrap_or_default()
+    }
+
+    // Sources that are due or that urgent picks, they count as run from now on
+    pub fn due(&self, urgent: impl Fn(&K) -> bool) -> Vec<K> {
+        let shown = SHOWN.load(Ordering::Relaxed);
+        let Ok(mut entries) = self.entries.lock() else {
+            return Vec::new();
+        };
+        entries.retain(|entry| entry.load >= shown);
+        entries
+            .iter_mut()
+            .filter(|entry| urgent(&entry.key) || entry.last_run.is_none_or(|last_run| last_run.elapsed() >= entry.refresh))
+            .map(|entry| {
+                entry.last_run = Some(Instant::now());
+                entry.key.clone()
+            })
+            .collect()
+    }
+}
+
+// Public API
+
+// Called before the layers of a configuration are built
+pub fn begin_load() {
+    BUILDING.fetch_add(1, Ordering::Relaxed);
+}
+
+// Called once they replaced the ones on the bar, a configuration that failed to load drops nothing
+pub fn finish_load() {
+    SHOWN.store(BUILDING.load(Ordering::Relaxed), Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload_drops_unwatched() {
+        let poller = Poller::new();
+        poller.watch("a", Duration::from_secs(60));
+        poller.watch("a", Duration::from_secs(5));
+        poller.watch("b", Duration::from_secs(60));
+        assert_eq!(poller.due(|_| false), vec!["a", "b"]);
+        assert!(poller.due(|_| false).is_empty());
+        assert_eq!(poller.due(|key| *key == "b"), vec!["b"]);
+        assert_eq!(poller.entries.lock().unwrap()[0].refresh, Duration::from_secs(5));
+
+        // A reload that fails keeps everything
+        begin_load();
+        poller.watch("a", Duration::from_secs(60));
+        assert_eq!(poller.due(|_| true), vec!["a", "b"]);
+        // The one after it only keeps what was watched again, at the interval asked for now
+        begin_load();
+        poller.watch("a", Duration::from_secs(60));
+        finish_load();
+        assert_eq!(poller.due(|_| true), vec!["a"]);
+        assert_eq!(poller.entries.lock().unwrap()[0].refresh, Duration::from_secs(60));
+    }
+}
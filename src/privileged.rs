@@ -0,0 +1,939 @@
+use crate::config;
+use crate::user_cache;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Component, Path};
+use std::process::{ExitStatus, Output};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Once privileges are dropped the daemon runs as nobody, with input and video to reach the
+// Touch Bar. What still needs root is asked of a helper started beforehand: it runs commands
+// as the session user and opens a few device files, handing back nothing but the open file.
+const UNPRIVILEGED_USER: &str = "nobody";
+const UNPRIVILEGED_GROUPS: [&str; 2] = ["input", "video"];
+pub const HELPER_ARG: &str = "--privileged-helper";
+// Requests are small, a command line and its environment
+const MAX_REQUEST: usize = 64 * 1024;
+// Files replaced through the helper are configuration, far smaller than this
+const MAX_REPLACEMENT: u64 = 4 * 1024 * 1024;
+
+// How long the helper trusts the session user it found, logind is asked again after that
+const SESSION_USER_TTL: Duration = Duration::from_secs(5);
+
+const RUNUSER: &str = "/usr/bin/runuser";
+// Programs the helper runs as root, anything else is refused
+const INHIBIT: &str = "systemd-inhibit";
+const LOGINCTL: &str = "loginctl";
+const ROOT_SHELL: &str = "sh";
+
+// Control socket to the helper, only there once privileges were dropped
+static HELPER: OnceLock<Mutex<OwnedFd>> = OnceLock::new();
+
+// What to run as the session user, runuser's arguments are put together by whoever runs it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+enum SessionCommand {
+    // A program and its arguments, through runuser -u
+    Exec(Vec<String>),
+    // A command line for the user's login shell, through runuser -l
+    LoginShell(String),
+}
+
+impl SessionCommand {
+    // Nothing from the command ends up where runuser takes its options
+    fn runuser_args(&self, user: &str) -> Vec<String> {
+        match self {
+            SessionCommand::Exec(command) => {
+                let mut args = vec!["-u".to_string(), user.to_string(), "--".to_string()];
+                args.extend(command.iter().cloned());
+                args
+            }
+            SessionCommand::LoginShell(command) => {
+                vec!["-l".to_string(), "-c".to_string(), command.clone(), "--".to_string(), user.to_string()]
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+enum Request {
+    // Comes with the session socket and the child's stdin, stdout and stderr. The helper
+    // runs it as the user of the desktop session, whoever the daemon thinks that is
+    RunAsSessionUser { command: SessionCommand },
+    // Comes with the session socket and the child's stdin, stdout and stderr
+    Spawn { program: String, args: Vec<String>, envs: Vec<(String, String)> },
+    // Comes with the session socket only, the file is sent back over it
+    Open { path: String, write: bool },
+    // Comes with the session socket and a pipe the new contents are read from
+    Replace { path: String },
+    // Comes with the session socket only, a connection to the session user's bus is sent back
+    ConnectSessionBus,
+    // Comes with the session socket only, a connection made as the session user is sent back
+    ConnectSessionSocket { path: String },
+    // Comes with the session socket only, the names in the directory are sent back
+    ReadSessionDir { path: String },
+}
+
+fn send_with_fds(socket: RawFd, data: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    let mut iov = libc::iovec { iov_base: data.as_ptr() as *mut libc::c_void, iov_len: data.len() };
+    let fds_len = std::mem::size_of_val(fds) as u32;
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_len) } as usize];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    if !fds.is_empty() {
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+            std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+        }
+    }
+    if unsafe { libc::sendmsg(socket, &msg, libc::MSG_NOSIGNAL) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// An empty message means the other end went away
+fn recv_with_fds(socket: RawFd) -> io::Result<(Vec<u8>, Vec<OwnedFd>)> {
+    let mut data = vec![0u8; MAX_REQUEST];
+    let mut iov = libc::iovec { iov_base: data.as_mut_ptr() as *mut libc::c_void, iov_len: data.len() };
+    let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(4 * std::mem::size_of::<RawFd>() as u32) } as usize];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = control.len() as _;
+    let read = unsafe { libc::recvmsg(socket, &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if read < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    data.truncate(read as usize);
+    let mut fds = Vec::new();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / std::mem::size_of::<RawFd>();
+                let first = libc::CMSG_DATA(cmsg) as *const RawFd;
+                for i in 0..count {
+                    fds.push(OwnedFd::from_raw_fd(std::ptr::read_unaligned(first.add(i))));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    Ok((data, fds))
+}
+
+fn helper_error(message: &[u8]) -> io::Error {
+    let message = String::from_utf8_lossy(message);
+    io::Error::other(message.strip_prefix("error ").unwrap_or(&message).trim().to_string())
+}
+
+// Sends a request along with a new session socket, the helper answers over the session
+fn request(request: &Request, fds: &[RawFd]) -> io::Result<UnixStream> {
+    let control = HELPER.get().ok_or_else(|| io::Error::other("no privileged helper"))?;
+    let (session, helper_session) = UnixStream::pair()?;
+    let data = serde_json::to_vec(request).map_err(io::Error::other)?;
+    let mut all_fds = vec![helper_session.as_raw_fd()];
+    all_fds.extend_from_slice(fds);
+    let control = control.lock().map_err(|_| io::Error::other("privileged helper lock poisoned"))?;
+    send_with_fds(control.as_raw_fd(), &data, &all_fds)?;
+    Ok(session)
+}
+
+// Public API
+
+// Whether privileges were dropped, what needs root goes through the helper from then on
+pub fn dropped() -> bool {
+    HELPER.get().is_some()
+}
+
+// Starts the helper and becomes nobody, leaving everything as it is when either fails
+pub fn drop_privileges() {
+    if dropped() || unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+    let mut sockets = [0; 2];
+    if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0, sockets.as_mut_ptr()) } < 0 {
+        eprintln!("Failed to create the privileged helper's socket: {}", io::Error::last_os_error());
+        return;
+    }
+    let (control, helper_control) = unsafe { (OwnedFd::from_raw_fd(sockets[0]), OwnedFd::from_raw_fd(sockets[1])) };
+    let helper = std::process::Command::new("/proc/self/exe")
+        .arg(HELPER_ARG)
+        .stdin(helper_control)
+        .spawn();
+    if let Err(e) = helper {
+        eprintln!("Failed to start the privileged helper, keeping root privileges: {}", e);
+        return;
+    }
+    let result = privdrop::PrivDrop::default()
+        .user(UNPRIVILEGED_USER)
+        .group_list(&UNPRIVILEGED_GROUPS)
+        .apply();
+    match result {
+        Ok(()) => {
+            let _ = HELPER.set(Mutex::new(control));
+            println!("Dropped privileges to {}", UNPRIVILEGED_USER);
+        }
+        // Closing the control socket makes the helper exit again
+        Err(e) => eprintln!("Failed to drop privileges, keeping them: {}", e),
+    }
+}
+
+// Opens one of the device files the daemon writes to, through the helper once privileges are dropped
+pub fn open(path: &str, write: bool) -> io::Result<File> {
+    if !dropped() {
+        return OpenOptions::new().read(!write).write(write).open(path);
+    }
+    let session = request(&Request::Open { path: path.to_string(), write }, &[])?;
+    let (reply, mut fds) = recv_with_fds(session.as_raw_fd())?;
+    match fds.pop() {
+        Some(fd) if reply == b"ok" => Ok(File::from(fd)),
+        _ => Err(helper_error(&reply)),
+    }
+}
+
+// Runs f with the user's credentials, given their uid, so it reaches what the user could
+fn as_user<T: Send + 'static>(user: &str, f: impl FnOnce(u32) -> io::Result<T> + Send + 'static) -> io::Result<T> {
+    let (uid, gid) = user_ids(user).ok_or_else(|| io::Error::other(format!("no such user {}", user)))?;
+    // Raw syscalls change the credentials of the calling thread only, libc's wrappers would
+    // change them for every thread. This one ends right after f.
+    thread::spawn(move || {
+        let unchanged = libc::uid_t::MAX;
+        let switched = unsafe {
+            libc::syscall(libc::SYS_setresgid, unchanged, gid, unchanged) == 0
+                && libc::syscall(libc::SYS_setresuid, unchanged, uid, unchanged) == 0
+        };
+        if !switched {
+            return Err(io::Error::last_os_error());
+        }
+        f(uid)
+    })
+    .join()
+    .map_err(|_| io::Error::other("acting as the session user panicked"))?
+}
+
+// The user's session bus only lets the user in, so it's connected to with their credentials
+fn connect_as_user(user: &str) -> io::Result<(UnixStream, u32)> {
+    as_user(user, |uid| Ok((UnixStream::connect(format!("/run/user/{}/bus", uid))?, uid)))
+}
+
+fn list_dir(path: &str) -> io::Result<Vec<String>> {
+    std::fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+        .collect()
+}
+
+// Connects to the session bus of the desktop's user, along with the uid to authenticate as.
+// Once privileges are dropped the helper connects for whoever the session user is.
+pub fn connect_session_bus(username: &str) -> io::Result<(UnixStream, u32)> {
+    if !dropped() {
+        return connect_as_user(username);
+    }
+    let session = request(&Request::ConnectSessionBus, &[])?;
+    let (reply, mut fds) = recv_with_fds(session.as_raw_fd())?;
+    let uid = std::str::from_utf8(&reply).ok().and_then(|reply| reply.strip_prefix("ok ")?.parse().ok());
+    match (fds.pop(), uid) {
+        (Some(fd), Some(uid)) => Ok((UnixStream::from(fd), uid)),
+        _ => Err(helper_error(&reply)),
+    }
+}
+
+// Connects to a socket of the desktop session, e.g. the compositor's. Once privileges are
+// dropped the helper connects as the session's user, in their runtime directory or /tmp/hypr.
+pub fn connect_session_socket(path: &str) -> io::Result<UnixStream> {
+    if !dropped() {
+        return UnixStream::connect(path);
+    }
+    let session = request(&Request::ConnectSessionSocket { path: path.to_string() }, &[])?;
+    let (reply, mut fds) = recv_with_fds(session.as_raw_fd())?;
+    match fds.pop() {
+        Some(fd) if reply == b"ok" => Ok(UnixStream::from(fd)),
+        _ => Err(helper_error(&reply)),
+    }
+}
+
+// Names in a directory of the desktop session, listed by the helper like connect_session_socket connects
+pub fn read_session_dir(path: &str) -> io::Result<Vec<String>> {
+    if !dropped() {
+        return list_dir(path);
+    }
+    let session = request(&Request::ReadSessionDir { path: path.to_string() }, &[])?;
+    let (reply, _) = recv_with_fds(session.as_raw_fd())?;
+    match reply.strip_prefix(b"ok") {
+        // Each name follows a nul, they can't contain one
+        Some(names) => Ok(names.split(|b| *b == 0).skip(1).map(|name| String::from_utf8_lossy(name).into_owned()).collect()),
+        None => Err(helper_error(&reply)),
+    }
+}
+
+// Writes next to the file and renames over it, so readers see the old or the new file but
+// never half of one. The file keeps its permissions, new ones are readable by everyone.
+fn replace_atomically(path: &str, contents: &[u8]) -> io::Result<()> {
+    let temporary = format!("{}.tmp", path);
+    let mode = std::fs::metadata(path).map(|metadata| metadata.permissions().mode()).unwrap_or(0o644);
+    let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(mode).open(&temporary)?;
+    let written = file.write_all(contents).and_then(|_| file.sync_all());
+    if let Err(e) = written.and_then(|_| std::fs::rename(&temporary, path)) {
+        let _ = std::fs::remove_file(&temporary);
+        return Err(e);
+    }
+    Ok(())
+}
+
+// Replaces a file the daemon writes back to, through the helper once privileges are dropped
+pub fn replace(path: &str, contents: &[u8]) -> io::Result<()> {
+    if !dropped() {
+        return replace_atomically(path, contents);
+    }
+    let (reader, writer) = io::pipe()?;
+    let session = request(&Request::Replace { path: path.to_string() }, &[reader.as_raw_fd()])?;
+    drop(reader);
+    // The helper reads while we write, the pipe closing marks the end
+    let mut writer = File::from(OwnedFd::from(writer));
+    writer.write_all(contents)?;
+    drop(writer);
+    let mut reply = String::new();
+    BufReader::new(session).read_line(&mut reply)?;
+    if reply.trim() != "ok" {
+        return Err(helper_error(reply.as_bytes()));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+pub enum Stdio {
+    Inherit,
+    Null,
+    Piped,
+}
+
+impl Stdio {
+    pub fn inherit() -> Stdio {
+        Stdio::Inherit
+    }
+    pub fn null() -> Stdio {
+        Stdio::Null
+    }
+    pub fn piped() -> Stdio {
+        Stdio::Piped
+    }
+}
+
+impl From<Stdio> for std::process::Stdio {
+    fn from(stdio: Stdio) -> std::process::Stdio {
+        match stdio {
+            Stdio::Inherit => std::process::Stdio::inherit(),
+            Stdio::Null => std::process::Stdio::null(),
+            Stdio::Piped => std::process::Stdio::piped(),
+        }
+    }
+}
+
+// What the child gets for one of its standard streams, and our end of it when piped
+fn child_stdio(stdio: Stdio, inherited: impl AsFd, input: bool) -> io::Result<(OwnedFd, Option<File>)> {
+    match stdio {
+        Stdio::Inherit => Ok((inherited.as_fd().try_clone_to_owned()?, None)),
+        Stdio::Null => Ok((OpenOptions::new().read(input).write(!input).open("/dev/null")?.into(), None)),
+        Stdio::Piped => {
+            let (reader, writer) = io::pipe()?;
+            if input {
+                Ok((reader.into(), Some(File::from(OwnedFd::from(writer)))))
+            } else {
+                Ok((writer.into(), Some(File::from(OwnedFd::from(reader)))))
+            }
+        }
+    }
+}
+
+enum Target {
+    Program(String),
+    // Run by runuser as the user named, the helper picks the session user itself
+    SessionUser(String, SessionCommand),
+}
+
+// std::process::Command for the commands that need root to run, the same as it when
+// privileges weren't dropped and handed to the helper when they were
+pub struct Command {
+    target: Target,
+    args: Vec<String>,
+    envs: Vec<(String, String)>,
+    stdout: Option<Stdio>,
+    stderr: Option<Stdio>,
+}
+
+impl Command {
+    pub fn new(program: impl AsRef<str>) -> Command {
+        Command::with_target(Target::Program(program.as_ref().to_string()))
+    }
+    // Runs the program as the logged in user, arguments are added as usual
+    pub fn session_user(username: &str, program: impl AsRef<str>) -> Command {
+        let command = SessionCommand::Exec(vec![program.as_ref().to_string()]);
+        Command::with_target(Target::SessionUser(username.to_string(), command))
+    }
+    // Runs a command line in the logged in user's login shell, reading their profile
+    pub fn login_shell(username: &str, command: impl AsRef<str>) -> Command {
+        let command = SessionCommand::LoginShell(command.as_ref().to_string());
+        Command::with_target(Target::SessionUser(username.to_string(), command))
+    }
+    fn with_target(target: Target) -> Command {
+        Command {
+            target,
+            args: Vec::new(),
+            envs: Vec::new(),
+            stdout: None,
+            stderr: None,
+        }
+    }
+    // What runs as the session user, the program along with the arguments added since
+    fn session_command(&self) -> Option<(&str, SessionCommand)> {
+        let Target::SessionUser(username, command) = &self.target else {
+            return None;
+        };
+        let command = match command {
+            SessionCommand::Exec(program) => SessionCommand::Exec(program.iter().chain(&self.args).cloned().collect()),
+            SessionCommand::LoginShell(line) => SessionCommand::LoginShell(line.clone()),
+        };
+        Some((username, command))
+    }
+    pub fn arg(&mut self, arg: impl AsRef<str>) -> &mut Command {
+        self.args.push(arg.as_ref().to_string());
+        self
+    }
+    pub fn args<I, S>(&mut self, args: I) -> &mut Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.args.extend(args.into_iter().map(|arg| arg.as_ref().to_string()));
+        self
+    }
+    pub fn envs<I, K, V>(&mut self, envs: I) -> &mut Command
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        self.envs.extend(envs.into_iter().map(|(key, value)| (key.as_ref().to_string(), value.as_ref().to_string())));
+        self
+    }
+    pub fn stdout(&mut self, stdio: Stdio) -> &mut Command {
+        self.stdout = Some(stdio);
+        self
+    }
+    pub fn stderr(&mut self, stdio: Stdio) -> &mut Command {
+        self.stderr = Some(stdio);
+        self
+    }
+    pub fn spawn(&mut self) -> io::Result<Child> {
+        self.spawn_with(Stdio::Inherit, Stdio::Inherit)
+    }
+    pub fn status(&mut self) -> io::Result<ExitStatus> {
+        self.spawn_with(Stdio::Inherit, Stdio::Inherit)?.wait()
+    }
+    pub fn output(&mut self) -> io::Result<Output> {
+        let mut child = self.spawn_with(Stdio::Null, Stdio::Piped)?;
+        // Read on the side so a child filling one pipe doesn't wait on us reading the other
+        let stderr_reader = child.stderr.take().map(|mut stderr| {
+            thread::spawn(move || {
+                let mut buffer = Vec::new();
+                let _ = stderr.read_to_end(&mut buffer);
+                buffer
+            })
+        });
+        let mut stdout = Vec::new();
+        if let Some(mut pipe) = child.stdout.take() {
+            pipe.read_to_end(&mut stdout)?;
+        }
+        let stderr = stderr_reader.and_then(|reader| reader.join().ok()).unwrap_or_default();
+        Ok(Output { status: child.wait()?, stdout, stderr })
+    }
+    // Streams left unset get the defaults of the method that was called, like std's
+    fn spawn_with(&mut self, default_stdin: Stdio, default_output: Stdio) -> io::Result<Child> {
+        // Nothing is ever written to a command's stdin
+        let stdin = default_stdin;
+        let stdout = self.stdout.unwrap_or(default_output);
+        let stderr = self.stderr.unwrap_or(default_output);
+        if !dropped() {
+            let (program, args) = match self.session_command() {
+                Some((username, command)) => (RUNUSER, command.runuser_args(username)),
+                None => match &self.target {
+                    Target::Program(program) => (program.as_str(), self.args.clone()),
+                    Target::SessionUser(..) => unreachable!(),
+                },
+            };
+            let mut child = std::process::Command::new(program)
+                .args(args)
+                .envs(self.envs.iter().map(|(key, value)| (key, value)))
+                .stdin(stdin)
+                .stdout(stdout)
+                .stderr(stderr)
+                .spawn()?;
+            return Ok(Child {
+                stdout: child.stdout.take().map(|pipe| File::from(OwnedFd::from(pipe))),
+                stderr: child.stderr.take().map(|pipe| File::from(OwnedFd::from(pipe))),
+                process: Process::Local(child),
+            });
+        }
+        let (child_stdin, _) = child_stdio(stdin, io::stdin(), true)?;
+        let (child_stdout, our_stdout) = child_stdio(stdout, io::stdout(), false)?;
+        let (child_stderr, our_stderr) = child_stdio(stderr, io::stderr(), false)?;
+        let spawn = match (self.session_command(), &self.target) {
+            (Some((_, command)), _) => Request::RunAsSessionUser { command },
+            (None, Target::Program(program)) => Request::Spawn {
+                program: program.clone(),
+                args: self.args.clone(),
+                envs: self.envs.clone(),
+            },
+            (None, Target::SessionUser(..)) => unreachable!(),
+        };
+        let session = request(
+            &spawn,
+            &[child_stdin.as_raw_fd(), child_stdout.as_raw_fd(), child_stderr.as_raw_fd()],
+        )?;
+        // The child has its own copies now, ours would keep the pipes from ever closing
+        drop((child_stdin, child_stdout, child_stderr));
+        let mut reader = BufReader::new(session.try_clone()?);
+        let mut reply = String::new();
+        reader.read_line(&mut reply)?;
+        if reply.trim() != "ok" {
+            return Err(helper_error(reply.as_bytes()));
+        }
+        Ok(Child {
+            stdout: our_stdout,
+            stderr: our_stderr,
+            process: Process::Remote(session, reader),
+        })
+    }
+}
+
+enum Process {
+    Local(std::process::Child),
+    // The session with the helper that spawned it, the helper reaps it once it's closed
+    Remote(UnixStream, BufReader<UnixStream>),
+}
+
+pub struct Child {
+    pub stdout: Option<File>,
+    pub stderr: Option<File>,
+    process: Process,
+}
+
+impl Child {
+    fn ask(&mut self, question: &str) -> io::Result<String> {
+        let Process::Remote(session, reader) = &mut self.process else {
+            unreachable!();
+        };
+        session.write_all(format!("{}\n", question).as_bytes())?;
+        let mut reply = String::new();
+        if reader.read_line(&mut reply)? == 0 {
+            return Err(io::Error::other("the privileged helper went away"));
+        }
+        if reply.starts_with("error ") {
+            return Err(helper_error(reply.as_bytes()));
+        }
+        Ok(reply.trim().to_string())
+    }
+    fn parse_status(reply: &str) -> io::Result<Option<ExitStatus>> {
+        if reply == "running" {
+            return Ok(None);
+        }
+        reply
+            .strip_prefix("exit ")
+            .and_then(|raw| raw.parse().ok())
+            .map(|raw| Some(ExitStatus::from_raw(raw)))
+            .ok_or_else(|| io::Error::other(format!("unexpected reply from the privileged helper: {}", reply)))
+    }
+    pub fn kill(&mut self) -> io::Result<()> {
+        match &mut self.process {
+            Process::Local(child) => child.kill(),
+            Process::Remote(..) => self.ask("kill").map(|_| ()),
+        }
+    }
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        match &mut self.process {
+            Process::Local(child) => child.wait(),
+            Process::Remote(..) => {
+                let reply = self.ask("wait")?;
+                Child::parse_status(&reply)?.ok_or_else(|| io::Error::other("the child is still running"))
+            }
+        }
+    }
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        match &mut self.process {
+            Process::Local(child) => child.try_wait(),
+            Process::Remote(..) => {
+                let reply = self.ask("try_wait")?;
+                Child::parse_status(&reply)
+            }
+        }
+    }
+}
+
+// The helper's side, run as root in its own process
+
+// The user's uid and primary gid
+fn user_ids(user: &str) -> Option<(u32, u32)> {
+    let name = std::ffi::CString::new(user).ok()?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16 * 1024];
+    let mut found = std::ptr::null_mut();
+    let result = unsafe { libc::getpwnam_r(name.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut found) };
+    (result == 0 && !found.is_null()).then_some((passwd.pw_uid, passwd.pw_gid))
+}
+
+fn is_root(user: &str) -> bool {
+    user_ids(user).is_some_and(|(uid, _)| uid == 0)
+}
+
+// Commands for the session go to its user and no one else, root only when allowed
+fn session_user_allowed(user: Option<&str>, root_allowed: bool) -> Result<String, String> {
+    match user {
+        None => Err("no desktop session to run the command in".to_string()),
+        Some(user) if is_root(user) && !root_allowed => {
+            Err("running commands as root is disabled, set AllowCommandsAsRoot = true to allow it".to_string())
+        }
+        Some(user) => Ok(user.to_string()),
+    }
+}
+
+// Whatever the daemon asks for runs as root first, so only what it's meant to ask for is run
+fn spawn_allowed(program: &str, args: &[String], envs: &[(String, String)], root_allowed: bool) -> Result<(), String> {
+    // LD_PRELOAD and friends would run in the root process itself
+    if !envs.is_empty() && (program != ROOT_SHELL || !root_allowed) {
+        return Err("no environment is passed to privileged programs".to_string());
+    }
+    match program {
+        // Holding idle and sleep locks for the Caffeine button, for as long as the daemon runs
+        INHIBIT => {
+            let command: Vec<&str> = args.iter().map(String::as_str).skip_while(|arg| arg.starts_with("--")).collect();
+            match command.as_slice() {
+                ["tail", pid, "-f", "/dev/null"] if pid.starts_with("--pid=") => Ok(()),
+                _ => Err("systemd-inhibit only waits on tiny-dfr".to_string()),
+            }
+        }
+        // Locking the session for the LockSession button
+        LOGINCTL => match args {
+            [verb, _] if verb == "lock-session" => Ok(()),
+            _ => Err("loginctl only locks sessions".to_string()),
+        },
+        ROOT_SHELL if root_allowed => Ok(()),
+        _ => Err(format!("{} is not run with privileges", program)),
+    }
+}
+
+// Device files the daemon writes to, paths into sysfs are resolved so links can't lead elsewhere
+fn open_allowed(path: &str) -> bool {
+    if path == "/dev/uinput" || path == "/dev/rfkill" {
+        return true;
+    }
+    // Input devices, for their force feedback
+    if let Some(event) = path.strip_prefix("/dev/input/event") {
+        return !event.is_empty() && event.bytes().all(|b| b.is_ascii_digit());
+    }
+    let Ok(resolved) = std::fs::canonicalize(path) else {
+        return false;
+    };
+    let resolved = resolved.to_string_lossy();
+    resolved.starts_with("/sys/devices/")
+        && resolved.ends_with("/brightness")
+        && (path.starts_with("/sys/class/backlight/") || path.starts_with("/sys/class/leds/"))
+}
+
+// What the helper reaches as the session's user for the daemon: their runtime directory and
+// where older Hyprland versions put their sockets, named without any way back out of them
+fn session_path_allowed(path: &str, uid: u32) -> bool {
+    let path = Path::new(path);
+    (path.starts_with(format!("/run/user/{}", uid)) || path.starts_with("/tmp/hypr"))
+        && path.components().all(|component| matches!(component, Component::RootDir | Component::Normal(_)))
+}
+
+fn reply(session: &UnixStream, message: &str) {
+    let _ = (&*session).write_all(format!("{}\n", message).as_bytes());
+}
+
+fn serve_spawn(session: UnixStream, program: &str, args: Vec<String>, envs: Vec<(String, String)>, stdio: Vec<OwnedFd>) {
+    let [stdin, stdout, stderr]: [OwnedFd; 3] = match stdio.try_into() {
+        Ok(stdio) => stdio,
+        Err(_) => return reply(&session, "error missing standard streams"),
+    };
+    let result = std::process::Command::new(program)
+        .args(&args)
+        .envs(envs)
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(stderr)
+        .spawn();
+    let mut child = match result {
+        Ok(child) => child,
+        Err(e) => return reply(&session, &format!("error {}", e)),
+    };
+    reply(&session, "ok");
+    let Ok(reader) = session.try_clone() else {
+        let _ = child.wait();
+        return;
+    };
+    for question in BufReader::new(reader).lines() {
+        let Ok(question) = question else {
+            break;
+        };
+        let answer = match question.as_str() {
+            "kill" => child.kill().map(|_| "ok".to_string()),
+            "wait" => child.wait().map(|status| format!("exit {}", status.into_raw())),
+            "try_wait" => child
+                .try_wait()
+                .map(|status| status.map_or_else(|| "running".to_string(), |status| format!("exit {}", status.into_raw()))),
+            _ => Ok("error unknown request".to_string()),
+        };
+        reply(&session, &answer.unwrap_or_else(|e| format!("error {}", e)));
+    }
+    // The daemon let go of the child, it keeps running and is reaped here
+    let _ = child.wait();
+}
+
+fn serve_open(session: UnixStream, path: String, write: bool) {
+    if !open_allowed(&path) {
+        eprintln!("Privileged helper refused to open {}", path);
+        let _ = send_with_fds(session.as_raw_fd(), format!("error {} is not opened with privileges", path).as_bytes(), &[]);
+        return;
+    }
+    let result = match OpenOptions::new().read(!write).write(write).open(&path) {
+        Ok(file) => send_with_fds(session.as_raw_fd(), b"ok", &[file.as_raw_fd()]),
+        Err(e) => send_with_fds(session.as_raw_fd(), format!("error {}", e).as_bytes(), &[]),
+    };
+    if let Err(e) = result {
+        eprintln!("Privileged helper failed to answer: {}", e);
+    }
+}
+
+// Layouts edited over D-Bus are saved to the user configuration, nothing else is replaced
+// and nothing but the layers changes
+fn serve_replace(session: UnixStream, path: String, contents: Vec<OwnedFd>) {
+    if path != config::USER_CFG_PATH {
+        eprintln!("Privileged helper refused to replace {}", path);
+        return reply(&session, &format!("error {} is not replaced with privileges", path));
+    }
+    let Some(contents) = contents.into_iter().next() else {
+        return reply(&session, "error missing the new contents");
+    };
+    let mut buffer = Vec::new();
+    let result = File::from(contents)
+        .take(MAX_REPLACEMENT + 1)
+        .read_to_end(&mut buffer)
+        .and_then(|read| {
+            if read as u64 > MAX_REPLACEMENT {
+                return Err(io::Error::other("the new contents are too large"));
+            }
+            // The same file says whether commands may run as root, so the daemon gets to
+            // change its layers and nothing else
+            let new = std::str::from_utf8(&buffer).map_err(io::Error::other)?;
+            let old = std::fs::read_to_string(&path).or_else(|e| match e.kind() {
+                io::ErrorKind::NotFound => Ok(String::new()),
+                _ => Err(e),
+            })?;
+            config::only_layout_changed(&old, new).map_err(io::Error::other)?;
+            replace_atomically(&path, &buffer)
+        });
+    match result {
+        Ok(()) => reply(&session, "ok"),
+        Err(e) => reply(&session, &format!("error {}", e)),
+    }
+}
+
+fn serve_connect_session_bus(session: UnixStream, user: &str) {
+    let result = match connect_as_user(user) {
+        Ok((bus, uid)) => send_with_fds(session.as_raw_fd(), format!("ok {}", uid).as_bytes(), &[bus.as_raw_fd()]),
+        Err(e) => send_with_fds(session.as_raw_fd(), format!("error {}", e).as_bytes(), &[]),
+    };
+    if let Err(e) = result {
+        eprintln!("Privileged helper failed to answer: {}", e);
+    }
+}
+
+// Connects or lists as the session's user, sending back the connection or the names
+fn serve_session_path(session: UnixStream, user: Option<&str>, path: String, list: bool) {
+    let Some(user) = user else {
+        let _ = send_with_fds(session.as_raw_fd(), b"error no desktop session", &[]);
+        return;
+    };
+    let allowed = user_ids(user).is_some_and(|(uid, _)| session_path_allowed(&path, uid));
+    let result = if !allowed {
+        eprintln!("Privileged helper refused to reach {} for the session", path);
+        send_with_fds(session.as_raw_fd(), format!("error {} is outside the session", path).as_bytes(), &[])
+    } else if list {
+        match as_user(user, move |_| list_dir(&path)) {
+            Ok(names) => {
+                let reply: Vec<u8> = names.iter().flat_map(|name| [&[0][..], name.as_bytes()].concat()).collect();
+                send_with_fds(session.as_raw_fd(), &[&b"ok"[..], &reply].concat(), &[])
+            }
+            Err(e) => send_with_fds(session.as_raw_fd(), format!("error {}", e).as_bytes(), &[]),
+        }
+    } else {
+        match as_user(user, move |_| UnixStream::connect(path)) {
+            Ok(socket) => send_with_fds(session.as_raw_fd(), b"ok", &[socket.as_raw_fd()]),
+            Err(e) => send_with_fds(session.as_raw_fd(), format!("error {}", e).as_bytes(), &[]),
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Privileged helper failed to answer: {}", e);
+    }
+}
+
+// Entry point of tiny-dfr --privileged-helper, serves requests until the daemon goes away
+pub fn run_helper() {
+    let control = io::stdin().as_raw_fd();
+    let mut session_user: Option<(Instant, Option<String>)> = None;
+    loop {
+        let (data, mut fds) = match recv_with_fds(control) {
+            Ok((data, _)) if data.is_empty() => return,
+            Ok(message) => message,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                eprintln!("Privileged helper failed to read a request: {}", e);
+                return;
+            }
+        };
+        if fds.is_empty() {
+            continue;
+        }
+        let session = UnixStream::from(fds.remove(0));
+        let request = serde_json::from_slice::<Request>(&data);
+        if matches!(
+            request,
+            Ok(Request::RunAsSessionUser { .. }
+                | Request::ConnectSessionBus
+                | Request::ConnectSessionSocket { .. }
+                | Request::ReadSessionDir { .. })
+        )
+            && session_user.as_ref().is_none_or(|(found_at, _)| found_at.elapsed() >= SESSION_USER_TTL)
+        {
+            session_user = Some((Instant::now(), user_cache::detect_session_user()));
+        }
+        let user = session_user.as_ref().and_then(|(_, user)| user.as_deref());
+        match request {
+            Ok(Request::RunAsSessionUser { command }) => {
+                let user = match session_user_allowed(user, config::commands_as_root_allowed()) {
+                    Ok(user) => user,
+                    Err(e) => {
+                        eprintln!("Privileged helper refused {:?}: {}", command, e);
+                        reply(&session, &format!("error {}", e));
+                        continue;
+                    }
+                };
+                thread::spawn(move || serve_spawn(session, RUNUSER, command.runuser_args(&user), Vec::new(), fds));
+            }
+            // Only listened on, so root's own session is fine too
+            Ok(Request::ConnectSessionBus) => match user {
+                Some(user) => serve_connect_session_bus(session, user),
+                None => {
+                    let _ = send_with_fds(session.as_raw_fd(), b"error no desktop session to connect to", &[]);
+                }
+            },
+            Ok(Request::ConnectSessionSocket { path }) => serve_session_path(session, user, path, false),
+            Ok(Request::ReadSessionDir { path }) => serve_session_path(session, user, path, true),
+            Ok(Request::Spawn { program, args, envs }) => {
+                if let Err(e) = spawn_allowed(&program, &args, &envs, config::commands_as_root_allowed()) {
+                    eprintln!("Privileged helper refused {} {:?}: {}", program, args, e);
+                    reply(&session, &format!("error {}", e));
+                    continue;
+                }
+                thread::spawn(move || serve_spawn(session, &program, args, envs, fds));
+            }
+            Ok(Request::Open { path, write }) => serve_open(session, path, write),
+            Ok(Request::Replace { path }) => serve_replace(session, path, fds),
+            Err(e) => eprintln!("Privileged helper got a malformed request: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_spawn_allowed() {
+        // runuser is never run as asked, only through RunAsSessionUser
+        for args in [
+            &["-l", "alice", "-u", "root", "-c", "id"][..],
+            &["-l", "alice", "--user=root", "-c", "id"],
+            &["-uroot", "id"],
+            &["-l", "-", "root"],
+            &["-u", "alice", "--", "id"],
+        ] {
+            assert!(spawn_allowed(RUNUSER, &strings(args), &[], true).is_err(), "{:?}", args);
+        }
+        assert!(spawn_allowed(LOGINCTL, &strings(&["lock-session", "3"]), &[], false).is_ok());
+        assert!(spawn_allowed(LOGINCTL, &strings(&["terminate-session", "3"]), &[], false).is_err());
+        assert!(spawn_allowed(INHIBIT, &strings(&["--what=idle", "tail", "--pid=42", "-f", "/dev/null"]), &[], false).is_ok());
+        assert!(spawn_allowed(INHIBIT, &strings(&["--what=idle", "sh", "-c", "id"]), &[], false).is_err());
+        let env = [("LD_PRELOAD".to_string(), "/tmp/evil.so".to_string())];
+        assert!(spawn_allowed(LOGINCTL, &strings(&["lock-session", "3"]), &env, true).is_err());
+        assert!(spawn_allowed(ROOT_SHELL, &strings(&["-c", "id"]), &[], false).is_err());
+        assert!(spawn_allowed(ROOT_SHELL, &strings(&["-c", "id"]), &env, true).is_ok());
+    }
+
+    #[test]
+    fn test_session_command() {
+        // Whatever the command is, it comes after the user the helper picked
+        let exec = SessionCommand::Exec(strings(&["-uroot", "--user=root", "id"]));
+        assert_eq!(exec.runuser_args("alice"), strings(&["-u", "alice", "--", "-uroot", "--user=root", "id"]));
+        let shell = SessionCommand::LoginShell("-u root".to_string());
+        assert_eq!(shell.runuser_args("alice"), strings(&["-l", "-c", "-u root", "--", "alice"]));
+        let mut command = Command::session_user("alice", "env");
+        command.arg("XDG_RUNTIME_DIR=/run/user/1000").args(["wpctl", "status"]);
+        let (user, exec) = command.session_command().unwrap();
+        assert_eq!(user, "alice");
+        assert_eq!(exec, SessionCommand::Exec(strings(&["env", "XDG_RUNTIME_DIR=/run/user/1000", "wpctl", "status"])));
+        assert!(Command::new(LOGINCTL).session_command().is_none());
+    }
+
+    #[test]
+    fn test_session_user_allowed() {
+        assert!(session_user_allowed(None, true).is_err());
+        assert!(session_user_allowed(Some("root"), false).is_err());
+        assert_eq!(session_user_allowed(Some("root"), true).as_deref(), Ok("root"));
+        assert_eq!(session_user_allowed(Some("nobody"), false).as_deref(), Ok("nobody"));
+    }
+
+    #[test]
+    fn test_session_path_allowed() {
+        assert!(session_path_allowed("/run/user/1000", 1000));
+        assert!(session_path_allowed("/run/user/1000/hypr/abc/.socket2.sock", 1000));
+        assert!(session_path_allowed("/tmp/hypr/abc/.socket.sock", 1000));
+        assert!(!session_path_allowed("/run/user/1001/bus", 1000));
+        assert!(!session_path_allowed("/run/user/10000/bus", 1000));
+        assert!(!session_path_allowed("/run/user/1000/../1001/bus", 1000));
+        assert!(!session_path_allowed("/tmp/hypr/../../etc", 1000));
+        assert!(!session_path_allowed("/run/dbus/system_bus_socket", 1000));
+    }
+
+    #[test]
+    fn test_open_allowed() {
+        assert!(open_allowed("/dev/uinput"));
+        assert!(open_allowed("/dev/rfkill"));
+        assert!(open_allowed("/dev/input/event12"));
+        assert!(!open_allowed("/dev/input/event"));
+        assert!(!open_allowed("/dev/input/event1/../../sda"));
+        assert!(!open_allowed("/dev/input/mice"));
+        assert!(!open_allowed("/etc/shadow"));
+        assert!(!open_allowed("/sys/class/backlight/../../../etc/shadow"));
+        assert!(!open_allowed("/sys/class/backlight/nonexistent/brightness"));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::ambient_light;
+use crate::backlight;
+use crate::config::{self, DisplayConfig};
+use crate::display::DrmBackend;
+use crate::keyboard_backlight;
+use std::path::Path;
+
+fn report(name: &str, result: Result<String, String>) -> bool {
+    match &result {
+        Ok(found) => println!("[ OK ] {}: {}", name, found),
+        Err(reason) => println!("[FAIL] {}: {}", name, reason),
+    }
+    result.is_ok()
+}
+
+fn probe_display(display_cfg: &DisplayConfig) -> Result<String, String> {
+    let drm = DrmBackend::open_card(display_cfg)
+        .map_err(|e| format!("{}\n       Stop the tiny-dfr service first, it keeps the display open", e))?;
+    let (width, height) = drm.mode().size();
+    Ok(format!("{}x{} mode", width, height))
+}
+
+// The same device the main loop picks: on the touch seat, with TouchDevice in its name
+fn probe_digitizer(display_cfg: &DisplayConfig) -> Result<String, String> {
+    let mut enumerator = udev::Enumerator::new().map_err(|e| e.to_string())?;
+    enumerator.match_subsystem("input").map_err(|e| e.to_string())?;
+    let mut named = Vec::new();
+    for device in enumerator.scan_devices().map_err(|e| e.to_string())? {
+        let Some(name) = device.attribute_value("name").map(|name| name.to_string_lossy().to_string()) else {
+            continue;
+        };
+        if !name.contains(display_cfg.touch_device.as_str()) {
+            continue;
+        }
+        let seat = device
+            .property_value("ID_SEAT")
+            .map_or("seat0".to_string(), |seat| seat.to_string_lossy().to_string());
+        if seat == display_cfg.touch_seat {
+            return Ok(format!("{} on {}", name, seat));
+        }
+        named.push(format!("{} on {}", name, seat));
+    }
+    if named.is_empty() {
+        Err(format!("no input device named like '{}'", display_cfg.touch_device))
+    } else {
+        Err(format!(
+            "found {}, but not on {}, are the udev rules installed?",
+            named.join(", "),
+            display_cfg.touch_seat
+        ))
+    }
+}
+
+fn describe(path: &Path) -> String {
+    path.display().to_string()
+}
+
+// Prints what tiny-dfr finds on this machine, returns whether the bar itself can work
+pub fn run_probe() -> bool {
+    let display_cfg = config::load_display_config();
+    let display = report("Touch Bar display", probe_display(&display_cfg));
+    let digitizer = report("Touch Bar digitizer", probe_digitizer(&display_cfg));
+    report(
+        "Touch Bar backlight (dimming)",
+        backlight::find_backlight().map(|path| describe(&path)).map_err(|e| e.to_string()),
+    );
+    report(
+        "Display backlight (AdaptiveBrightness, OSD)",
+        backlight::find_display_backlight().map(|path| describe(&path)).map_err(|e| e.to_string()),
+    );
+    report(
+        "Ambient light sensor (AmbientLight buttons)",
+        ambient_light::sensor_path()
+            .map(|path| describe(path))
+            .ok_or_else(|| "no IIO device with an illuminance reading".to_string()),
+    );
+    report(
+        "Battery (Battery and BatteryHistory buttons)",
+        crate::find_battery_device()
+            .map(|battery| format!("/sys/class/power_supply/{}", battery))
+            .ok_or_else(|| "no power supply of type Battery".to_string()),
+    );
+    report(
+        "Keyboard backlight (IllumUp and IllumDown)",
+        keyboard_backlight::find_keyboard_backlight().map(|path| describe(&path)).map_err(|e| e.to_string()),
+    );
+    display && digitizer
+}
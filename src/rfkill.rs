@@ -0,0 +1,102 @@
+use crate::media_state;
+use crate::privileged;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Talks to the kernel directly, so it works without NetworkManager or the rfkill tool
+const RFKILL_DEVICE: &str = "/dev/rfkill";
+// struct rfkill_event, newer kernels send a longer one that starts the same way
+const EVENT_SIZE: usize = 8;
+const TYPE_ALL: u8 = 0;
+const OP_ADD: u8 = 0;
+const OP_DEL: u8 = 1;
+const OP_CHANGE: u8 = 2;
+const OP_CHANGE_ALL: u8 = 3;
+// How long to wait before opening the device again after reading failed
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+// Soft block of every radio by index, kept up to date by the kernel's events
+static RADIOS: std::sync::LazyLock<Arc<Mutex<HashMap<u32, bool>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
+static AIRPLANE_MODE: AtomicBool = AtomicBool::new(false);
+// Bumped whenever airplane mode turns on or off so the main loop knows to redraw
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// Only started once an AirplaneMode button is created
+static RFKILL_MONITOR: std::sync::LazyLock<thread::JoinHandle<()>> =
+    std::sync::LazyLock::new(|| thread::spawn(monitor_loop));
+
+fn handle_event(event: &[u8]) {
+    let index = u32::from_ne_bytes(event[0..4].try_into().unwrap());
+    let (op, soft) = (event[5], event[6] != 0);
+    let Ok(mut radios) = RADIOS.lock() else {
+        return;
+    };
+    match op {
+        OP_ADD | OP_CHANGE => {
+            radios.insert(index, soft);
+        }
+        OP_DEL => {
+            radios.remove(&index);
+        }
+        _ => {}
+    }
+    // Airplane mode is every radio there is blocked
+    let airplane_mode = !radios.is_empty() && radios.values().all(|soft| *soft);
+    if AIRPLANE_MODE.swap(airplane_mode, Ordering::Relaxed) != airplane_mode {
+        GENERATION.fetch_add(1, Ordering::Relaxed);
+        media_state::wake();
+    }
+}
+
+fn monitor(device: &mut File) -> std::io::Result<()> {
+    // Opening the device sends an add event for each radio, changes follow
+    let mut buffer = [0u8; 64];
+    loop {
+        let read = device.read(&mut buffer)?;
+        if read < EVENT_SIZE {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "short rfkill event"));
+        }
+        handle_event(&buffer[..read]);
+    }
+}
+
+fn monitor_loop() {
+    loop {
+        let result = privileged::open(RFKILL_DEVICE, false).and_then(|mut device| monitor(&mut device));
+        if let Err(e) = result {
+            eprintln!("Failed to follow {}: {}", RFKILL_DEVICE, e);
+        }
+        thread::sleep(RETRY_DELAY);
+    }
+}
+
+// Public API
+
+pub fn initialize_rfkill_monitor() {
+    std::sync::LazyLock::force(&RFKILL_MONITOR);
+}
+
+pub fn airplane_mode() -> bool {
+    AIRPLANE_MODE.load(Ordering::Relaxed)
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+// Blocks every radio, or unblocks them all again when they already are
+pub fn toggle_airplane_mode() {
+    let block = !airplane_mode();
+    let event = [0, 0, 0, 0, TYPE_ALL, OP_CHANGE_ALL, block as u8, 0];
+    let result = privileged::open(RFKILL_DEVICE, true).and_then(|mut device| device.write_all(&event));
+    match result {
+        Ok(()) => println!("Airplane mode {}", if block { "on" } else { "off" }),
+        Err(e) => eprintln!("Failed to turn airplane mode {}: {}", if block { "on" } else { "off" }, e),
+    }
+}
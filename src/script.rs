@@ -0,0 +1,87 @@
+use crate::media_state;
+use crate::poller::Poller;
+use crate::privileged::Command;
+use crate::user_cache;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const DEFAULT_REFRESH: Duration = Duration::from_secs(5);
+
+// Commands being run
+static SCRIPTS: Poller<String> = Poller::new();
+// What each command last printed, keyed by the command
+static OUTPUTS: std::sync::LazyLock<Arc<Mutex<HashMap<String, String>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
+// Bumped whenever an output changes so the main loop knows to redraw
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// Only started once a script widget is created
+static SCRIPT_MONITOR: std::sync::LazyLock<thread::JoinHandle<()>> =
+    std::sync::LazyLock::new(|| thread::spawn(monitor_loop));
+
+// The first line the command printed, there's room for nothing more
+fn first_line(stdout: &[u8]) -> String {
+    String::from_utf8_lossy(stdout).lines().map(str::trim).find(|line| !line.is_empty()).unwrap_or_default().to_string()
+}
+
+// Runs the command as the logged in user, failures are only reported once in a row
+fn run(command: &str, failing: &mut bool) -> Option<String> {
+    let env = user_cache::get_cached_user_environment()?;
+    let output = Command::login_shell(&env.username, command).output();
+    let result = match output {
+        Ok(output) if output.status.success() => Ok(first_line(&output.stdout)),
+        Ok(output) => Err(format!("Script '{}' exited with {}", command, output.status)),
+        Err(e) => Err(format!("Failed to run script '{}': {}", command, e)),
+    };
+    match result {
+        Ok(text) => {
+            *failing = false;
+            Some(text)
+        }
+        Err(e) => {
+            if !*failing {
+                eprintln!("{}", e);
+            }
+            *failing = true;
+            None
+        }
+    }
+}
+
+fn monitor_loop() {
+    let mut failing: HashMap<String, bool> = HashMap::new();
+    loop {
+        for command in SCRIPTS.due(|_| false) {
+            let Some(text) = run(&command, failing.entry(command.clone()).or_default()) else {
+                continue;
+            };
+            if let Ok(mut outputs) = OUTPUTS.lock() {
+                if outputs.get(&command) != Some(&text) {
+                    outputs.insert(command, text);
+                    GENERATION.fetch_add(1, Ordering::Relaxed);
+                    media_state::wake();
+                }
+            }
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+// Public API
+
+pub fn watch(command: &str, refresh_seconds: Option<u64>) {
+    let refresh = refresh_seconds.map_or(DEFAULT_REFRESH, |seconds| Duration::from_secs(seconds.max(1)));
+    SCRIPTS.watch(command.to_string(), refresh);
+    std::sync::LazyLock::force(&SCRIPT_MONITOR);
+}
+
+pub fn output(command: &str) -> Option<String> {
+    OUTPUTS.lock().ok()?.get(command).cloned()
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
@@ -0,0 +1,73 @@
+use crate::dbus;
+use crate::media_state;
+use crate::privileged::Command;
+use crate::user_cache;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+// logind announces LockedHint changes of a session with PropertiesChanged
+const LOCK_CHANGES: &str = "type='signal',sender='org.freedesktop.login1',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged',arg0='org.freedesktop.login1.Session'";
+// How long to wait before connecting to the system bus again
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+static LOCKED: AtomicBool = AtomicBool::new(false);
+// Bumped whenever the session locks or unlocks so the main loop knows to redraw
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// Only started once a LockSession button is created
+static LOCK_MONITOR: std::sync::LazyLock<thread::JoinHandle<()>> =
+    std::sync::LazyLock::new(|| thread::spawn(monitor_loop));
+
+// Signals only say some session changed, logind is asked about ours
+fn refresh() {
+    let locked = user_cache::get_cached_user_environment().is_some_and(|env| user_cache::session_locked(&env.username));
+    if LOCKED.swap(locked, Ordering::Relaxed) != locked {
+        GENERATION.fetch_add(1, Ordering::Relaxed);
+        media_state::wake();
+    }
+}
+
+fn monitor_loop() {
+    loop {
+        // Whatever changed while nobody was listening
+        refresh();
+        if let Err(e) = dbus::watch_signals(LOCK_CHANGES, refresh) {
+            eprintln!("Failed to follow logind's lock state: {}", e);
+        }
+        thread::sleep(RECONNECT_DELAY);
+    }
+}
+
+// Public API
+
+pub fn initialize_lock_monitor() {
+    std::sync::LazyLock::force(&LOCK_MONITOR);
+}
+
+pub fn locked() -> bool {
+    LOCKED.load(Ordering::Relaxed)
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+// Has logind ask the user's screen locker to lock the session, the monitor sees it happen
+pub fn lock_session() {
+    thread::spawn(|| {
+        let Some(env) = user_cache::get_cached_user_environment() else {
+            eprintln!("No logged in user whose session could be locked");
+            return;
+        };
+        let Some(session) = user_cache::display_session(&env.username) else {
+            eprintln!("No display session of {} to lock", env.username);
+            return;
+        };
+        match Command::new("loginctl").args(["lock-session", &session]).status() {
+            Ok(status) if status.success() => println!("Locking session {}", session),
+            Ok(status) => eprintln!("loginctl lock-session {} failed: {}", session, status),
+            Err(e) => eprintln!("Failed to run loginctl: {}", e),
+        }
+    });
+}
@@ -0,0 +1,102 @@
+use crate::backlight;
+use crate::config::SliderKind;
+use crate::privileged::Command;
+use crate::status;
+use crate::user_cache;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often levels changed elsewhere, e.g. by the keyboard, are picked up
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+// Last known level of each kind of slider, from 0 to 1
+static LEVELS: std::sync::LazyLock<Arc<Mutex<HashMap<SliderKind, f64>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
+// Volume a drag asked for, only the latest one is applied when wpctl can't keep up
+static VOLUME_TARGET: std::sync::LazyLock<Arc<Mutex<Option<f64>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(None)));
+// Bumped whenever a level changes so the main loop knows to redraw
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// Only started once a slider is created
+static SLIDER_MONITOR: std::sync::LazyLock<thread::JoinHandle<()>> =
+    std::sync::LazyLock::new(|| thread::spawn(monitor_loop));
+
+fn store_level(kind: SliderKind, level: f64) {
+    if let Ok(mut levels) = LEVELS.lock() {
+        if levels.insert(kind, level) != Some(level) {
+            GENERATION.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn read_display_brightness() -> Option<f64> {
+    let path = backlight::find_display_backlight().ok()?;
+    let read = |attr: &str| fs::read_to_string(path.join(attr)).ok()?.trim().parse::<f64>().ok();
+    Some(read("brightness")? / read("max_brightness")?.max(1.0))
+}
+
+fn apply_volume(level: f64) {
+    let Some(env) = user_cache::get_cached_user_environment() else {
+        return;
+    };
+    let result = Command::session_user(&env.username, "env")
+        .arg(format!("XDG_RUNTIME_DIR={}", env.runtime_dir))
+        .args(["wpctl", "set-volume", "@DEFAULT_AUDIO_SINK@", &format!("{:.2}", level)])
+        .status();
+    if let Err(e) = result {
+        eprintln!("Failed to set the volume with wpctl: {}", e);
+    }
+}
+
+fn monitor_loop() {
+    let mut last_refresh: Option<Instant> = None;
+    let mut last_set = Instant::now();
+    loop {
+        let target = VOLUME_TARGET.lock().ok().and_then(|mut target| target.take());
+        if let Some(level) = target {
+            apply_volume(level);
+            last_set = Instant::now();
+        }
+        // Reading back right after a drag would make the slider jump back and forth
+        let due = last_refresh.is_none_or(|last_refresh| last_refresh.elapsed() >= REFRESH_INTERVAL);
+        if due && last_set.elapsed() >= REFRESH_INTERVAL {
+            if let Some((volume, _)) = status::read_volume() {
+                store_level(SliderKind::Volume, volume);
+            }
+            if let Some(brightness) = read_display_brightness() {
+                store_level(SliderKind::Brightness, brightness);
+            }
+            last_refresh = Some(Instant::now());
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// Public API
+
+pub fn initialize_slider_monitor() {
+    std::sync::LazyLock::force(&SLIDER_MONITOR);
+}
+
+pub fn level(kind: SliderKind) -> Option<f64> {
+    LEVELS.lock().ok()?.get(&kind).copied()
+}
+
+// Shows the new level right away, the display brightness is set by the caller
+pub fn set_level(kind: SliderKind, level: f64) {
+    store_level(kind, level);
+    if kind == SliderKind::Volume {
+        if let Ok(mut target) = VOLUME_TARGET.lock() {
+            *target = Some(level);
+        }
+    }
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
@@ -0,0 +1,133 @@
+use crate::battery_monitor::BatteryState;
+use crate::user_cache;
+use chrono::{
+    format::{Item as ChronoItem, StrftimeItems},
+    Local,
+};
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// How often the slower sources are polled in the background
+pub const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Default)]
+struct StatusValues {
+    wifi: Option<u32>,           // link quality in percent, None without a wireless link
+    volume: Option<(u32, bool)>, // percent and muted
+}
+
+static VALUES: std::sync::LazyLock<Arc<Mutex<StatusValues>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(StatusValues::default())));
+
+// Only started once a status widget is created
+static STATUS_MONITOR: std::sync::LazyLock<thread::JoinHandle<()>> =
+    std::sync::LazyLock::new(|| thread::spawn(monitor_loop));
+
+fn monitor_loop() {
+    loop {
+        let values = StatusValues {
+            wifi: read_wifi(),
+            volume: read_volume().map(|(level, muted)| ((level * 100.0).round() as u32, muted)),
+        };
+        if let Ok(mut current) = VALUES.lock() {
+            *current = values;
+        }
+        thread::sleep(STATUS_INTERVAL);
+    }
+}
+
+// One entry of a status widget, each gets an equal share of the button
+#[derive(Clone)]
+pub enum StatusItem {
+    Clock(Vec<ChronoItem<'static>>),
+    Battery(Option<String>), // battery device, looked up once
+    Wifi,
+    Volume,
+}
+
+impl StatusItem {
+    pub fn parse(item: &str, format: Option<&str>) -> Option<StatusItem> {
+        initialize_status_monitor();
+        match item {
+            "clock" => {
+                let items = StrftimeItems::new(format.unwrap_or("%H:%M")).parse_to_owned();
+                match items {
+                    Ok(items) => Some(StatusItem::Clock(items)),
+                    Err(e) => {
+                        eprintln!("Invalid clock format in status widget: {:?}", e);
+                        None
+                    }
+                }
+            }
+            "battery" => Some(StatusItem::Battery(crate::find_battery_device())),
+            "wifi" => Some(StatusItem::Wifi),
+            "volume" => Some(StatusItem::Volume),
+            _ => {
+                eprintln!("Unknown status item '{}', accepted items: clock, battery, wifi, volume", item);
+                None
+            }
+        }
+    }
+
+    pub fn text(&self) -> String {
+        let values = VALUES.lock().map(|values| *values).unwrap_or_default();
+        match self {
+            StatusItem::Clock(items) => Local::now().format_with_items(items.iter()).to_string(),
+            StatusItem::Battery(Some(battery)) => {
+                let (capacity, state) = crate::get_battery_state(battery);
+                match state {
+                    BatteryState::Charging => format!("{}%+", capacity),
+                    _ => format!("{}%", capacity),
+                }
+            }
+            StatusItem::Battery(None) => "No battery".to_string(),
+            StatusItem::Wifi => match values.wifi {
+                Some(quality) => format!("Wi-Fi {}%", quality),
+                None => "Wi-Fi off".to_string(),
+            },
+            StatusItem::Volume => match values.volume {
+                Some((_, true)) => "Muted".to_string(),
+                Some((volume, false)) => format!("Vol {}%", volume),
+                None => "Vol -".to_string(),
+            },
+        }
+    }
+}
+
+// Link quality of the first wireless interface, as reported next to the signal level
+fn read_wifi() -> Option<u32> {
+    let wireless = fs::read_to_string("/proc/net/wireless").ok()?;
+    // Two header lines, then "wlan0: 0000   54.  -56.  -256 ..."
+    let line = wireless.lines().nth(2)?;
+    let (_, stats) = line.split_once(':')?;
+    let link = stats.split_whitespace().nth(1)?.trim_end_matches('.').parse::<f64>().ok()?;
+    // Most drivers report the quality out of 70
+    Some(((link / 70.0) * 100.0).round().clamp(0.0, 100.0) as u32)
+}
+
+// Asks the logged in user's PipeWire for the default sink's volume, from 0 to 1
+pub fn read_volume() -> Option<(f64, bool)> {
+    let env = user_cache::get_cached_user_environment()?;
+    let output = crate::privileged::Command::session_user(&env.username, "env")
+        .arg(format!("XDG_RUNTIME_DIR={}", env.runtime_dir))
+        .args(["wpctl", "get-volume", "@DEFAULT_AUDIO_SINK@"])
+        .output()
+        .ok()?;
+    // "Volume: 0.45" optionally followed by "[MUTED]"
+    let output = String::from_utf8(output.stdout).ok()?;
+    let level = output
+        .strip_prefix("Volume:")?
+        .split_whitespace()
+        .next()?
+        .parse::<f64>()
+        .ok()?;
+    Some((level.min(1.0), output.contains("[MUTED]")))
+}
+
+// Public API
+
+pub fn initialize_status_monitor() {
+    std::sync::LazyLock::force(&STATUS_MONITOR);
+}
@@ -0,0 +1,292 @@
+use crate::hyprland::{self, ActiveWindowInfo, HyprlandWorkspace, WindowInfoProvider};
+use crate::privileged;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// i3 IPC message types, sway speaks the same protocol
+const GET_WORKSPACES: u32 = 1;
+const SUBSCRIBE: u32 = 2;
+const GET_TREE: u32 = 4;
+// Events have the highest bit set on top of their number
+const WORKSPACE_EVENT: u32 = 0x80000000;
+const WINDOW_EVENT: u32 = 0x80000003;
+
+const MAGIC: &[u8; 6] = b"i3-ipc";
+// How long the listener waits before looking for the socket again
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+static EVENT_LISTENER_STARTED: std::sync::LazyLock<Arc<Mutex<bool>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(false)));
+
+pub struct SwayIpc {
+    socket_path: String,
+}
+
+impl SwayIpc {
+    pub fn new() -> Result<Self> {
+        for variable in ["SWAYSOCK", "I3SOCK"] {
+            if let Ok(socket_path) = std::env::var(variable) {
+                return Ok(SwayIpc { socket_path });
+            }
+        }
+
+        // tiny-dfr runs outside the session, look where sway and i3 put their sockets:
+        // /run/user/<uid>/sway-ipc.<uid>.<pid>.sock and /run/user/<uid>/i3/ipc-socket.<pid>
+        if let Ok(run_user_entries) = std::fs::read_dir("/run/user") {
+            for user_entry in run_user_entries.flatten() {
+                let user_dir = user_entry.path().to_string_lossy().into_owned();
+                let i3_dir = format!("{}/i3", user_dir);
+                let candidates = [user_dir, i3_dir]
+                    .into_iter()
+                    .flat_map(|dir| privileged::read_session_dir(&dir).unwrap_or_default().into_iter().map(move |name| (format!("{}/{}", dir, name), name)));
+                for (path_str, name) in candidates {
+                    if (name.starts_with("sway-ipc.") && name.ends_with(".sock")) || name.starts_with("ipc-socket.") {
+                        // Sockets of compositors that crashed stay behind
+                        if privileged::connect_session_socket(&path_str).is_ok() {
+                            return Ok(SwayIpc { socket_path: path_str });
+                        }
+                    }
+                }
+            }
+        }
+
+        Err(anyhow!("Could not find a sway or i3 socket. Make sure sway or i3 is running."))
+    }
+
+    fn connect(&self) -> Result<UnixStream> {
+        privileged::connect_session_socket(&self.socket_path).map_err(|e| anyhow!("Failed to connect to sway socket: {}", e))
+    }
+
+    pub fn send_message(&self, message_type: u32, payload: &str) -> Result<Value> {
+        let mut stream = self.connect()?;
+        write_message(&mut stream, message_type, payload)?;
+        let (_, reply) = read_message(&mut stream)?;
+        Ok(reply)
+    }
+
+    pub fn get_active_window(&self) -> Result<ActiveWindowInfo> {
+        let tree = self.send_message(GET_TREE, "")?;
+        // The workspace itself is focused while it has no windows
+        Ok(find_focused(&tree).map(window_info).unwrap_or_else(empty_window))
+    }
+
+    pub fn get_active_workspace(&self) -> Result<HyprlandWorkspace> {
+        let workspaces = self.send_message(GET_WORKSPACES, "")?;
+        workspaces
+            .as_array()
+            .and_then(|workspaces| workspaces.iter().find(|workspace| workspace["focused"] == true))
+            .map(workspace)
+            .ok_or_else(|| anyhow!("No focused workspace in sway's reply"))
+    }
+
+    pub fn start_event_listener(&self) {
+        thread::spawn(event_listener_loop);
+    }
+}
+
+fn write_message(stream: &mut UnixStream, message_type: u32, payload: &str) -> Result<()> {
+    let mut message = MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&message_type.to_ne_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    stream.write_all(&message).map_err(|e| anyhow!("Failed to send message: {}", e))
+}
+
+fn read_message(stream: &mut UnixStream) -> Result<(u32, Value)> {
+    let mut header = [0u8; 14];
+    stream.read_exact(&mut header).map_err(|e| anyhow!("Failed to read message: {}", e))?;
+    if &header[..6] != MAGIC {
+        return Err(anyhow!("Not an i3 IPC message"));
+    }
+    let length = u32::from_ne_bytes(header[6..10].try_into().unwrap()) as usize;
+    let message_type = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload).map_err(|e| anyhow!("Failed to read message: {}", e))?;
+    let value = serde_json::from_slice(&payload).map_err(|e| anyhow!("Failed to parse message: {}", e))?;
+    Ok((message_type, value))
+}
+
+// The focused container, searched depth first through tiled and floating nodes
+fn find_focused(node: &Value) -> Option<&Value> {
+    if node["focused"] == true {
+        return (node["type"] != "workspace").then_some(node);
+    }
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|children| node[children].as_array())
+        .flatten()
+        .find_map(find_focused)
+}
+
+// Wayland windows have an app_id, X11 ones a class
+fn window_info(container: &Value) -> ActiveWindowInfo {
+    let class = container["app_id"]
+        .as_str()
+        .or_else(|| container["window_properties"]["class"].as_str())
+        .unwrap_or_default()
+        .to_string();
+    let title = container["name"].as_str().unwrap_or_default().to_string();
+    let initial_class = container["window_properties"]["instance"].as_str().map_or_else(|| class.clone(), str::to_string);
+    ActiveWindowInfo {
+        initial_title: title.clone(), // sway doesn't keep the first title
+        initial_class,
+        title,
+        class,
+    }
+}
+
+fn empty_window() -> ActiveWindowInfo {
+    ActiveWindowInfo {
+        title: String::new(),
+        class: String::new(),
+        initial_title: String::new(),
+        initial_class: String::new(),
+    }
+}
+
+fn workspace(workspace: &Value) -> HyprlandWorkspace {
+    HyprlandWorkspace {
+        id: workspace["num"].as_i64().unwrap_or(-1) as i32,
+        name: workspace["name"].as_str().unwrap_or_default().to_string(),
+    }
+}
+
+fn event_listener_loop() {
+    loop {
+        // Looked up again every time, the socket changes when sway is restarted
+        match SwayIpc::new().and_then(|ipc| listen(&ipc)) {
+            Ok(()) => {}
+            Err(e) => println!("Sway event listener error: {}", e),
+        }
+        thread::sleep(RECONNECT_INTERVAL);
+    }
+}
+
+fn listen(ipc: &SwayIpc) -> Result<()> {
+    let mut stream = ipc.connect()?;
+    write_message(&mut stream, SUBSCRIBE, r#"["window","workspace"]"#)?;
+    let (_, reply) = read_message(&mut stream)?;
+    if reply["success"] != true {
+        return Err(anyhow!("sway refused the event subscription"));
+    }
+    println!("Starting sway event listener on: {}", ipc.socket_path);
+    // Whatever changed while nobody was listening
+    if let Ok(window) = ipc.get_active_window() {
+        hyprland::set_active_window(window);
+    }
+    loop {
+        let (message_type, event) = read_message(&mut stream)?;
+        handle_event(message_type, &event);
+    }
+}
+
+fn handle_event(message_type: u32, event: &Value) {
+    match (message_type, event["change"].as_str()) {
+        (WINDOW_EVENT, Some("focus")) => hyprland::set_active_window(window_info(&event["container"])),
+        (WINDOW_EVENT, Some("title")) if event["container"]["focused"] == true => {
+            hyprland::set_active_window(window_info(&event["container"]))
+        }
+        // Closing the last window of a workspace sends no focus event
+        (WINDOW_EVENT, Some("close")) if event["container"]["focused"] == true => hyprland::set_active_window(empty_window()),
+        (WORKSPACE_EVENT, Some("focus")) => {
+            hyprland::set_active_workspace(workspace(&event["current"]));
+            // Focus moving to an empty workspace sends no window event either
+            if event["current"]["focused"] == true {
+                hyprland::set_active_window(empty_window());
+            }
+        }
+        _ => {}
+    }
+}
+
+pub struct SwayProvider;
+
+impl WindowInfoProvider for SwayProvider {
+    fn active_window(&self) -> Result<ActiveWindowInfo> {
+        // Once the event listener runs it keeps the cache current, no need to find the socket again
+        if *EVENT_LISTENER_STARTED.lock().unwrap() {
+            if let Some(cached_info) = hyprland::cached_active_window() {
+                return Ok(cached_info);
+            }
+        }
+
+        let ipc = SwayIpc::new()?;
+
+        {
+            let mut started = EVENT_LISTENER_STARTED.lock().unwrap();
+            if !*started {
+                ipc.start_event_listener();
+                *started = true;
+            }
+        }
+
+        let window_info = ipc.get_active_window()?;
+        hyprland::set_active_window(window_info.clone());
+        Ok(window_info)
+    }
+
+    fn active_workspace(&self) -> Option<HyprlandWorkspace> {
+        SwayIpc::new().ok()?.get_active_workspace().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_framing() {
+        let (mut ours, mut sway) = UnixStream::pair().unwrap();
+        write_message(&mut ours, SUBSCRIBE, r#"["window"]"#).unwrap();
+        // Magic, payload length and type, then the payload
+        let mut header = [0u8; 14];
+        sway.read_exact(&mut header).unwrap();
+        assert_eq!(&header[..6], MAGIC);
+        assert_eq!(u32::from_ne_bytes(header[6..10].try_into().unwrap()), 10);
+        assert_eq!(u32::from_ne_bytes(header[10..14].try_into().unwrap()), SUBSCRIBE);
+        let mut payload = [0u8; 10];
+        sway.read_exact(&mut payload).unwrap();
+        assert_eq!(&payload, br#"["window"]"#);
+
+        let event = r#"{"change":"focus"}"#;
+        let mut message = MAGIC.to_vec();
+        message.extend_from_slice(&(event.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&WINDOW_EVENT.to_ne_bytes());
+        message.extend_from_slice(event.as_bytes());
+        sway.write_all(&message).unwrap();
+        let (message_type, value) = read_message(&mut ours).unwrap();
+        assert_eq!(message_type, WINDOW_EVENT);
+        assert_eq!(value["change"], "focus");
+
+        // Anything but the magic is refused, a payload cut short is an error
+        sway.write_all(b"i4-ipc\0\0\0\0\0\0\0\0").unwrap();
+        assert!(read_message(&mut ours).is_err());
+        sway.write_all(&message[..message.len() - 1]).unwrap();
+        drop(sway);
+        assert!(read_message(&mut ours).is_err());
+    }
+
+    #[test]
+    fn test_find_focused() {
+        let tree: Value = serde_json::from_str(
+            r#"{"type":"root","focused":false,"nodes":[{"type":"workspace","focused":false,"nodes":[
+                {"type":"con","focused":false,"app_id":"foot","name":"~"}],
+                "floating_nodes":[{"type":"con","focused":true,"app_id":null,"name":"Firefox",
+                "window_properties":{"class":"firefox","instance":"Navigator"}}]}]}"#,
+        )
+        .unwrap();
+        let window = window_info(find_focused(&tree).unwrap());
+        assert_eq!(window.class, "firefox");
+        assert_eq!(window.initial_class, "Navigator");
+        assert_eq!(window.title, "Firefox");
+
+        // A focused workspace means there's no window to name
+        let empty: Value = serde_json::from_str(r#"{"type":"root","nodes":[{"type":"workspace","focused":true,"nodes":[]}]}"#).unwrap();
+        assert!(find_focused(&empty).is_none());
+    }
+}
@@ -1,4 +1,6 @@
 use chrono::{Local, Timelike};
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use std::os::fd::{AsFd, BorrowedFd};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
@@ -25,6 +27,9 @@ impl SystemState {
 static SYSTEM_STATE: std::sync::LazyLock<Arc<Mutex<SystemState>>> =
     std::sync::LazyLock::new(|| Arc::new(Mutex::new(SystemState::new())));
 
+// real_main runs again after a crash or a configuration error, the thread is started only once
+static SYSTEM_MONITOR: std::sync::LazyLock<SystemMonitor> = std::sync::LazyLock::new(SystemMonitor::new);
+
 pub struct SystemMonitor {
     _handle: thread::JoinHandle<()>,
 }
@@ -79,7 +84,52 @@ impl SystemMonitor {
     }
 }
 
+// Sees /etc/localtime being replaced, which is how timedatectl and most tools
+// switch the timezone, so the clock doesn't wait for the next minute to catch up
+pub struct LocaltimeWatch {
+    inotify: Option<Inotify>,
+}
+
+impl LocaltimeWatch {
+    pub fn new() -> Self {
+        // The link itself is swapped out, so watch the directory it lives in
+        let flags = AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_DELETE;
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK)
+            .ok()
+            .filter(|inotify| inotify.add_watch("/etc", flags).is_ok());
+        if inotify.is_none() {
+            eprintln!("Failed to watch /etc/localtime, timezone changes show up with the next minute");
+        }
+        LocaltimeWatch { inotify }
+    }
+
+    pub fn fd(&self) -> Option<BorrowedFd<'_>> {
+        self.inotify.as_ref().map(|inotify| inotify.as_fd())
+    }
+
+    pub fn changed(&self) -> bool {
+        let Some(inotify) = &self.inotify else {
+            return false;
+        };
+        let mut changed = false;
+        while let Ok(events) = inotify.read_events() {
+            if events.is_empty() {
+                break;
+            }
+            changed |= events.iter().any(|event| event.name.as_deref().is_some_and(|name| name == "localtime"));
+        }
+        changed
+    }
+}
+
 // Public API
+pub fn initialize_system_monitor() {
+    std::sync::LazyLock::force(&SYSTEM_MONITOR);
+}
+
 pub fn get_current_minute() -> u32 {
     if let Ok(state) = SYSTEM_STATE.lock() {
         state.current_minute
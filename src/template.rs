@@ -0,0 +1,128 @@
+use crate::hyprland;
+use chrono::{
+    format::{Item as ChronoItem, StrftimeItems},
+    Local, Locale,
+};
+
+#[derive(Clone)]
+enum Part {
+    Literal(String),
+    Title,
+    Class,
+    Workspace,
+    Battery(Option<String>), // battery device, looked up once
+    Time(Vec<ChronoItem<'static>>),
+}
+
+// Button text with placeholders filled in whenever the button is drawn, e.g.
+// "{class} on {workspace}" or "{time:%H:%M} {battery}"
+#[derive(Clone)]
+pub struct TextTemplate {
+    parts: Vec<Part>,
+    locale: Locale,
+}
+
+impl TextTemplate {
+    // Returns None for text without placeholders, so plain labels stay plain
+    pub fn parse(text: &str, locale: Option<&str>) -> Option<TextTemplate> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(start) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..start]);
+            let brace = &rest[start..];
+            // Doubled braces stand for themselves
+            if brace.starts_with("{{") || brace.starts_with("}}") {
+                literal.push_str(&brace[..1]);
+                rest = &brace[2..];
+                continue;
+            }
+            let Some(end) = brace.find('}').filter(|_| brace.starts_with('{')) else {
+                literal.push_str(&brace[..1]);
+                rest = &brace[1..];
+                continue;
+            };
+            let placeholder = &brace[1..end];
+            let part = match placeholder.split_once(':') {
+                Some(("time", format)) => match StrftimeItems::new(format).parse_to_owned() {
+                    Ok(items) => Some(Part::Time(items)),
+                    Err(e) => {
+                        eprintln!("Invalid time format '{}' in button text '{}': {:?}", format, text, e);
+                        None
+                    }
+                },
+                _ => match placeholder {
+                    "title" => Some(Part::Title),
+                    "class" => Some(Part::Class),
+                    "workspace" => Some(Part::Workspace),
+                    "battery" => Some(Part::Battery(crate::find_battery_device())),
+                    "time" => Some(Part::Time(StrftimeItems::new("%H:%M").parse_to_owned().unwrap())),
+                    _ => {
+                        eprintln!("Unknown placeholder '{{{}}}' in button text '{}'", placeholder, text);
+                        None
+                    }
+                },
+            };
+            match part {
+                Some(part) => {
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(part);
+                }
+                // Unknown placeholders are shown as written
+                None => literal.push_str(&brace[..=end]),
+            }
+            rest = &brace[end + 1..];
+        }
+        literal.push_str(rest);
+        if parts.is_empty() {
+            return None;
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Some(TextTemplate {
+            parts,
+            locale: locale.and_then(|l| Locale::try_from(l).ok()).unwrap_or(Locale::POSIX),
+        })
+    }
+
+    pub fn render(&self) -> String {
+        let window = if self.uses_window() {
+            hyprland::get_active_window_info().ok()
+        } else {
+            None
+        };
+        let mut text = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => text.push_str(literal),
+                Part::Title => text.push_str(window.as_ref().map_or("", |w| w.title.as_str())),
+                Part::Class => text.push_str(window.as_ref().map_or("", |w| w.class.as_str())),
+                Part::Workspace => text.push_str(&hyprland::get_active_workspace_name().unwrap_or_default()),
+                Part::Battery(Some(battery)) => {
+                    let (capacity, _) = crate::get_battery_state(battery);
+                    text.push_str(&format!("{}%", capacity));
+                }
+                Part::Battery(None) => text.push_str("N/A"),
+                Part::Time(items) => {
+                    text.push_str(&Local::now().format_localized_with_items(items.iter(), self.locale).to_string())
+                }
+            }
+        }
+        text
+    }
+
+    fn uses_window(&self) -> bool {
+        self.parts.iter().any(|part| matches!(part, Part::Title | Part::Class))
+    }
+
+    pub fn uses_time(&self) -> bool {
+        self.parts.iter().any(|part| matches!(part, Part::Time(_)))
+    }
+
+    pub fn uses_battery(&self) -> bool {
+        self.parts.iter().any(|part| matches!(part, Part::Battery(_)))
+    }
+}
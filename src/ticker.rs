@@ -0,0 +1,87 @@
+use crate::poller::Poller;
+use crate::privileged::Command;
+use crate::user_cache;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// How long each symbol stays on the button when several are configured
+pub const CYCLE_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_REFRESH: Duration = Duration::from_secs(300);
+
+#[derive(Clone)]
+pub struct Quote {
+    pub price: String,
+    pub delta: Option<String>, // as printed, e.g. "+1.2%"
+    pub rising: Option<bool>,
+}
+
+// Latest quote of each symbol, keyed by command and symbol
+type Quotes = HashMap<(String, String), Quote>;
+
+// Symbols being watched, by command and symbol
+static SOURCES: Poller<(String, String)> = Poller::new();
+static QUOTES: std::sync::LazyLock<Arc<Mutex<Quotes>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+// Only started once a ticker widget is created
+static TICKER_MONITOR: std::sync::LazyLock<thread::JoinHandle<()>> =
+    std::sync::LazyLock::new(|| thread::spawn(monitor_loop));
+
+fn monitor_loop() {
+    loop {
+        for (command, symbol) in SOURCES.due(|_| false) {
+            if let Some(quote) = fetch_quote(&command, &symbol) {
+                if let Ok(mut quotes) = QUOTES.lock() {
+                    quotes.insert((command, symbol), quote);
+                }
+            }
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+// Runs the price command as the logged in user, it prints the price optionally followed by the change
+fn fetch_quote(command: &str, symbol: &str) -> Option<Quote> {
+    let env = user_cache::get_cached_user_environment()?;
+    let quoted = format!("'{}'", symbol.replace('\'', "'\\''"));
+    let output = Command::login_shell(&env.username, command.replace("{symbol}", &quoted)).output();
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            eprintln!("Ticker command for {} exited with {}", symbol, output.status);
+            return None;
+        }
+        Err(e) => {
+            eprintln!("Failed to run ticker command for {}: {}", symbol, e);
+            return None;
+        }
+    };
+    // "67012.50 +2.3%" or just "67012.50"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut fields = stdout.split_whitespace();
+    let price = fields.next()?.to_string();
+    let delta = fields.next().map(|delta| delta.to_string());
+    let rising = delta
+        .as_deref()
+        .and_then(|delta| delta.trim_end_matches('%').parse::<f64>().ok())
+        .filter(|change| *change != 0.0)
+        .map(|change| change > 0.0);
+    Some(Quote { price, delta, rising })
+}
+
+// Public API
+
+pub fn watch(command: &str, symbols: &[String], refresh_seconds: Option<u64>) {
+    let refresh = refresh_seconds.map_or(DEFAULT_REFRESH, |seconds| Duration::from_secs(seconds.max(10)));
+    for symbol in symbols {
+        SOURCES.watch((command.to_string(), symbol.clone()), refresh);
+    }
+    std::sync::LazyLock::force(&TICKER_MONITOR);
+}
+
+pub fn quote(command: &str, symbol: &str) -> Option<Quote> {
+    QUOTES.lock().ok()?.get(&(command.to_string(), symbol.to_string())).cloned()
+}
+
@@ -0,0 +1,403 @@
+use crate::hyprland::{self, ActiveWindowInfo, WindowInfoProvider};
+use crate::privileged;
+use crate::user_cache;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+// Just enough of the Wayland wire protocol to follow wlr-foreign-toplevel-management,
+// which wlroots compositors offer to taskbars, there is no Wayland library to lean on
+const MANAGER_INTERFACE: &str = "zwlr_foreign_toplevel_manager_v1";
+// Version 3 adds the parent event, which only needs to be skipped
+const MANAGER_VERSION: u32 = 3;
+// How long to wait before connecting again after the compositor went away
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+// Compositors without the protocol are asked again this rarely, the session might change
+const UNSUPPORTED_DELAY: Duration = Duration::from_secs(60);
+
+const DISPLAY_ID: u32 = 1;
+const REGISTRY_ID: u32 = 2;
+const SYNC_CALLBACK_ID: u32 = 3;
+const MANAGER_ID: u32 = 4;
+
+// wl_display
+const DISPLAY_SYNC: u16 = 0;
+const DISPLAY_GET_REGISTRY: u16 = 1;
+const DISPLAY_ERROR: u16 = 0;
+// wl_registry
+const REGISTRY_BIND: u16 = 0;
+const REGISTRY_GLOBAL: u16 = 0;
+// zwlr_foreign_toplevel_manager_v1
+const MANAGER_TOPLEVEL: u16 = 0;
+const MANAGER_FINISHED: u16 = 1;
+// zwlr_foreign_toplevel_handle_v1
+const HANDLE_TITLE: u16 = 0;
+const HANDLE_APP_ID: u16 = 1;
+const HANDLE_STATE: u16 = 4;
+const HANDLE_DONE: u16 = 5;
+const HANDLE_CLOSED: u16 = 6;
+const HANDLE_DESTROY: u16 = 7;
+const STATE_ACTIVATED: u32 = 2;
+
+static LISTENER_STARTED: AtomicBool = AtomicBool::new(false);
+
+struct Connection {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    fn send(&mut self, object: u32, opcode: u16, args: &[u8]) -> Result<()> {
+        let size = 8 + args.len() as u32;
+        let mut message = Vec::with_capacity(size as usize);
+        message.extend_from_slice(&object.to_ne_bytes());
+        message.extend_from_slice(&((size << 16) | opcode as u32).to_ne_bytes());
+        message.extend_from_slice(args);
+        self.stream.write_all(&message).map_err(|e| anyhow!("Failed to write to the compositor: {}", e))
+    }
+
+    // Blocks until at least one whole event came in
+    fn read_events(&mut self) -> Result<Vec<(u32, u16, Vec<u8>)>> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            let mut events = Vec::new();
+            while self.buffer.len() >= 8 {
+                let object = u32::from_ne_bytes(self.buffer[0..4].try_into().unwrap());
+                let header = u32::from_ne_bytes(self.buffer[4..8].try_into().unwrap());
+                let size = (header >> 16) as usize;
+                if size < 8 {
+                    return Err(anyhow!("Malformed event from the compositor"));
+                }
+                if self.buffer.len() < size {
+                    break;
+                }
+                let args = self.buffer[8..size].to_vec();
+                self.buffer.drain(..size);
+                events.push((object, (header & 0xffff) as u16, args));
+            }
+            if !events.is_empty() {
+                return Ok(events);
+            }
+            let read = self.stream.read(&mut chunk).map_err(|e| anyhow!("Failed to read from the compositor: {}", e))?;
+            if read == 0 {
+                return Err(anyhow!("The compositor closed the connection"));
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+fn push_uint(args: &mut Vec<u8>, value: u32) {
+    args.extend_from_slice(&value.to_ne_bytes());
+}
+
+fn push_string(args: &mut Vec<u8>, value: &str) {
+    push_uint(args, value.len() as u32 + 1);
+    args.extend_from_slice(value.as_bytes());
+    args.push(0);
+    while !args.len().is_multiple_of(4) {
+        args.push(0);
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn uint(&mut self) -> Option<u32> {
+        let value = u32::from_ne_bytes(self.data.get(self.pos..self.pos + 4)?.try_into().ok()?);
+        self.pos += 4;
+        Some(value)
+    }
+
+    fn bytes(&mut self) -> Option<&[u8]> {
+        let length = self.uint()? as usize;
+        let bytes = self.data.get(self.pos..self.pos + length)?;
+        self.pos += length.div_ceil(4) * 4;
+        Some(bytes)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let bytes = self.bytes()?;
+        // Includes the terminating NUL, a null string has no bytes at all
+        Some(String::from_utf8_lossy(bytes.strip_suffix(&[0]).unwrap_or(bytes)).into_owned())
+    }
+}
+
+#[derive(Default)]
+struct Toplevel {
+    title: String,
+    app_id: String,
+    activated: bool,
+}
+
+fn window_info(toplevel: &Toplevel) -> ActiveWindowInfo {
+    ActiveWindowInfo {
+        title: toplevel.title.clone(),
+        class: toplevel.app_id.clone(),
+        initial_title: toplevel.title.clone(), // The protocol doesn't keep the first title
+        initial_class: toplevel.app_id.clone(),
+    }
+}
+
+fn no_window() -> ActiveWindowInfo {
+    window_info(&Toplevel::default())
+}
+
+fn connect() -> Result<Connection> {
+    let env = user_cache::get_cached_user_environment().ok_or_else(|| anyhow!("No user session"))?;
+    let socket_path = if env.wayland_display.starts_with('/') {
+        env.wayland_display.clone()
+    } else {
+        format!("{}/{}", env.runtime_dir, env.wayland_display)
+    };
+    let stream = privileged::connect_session_socket(&socket_path).map_err(|e| anyhow!("Failed to connect to {}: {}", socket_path, e))?;
+    Ok(Connection { stream, buffer: Vec::new() })
+}
+
+// Binds the toplevel manager, false when the compositor doesn't offer it
+fn bind_manager(connection: &mut Connection) -> Result<bool> {
+    let mut args = Vec::new();
+    push_uint(&mut args, REGISTRY_ID);
+    connection.send(DISPLAY_ID, DISPLAY_GET_REGISTRY, &args)?;
+    let mut args = Vec::new();
+    push_uint(&mut args, SYNC_CALLBACK_ID);
+    connection.send(DISPLAY_ID, DISPLAY_SYNC, &args)?;
+    // Every global is announced before the sync callback is done
+    let mut manager = None;
+    loop {
+        for (object, opcode, args) in connection.read_events()? {
+            let mut reader = Reader { data: &args, pos: 0 };
+            match (object, opcode) {
+                (REGISTRY_ID, REGISTRY_GLOBAL) => {
+                    let name = reader.uint();
+                    let interface = reader.string();
+                    let version = reader.uint();
+                    if let (Some(name), Some(MANAGER_INTERFACE), Some(version)) = (name, interface.as_deref(), version) {
+                        manager = Some((name, version));
+                    }
+                }
+                (DISPLAY_ID, DISPLAY_ERROR) => {
+                    reader.pos = 8;
+                    return Err(anyhow!("Compositor error: {:?}", reader.string()));
+                }
+                (SYNC_CALLBACK_ID, _) => {
+                    let Some((name, version)) = manager else {
+                        return Ok(false);
+                    };
+                    let mut args = Vec::new();
+                    push_uint(&mut args, name);
+                    push_string(&mut args, MANAGER_INTERFACE);
+                    push_uint(&mut args, version.min(MANAGER_VERSION));
+                    push_uint(&mut args, MANAGER_ID);
+                    connection.send(REGISTRY_ID, REGISTRY_BIND, &args)?;
+                    return Ok(true);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn listen(connection: &mut Connection) -> Result<()> {
+    // Pending state of each window, applied with its done event
+    let mut toplevels: HashMap<u32, Toplevel> = HashMap::new();
+    let mut active = None;
+    loop {
+        for (object, opcode, args) in connection.read_events()? {
+            let mut reader = Reader { data: &args, pos: 0 };
+            if object == DISPLAY_ID && opcode == DISPLAY_ERROR {
+                reader.pos = 8;
+                return Err(anyhow!("Compositor error: {:?}", reader.string()));
+            }
+            if object == MANAGER_ID {
+                match opcode {
+                    MANAGER_TOPLEVEL => {
+                        if let Some(handle) = reader.uint() {
+                            toplevels.insert(handle, Toplevel::default());
+                        }
+                    }
+                    MANAGER_FINISHED => return Ok(()),
+                    _ => {}
+                }
+                continue;
+            }
+            let Some(toplevel) = toplevels.get_mut(&object) else {
+                continue;
+            };
+            match opcode {
+                HANDLE_TITLE => toplevel.title = reader.string().unwrap_or_default(),
+                HANDLE_APP_ID => toplevel.app_id = reader.string().unwrap_or_default(),
+                HANDLE_STATE => {
+                    let states = reader.bytes().unwrap_or_default();
+                    toplevel.activated = states
+                        .chunks_exact(4)
+                        .any(|state| u32::from_ne_bytes(state.try_into().unwrap()) == STATE_ACTIVATED);
+                }
+                HANDLE_DONE => {
+                    if toplevel.activated {
+                        active = Some(object);
+                        hyprland::set_active_window(window_info(toplevel));
+                    } else if active == Some(object) {
+                        // Another window's done event follows if focus moved on
+                        active = None;
+                        hyprland::set_active_window(no_window());
+                    }
+                }
+                HANDLE_CLOSED => {
+                    toplevels.remove(&object);
+                    connection.send(object, HANDLE_DESTROY, &[])?;
+                    if active == Some(object) {
+                        active = None;
+                        hyprland::set_active_window(no_window());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn listener_loop() {
+    loop {
+        let delay = match connect().and_then(|mut connection| {
+            if !bind_manager(&mut connection)? {
+                return Ok(false);
+            }
+            println!("Following the active window with {}", MANAGER_INTERFACE);
+            listen(&mut connection).map(|_| true)
+        }) {
+            Ok(true) => RECONNECT_DELAY,
+            Ok(false) => {
+                println!("The compositor does not offer {}", MANAGER_INTERFACE);
+                UNSUPPORTED_DELAY
+            }
+            Err(e) => {
+                println!("Foreign toplevel listener error: {}", e);
+                RECONNECT_DELAY
+            }
+        };
+        thread::sleep(delay);
+    }
+}
+
+// Any wlroots compositor, tried once Hyprland and sway aren't running
+pub struct ForeignToplevelProvider;
+
+impl WindowInfoProvider for ForeignToplevelProvider {
+    fn active_window(&self) -> Result<ActiveWindowInfo> {
+        if user_cache::get_cached_user_environment().is_none() {
+            return Err(anyhow!("No user session"));
+        }
+        if !LISTENER_STARTED.swap(true, Ordering::Relaxed) {
+            thread::spawn(listener_loop);
+        }
+        hyprland::cached_active_window().ok_or_else(|| anyhow!("Waiting for the compositor to name the active window"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(object: u32, opcode: u16, args: &[u8]) -> Vec<u8> {
+        let size = 8 + args.len() as u32;
+        let mut message = object.to_ne_bytes().to_vec();
+        message.extend_from_slice(&((size << 16) | opcode as u32).to_ne_bytes());
+        message.extend_from_slice(args);
+        message
+    }
+
+    #[test]
+    fn test_reader() {
+        let mut args = Vec::new();
+        push_uint(&mut args, 7);
+        push_string(&mut args, "kitty");
+        push_string(&mut args, "abc");
+        push_uint(&mut args, 0);
+        // Strings carry their NUL and are padded to 4 bytes
+        assert_eq!(args.len(), 4 + 4 + 8 + 4 + 4 + 4);
+        let mut reader = Reader { data: &args, pos: 0 };
+        assert_eq!(reader.uint(), Some(7));
+        assert_eq!(reader.string().as_deref(), Some("kitty"));
+        assert_eq!(reader.string().as_deref(), Some("abc"));
+        // A null string is a zero length
+        assert_eq!(reader.string().as_deref(), Some(""));
+        assert_eq!(reader.uint(), None);
+        // A length running past the end is nothing rather than a panic
+        let mut reader = Reader { data: &[16, 0, 0, 0, b'a', 0, 0, 0], pos: 0 };
+        assert_eq!(reader.string(), None);
+    }
+
+    #[test]
+    fn test_read_events_framing() {
+        let (stream, mut compositor) = UnixStream::pair().unwrap();
+        let mut connection = Connection { stream, buffer: Vec::new() };
+        let mut title = Vec::new();
+        push_string(&mut title, "Terminal");
+        let first = event(9, HANDLE_TITLE, &title);
+        let second = event(9, HANDLE_DONE, &[]);
+        // An event split across reads waits for the rest of it
+        compositor.write_all(&first[..6]).unwrap();
+        compositor.write_all(&first[6..]).unwrap();
+        compositor.write_all(&second).unwrap();
+        let mut events = Vec::new();
+        while events.len() < 2 {
+            events.extend(connection.read_events().unwrap());
+        }
+        assert_eq!(events, vec![(9, HANDLE_TITLE, title), (9, HANDLE_DONE, Vec::new())]);
+        assert!(connection.buffer.is_empty());
+
+        // A size smaller than the header can't be framed
+        compositor.write_all(&[9, 0, 0, 0, 0, 0, 4, 0]).unwrap();
+        assert!(connection.read_events().is_err());
+        connection.buffer.clear();
+        drop(compositor);
+        assert!(connection.read_events().is_err());
+    }
+
+    #[test]
+    fn test_bind_manager() {
+        let (stream, mut compositor) = UnixStream::pair().unwrap();
+        let mut connection = Connection { stream, buffer: Vec::new() };
+        let global = |name: u32, interface: &str, version: u32| {
+            let mut args = Vec::new();
+            push_uint(&mut args, name);
+            push_string(&mut args, interface);
+            push_uint(&mut args, version);
+            event(REGISTRY_ID, REGISTRY_GLOBAL, &args)
+        };
+        compositor.write_all(&global(1, "wl_compositor", 6)).unwrap();
+        compositor.write_all(&global(12, MANAGER_INTERFACE, 99)).unwrap();
+        compositor.write_all(&event(SYNC_CALLBACK_ID, 0, &[0, 0, 0, 0])).unwrap();
+        assert!(bind_manager(&mut connection).unwrap());
+
+        // get_registry, sync, then the bind asking for no more than we understand
+        let mut sent = Vec::new();
+        compositor.set_nonblocking(true).unwrap();
+        let _ = compositor.read_to_end(&mut sent);
+        // Requests are framed like events, everything is buffered already
+        let mut client = Connection { stream: UnixStream::pair().unwrap().0, buffer: sent };
+        let requests = client.read_events().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!((requests[2].0, requests[2].1), (REGISTRY_ID, REGISTRY_BIND));
+        let mut reader = Reader { data: &requests[2].2, pos: 0 };
+        assert_eq!(reader.uint(), Some(12));
+        assert_eq!(reader.string().as_deref(), Some(MANAGER_INTERFACE));
+        assert_eq!(reader.uint(), Some(MANAGER_VERSION));
+        assert_eq!(reader.uint(), Some(MANAGER_ID));
+
+        // Without the global there's nothing to bind
+        let (stream, mut compositor) = UnixStream::pair().unwrap();
+        let mut connection = Connection { stream, buffer: Vec::new() };
+        compositor.write_all(&global(1, "wl_compositor", 6)).unwrap();
+        compositor.write_all(&event(SYNC_CALLBACK_ID, 0, &[0, 0, 0, 0])).unwrap();
+        assert!(!bind_manager(&mut connection).unwrap());
+    }
+}
@@ -1,7 +1,21 @@
+use nix::{
+    errno::Errno,
+    sys::{
+        epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags},
+        inotify::{AddWatchFlags, InitFlags, Inotify},
+    },
+};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
 
+// logind keeps one file per session and per seat here, rewriting them on every change
+const SESSION_DIRS: [&str; 2] = ["/run/systemd/sessions", "/run/systemd/seats"];
+// A user switch touches several files, wait for logind to finish before detecting again
+const SESSION_SETTLE: Duration = Duration::from_millis(500);
+// The compositor socket shows up a bit after the session, so keep checking for a dead one
+const SESSION_RECHECK_MS: u16 = 60 * 1000;
+
 #[derive(Debug, Clone)]
 pub struct CachedUserEnvironment {
     pub username: String,
@@ -43,6 +57,12 @@ impl CachedUserEnvironment {
     fn is_stale(&self) -> bool {
         self.last_updated.elapsed() > Duration::from_secs(300) // 5 minutes
     }
+
+    fn is_dead(&self) -> bool {
+        !std::path::Path::new(&self.runtime_dir)
+            .join(&self.wayland_display)
+            .exists()
+    }
 }
 
 // Global user environment cache
@@ -70,6 +90,47 @@ impl UserEnvironmentMonitor {
     }
 
     fn monitor_loop() {
+        match watch_sessions() {
+            Some(inotify) => Self::session_loop(inotify),
+            None => Self::polling_loop(),
+        }
+    }
+
+    // Re-detect as soon as logind reports a login, logout or user switch
+    fn session_loop(inotify: Inotify) {
+        let epoll = Epoll::new(EpollCreateFlags::empty()).unwrap();
+        epoll.add(&inotify, EpollEvent::new(EpollFlags::EPOLLIN, 0)).unwrap();
+
+        loop {
+            let mut events = [EpollEvent::empty()];
+            let changed = match epoll.wait(&mut events, SESSION_RECHECK_MS) {
+                Ok(0) => false,
+                Ok(_) => true,
+                Err(Errno::EINTR) => continue,
+                Err(e) => {
+                    eprintln!("Failed to wait for session changes: {}, falling back to polling", e);
+                    return Self::polling_loop();
+                }
+            };
+
+            if changed {
+                thread::sleep(SESSION_SETTLE);
+                // Drain everything queued meanwhile, one detection covers it all
+                while inotify.read_events().is_ok() {}
+                refresh_cache(true);
+            } else {
+                let should_refresh = USER_ENV_CACHE
+                    .lock()
+                    .map_or(true, |cache| cache.as_ref().is_none_or(|env| env.is_dead()));
+                if should_refresh {
+                    refresh_cache(false);
+                }
+            }
+        }
+    }
+
+    // Without logind's runtime files, fall back to re-detecting stale entries
+    fn polling_loop() {
         let mut last_refresh = Instant::now();
         let refresh_interval = Duration::from_secs(60); // Check every minute
 
@@ -87,9 +148,7 @@ impl UserEnvironmentMonitor {
                 };
 
                 if should_refresh {
-                    if let Ok(mut cache) = USER_ENV_CACHE.lock() {
-                        *cache = CachedUserEnvironment::new();
-                    }
+                    refresh_cache(false);
                 }
 
                 last_refresh = now;
@@ -100,6 +159,36 @@ impl UserEnvironmentMonitor {
     }
 }
 
+fn watch_sessions() -> Option<Inotify> {
+    let inotify = Inotify::init(InitFlags::IN_NONBLOCK).ok()?;
+    let flags = AddWatchFlags::IN_CREATE
+        | AddWatchFlags::IN_DELETE
+        | AddWatchFlags::IN_MOVED_TO
+        | AddWatchFlags::IN_CLOSE_WRITE;
+    let mut watching = false;
+    for dir in SESSION_DIRS {
+        watching |= inotify.add_watch(dir, flags).is_ok();
+    }
+    watching.then_some(inotify)
+}
+
+// Detection runs outside the lock since it spawns loginctl
+fn refresh_cache(session_changed: bool) {
+    let env = CachedUserEnvironment::new();
+    let Ok(mut cache) = USER_ENV_CACHE.lock() else {
+        return;
+    };
+    let before = cache.as_ref().map(|env| (env.username.clone(), env.wayland_display.clone()));
+    let after = env.as_ref().map(|env| (env.username.clone(), env.wayland_display.clone()));
+    if session_changed && before != after {
+        match &after {
+            Some((username, display)) => println!("Desktop session changed to user {} on {}", username, display),
+            None => println!("Desktop session ended, no logged in user"),
+        }
+    }
+    *cache = env;
+}
+
 // Public API
 pub fn get_cached_user_environment() -> Option<CachedUserEnvironment> {
     if let Ok(cache) = USER_ENV_CACHE.lock() {
@@ -109,6 +198,33 @@ pub fn get_cached_user_environment() -> Option<CachedUserEnvironment> {
     }
 }
 
+// Found afresh rather than cached, for the privileged helper which keeps no monitor of its own
+pub fn detect_session_user() -> Option<String> {
+    detect_desktop_user()
+}
+
+fn loginctl(args: &[&str]) -> Option<String> {
+    std::process::Command::new("loginctl")
+        .args(args)
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|value| value.trim().to_string())
+}
+
+// The id of the session the user's desktop runs in
+pub fn display_session(username: &str) -> Option<String> {
+    loginctl(&["show-user", username, "-p", "Display", "--value"]).filter(|s| !s.is_empty())
+}
+
+// Asks logind whether the user's display session is locked, errs on the side of unlocked
+pub fn session_locked(username: &str) -> bool {
+    let Some(session) = display_session(username) else {
+        return false;
+    };
+    loginctl(&["show-session", &session, "-p", "LockedHint", "--value"]).as_deref() == Some("yes")
+}
+
 // Move the detection functions here to avoid blocking main thread
 fn detect_desktop_user() -> Option<String> {
     // Method 1: Check SUDO_USER if running via sudo
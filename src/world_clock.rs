@@ -0,0 +1,79 @@
+use chrono::{FixedOffset, Local, Utc};
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Offsets only change at DST switches, which happen on a full minute
+const OFFSET_INTERVAL: Duration = Duration::from_secs(60);
+
+// UTC offset of every zone shown on a world clock, in seconds
+static OFFSETS: std::sync::LazyLock<Arc<Mutex<HashMap<String, i32>>>> =
+    std::sync::LazyLock::new(|| Arc::new(Mutex::new(HashMap::new())));
+
+// Only started once a world clock button is created
+static OFFSET_MONITOR: std::sync::LazyLock<thread::JoinHandle<()>> =
+    std::sync::LazyLock::new(|| thread::spawn(monitor_loop));
+
+fn monitor_loop() {
+    loop {
+        thread::sleep(OFFSET_INTERVAL);
+        let zones: Vec<String> = OFFSETS.lock().map(|offsets| offsets.keys().cloned().collect()).unwrap_or_default();
+        for zone in zones {
+            if let Some(offset) = lookup_offset(&zone) {
+                if let Ok(mut offsets) = OFFSETS.lock() {
+                    offsets.insert(zone, offset);
+                }
+            }
+        }
+    }
+}
+
+// chrono has no timezone database of its own, let date(1) read the one in /usr/share/zoneinfo
+fn lookup_offset(zone: &str) -> Option<i32> {
+    let output = Command::new("date").env("TZ", zone).arg("+%z").output().ok()?;
+    // "+0530" or "-0800"
+    let offset = String::from_utf8(output.stdout).ok()?;
+    let offset = offset.trim();
+    let sign = match offset.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let hours: i32 = offset.get(1..3)?.parse().ok()?;
+    let minutes: i32 = offset.get(3..5)?.parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+// Public API
+
+// Starts keeping track of a zone, returns false if there is no such zone
+pub fn watch_zone(zone: &str) -> bool {
+    // date(1) quietly falls back to UTC for unknown zones
+    if !Path::new("/usr/share/zoneinfo").join(zone).is_file() {
+        eprintln!("Unknown WorldClock zone '{}', expected a name like Europe/Paris", zone);
+        return false;
+    }
+    let known = OFFSETS.lock().is_ok_and(|offsets| offsets.contains_key(zone));
+    if !known {
+        if let (Some(offset), Ok(mut offsets)) = (lookup_offset(zone), OFFSETS.lock()) {
+            offsets.insert(zone.to_string(), offset);
+        }
+    }
+    std::sync::LazyLock::force(&OFFSET_MONITOR);
+    true
+}
+
+// Time in the zone formatted as "14:05", plus "+1d" or "-1d" when its date differs from the local one
+pub fn zone_time(zone: &str) -> Option<String> {
+    let offset = OFFSETS.lock().ok()?.get(zone).copied()?;
+    let zone_now = Utc::now().with_timezone(&FixedOffset::east_opt(offset)?);
+    let days = (zone_now.date_naive() - Local::now().date_naive()).num_days();
+    let time = zone_now.format("%H:%M").to_string();
+    Some(match days {
+        0 => time,
+        days => format!("{} {:+}d", time, days),
+    })
+}